@@ -0,0 +1,60 @@
+use std::process::Command;
+
+/// The version of a package that the lock file resolves to
+fn locked_version(lock_file: &str, package: &str) -> Option<String> {
+    let name_line = format!("name = \"{package}\"");
+    let mut lines = lock_file.lines();
+    lines.find(|line| line.trim() == name_line)?;
+    lines
+        .next()?
+        .trim()
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+        .map(String::from)
+}
+
+/// Record the commit that mmoreseqs is built from, and what it is built with,
+/// for the provenance of its outputs and --version-json.
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+
+    // a build from a source archive has no commit to record
+    if let Some(commit) = commit.filter(|commit| !commit.is_empty()) {
+        println!("cargo:rustc-env=MMORESEQS_GIT_COMMIT={commit}");
+    }
+
+    // a build as a dependency of another crate has no lock file of its own
+    let nale_version = std::fs::read_to_string("Cargo.lock")
+        .ok()
+        .and_then(|lock_file| locked_version(&lock_file, "nale"));
+    if let Some(nale_version) = nale_version {
+        println!("cargo:rustc-env=MMORESEQS_NALE_VERSION={nale_version}");
+    }
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(name, _)| {
+            name.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=MMORESEQS_FEATURES={}", features.join(","));
+
+    for (name, var) in [
+        ("PROFILE", "MMORESEQS_BUILD_PROFILE"),
+        ("TARGET", "MMORESEQS_BUILD_TARGET"),
+    ] {
+        if let Ok(value) = std::env::var(name) {
+            println!("cargo:rustc-env={var}={value}");
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::io::Write;
+
+use crate::alt_alignments::AltAlignment;
+use crate::bound_stats::BoundStats;
+use crate::domain_stats::DomainStats;
+use crate::envelope::Envelope;
+use crate::null2::null2_bias;
+use crate::provenance::Provenance;
+use crate::seeds_header::{fnv1a64, FNV_OFFSET_BASIS};
+use crate::trace_output::HitTrace;
+use anyhow::Result;
+use clap::ValueEnum;
+use mmoreseqs::seq_io::{self, write_fasta, Msa, MsaRow, SeqRecord, WriteOptions};
+
+use nale::structs::trace::constants::{TRACE_B, TRACE_D, TRACE_E, TRACE_I, TRACE_M};
+use nale::structs::{Alignment, Profile, Sequence, Trace};
+
+/// The format used to write per-hit alignments
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+pub enum AliFormat {
+    /// Human-readable pairwise alignments
+    #[default]
+    Pairwise,
+    /// One Stockholm block per hit, with RF and PP annotation
+    Stockholm,
+    /// Aligned FASTA (A2M), with inserts in lower case
+    A2m,
+}
+
+impl AliFormat {
+    /// The conventional file extension for the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AliFormat::Pairwise => "txt",
+            AliFormat::Stockholm => "sto",
+            AliFormat::A2m => "a2m",
+        }
+    }
+}
+
+/// The aligned rows of a single hit, built directly from its trace.
+///
+/// Each of the strings has one character per alignment column.
+pub struct AlignedHit {
+    /// The hit's identifier, which is the same in every output and across re-runs
+    pub id: String,
+    pub profile_name: String,
+    pub profile_accession: String,
+    pub target_name: String,
+    pub target_start: usize,
+    pub target_end: usize,
+    /// The target region with appreciable posterior mass, which contains the alignment
+    pub envelope: Envelope,
+    /// The profile consensus, with '.' in insert columns
+    pub reference: String,
+    /// The target residues: upper case for matches, lower case for inserts, and '-' for deletes
+    pub target: String,
+    /// The binned posterior probability of each residue, with '.' in delete columns
+    pub posterior: String,
+    /// The fraction of alignment columns where the target residue matches the consensus
+    pub identity: f32,
+    /// The fraction of alignment columns where the target residue has a positive match score
+    pub similarity: f32,
+    /// The fraction of alignment columns that are inserts or deletes
+    pub gap_fraction: f32,
+    /// The null2 bias correction, in bits like the hit's score
+    pub bias: f32,
+    /// The fraction of the profile's positions that the alignment spans
+    pub profile_coverage: f32,
+    /// The fraction of the target's residues that the alignment spans
+    pub target_coverage: f32,
+    /// The seed that the hit was aligned from
+    pub seed: Option<SeedProvenance>,
+    /// The number of seeds that converged on this hit
+    pub supporting_seeds: usize,
+    /// The full sequence and conditional statistics, once the reported hits are known
+    pub domain_stats: Option<DomainStats>,
+    /// Whether the hit meets its query's inclusion thresholds, with --incE or --incT
+    pub included: Option<bool>,
+    /// The suboptimal alignments of the hit's seed, with --alt-alignments
+    pub alternatives: Vec<AltAlignment>,
+    /// The geometry of the DP bounds that the hit was aligned in, with --bound-stats
+    pub bound_stats: Option<BoundStats>,
+    /// The state path of the hit's alignment, with --trace-output
+    pub trace: Option<HitTrace>,
+}
+
+/// The mmseqs scores and coordinates of the seed that a hit was aligned from
+#[derive(Debug, Clone)]
+pub struct SeedProvenance {
+    pub evalue: f32,
+    /// Not available in seed files written by older versions
+    pub bit_score: Option<f32>,
+    /// The seed's profile coordinates, mapped onto the query HMM
+    pub profile_start: usize,
+    pub profile_end: usize,
+    pub target_start: usize,
+    pub target_end: usize,
+}
+
+/// Map a posterior probability to the characters 0..9 or * (which represents 10)
+fn posterior_probability_char(probability: f32) -> char {
+    match (probability * 10.0).round() as u32 {
+        bin @ 0..=9 => char::from_digit(bin, 10).unwrap(),
+        _ => '*',
+    }
+}
+
+impl AlignedHit {
+    pub fn new(trace: &Trace, profile: &Profile, target: &Sequence) -> Self {
+        let mut reference = String::new();
+        let mut target_row = String::new();
+        let mut posterior = String::new();
+
+        let mut target_start: usize = 0;
+        let mut target_end: usize = 0;
+        let mut profile_start: usize = 0;
+        let mut profile_end: usize = 0;
+
+        let mut identical_count: usize = 0;
+        let mut similar_count: usize = 0;
+        let mut gap_count: usize = 0;
+
+        let mut in_alignment = false;
+        for trace_idx in 0..trace.length {
+            let state = trace.states[trace_idx];
+            let profile_idx = trace.profile_idx[trace_idx];
+            let target_idx = trace.target_idx[trace_idx];
+
+            match state {
+                TRACE_B => {
+                    in_alignment = true;
+                    continue;
+                }
+                TRACE_E => break,
+                _ if !in_alignment => continue,
+                _ => {}
+            }
+
+            let consensus_char = profile.consensus_sequence[profile_idx] as char;
+            let target_char = target.utf8_bytes[target_idx] as char;
+            let posterior_char =
+                posterior_probability_char(trace.posterior_probabilities[trace_idx]);
+
+            match state {
+                TRACE_M => {
+                    if consensus_char.eq_ignore_ascii_case(&target_char) {
+                        identical_count += 1;
+                    }
+                    if profile.match_score(target.digital_bytes[target_idx] as usize, profile_idx)
+                        > 0.0
+                    {
+                        similar_count += 1;
+                    }
+                    reference.push(consensus_char.to_ascii_uppercase());
+                    target_row.push(target_char.to_ascii_uppercase());
+                    posterior.push(posterior_char);
+                }
+                TRACE_I => {
+                    gap_count += 1;
+                    reference.push('.');
+                    target_row.push(target_char.to_ascii_lowercase());
+                    posterior.push(posterior_char);
+                }
+                TRACE_D => {
+                    gap_count += 1;
+                    reference.push(consensus_char.to_ascii_uppercase());
+                    target_row.push('-');
+                    posterior.push('.');
+                }
+                _ => continue,
+            }
+
+            if state != TRACE_I {
+                if profile_start == 0 {
+                    profile_start = profile_idx;
+                }
+                profile_end = profile_idx;
+            }
+
+            if state != TRACE_D {
+                if target_start == 0 {
+                    target_start = target_idx;
+                }
+                target_end = target_idx;
+            }
+        }
+
+        // guard against dividing by zero for an empty alignment
+        let column_count = reference.len().max(1) as f32;
+        let span = |start: usize, end: usize, length: usize| match start {
+            0 => 0.0,
+            _ => (end + 1 - start) as f32 / length.max(1) as f32,
+        };
+
+        AlignedHit {
+            id: String::new(),
+            profile_name: profile.name.clone(),
+            profile_accession: profile.accession.clone(),
+            target_name: target.name.clone(),
+            target_start,
+            target_end,
+            envelope: Envelope {
+                start: target_start,
+                end: target_end,
+            },
+            reference,
+            target: target_row,
+            posterior,
+            identity: identical_count as f32 / column_count,
+            similarity: similar_count as f32 / column_count,
+            gap_fraction: gap_count as f32 / column_count,
+            bias: null2_bias(trace, profile, target),
+            profile_coverage: span(profile_start, profile_end, profile.length),
+            target_coverage: span(target_start, target_end, target.length),
+            seed: None,
+            supporting_seeds: 1,
+            domain_stats: None,
+            included: None,
+            alternatives: vec![],
+            bound_stats: None,
+            trace: None,
+        }
+    }
+
+    /// Set the hit's identifier: a hash of its query accession, target, envelope,
+    /// and bit score, so that it doesn't depend on the run's order or options.
+    ///
+    /// The score is hashed to one decimal, as it is reported.
+    pub fn assign_id(&mut self, bit_score: f32) {
+        let key = format!(
+            "{}\t{}\t{}\t{}\t{bit_score:.1}",
+            self.profile_accession, self.target_name, self.envelope.start, self.envelope.end
+        );
+        self.id = format!("{:016x}", fnv1a64(FNV_OFFSET_BASIS, key.as_bytes()));
+    }
+
+    /// The Stockholm/A2M style name for the aligned target region, e.g. "seq1/10-90"
+    fn region_name(&self) -> String {
+        format!(
+            "{}/{}-{}",
+            self.target_name, self.target_start, self.target_end
+        )
+    }
+}
+
+pub fn write_pairwise(
+    alignments: &[Alignment],
+    hits: &[AlignedHit],
+    provenance: &Provenance,
+    out: &mut impl Write,
+) -> Result<()> {
+    for line in provenance.comment_lines() {
+        writeln!(out, "# {line}")?;
+    }
+    for (alignment, hit) in alignments.iter().zip(hits) {
+        writeln!(
+            out,
+            ">> {} vs {} [hit-id {}]",
+            alignment.profile_name, alignment.target_name, hit.id
+        )?;
+        alignment.dump(out)?;
+    }
+    Ok(())
+}
+
+/// Write each hit as a Stockholm alignment, with the provenance as comments on
+/// the first, and the hit's identifier as a comment on each.
+pub fn write_stockholm(
+    hits: &[AlignedHit],
+    provenance: &Provenance,
+    out: &mut impl Write,
+) -> Result<()> {
+    // each hit is a single block, however long
+    let options = WriteOptions {
+        wrap_width: None,
+        ..Default::default()
+    };
+
+    for (hit_idx, hit) in hits.iter().enumerate() {
+        let msa = Msa {
+            id: Some(hit.profile_name.clone()),
+            accession: None,
+            comments: match hit_idx {
+                0 => provenance.comment_lines(),
+                _ => vec![],
+            }
+            .into_iter()
+            .chain([format!("hit-id {}", hit.id)])
+            .collect(),
+            rows: vec![MsaRow {
+                name: hit.region_name(),
+                sequence: hit.target.clone(),
+                posterior: Some(hit.posterior.clone()),
+            }],
+            reference: Some(hit.reference.clone()),
+        };
+        seq_io::write_stockholm(&msa, &options, out)?;
+    }
+    Ok(())
+}
+
+pub fn write_a2m(hits: &[AlignedHit], out: &mut impl Write) -> Result<()> {
+    let records: Vec<SeqRecord> = hits
+        .iter()
+        .map(|hit| SeqRecord {
+            name: hit.region_name(),
+            description: Some(format!("{} hit-id={}", hit.profile_name, hit.id)),
+            sequence: hit.target.clone(),
+        })
+        .collect();
+
+    write_fasta(&records, &WriteOptions::default(), out)
+}
@@ -0,0 +1,273 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use nale::structs::Sequence;
+use thiserror::Error;
+
+/// The amino acid characters outside of the 20 standard residues that may appear in targets:
+/// the ambiguity codes B (D/N), Z (E/Q), J (I/L), and X (any), selenocysteine (U),
+/// pyrrolysine (O), and the translated stop codon (*)
+const AMBIGUOUS_RESIDUES: [u8; 7] = [b'B', b'Z', b'J', b'U', b'O', b'X', b'*'];
+
+const STANDARD_RESIDUES: &[u8; 20] = b"ACDEFGHIKLMNPQRSTVWY";
+
+#[derive(Error, Debug)]
+#[error("target {target} has the ambiguous residue '{residue}' at position {position}")]
+pub struct AmbiguousResidueError {
+    target: String,
+    residue: char,
+    position: usize,
+}
+
+#[derive(Error, Debug)]
+#[error("target {target} has the unknown character '{character}' at position {position}")]
+pub struct UnknownResidueError {
+    target: String,
+    character: char,
+    position: usize,
+}
+
+/// How to treat targets that contain ambiguous residues (B, Z, J, U, O, X, or *)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AmbiguityPolicy {
+    /// Score ambiguous residues as X (the background-weighted average of the match scores)
+    #[default]
+    ScoreAsX,
+    /// Drop any target that contains an ambiguous residue
+    SkipSequence,
+    /// Stop with an error at the first ambiguous residue
+    Error,
+}
+
+/// Build a sequence from its residues, applying the ambiguity policy.
+///
+/// A character that isn't an amino acid at all is treated as an ambiguous residue,
+/// and the first of them is added to the unknown residues, to be warned about.
+///
+/// Returns None if the policy is to skip the sequence.
+fn build_sequence(
+    name: String,
+    residues: &[u8],
+    policy: AmbiguityPolicy,
+    unknown_residues: &mut Vec<UnknownResidueError>,
+) -> Result<Option<Sequence>> {
+    let mut scored_residues: Vec<u8> = Vec::with_capacity(residues.len());
+    let mut first_unknown: Option<UnknownResidueError> = None;
+
+    for (residue_idx, &residue) in residues.iter().enumerate() {
+        let upper = residue.to_ascii_uppercase();
+
+        if STANDARD_RESIDUES.contains(&upper) {
+            scored_residues.push(residue);
+            continue;
+        }
+
+        if !AMBIGUOUS_RESIDUES.contains(&upper) {
+            let unknown = UnknownResidueError {
+                target: name.clone(),
+                character: residue as char,
+                position: residue_idx + 1,
+            };
+            if policy == AmbiguityPolicy::Error {
+                return Err(unknown.into());
+            }
+            first_unknown.get_or_insert(unknown);
+        }
+
+        match policy {
+            AmbiguityPolicy::ScoreAsX => scored_residues.push(b'X'),
+            AmbiguityPolicy::SkipSequence => break,
+            AmbiguityPolicy::Error => {
+                return Err(AmbiguousResidueError {
+                    target: name,
+                    residue: residue as char,
+                    position: residue_idx + 1,
+                }
+                .into())
+            }
+        }
+    }
+
+    unknown_residues.extend(first_unknown);
+    if scored_residues.len() < residues.len() {
+        return Ok(None);
+    }
+
+    let mut sequence = Sequence::from_utf8(&scored_residues)?;
+    // the residues are scored as X, but are still displayed as they were written
+    sequence.utf8_bytes[1..].copy_from_slice(residues);
+    sequence.name = name;
+
+    Ok(Some(sequence))
+}
+
+/// The targets read from a fasta file
+pub struct ParsedTargets {
+    pub sequences: Vec<Sequence>,
+    /// The names of the targets that were parsed, but left out of the sequences
+    /// because they weren't among the targets to keep
+    pub unloaded_names: Vec<String>,
+    /// The names of the targets that were dropped for containing ambiguous residues
+    pub skipped_names: Vec<String>,
+    /// The first character of each target that isn't an amino acid, for the targets
+    /// that have any, which were treated as ambiguous residues
+    pub unknown_residues: Vec<UnknownResidueError>,
+}
+
+/// Read an amino acid fasta file, applying the ambiguity policy to each sequence.
+///
+/// If the targets to keep are given, only their sequences are kept. The others are
+/// still parsed, so that they are skipped by the ambiguity policy as they would be
+/// if they were kept, but only their names are kept.
+pub fn read_amino_fasta(
+    path: &Path,
+    policy: AmbiguityPolicy,
+    keep: Option<&HashSet<String>>,
+) -> Result<ParsedTargets> {
+    let file = File::open(path).context(format!(
+        "failed to open target fasta: {}",
+        path.to_string_lossy()
+    ))?;
+
+    parse_amino_fasta(BufReader::new(file), policy, keep)
+}
+
+/// Parse amino acid fasta records, applying the ambiguity policy to each sequence,
+/// and keeping only the sequences of the targets to keep, if they are given.
+///
+/// Each target is named by the first word of its header, which is how mmseqs
+/// names it in the seeds, and keeps the names free of the spaces that the
+/// results table can't hold.
+pub fn parse_amino_fasta(
+    reader: impl BufRead,
+    policy: AmbiguityPolicy,
+    keep: Option<&HashSet<String>>,
+) -> Result<ParsedTargets> {
+    let mut parsed = ParsedTargets {
+        sequences: vec![],
+        unloaded_names: vec![],
+        skipped_names: vec![],
+        unknown_residues: vec![],
+    };
+
+    let mut current: Option<(String, Vec<u8>)> = None;
+
+    let mut finish = |record: Option<(String, Vec<u8>)>| -> Result<()> {
+        if let Some((name, residues)) = record {
+            match build_sequence(
+                name.clone(),
+                &residues,
+                policy,
+                &mut parsed.unknown_residues,
+            )? {
+                Some(sequence) if keep.is_none_or(|keep| keep.contains(&name)) => {
+                    parsed.sequences.push(sequence)
+                }
+                Some(_) => parsed.unloaded_names.push(name),
+                None => parsed.skipped_names.push(name),
+            }
+        }
+        Ok(())
+    };
+
+    for line in reader.lines() {
+        let line = line?;
+        match line.strip_prefix('>') {
+            Some(header) => {
+                finish(current.take())?;
+                let name = header.split_whitespace().next().unwrap_or_default();
+                current = Some((name.to_string(), vec![]));
+            }
+            None => {
+                if let Some((_, residues)) = current.as_mut() {
+                    residues.extend(line.bytes().filter(|b| !b.is_ascii_whitespace()));
+                }
+            }
+        }
+    }
+    finish(current.take())?;
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(fasta: &str, policy: AmbiguityPolicy) -> Result<ParsedTargets> {
+        parse_amino_fasta(fasta.as_bytes(), policy, None)
+    }
+
+    #[test]
+    fn targets_are_named_by_the_first_word_of_their_header() {
+        let parsed = parse(
+            ">tgt1 some description\nACDEF\nGHIK\n>tgt2\tother\nLMNPQ\n",
+            AmbiguityPolicy::ScoreAsX,
+        )
+        .unwrap();
+
+        let names: Vec<&str> = parsed.sequences.iter().map(|s| &s.name[..]).collect();
+        assert_eq!(names, ["tgt1", "tgt2"]);
+        assert_eq!(parsed.sequences[0].length, 9);
+    }
+
+    #[test]
+    fn only_the_targets_to_keep_are_loaded() {
+        let keep = HashSet::from(["tgt2".to_string()]);
+        let parsed = parse_amino_fasta(
+            ">tgt1\nACDEF\n>tgt2\nGHIK\n>tgt3\nAC1EF\n>tgt4\nLMNPQ\n".as_bytes(),
+            AmbiguityPolicy::SkipSequence,
+            Some(&keep),
+        )
+        .unwrap();
+
+        let names: Vec<&str> = parsed.sequences.iter().map(|s| &s.name[..]).collect();
+        assert_eq!(names, ["tgt2"]);
+        // the targets that aren't kept are still skipped by the ambiguity policy
+        assert_eq!(parsed.unloaded_names, ["tgt1", "tgt4"]);
+        assert_eq!(parsed.skipped_names, ["tgt3"]);
+    }
+
+    #[test]
+    fn unknown_characters_are_scored_as_x() {
+        let parsed = parse(">tgt1\nAC1EF\n>tgt2\nACDEF\n", AmbiguityPolicy::ScoreAsX).unwrap();
+
+        assert_eq!(parsed.sequences.len(), 2);
+        assert!(parsed.skipped_names.is_empty());
+        // the character is scored as X, and shown as it was written
+        assert_eq!(parsed.sequences[0].length, 5);
+        assert_eq!(&parsed.sequences[0].utf8_bytes[1..], b"AC1EF");
+
+        assert_eq!(parsed.unknown_residues.len(), 1);
+        assert_eq!(
+            parsed.unknown_residues[0].to_string(),
+            "target tgt1 has the unknown character '1' at position 3"
+        );
+    }
+
+    #[test]
+    fn targets_with_unknown_characters_can_be_skipped() {
+        let parsed = parse(
+            ">tgt1\nAC1EF\n>tgt2\nACDEF\n",
+            AmbiguityPolicy::SkipSequence,
+        )
+        .unwrap();
+
+        let names: Vec<&str> = parsed.sequences.iter().map(|s| &s.name[..]).collect();
+        assert_eq!(names, ["tgt2"]);
+        assert_eq!(parsed.skipped_names, ["tgt1"]);
+        assert_eq!(parsed.unknown_residues.len(), 1);
+    }
+
+    #[test]
+    fn unknown_characters_are_an_error_under_the_error_policy() {
+        let err = parse(">tgt1\nAC1EF\n", AmbiguityPolicy::Error)
+            .err()
+            .unwrap();
+        assert!(err.is::<UnknownResidueError>());
+    }
+}
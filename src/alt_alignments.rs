@@ -0,0 +1,224 @@
+use std::io::Write;
+use std::ops::Range;
+
+use anyhow::Result;
+use nale::align::bounded::structs::RowBounds;
+use nale::align::bounded::{optimal_accuracy_bounded, traceback_bounded};
+use nale::structs::dp_matrix::DpMatrix;
+use nale::structs::trace::constants::{
+    TRACE_B, TRACE_C, TRACE_E, TRACE_I, TRACE_M, TRACE_S, TRACE_T,
+};
+use nale::structs::{Alignment, Profile, Sequence, Trace};
+
+use crate::alignment_output::AlignedHit;
+use crate::dp_matrices::DpMatrices;
+use crate::number_format::NumberFormat;
+use crate::provenance::Provenance;
+use crate::tabular::{alignment_row, Table, DEFAULT_COLUMN_HEADERS};
+
+/// The fewest residues that a traceback is expected to align correctly for it to be
+/// reported; below this, it only crosses cells that earlier tracebacks have used up.
+const MIN_EXPECTED_RESIDUES: f32 = 1.0;
+
+/// A suboptimal alignment of a seed, from --alt-alignments
+pub struct AltAlignment {
+    /// 1 for the best alternative to the hit, 2 for the next, and so on
+    pub rank: usize,
+    pub alignment: Alignment,
+    /// The mean posterior probability of the aligned residues
+    pub accuracy: f32,
+}
+
+/// The sum of the posterior probabilities of the aligned residues in a range of
+/// trace positions, and their number
+fn aligned_posteriors(trace: &Trace, trace_range: Range<usize>) -> (f32, usize) {
+    trace_range
+        .filter(|&trace_idx| matches!(trace.states[trace_idx], TRACE_M | TRACE_I))
+        .fold((0.0, 0), |(sum, count), trace_idx| {
+            (sum + trace.posterior_probabilities[trace_idx], count + 1)
+        })
+}
+
+/// The trace positions of each domain of a trace, from its B state to its E state
+fn domain_ranges(trace: &Trace) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = vec![];
+    let mut domain_start: Option<usize> = None;
+    for trace_idx in 0..trace.length {
+        match trace.states[trace_idx] {
+            TRACE_B => domain_start = Some(trace_idx),
+            TRACE_E => {
+                if let Some(start) = domain_start.take() {
+                    ranges.push(start..trace_idx + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+/// A trace of just one domain of a trace, since Alignment::new() only reads the first domain
+fn domain_trace(trace: &Trace, domain_range: Range<usize>) -> Trace {
+    let mut domain = Trace::new(trace.target_length, trace.profile_length);
+    let mut push = |state: usize, trace_idx: Option<usize>| {
+        domain.states.push(state);
+        domain
+            .target_idx
+            .push(trace_idx.map_or(0, |idx| trace.target_idx[idx]));
+        domain
+            .profile_idx
+            .push(trace_idx.map_or(0, |idx| trace.profile_idx[idx]));
+        domain
+            .posterior_probabilities
+            .push(trace_idx.map_or(0.0, |idx| trace.posterior_probabilities[idx]));
+    };
+
+    push(TRACE_S, None);
+    for trace_idx in domain_range {
+        push(trace.states[trace_idx], Some(trace_idx));
+    }
+    push(TRACE_C, None);
+    push(TRACE_T, None);
+
+    domain.length = domain.states.len();
+    domain
+}
+
+/// Zero the match and insert posterior probabilities of the target residues
+/// that an alignment spans, so that no later traceback can align them.
+fn mask_target_range(
+    target_start: usize,
+    target_end: usize,
+    row_bounds: &RowBounds,
+    posterior: &mut impl DpMatrix,
+) {
+    let target_start = target_start.max(row_bounds.target_start);
+    let target_end = target_end.min(row_bounds.target_end);
+    for target_idx in target_start..=target_end {
+        let profile_start = row_bounds.left_row_bounds[target_idx];
+        let profile_end = row_bounds.right_row_bounds[target_idx];
+        for profile_idx in profile_start..=profile_end {
+            posterior.set_match(target_idx, profile_idx, 0.0);
+            posterior.set_insert(target_idx, profile_idx, 0.0);
+        }
+    }
+}
+
+/// Trace back up to `count` suboptimal alignments of a seed, after its hit.
+///
+/// Before each traceback, the target residues of the hit and of the alternatives
+/// so far are zeroed in the posterior matrix and the optimal accuracy matrix is
+/// refilled, so that each alternative places the domain on other residues, e.g.
+/// on a neighboring repeat. Tracebacks that are expected to align fewer than
+/// MIN_EXPECTED_RESIDUES correctly end the search. This overwrites the posterior matrix.
+pub fn trace_alternatives(
+    profile: &Profile,
+    target: &Sequence,
+    matrices: &mut DpMatrices,
+    row_bounds: &RowBounds,
+    hit: &Alignment,
+    count: usize,
+    target_count: usize,
+) -> Vec<AltAlignment> {
+    let mut alternatives: Vec<AltAlignment> = vec![];
+    mask_target_range(
+        hit.target_start,
+        hit.target_end,
+        row_bounds,
+        &mut matrices.posterior,
+    );
+
+    for rank in 1..=count {
+        optimal_accuracy_bounded(
+            profile,
+            &matrices.posterior,
+            &mut matrices.optimal,
+            row_bounds,
+        );
+
+        let mut trace = Trace::new(target.length, profile.length);
+        traceback_bounded(
+            profile,
+            &matrices.posterior,
+            &matrices.optimal,
+            &mut trace,
+            row_bounds.target_end,
+        );
+
+        // the traceback may pass through several domains, most of them empty
+        let best_domain = domain_ranges(&trace)
+            .into_iter()
+            .map(|range| (aligned_posteriors(&trace, range.clone()), range))
+            .max_by(|((a, _), _), ((b, _), _)| a.total_cmp(b));
+        let Some(((posterior_sum, residue_count), domain_range)) = best_domain else {
+            break;
+        };
+        if posterior_sum < MIN_EXPECTED_RESIDUES {
+            break;
+        }
+
+        let alignment = Alignment::new(
+            &domain_trace(&trace, domain_range),
+            profile,
+            target,
+            target_count,
+        );
+        mask_target_range(
+            alignment.target_start,
+            alignment.target_end,
+            row_bounds,
+            &mut matrices.posterior,
+        );
+        alternatives.push(AltAlignment {
+            rank,
+            alignment,
+            accuracy: posterior_sum / residue_count as f32,
+        });
+    }
+
+    alternatives
+}
+
+/// Write the suboptimal alignments of each hit's seed as a results table, with
+/// columns for their rank, their accuracy, and the target range and identifier of their hit.
+pub fn write_alt_alignments(
+    aligned_hits: &[AlignedHit],
+    provenance: &Provenance,
+    format: &NumberFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut headers: Vec<String> = DEFAULT_COLUMN_HEADERS.map(String::from).to_vec();
+    headers.extend(
+        [
+            "alt rank",
+            "accuracy",
+            "hit target start",
+            "hit target end",
+            "hit id",
+        ]
+        .map(String::from),
+    );
+
+    let mut rows: Vec<Vec<String>> = vec![];
+    for hit in aligned_hits {
+        for alternative in &hit.alternatives {
+            let mut row = alignment_row(&alternative.alignment, format);
+            row.extend([
+                alternative.rank.to_string(),
+                format!("{:.3}", alternative.accuracy),
+                hit.target_start.to_string(),
+                hit.target_end.to_string(),
+                hit.id.clone(),
+            ]);
+            rows.push(row);
+        }
+    }
+
+    Table {
+        comments: provenance.comment_lines(),
+        headers,
+        rows,
+        ..Table::default()
+    }
+    .write(out)
+}
@@ -0,0 +1,107 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use nale::align::bounded::structs::Seed;
+use nale::structs::{Profile, Sequence};
+use thiserror::Error;
+
+use crate::alignment_output::SeedProvenance;
+
+#[derive(Error, Debug)]
+#[error("invalid range (expected START-END): {0}")]
+pub struct InvalidRangeError(String);
+
+#[derive(Error, Debug)]
+#[error("{kind} range {start}-{end} is outside of {name}, which has length {length}")]
+pub struct RegionOutOfRangeError {
+    kind: &'static str,
+    name: String,
+    start: usize,
+    end: usize,
+    length: usize,
+}
+
+/// An inclusive, 1-based range of positions, written as START-END
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CoordRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl FromStr for CoordRange {
+    type Err = InvalidRangeError;
+
+    fn from_str(range: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidRangeError(range.to_string());
+
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+        let start: usize = start.trim().parse().map_err(|_| invalid())?;
+        let end: usize = end.trim().parse().map_err(|_| invalid())?;
+
+        match start >= 1 && start <= end {
+            true => Ok(CoordRange { start, end }),
+            false => Err(invalid()),
+        }
+    }
+}
+
+impl CoordRange {
+    fn check(&self, kind: &'static str, name: &str, length: usize) -> Result<()> {
+        match self.end <= length {
+            true => Ok(()),
+            false => Err(RegionOutOfRangeError {
+                kind,
+                name: name.to_string(),
+                start: self.start,
+                end: self.end,
+                length,
+            }
+            .into()),
+        }
+    }
+}
+
+/// A profile and target region given by hand, to be aligned without seeds
+#[derive(Debug, Default, Clone)]
+pub struct AnchorRegion {
+    /// The name or accession of the profile
+    pub profile_name: String,
+    pub target_name: String,
+    pub target_range: CoordRange,
+    /// The whole profile, if not set
+    pub profile_range: Option<CoordRange>,
+}
+
+impl AnchorRegion {
+    /// Build a synthetic seed that covers the region.
+    pub fn seed(&self, profile: &Profile, target: &Sequence) -> Result<(Seed, SeedProvenance)> {
+        self.target_range
+            .check("target", &target.name, target.length)?;
+
+        let profile_range = self.profile_range.unwrap_or(CoordRange {
+            start: 1,
+            end: profile.length,
+        });
+        profile_range.check("profile", &profile.name, profile.length)?;
+
+        let seed = Seed {
+            target_name: target.name.clone(),
+            target_start: self.target_range.start,
+            target_end: self.target_range.end,
+            profile_start: profile_range.start,
+            profile_end: profile_range.end,
+        };
+
+        // there was no mmseqs alignment, so the seed has no scores
+        let provenance = SeedProvenance {
+            evalue: f32::NAN,
+            bit_score: None,
+            profile_start: seed.profile_start,
+            profile_end: seed.profile_end,
+            target_start: seed.target_start,
+            target_end: seed.target_end,
+        };
+
+        Ok((seed, provenance))
+    }
+}
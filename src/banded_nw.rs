@@ -0,0 +1,248 @@
+use nale::align::needleman_wunsch::{SimpleTrace, SimpleTraceStep};
+use nale::structs::Sequence;
+
+// the scores match nale's needleman_wunsch, so that the two give the same mappings
+const MATCH_SCORE: isize = 1;
+const MISMATCH_SCORE: isize = -1;
+const GAP_SCORE: isize = -2;
+
+/// The score of the cells outside of the band, low enough that no path goes through them
+const OUT_OF_BAND: isize = isize::MIN / 2;
+
+/// The number of diagonals on either side of the main diagonals that are computed at first
+const INITIAL_BAND_WIDTH: usize = 16;
+
+/// A Needleman-Wunsch global alignment restricted to a band around the diagonal.
+///
+/// The p7 and mmseqs consensus sequences of a profile are nearly identical, so the
+/// alignment stays close to the diagonal, and the band saves computing most of the
+/// matrix. A better alignment may still leave the band, so the band is doubled
+/// until the alignment in it scores more than any alignment that leaves it could.
+pub fn banded_needleman_wunsch(seq_1: &Sequence, seq_2: &Sequence) -> SimpleTrace {
+    let mut width = INITIAL_BAND_WIDTH;
+    loop {
+        let (trace, score) = align_in_band(seq_1, seq_2, width);
+        // once the band is as wide as the longer sequence, it covers the whole matrix,
+        // so nothing can leave it
+        if score > best_score_outside_band(seq_1.length, seq_2.length, width)
+            || width >= seq_1.length.max(seq_2.length)
+        {
+            return trace;
+        }
+        width *= 2;
+    }
+}
+
+/// The highest score that an alignment which leaves the band could have.
+///
+/// To reach a diagonal outside of the band and still end on the last cell, an
+/// alignment needs at least the length difference plus twice the band's width
+/// in gaps, and the rest of the residues can at best all be matches.
+fn best_score_outside_band(seq_1_length: usize, seq_2_length: usize, width: usize) -> isize {
+    let gap_count = seq_1_length.abs_diff(seq_2_length) + 2 * (width + 1);
+    match (seq_1_length + seq_2_length).checked_sub(gap_count) {
+        Some(residue_count) => {
+            (residue_count / 2) as isize * MATCH_SCORE + gap_count as isize * GAP_SCORE
+        }
+        None => OUT_OF_BAND,
+    }
+}
+
+/// The matrix cells within a band of diagonals, where the diagonal of
+/// cell (i, j) is j - i, stored row by row.
+struct BandedMatrix {
+    /// The lowest and highest diagonals in the band
+    low: isize,
+    high: isize,
+    seq_2_length: isize,
+    cells: Vec<isize>,
+}
+
+impl BandedMatrix {
+    fn new(seq_1_length: usize, seq_2_length: usize, width: usize) -> Self {
+        let length_diff = seq_2_length as isize - seq_1_length as isize;
+        let low = length_diff.min(0) - width as isize;
+        let high = length_diff.max(0) + width as isize;
+        BandedMatrix {
+            low,
+            high,
+            seq_2_length: seq_2_length as isize,
+            cells: vec![OUT_OF_BAND; (seq_1_length + 1) * (high - low + 1) as usize],
+        }
+    }
+
+    fn cell_idx(&self, row: usize, col: usize) -> Option<usize> {
+        let diagonal = col as isize - row as isize;
+        match diagonal >= self.low && diagonal <= self.high && col as isize <= self.seq_2_length {
+            true => {
+                Some(row * (self.high - self.low + 1) as usize + (diagonal - self.low) as usize)
+            }
+            false => None,
+        }
+    }
+
+    fn get(&self, row: usize, col: usize) -> isize {
+        self.cell_idx(row, col)
+            .map_or(OUT_OF_BAND, |cell_idx| self.cells[cell_idx])
+    }
+
+    fn set(&mut self, row: usize, col: usize, score: isize) {
+        if let Some(cell_idx) = self.cell_idx(row, col) {
+            self.cells[cell_idx] = score;
+        }
+    }
+
+    /// The columns of a row that are inside the band
+    fn cols(&self, row: usize) -> std::ops::RangeInclusive<usize> {
+        let first = (row as isize + self.low).max(0) as usize;
+        let last = (row as isize + self.high).min(self.seq_2_length).max(0) as usize;
+        first..=last
+    }
+}
+
+fn match_score(seq_1: &Sequence, seq_2: &Sequence, seq_1_idx: usize, seq_2_idx: usize) -> isize {
+    match seq_1.digital_bytes[seq_1_idx] == seq_2.digital_bytes[seq_2_idx] {
+        true => MATCH_SCORE,
+        false => MISMATCH_SCORE,
+    }
+}
+
+/// Align within a band, and return the alignment and its score.
+fn align_in_band(seq_1: &Sequence, seq_2: &Sequence, width: usize) -> (SimpleTrace, isize) {
+    let mut matrix = BandedMatrix::new(seq_1.length, seq_2.length, width);
+
+    for seq_1_idx in 0..=seq_1.length {
+        for seq_2_idx in matrix.cols(seq_1_idx) {
+            let score = match (seq_1_idx, seq_2_idx) {
+                (0, _) => seq_2_idx as isize * GAP_SCORE,
+                (_, 0) => seq_1_idx as isize * GAP_SCORE,
+                _ => {
+                    let diag_score = matrix.get(seq_1_idx - 1, seq_2_idx - 1)
+                        + match_score(seq_1, seq_2, seq_1_idx, seq_2_idx);
+                    let up_score = matrix.get(seq_1_idx - 1, seq_2_idx) + GAP_SCORE;
+                    let left_score = matrix.get(seq_1_idx, seq_2_idx - 1) + GAP_SCORE;
+                    diag_score.max(up_score.max(left_score))
+                }
+            };
+            matrix.set(seq_1_idx, seq_2_idx, score);
+        }
+    }
+
+    // the traceback breaks ties in the same order as nale's needleman_wunsch
+    let score = matrix.get(seq_1.length, seq_2.length);
+    let mut trace: SimpleTrace = vec![];
+    let mut seq_1_idx = seq_1.length;
+    let mut seq_2_idx = seq_2.length;
+
+    while seq_1_idx > 0 && seq_2_idx > 0 {
+        let current_score = matrix.get(seq_1_idx, seq_2_idx);
+        let diag_target = current_score - match_score(seq_1, seq_2, seq_1_idx, seq_2_idx);
+        let gap_target = current_score - GAP_SCORE;
+
+        if matrix.get(seq_1_idx - 1, seq_2_idx - 1) == diag_target {
+            seq_1_idx -= 1;
+            seq_2_idx -= 1;
+            trace.push(SimpleTraceStep::Diagonal);
+        } else if matrix.get(seq_1_idx - 1, seq_2_idx) == gap_target {
+            seq_1_idx -= 1;
+            trace.push(SimpleTraceStep::Up);
+        } else if matrix.get(seq_1_idx, seq_2_idx - 1) == gap_target {
+            seq_2_idx -= 1;
+            trace.push(SimpleTraceStep::Left);
+        } else {
+            panic!("banded traceback failed")
+        }
+    }
+
+    trace.extend((0..seq_1_idx).map(|_| SimpleTraceStep::Up));
+    trace.extend((0..seq_2_idx).map(|_| SimpleTraceStep::Left));
+    trace.reverse();
+
+    (trace, score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nale::align::needleman_wunsch::needleman_wunsch;
+
+    const AMINO_ACIDS: &[u8] = b"ACDEFGHIKLMNPQRSTVWY";
+
+    /// A repeatable pseudo-random amino acid sequence
+    fn random_residues(length: usize, mut state: u64) -> Vec<u8> {
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                AMINO_ACIDS[(state % AMINO_ACIDS.len() as u64) as usize]
+            })
+            .collect()
+    }
+
+    fn trace_string(trace: &SimpleTrace) -> String {
+        trace
+            .iter()
+            .map(|step| match step {
+                SimpleTraceStep::Diagonal => 'D',
+                SimpleTraceStep::Up => 'U',
+                SimpleTraceStep::Left => 'L',
+            })
+            .collect()
+    }
+
+    /// Check that the banded alignment is the same as the full one.
+    fn assert_matches_full_alignment(residues_1: &[u8], residues_2: &[u8]) {
+        let seq_1 = Sequence::from_utf8(residues_1).unwrap();
+        let seq_2 = Sequence::from_utf8(residues_2).unwrap();
+        assert_eq!(
+            trace_string(&banded_needleman_wunsch(&seq_1, &seq_2)),
+            trace_string(&needleman_wunsch(&seq_1, &seq_2))
+        );
+    }
+
+    #[test]
+    fn identical_sequences_align_on_the_diagonal() {
+        let residues = random_residues(200, 1);
+        let seq = Sequence::from_utf8(&residues).unwrap();
+        assert_eq!(
+            trace_string(&banded_needleman_wunsch(&seq, &seq)),
+            "D".repeat(200)
+        );
+    }
+
+    #[test]
+    fn near_identical_sequences_align_as_in_full() {
+        let residues_1 = random_residues(300, 2);
+        let mut residues_2 = residues_1.clone();
+        residues_2[10] = b'W';
+        residues_2[150] = b'C';
+        residues_2.remove(80);
+        residues_2.insert(200, b'K');
+        residues_2.insert(200, b'K');
+        assert_matches_full_alignment(&residues_1, &residues_2);
+    }
+
+    #[test]
+    fn the_band_widens_for_long_gaps() {
+        let residues_1 = random_residues(300, 3);
+        // the same length, but with a deletion and an insertion that take
+        // the alignment 40 diagonals away from the main diagonal
+        let mut residues_2 = residues_1[..100].to_vec();
+        residues_2.extend_from_slice(&residues_1[140..]);
+        residues_2.extend_from_slice(&random_residues(40, 4));
+        assert_eq!(residues_1.len(), residues_2.len());
+
+        let seq_1 = Sequence::from_utf8(&residues_1).unwrap();
+        let seq_2 = Sequence::from_utf8(&residues_2).unwrap();
+        let (_, score) = align_in_band(&seq_1, &seq_2, INITIAL_BAND_WIDTH);
+        assert!(score <= best_score_outside_band(300, 300, INITIAL_BAND_WIDTH));
+        assert_matches_full_alignment(&residues_1, &residues_2);
+    }
+
+    #[test]
+    fn unrelated_sequences_of_different_lengths_align_as_in_full() {
+        assert_matches_full_alignment(&random_residues(40, 5), &random_residues(120, 6));
+        assert_matches_full_alignment(&random_residues(1, 7), &random_residues(30, 8));
+    }
+}
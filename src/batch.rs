@@ -0,0 +1,348 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::manifest::RunManifest;
+use crate::pipeline::{
+    align_targets, discard_if_cancelled, prep_query, prep_target, run_seed_steps, QuerySet,
+    TargetStore,
+};
+use crate::run_summary::RunSummary;
+use crate::shutdown::{self, CancelledError};
+use crate::workspace::{safe_file_name, Stage, Workspace};
+use crate::{Args, OnBoundFail};
+
+#[derive(Error, Debug)]
+#[error("batch file line {line}: {reason}")]
+pub struct MalformedBatchError {
+    line: usize,
+    reason: String,
+}
+
+#[derive(Error, Debug)]
+#[error("batch entry name is used more than once: {0}")]
+pub struct DuplicateBatchEntryError(String);
+
+#[derive(Error, Debug)]
+#[error(
+    "a batch writes a results file per entry, so -o can't be an object store URI; \
+     give the URI as --output-dir instead, which uploads every entry's files"
+)]
+pub struct BatchObjectOutputError;
+
+/// A single query in a batch file, along with its parameter overrides.
+#[derive(Debug, Deserialize)]
+pub struct BatchEntry {
+    /// The query MSA (stockholm) or HMM file, relative to the batch file
+    pub query: PathBuf,
+    /// The name used for the entry's outputs; defaults to the query file stem, and is
+    /// made safe to use in a file name once the batch is read
+    pub name: Option<String>,
+    pub evalue_cutoff: Option<f32>,
+    pub on_bound_fail: Option<OnBoundFail>,
+    pub fast_pass: Option<bool>,
+}
+
+impl BatchEntry {
+    fn name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self
+                .query
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Parse a TSV batch file.
+///
+/// The first line is a header that names the columns: the query column is
+/// required, and the name, evalue_cutoff, on_bound_fail, and fast_pass columns
+/// are optional. An empty or "-" field leaves the parameter at its default.
+fn parse_tsv(path: &Path) -> Result<Vec<BatchEntry>> {
+    let file = File::open(path).context("failed to open batch file")?;
+    let mut lines = BufReader::new(file).lines();
+
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let columns: Vec<String> = header.split('\t').map(|c| c.trim().to_string()).collect();
+
+    if !columns.iter().any(|c| c == "query") {
+        return Err(MalformedBatchError {
+            line: 1,
+            reason: "the header has no query column".to_string(),
+        }
+        .into());
+    }
+
+    let mut entries = vec![];
+    for (line_idx, line) in lines.enumerate() {
+        let line = line?;
+        let line_number = line_idx + 2;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let malformed = |reason: String| MalformedBatchError {
+            line: line_number,
+            reason,
+        };
+
+        let mut entry = BatchEntry {
+            query: PathBuf::new(),
+            name: None,
+            evalue_cutoff: None,
+            on_bound_fail: None,
+            fast_pass: None,
+        };
+
+        for (column, field) in columns.iter().zip(line.split('\t')) {
+            let field = field.trim();
+            if field.is_empty() || field == "-" {
+                continue;
+            }
+
+            match &column[..] {
+                "query" => entry.query = PathBuf::from(field),
+                "name" => entry.name = Some(field.to_string()),
+                "evalue_cutoff" => {
+                    entry.evalue_cutoff = Some(
+                        field
+                            .parse()
+                            .map_err(|_| malformed(format!("invalid E-value: {field}")))?,
+                    )
+                }
+                "on_bound_fail" => {
+                    entry.on_bound_fail =
+                        Some(OnBoundFail::from_str(field, true).map_err(malformed)?)
+                }
+                "fast_pass" => {
+                    entry.fast_pass = Some(
+                        field
+                            .parse()
+                            .map_err(|_| malformed(format!("invalid boolean: {field}")))?,
+                    )
+                }
+                other => return Err(malformed(format!("unknown column: {other}")).into()),
+            }
+        }
+
+        if entry.query.as_os_str().is_empty() {
+            return Err(malformed("missing query".to_string()).into());
+        }
+
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Read a batch file, which is either a TSV file or (with a .json extension) a JSON list of entries.
+pub fn read_batch(path: &Path) -> Result<Vec<BatchEntry>> {
+    let mut entries = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_reader(BufReader::new(
+            File::open(path).context("failed to open batch file")?,
+        ))
+        .context("failed to parse batch file")?,
+        _ => parse_tsv(path)?,
+    };
+
+    // query paths are relative to the batch file
+    let batch_dir = path.parent().unwrap_or(Path::new(""));
+    for entry in entries.iter_mut() {
+        entry.query = batch_dir.join(&entry.query);
+    }
+
+    // the names are used in the paths of the entries' workspaces and outputs
+    let mut names = HashSet::new();
+    for entry in entries.iter_mut() {
+        let name = safe_file_name(&entry.name())?;
+        entry.name = Some(name.clone());
+        if !names.insert(name.clone()) {
+            return Err(DuplicateBatchEntryError(name).into());
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Insert the entry name into an output path, e.g. results.tsv -> results.NAME.tsv
+fn entry_path(path: &Path, name: &str) -> PathBuf {
+    let file_name = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => format!(
+            "{}.{name}.{}",
+            stem.to_string_lossy(),
+            extension.to_string_lossy()
+        ),
+        (Some(stem), None) => format!("{}.{name}", stem.to_string_lossy()),
+        _ => name.to_string(),
+    };
+    path.with_file_name(file_name)
+}
+
+/// Configure the arguments for a single batch entry.
+///
+/// Each entry gets its own query workspace under the shared
+/// workspace, but shares the target database that lives there.
+fn entry_args(args: &Args, entry: &BatchEntry) -> Args {
+    let name = entry.name();
+    let workspace = Workspace::new(args.workspace.dir.join(&name));
+
+    let mut entry_args = args.clone();
+    workspace.fill_query_paths(&mut entry_args.paths);
+    entry_args.workspace = workspace;
+    entry_args.paths.query = entry.query.clone();
+    entry_args.paths.results = entry_path(&args.paths.results, &name);
+    entry_args.paths.skipped = entry_path(&args.paths.skipped, &name);
+    entry_args.paths.alignments = args
+        .paths
+        .alignments
+        .as_ref()
+        .map(|path| entry_path(path, &name));
+    entry_args.paths.hitmap = args
+        .paths
+        .hitmap
+        .as_ref()
+        .map(|path| entry_path(path, &name));
+    entry_args.paths.hits_fasta = args
+        .paths
+        .hits_fasta
+        .as_ref()
+        .map(|path| entry_path(path, &name));
+    entry_args.paths.alt_alignments = args
+        .paths
+        .alt_alignments
+        .as_ref()
+        .map(|path| entry_path(path, &name));
+    entry_args.paths.abc = args.paths.abc.as_ref().map(|path| entry_path(path, &name));
+    entry_args.paths.edge_list = args
+        .paths
+        .edge_list
+        .as_ref()
+        .map(|path| entry_path(path, &name));
+    entry_args.paths.bound_stats = args
+        .paths
+        .bound_stats
+        .as_ref()
+        .map(|path| entry_path(path, &name));
+    entry_args.paths.trace_output = args
+        .paths
+        .trace_output
+        .as_ref()
+        .map(|path| entry_path(path, &name));
+
+    if let Some(evalue_cutoff) = entry.evalue_cutoff {
+        entry_args.evalue_cutoff = evalue_cutoff;
+    }
+    if let Some(on_bound_fail) = entry.on_bound_fail {
+        entry_args.on_bound_fail = on_bound_fail;
+    }
+    if let Some(fast_pass) = entry.fast_pass {
+        entry_args.fast_pass = fast_pass;
+    }
+
+    entry_args
+}
+
+/// Run the selected pipeline stages for each entry in the batch file.
+///
+/// The target is prepared once, and the targets are loaded once for all of the entries.
+pub fn run_batch(args: &Args) -> Result<()> {
+    let batch = args
+        .paths
+        .batch
+        .as_ref()
+        .expect("run_batch requires a batch file");
+    if args.uploads.is_staged(&args.paths.results) {
+        return Err(BatchObjectOutputError.into());
+    }
+    let entries = read_batch(batch)?;
+
+    let mut stages = args.stages.clone();
+    stages.sort();
+    stages.dedup();
+
+    let mut summary = RunSummary::new();
+
+    if stages.contains(&Stage::Prep) {
+        let start = Instant::now();
+        args.progress.start_stage(Stage::Prep);
+        discard_if_cancelled(args, Stage::Prep, prep_target(args))?;
+        args.progress.end_stage();
+        summary.add_stage_time(Stage::Prep, start.elapsed());
+    }
+
+    if stages.contains(&Stage::Seed) {
+        args.workspace.validate_target_index(&args.paths)?;
+    }
+
+    let targets = match stages.contains(&Stage::Align) {
+        true => Some(TargetStore::load(args)?),
+        false => None,
+    };
+
+    'entries: for entry in &entries {
+        // once cancelled or at the time limit, the entries that are left aren't
+        // worth preparing and seeding
+        let stop_cause = match () {
+            _ if shutdown::requested() => Some("cancelled"),
+            _ if args.time_limits.expired() => Some("time limit reached"),
+            _ => None,
+        };
+        if let Some(cause) = stop_cause {
+            eprintln!("{cause}; not running batch entry: {}", entry.name());
+            summary.incomplete_queries.push(entry.name().to_string());
+            continue;
+        }
+
+        let entry_args = entry_args(args, entry);
+        eprintln!("running batch entry: {}", entry.name());
+
+        for stage in &stages {
+            // a cancelled entry stops between stages, and is left to be run again
+            if shutdown::requested() {
+                summary.incomplete_queries.push(entry.name().to_string());
+                continue 'entries;
+            }
+            let start = Instant::now();
+            args.progress.start_stage(*stage);
+            let result = match stage {
+                Stage::Prep => discard_if_cancelled(&entry_args, *stage, prep_query(&entry_args)),
+                Stage::Seed => {
+                    discard_if_cancelled(&entry_args, *stage, run_seed_steps(&entry_args))
+                }
+                Stage::Align => {
+                    entry_args
+                        .workspace
+                        .validate(Stage::Align, &entry_args.paths)?;
+                    let queries = QuerySet::load(&entry_args)?;
+                    align_targets(&entry_args, &queries, targets.as_ref().unwrap())
+                        .map(|stats| summary.add_align(&stats))
+                }
+            };
+            match result {
+                Err(err) if err.is::<CancelledError>() => {
+                    summary.incomplete_queries.push(entry.name().to_string());
+                    continue 'entries;
+                }
+                result => result?,
+            }
+            args.progress.end_stage();
+            summary.add_stage_time(*stage, start.elapsed());
+        }
+    }
+
+    if let Some(manifest) = &args.paths.manifest {
+        RunManifest::new(args, summary.status()).write(manifest)?;
+    }
+
+    summary.finish(args)
+}
@@ -0,0 +1,505 @@
+use std::collections::{HashMap, HashSet};
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use nale::align::bounded::structs::{CloudBoundGroup, CloudMatrixLinear, RowBounds, Seed};
+use nale::align::bounded::{
+    backward_bounded, forward_bounded, optimal_accuracy_bounded, posterior_bounded,
+    traceback_bounded,
+};
+use nale::alphabet::AMINO_ALPHABET;
+use nale::structs::hmm::parse_hmms_from_p7hmm_file;
+use nale::structs::{Profile, Sequence, Trace};
+use thiserror::Error;
+
+use crate::alignment_output::SeedProvenance;
+use crate::dp_matrices::DpMatrices;
+use crate::msv_filter::MsvStats;
+use crate::pipeline::{
+    cloud_search_row_bounds, full_row_bounds, run_align_workers, TargetStore, WorkItem,
+    DEFAULT_DENSE_CELLS,
+};
+use crate::provenance::value_name;
+use crate::schedule::Schedule;
+use crate::Args;
+
+/// The seed of the synthetic workload generator, so that a benchmark can be run again
+const WORKLOAD_SEED: u64 = 0x2545f4914f6cdd1d;
+
+/// The shortest synthetic target, however short the skew makes it
+const MIN_TARGET_LENGTH: usize = 10;
+
+/// The shortest synthetic target of the dense cutoff benchmark; the lengths double from here
+const MIN_DENSE_TARGET_LENGTH: usize = 8;
+
+/// How much slower than the best a configuration may be, and still be recommended
+/// for needing fewer threads
+const RECOMMENDATION_TOLERANCE: f64 = 0.05;
+
+#[derive(Error, Debug)]
+#[error("no models to draw a workload for in {0}")]
+pub struct NoBenchModelsError(PathBuf);
+
+/// A xorshift generator, which is plenty for drawing synthetic workloads
+struct Rng(u64);
+
+impl Rng {
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// The size and shape of the synthetic workload, and the configurations to time on it
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ParallelBenchParams {
+    pub seeds: usize,
+    pub skew: f64,
+    pub max_threads: usize,
+    pub repeats: usize,
+}
+
+/// The sizes to measure the dense cutoff at, and how many seeds to time at each
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DenseBenchParams {
+    pub max_target_length: usize,
+    pub seeds: usize,
+    pub repeats: usize,
+}
+
+/// A synthetic target of a length, with as much of the profile's consensus as fits
+/// planted in its middle, and the seed along the planted consensus.
+fn planted_target(
+    profile: &Profile,
+    name: String,
+    length: usize,
+    rng: &mut Rng,
+) -> Result<(Sequence, Seed)> {
+    let planted_length = profile.length.min(length);
+    let profile_start = (profile.length - planted_length) / 2 + 1;
+    let target_start = (length - planted_length) / 2 + 1;
+
+    let mut residues: Vec<u8> = (0..length)
+        .map(|_| AMINO_ALPHABET[(rng.next_f64() * 20.0) as usize].as_bytes()[0])
+        .collect();
+    // the consensus is 1-based, and lowercase where the model is weakly conserved
+    residues[target_start - 1..target_start - 1 + planted_length].copy_from_slice(
+        &profile.consensus_sequence[profile_start..profile_start + planted_length]
+            .to_ascii_uppercase(),
+    );
+
+    let mut target = Sequence::from_utf8(&residues)?;
+    target.name = name;
+    let seed = Seed {
+        target_name: target.name.clone(),
+        target_start,
+        target_end: target_start + planted_length - 1,
+        profile_start,
+        profile_end: profile_start + planted_length - 1,
+    };
+    Ok((target, seed))
+}
+
+/// The profiles, targets, and seeds of a synthetic align step
+struct Workload {
+    profile_map: HashMap<String, Arc<Profile>>,
+    msv_stats: HashMap<String, MsvStats>,
+    targets: TargetStore,
+    /// The profile of each seed, by its key in the profile map
+    seed_profiles: Vec<String>,
+    seeds: Vec<Seed>,
+    provenances: Vec<SeedProvenance>,
+}
+
+impl Workload {
+    /// Draw the seeds of a workload over the query's models, each on a target of
+    /// its own.
+    ///
+    /// With a skew of 0, every model has about as many seeds, on targets twice the
+    /// model's length. The skew gives the first models of the file more of the
+    /// seeds, in proportion to 1 / rank^skew, and spreads the target lengths over
+    /// 4^skew times shorter to 4^skew times longer.
+    fn generate(args: &Args, params: &ParallelBenchParams) -> Result<Self> {
+        let hmms = parse_hmms_from_p7hmm_file(args.paths.query_hmm.to_str().unwrap())?;
+        if hmms.is_empty() {
+            return Err(NoBenchModelsError(args.paths.query_hmm.clone()).into());
+        }
+        let mut rng = Rng(WORKLOAD_SEED);
+
+        let mut profile_map: HashMap<String, Arc<Profile>> = HashMap::new();
+        let mut msv_stats: HashMap<String, MsvStats> = HashMap::new();
+        let mut keys: Vec<String> = vec![];
+        for hmm in &hmms {
+            let profile = Profile::new(hmm);
+            // the models are keyed by their position, as a file may lack accessions
+            let key = format!("model-{}", keys.len() + 1);
+            msv_stats.insert(key.clone(), MsvStats::new(hmm));
+            profile_map.insert(key.clone(), Arc::new(profile));
+            keys.push(key);
+        }
+
+        let weights: Vec<f64> = (1..=keys.len())
+            .map(|rank| (rank as f64).powf(-params.skew))
+            .collect();
+        let total_weight: f64 = weights.iter().sum();
+
+        let mut sequences: HashMap<String, Sequence> = HashMap::new();
+        let mut seed_profiles: Vec<String> = vec![];
+        let mut seeds: Vec<Seed> = vec![];
+        let mut provenances: Vec<SeedProvenance> = vec![];
+        for seed_idx in 0..params.seeds {
+            let mut draw = rng.next_f64() * total_weight;
+            let profile_idx = weights
+                .iter()
+                .position(|weight| {
+                    draw -= weight;
+                    draw < 0.0
+                })
+                .unwrap_or(keys.len() - 1);
+            let profile = &profile_map[&keys[profile_idx]];
+
+            let scale = 4f64.powf(params.skew * (2.0 * rng.next_f64() - 1.0));
+            let length = ((2 * profile.length) as f64 * scale) as usize;
+            let (target, seed) = planted_target(
+                profile,
+                format!("synthetic-{}", seed_idx + 1),
+                length.max(MIN_TARGET_LENGTH),
+                &mut rng,
+            )?;
+
+            provenances.push(SeedProvenance {
+                evalue: 0.0,
+                bit_score: None,
+                profile_start: seed.profile_start,
+                profile_end: seed.profile_end,
+                target_start: seed.target_start,
+                target_end: seed.target_end,
+            });
+            seed_profiles.push(keys[profile_idx].clone());
+            seeds.push(seed);
+            sequences.insert(target.name.clone(), target);
+        }
+
+        let targets = TargetStore {
+            count: sequences.len(),
+            sequences,
+            taxonomy: None,
+            ambiguous_names: HashSet::new(),
+            sources: None,
+            orfs: None,
+        };
+        Ok(Workload {
+            profile_map,
+            msv_stats,
+            targets,
+            seed_profiles,
+            seeds,
+            provenances,
+        })
+    }
+
+    /// The seeds as align work, in the order they were drawn
+    fn work(&self) -> Vec<WorkItem<'_>> {
+        self.seeds
+            .iter()
+            .zip(&self.provenances)
+            .zip(&self.seed_profiles)
+            .enumerate()
+            .map(|(work_idx, ((seed, provenance), key))| (work_idx, key, seed, provenance))
+            .collect()
+    }
+}
+
+/// The time a configuration took to align the workload, the fastest of the repeats
+struct ParallelMeasurement {
+    schedule: Schedule,
+    threads: usize,
+    elapsed: Duration,
+    hits: usize,
+}
+
+/// The thread counts to measure: the powers of two below the most, and the most
+fn thread_counts(max_threads: usize) -> Vec<usize> {
+    let max_threads = max_threads.max(1);
+    let mut counts: Vec<usize> = std::iter::successors(Some(1usize), |count| Some(count * 2))
+        .take_while(|&count| count < max_threads)
+        .collect();
+    counts.push(max_threads);
+    counts
+}
+
+/// Align a synthetic workload drawn from the query's models with each --schedule
+/// and a range of --threads, and print the throughput of each, and the fastest
+/// configuration within 5% of the best with the fewest threads.
+pub fn bench_parallel(args: &Args) -> Result<()> {
+    let params = &args.parallel_bench;
+    let workload = Workload::generate(args, params)?;
+    // every seed's hit is kept, so that each configuration does the same work
+    let evalue_cutoffs_by_accession: HashMap<String, f32> = workload
+        .profile_map
+        .keys()
+        .map(|accession| (accession.clone(), f32::INFINITY))
+        .collect();
+
+    let mut measurements: Vec<ParallelMeasurement> = vec![];
+    for schedule in Schedule::value_variants() {
+        for threads in thread_counts(params.max_threads) {
+            let mut run_args = args.clone();
+            run_args.schedule = *schedule;
+            run_args.threads = threads;
+
+            let mut fastest: Option<(Duration, usize)> = None;
+            for _ in 0..params.repeats.max(1) {
+                let work = workload.work();
+                let start = Instant::now();
+                let output = run_align_workers(
+                    &run_args,
+                    &workload.profile_map,
+                    &workload.targets,
+                    &workload.msv_stats,
+                    &evalue_cutoffs_by_accession,
+                    None,
+                    work,
+                );
+                let elapsed = start.elapsed();
+                if fastest.is_none_or(|(fastest, _)| elapsed < fastest) {
+                    fastest = Some((elapsed, output.hits.len()));
+                }
+            }
+
+            let (elapsed, hits) = fastest.unwrap_or_default();
+            measurements.push(ParallelMeasurement {
+                schedule: *schedule,
+                threads,
+                elapsed,
+                hits,
+            });
+        }
+    }
+
+    let seeds_per_second =
+        |m: &ParallelMeasurement| workload.seeds.len() as f64 / m.elapsed.as_secs_f64().max(1e-9);
+    // the speedups are over the defaults, a single thread in seeds file order
+    let baseline = measurements
+        .iter()
+        .find(|m| matches!(m.schedule, Schedule::Fifo) && m.threads == 1)
+        .map_or(1.0, seeds_per_second);
+
+    println!("schedule\tthreads\tseconds\tseeds/s\tspeedup\thits");
+    for m in &measurements {
+        println!(
+            "{}\t{}\t{:.3}\t{:.1}\t{:.2}\t{}",
+            value_name(m.schedule),
+            m.threads,
+            m.elapsed.as_secs_f64(),
+            seeds_per_second(m),
+            seeds_per_second(m) / baseline,
+            m.hits,
+        );
+    }
+
+    let best = measurements
+        .iter()
+        .map(seeds_per_second)
+        .fold(0.0, f64::max);
+    let recommended = measurements
+        .iter()
+        .filter(|m| seeds_per_second(m) >= best * (1.0 - RECOMMENDATION_TOLERANCE))
+        .min_by(|a, b| {
+            a.threads
+                .cmp(&b.threads)
+                .then(seeds_per_second(b).total_cmp(&seeds_per_second(a)))
+        });
+    if let Some(m) = recommended {
+        println!(
+            "recommendation: --schedule {} --threads {}",
+            value_name(m.schedule),
+            m.threads
+        );
+    }
+
+    Ok(())
+}
+
+/// The DP that follows the bounds, as it is run for every seed
+fn align_within_bounds(
+    profile: &Profile,
+    target: &Sequence,
+    row_bounds: &RowBounds,
+    matrices: &mut DpMatrices,
+) -> Trace {
+    matrices.reuse(target.length, profile.length);
+    forward_bounded(profile, target, &mut matrices.forward, row_bounds);
+    backward_bounded(profile, target, &mut matrices.backward, row_bounds);
+    posterior_bounded(
+        profile,
+        &matrices.forward,
+        &matrices.backward,
+        &mut matrices.posterior,
+        row_bounds,
+    );
+    optimal_accuracy_bounded(
+        profile,
+        &matrices.posterior,
+        &mut matrices.optimal,
+        row_bounds,
+    );
+    let mut trace = Trace::new(target.length, profile.length);
+    traceback_bounded(
+        profile,
+        &matrices.posterior,
+        &matrices.optimal,
+        &mut trace,
+        row_bounds.target_end,
+    );
+    trace
+}
+
+/// A measured size: the seconds per seed of the cloud search and of the dense path
+struct DenseMeasurement {
+    profile_name: String,
+    profile_length: usize,
+    target_length: usize,
+    cloud_seconds: f64,
+    dense_seconds: f64,
+}
+
+impl DenseMeasurement {
+    fn cells(&self) -> usize {
+        self.profile_length * self.target_length
+    }
+}
+
+/// Time a path over the seeds, keeping the fastest of the repeats, in seconds per seed.
+fn time_per_seed(params: &DenseBenchParams, mut align_seed: impl FnMut()) -> f64 {
+    let seeds = params.seeds.max(1);
+    (0..params.repeats.max(1))
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..seeds {
+                align_seed();
+            }
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+        .as_secs_f64()
+        / seeds as f64
+}
+
+/// Time the cloud search and the dense path on seeds of each query model against
+/// synthetic targets of doubling lengths, and print the measurements and the
+/// --dense-cells below which the dense path was always the faster.
+pub fn bench_dense(args: &Args) -> Result<()> {
+    let params = &args.dense_bench;
+    let hmms = parse_hmms_from_p7hmm_file(args.paths.query_hmm.to_str().unwrap())?;
+    let cloud_params = args.sensitivity.cloud_search_params();
+    let target_lengths: Vec<usize> =
+        std::iter::successors(Some(MIN_DENSE_TARGET_LENGTH), |length| Some(length * 2))
+            .take_while(|&length| length <= params.max_target_length.max(MIN_DENSE_TARGET_LENGTH))
+            .collect();
+
+    let mut rng = Rng(WORKLOAD_SEED);
+    let mut measurements: Vec<DenseMeasurement> = vec![];
+    for hmm in &hmms {
+        let mut profile = Profile::new(hmm);
+        let max_target_length = *target_lengths.last().unwrap_or(&MIN_DENSE_TARGET_LENGTH);
+        let mut cloud_matrix = CloudMatrixLinear::new(profile.length);
+        let mut forward_bounds = CloudBoundGroup::new(max_target_length, profile.length);
+        let mut backward_bounds = CloudBoundGroup::new(max_target_length, profile.length);
+        let mut matrices = DpMatrices::new(None);
+
+        for &target_length in &target_lengths {
+            let (target, seed) = planted_target(
+                &profile,
+                format!("synthetic-{target_length}"),
+                target_length,
+                &mut rng,
+            )?;
+            profile.configure_for_target_length(target.length);
+
+            let cloud_seconds = time_per_seed(params, || {
+                if let Some(row_bounds) = cloud_search_row_bounds(
+                    &profile,
+                    &target,
+                    &seed,
+                    &cloud_params,
+                    &mut cloud_matrix,
+                    &mut forward_bounds,
+                    &mut backward_bounds,
+                ) {
+                    black_box(align_within_bounds(
+                        &profile,
+                        &target,
+                        &row_bounds,
+                        &mut matrices,
+                    ));
+                }
+            });
+            let dense_seconds = time_per_seed(params, || {
+                let row_bounds = full_row_bounds(&profile, &target);
+                black_box(align_within_bounds(
+                    &profile,
+                    &target,
+                    &row_bounds,
+                    &mut matrices,
+                ));
+            });
+
+            measurements.push(DenseMeasurement {
+                profile_name: profile.name.clone(),
+                profile_length: profile.length,
+                target_length,
+                cloud_seconds,
+                dense_seconds,
+            });
+        }
+    }
+
+    println!(
+        "profile\tprofile length\ttarget length\tcells\tcloud us/seed\tdense us/seed\tspeedup"
+    );
+    for m in &measurements {
+        println!(
+            "{}\t{}\t{}\t{}\t{:.1}\t{:.1}\t{:.2}",
+            m.profile_name,
+            m.profile_length,
+            m.target_length,
+            m.cells(),
+            m.cloud_seconds * 1e6,
+            m.dense_seconds * 1e6,
+            m.cloud_seconds / m.dense_seconds.max(f64::EPSILON),
+        );
+    }
+
+    // the cutoff is the most cells for which the dense path won every smaller size too
+    let mut by_cells: Vec<&DenseMeasurement> = measurements.iter().collect();
+    by_cells.sort_by_key(|m| m.cells());
+    let cutoff = by_cells
+        .iter()
+        .take_while(|m| m.dense_seconds <= m.cloud_seconds)
+        .last()
+        .map_or(0, |m| m.cells());
+    println!("recommendation: --dense-cells {cutoff} (the default is {DEFAULT_DENSE_CELLS})");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thread_counts_double_up_to_the_most() {
+        assert_eq!(thread_counts(0), [1]);
+        assert_eq!(thread_counts(1), [1]);
+        assert_eq!(thread_counts(4), [1, 2, 4]);
+        assert_eq!(thread_counts(6), [1, 2, 4, 6]);
+    }
+}
@@ -0,0 +1,173 @@
+use std::io::Write;
+
+use anyhow::Result;
+use nale::align::bounded::structs::RowBounds;
+use nale::structs::Alignment;
+
+use crate::alignment_output::AlignedHit;
+use crate::number_format::NumberFormat;
+use crate::provenance::Provenance;
+use crate::tabular::{alignment_row, Table, DEFAULT_COLUMN_HEADERS};
+
+/// How a hit's row bounds were found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoundSource {
+    /// The cloud search around the seed
+    Cloud,
+    /// The cloud search with the relaxed parameters of --on-bound-fail widen-retry
+    Widened,
+    /// The whole matrix, with --on-bound-fail full-dp
+    Full,
+    /// The whole matrix, which was under --dense-cells
+    Dense,
+}
+
+impl BoundSource {
+    pub fn code(&self) -> &'static str {
+        match self {
+            BoundSource::Cloud => "cloud",
+            BoundSource::Widened => "widened",
+            BoundSource::Full => "full_dp",
+            BoundSource::Dense => "dense",
+        }
+    }
+}
+
+/// The geometry of the DP region that a hit was aligned in, from --bound-stats
+#[derive(Debug, Clone, Copy)]
+pub struct BoundStats {
+    pub source: BoundSource,
+    /// The number of cells inside the row bounds
+    pub cells: usize,
+    /// The number of cells in the full profile × target matrix
+    pub full_cells: usize,
+    pub target_start: usize,
+    pub target_end: usize,
+    /// The first and last anti-diagonals (target + profile position) that the bounds touch
+    pub anti_diagonal_start: usize,
+    pub anti_diagonal_end: usize,
+}
+
+impl BoundStats {
+    pub fn new(
+        row_bounds: &RowBounds,
+        source: BoundSource,
+        profile_length: usize,
+        target_length: usize,
+    ) -> Self {
+        let rows = row_bounds.target_start..=row_bounds.target_end;
+        let row_cells = |target_idx: usize| {
+            (row_bounds.right_row_bounds[target_idx] + 1)
+                .saturating_sub(row_bounds.left_row_bounds[target_idx])
+        };
+
+        BoundStats {
+            source,
+            cells: rows.clone().map(row_cells).sum(),
+            full_cells: profile_length * target_length,
+            target_start: row_bounds.target_start,
+            target_end: row_bounds.target_end,
+            anti_diagonal_start: rows
+                .clone()
+                .map(|target_idx| target_idx + row_bounds.left_row_bounds[target_idx])
+                .min()
+                .unwrap_or(0),
+            anti_diagonal_end: rows
+                .map(|target_idx| target_idx + row_bounds.right_row_bounds[target_idx])
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    /// The fraction of the full matrix that the bounds cover
+    pub fn fraction(&self) -> f32 {
+        self.cells as f32 / self.full_cells.max(1) as f32
+    }
+
+    /// The number of anti-diagonals that the bounds touch
+    pub fn anti_diagonal_span(&self) -> usize {
+        (self.anti_diagonal_end + 1).saturating_sub(self.anti_diagonal_start)
+    }
+}
+
+/// Print the share of the full matrices that the bounds of the hits covered.
+pub fn print_bound_summary(aligned_hits: &[AlignedHit]) {
+    let (cells, full_cells) = aligned_hits
+        .iter()
+        .filter_map(|hit| hit.bound_stats)
+        .fold((0, 0), |(cells, full_cells), bounds| {
+            (cells + bounds.cells, full_cells + bounds.full_cells)
+        });
+    if full_cells > 0 {
+        eprintln!(
+            "the DP bounds of the hits covered {cells} of {full_cells} cells ({:.2}%) of their full matrices",
+            100.0 * cells as f64 / full_cells as f64
+        );
+    }
+}
+
+/// Write the bound geometry of each hit, with the hit's columns and the score of
+/// the seed that it was aligned from, so that the saving of the bounded DP can be
+/// compared across seeds of different quality.
+pub fn write_bound_stats(
+    alignments: &[Alignment],
+    aligned_hits: &[AlignedHit],
+    provenance: &Provenance,
+    format: &NumberFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut headers: Vec<String> = DEFAULT_COLUMN_HEADERS.map(String::from).to_vec();
+    headers.extend(
+        [
+            "seed e-value",
+            "seed bit score",
+            "bound source",
+            "cells",
+            "full cells",
+            "fraction",
+            "bound target start",
+            "bound target end",
+            "anti-diagonal start",
+            "anti-diagonal end",
+            "anti-diagonal span",
+            "hit id",
+        ]
+        .map(String::from),
+    );
+
+    let mut rows: Vec<Vec<String>> = vec![];
+    for (alignment, hit) in alignments.iter().zip(aligned_hits) {
+        let Some(bounds) = &hit.bound_stats else {
+            continue;
+        };
+        let mut row = alignment_row(alignment, format);
+        row.extend([
+            hit.seed
+                .as_ref()
+                .map_or("-".to_string(), |seed| format.evalue(seed.evalue)),
+            hit.seed
+                .as_ref()
+                .and_then(|seed| seed.bit_score)
+                .map_or("-".to_string(), |bit_score| format.score(bit_score, 1)),
+            bounds.source.code().to_string(),
+            bounds.cells.to_string(),
+            bounds.full_cells.to_string(),
+            format!("{:.4}", bounds.fraction()),
+            bounds.target_start.to_string(),
+            bounds.target_end.to_string(),
+            bounds.anti_diagonal_start.to_string(),
+            bounds.anti_diagonal_end.to_string(),
+            bounds.anti_diagonal_span().to_string(),
+            hit.id.clone(),
+        ]);
+        rows.push(row);
+    }
+
+    Table {
+        comments: provenance.comment_lines(),
+        headers,
+        rows,
+        ..Table::default()
+    }
+    .write(out)
+}
@@ -1,3 +1,6 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use anyhow::{Context, Result};
@@ -7,9 +10,18 @@ use thiserror::Error;
 #[error("command exited without success")]
 struct CommandExitStatusError;
 
+#[derive(Error, Debug)]
+#[error("{program} exited without success; see the log at {}", log.to_string_lossy())]
+struct LoggedCommandExitStatusError {
+    program: String,
+    log: PathBuf,
+}
+
 /// An extension trait that is intended to add a run method to the std::process::Command struct.
 pub trait CommandExt {
     fn run(&mut self) -> Result<()>;
+    /// Run the command, writing its stdout and stderr to a log file.
+    fn run_logged(&mut self, log: &Path) -> Result<()>;
 }
 
 impl CommandExt for Command {
@@ -23,10 +35,34 @@ impl CommandExt for Command {
                     .context("failed to convert sdtout to UTF8")?;
                 let stderr = std::str::from_utf8(&output.stderr)
                     .context("failed to convert sdterr to UTF8")?;
-                println!("stdout: {stdout}");
-                println!("stderr: {stderr}");
+                eprintln!("stdout: {stdout}");
+                eprintln!("stderr: {stderr}");
                 Err(CommandExitStatusError.into())
             }
         }
     }
+
+    fn run_logged(&mut self, log: &Path) -> Result<()> {
+        let output = self.output().context("failed to run command")?;
+
+        let mut log_file = File::create(log).context(format!(
+            "failed to create log file: {}",
+            log.to_string_lossy()
+        ))?;
+        writeln!(log_file, "command: {self:?}")?;
+        writeln!(log_file, "status: {}", output.status)?;
+        writeln!(log_file, "--- stdout ---")?;
+        log_file.write_all(&output.stdout)?;
+        writeln!(log_file, "--- stderr ---")?;
+        log_file.write_all(&output.stderr)?;
+
+        match output.status.success() {
+            true => Ok(()),
+            false => Err(LoggedCommandExitStatusError {
+                program: self.get_program().to_string_lossy().to_string(),
+                log: log.to_path_buf(),
+            }
+            .into()),
+        }
+    }
 }
@@ -0,0 +1,66 @@
+use nale::align::bounded::structs::Seed;
+use nale::structs::{Profile, Sequence};
+
+/// The width of the windows that the residue entropy is measured over (SEG's W)
+const WINDOW_LENGTH: usize = 12;
+
+/// Windows with less entropy than this (in bits) are low complexity (SEG's K1)
+const MIN_WINDOW_ENTROPY: f32 = 2.2;
+
+/// Seeds with more than this fraction of their target range in low complexity windows are skipped
+const MAX_LOW_COMPLEXITY_FRACTION: f32 = 0.5;
+
+/// The Shannon entropy (in bits) of the standard residues in a window of digital residues
+fn window_entropy(residues: &[u8]) -> f32 {
+    let mut counts = [0usize; Profile::MAX_ALPHABET_SIZE];
+    for &residue in residues {
+        // the degenerate residues carry no composition information
+        if let Some(count) = counts.get_mut(residue as usize) {
+            *count += 1;
+        }
+    }
+
+    let total: usize = counts.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f32 / total as f32;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// The fraction of a seed's target range that is covered by low complexity windows.
+///
+/// This is a simplified version of the SEG trigger step: every window whose
+/// residue entropy falls below MIN_WINDOW_ENTROPY is masked.
+fn low_complexity_fraction(target: &Sequence, seed: &Seed) -> f32 {
+    // the digital residues are 1-indexed, like the seed coordinates
+    let residues = &target.digital_bytes[seed.target_start..=seed.target_end];
+    if residues.len() < WINDOW_LENGTH {
+        return match window_entropy(residues) < MIN_WINDOW_ENTROPY {
+            true => 1.0,
+            false => 0.0,
+        };
+    }
+
+    let mut masked = vec![false; residues.len()];
+    for (window_start, window) in residues.windows(WINDOW_LENGTH).enumerate() {
+        if window_entropy(window) < MIN_WINDOW_ENTROPY {
+            masked[window_start..window_start + WINDOW_LENGTH].fill(true);
+        }
+    }
+
+    masked.iter().filter(|&&m| m).count() as f32 / residues.len() as f32
+}
+
+/// Whether a seed lies mostly in low complexity sequence, such as
+/// repeats or compositionally biased regions like coiled coils.
+pub fn is_biased(target: &Sequence, seed: &Seed) -> bool {
+    low_complexity_fraction(target, seed) > MAX_LOW_COMPLEXITY_FRACTION
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result};
+
+use crate::runner::{ExternalRunner, Runner};
+
+/// A container image that the external tools are run in
+#[derive(Debug, Clone)]
+pub enum Container {
+    Docker(String),
+    Singularity(String),
+}
+
+impl Container {
+    /// The flag that chose the container, for messages
+    pub fn flag(&self) -> &'static str {
+        match self {
+            Container::Docker(_) => "--use-docker",
+            Container::Singularity(_) => "--use-singularity",
+        }
+    }
+
+    pub fn image(&self) -> &str {
+        match self {
+            Container::Docker(image) | Container::Singularity(image) => image,
+        }
+    }
+
+    /// The command that runs a tool command inside the container.
+    ///
+    /// Every directory that the command's arguments point into is bind-mounted
+    /// at the same path in the container, and the command runs from the same
+    /// working directory, so the arguments mean the same thing on either side.
+    pub fn wrap(&self, command: &Command) -> Result<Command> {
+        let working_dir =
+            std::env::current_dir().context("failed to read the working directory")?;
+        let mounts = mount_dirs(command, &working_dir);
+
+        let mut wrapped = match self {
+            Container::Docker(image) => {
+                let mut wrapped = Command::new("docker");
+                wrapped.args(["run", "--rm", "-i"]);
+                // run as the calling user, so that the files written
+                // to the mounts aren't owned by the container's root
+                if let Some(user) = current_user() {
+                    wrapped.args(["--user", &user]);
+                }
+                for dir in &mounts {
+                    wrapped.arg("-v").arg(mount_spec(dir));
+                }
+                wrapped.arg("-w").arg(&working_dir).arg(image);
+                wrapped
+            }
+            Container::Singularity(image) => {
+                // singularity already runs as the calling user
+                let mut wrapped = Command::new("singularity");
+                wrapped.arg("exec");
+                for dir in &mounts {
+                    wrapped.arg("--bind").arg(mount_spec(dir));
+                }
+                wrapped.arg("--pwd").arg(&working_dir).arg(image);
+                wrapped
+            }
+        };
+
+        wrapped.arg(command.get_program()).args(command.get_args());
+        Ok(wrapped)
+    }
+}
+
+/// A bind mount of a host directory at the same path in the container
+fn mount_spec(dir: &Path) -> String {
+    let dir = dir.to_string_lossy();
+    format!("{dir}:{dir}")
+}
+
+/// The directories to mount for a command: the working directory, and the nearest
+/// existing directory of each argument that could be a path. An argument that
+/// isn't a path falls back to the working directory, which is mounted anyway.
+fn mount_dirs(command: &Command, working_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: BTreeSet<PathBuf> = BTreeSet::new();
+    dirs.insert(working_dir.to_path_buf());
+
+    for arg in command.get_args() {
+        let path = working_dir.join(arg);
+        // an output path doesn't exist yet, so its parent is mounted
+        if let Some(dir) = path.ancestors().find(|a| a.is_dir()) {
+            if dir.parent().is_some() {
+                dirs.insert(dir.to_path_buf());
+            }
+        }
+    }
+
+    // a directory inside another mount comes along with it
+    let mut mounts: Vec<PathBuf> = vec![];
+    for dir in dirs {
+        if !mounts.iter().any(|mount| dir.starts_with(mount)) {
+            mounts.push(dir);
+        }
+    }
+    mounts
+}
+
+/// The calling user's uid:gid, for docker's --user
+#[cfg(unix)]
+fn current_user() -> Option<String> {
+    let id = |flag: &str| -> Option<String> {
+        let output = Command::new("id").arg(flag).output().ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    Some(format!("{}:{}", id("-u")?, id("-g")?))
+}
+
+#[cfg(not(unix))]
+fn current_user() -> Option<String> {
+    None
+}
+
+/// Runs the external tools inside a container, through another runner.
+pub struct ContainerRunner {
+    container: Container,
+    inner: Runner,
+}
+
+impl ContainerRunner {
+    pub fn new(container: Container, inner: Runner) -> Self {
+        ContainerRunner { container, inner }
+    }
+}
+
+impl ExternalRunner for ContainerRunner {
+    fn run(&self, command: &mut Command) -> Result<()> {
+        self.inner.run(&mut self.container.wrap(command)?)
+    }
+
+    fn run_to_file(&self, command: &mut Command, out: &Path) -> Result<()> {
+        // the output is written on the host, so its directory needn't be mounted
+        self.inner
+            .run_to_file(&mut self.container.wrap(command)?, out)
+    }
+}
@@ -0,0 +1,305 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::Args;
+
+const SYSFS_CPU_DIR: &str = "/sys/devices/system/cpu";
+const SYSFS_NODE_DIR: &str = "/sys/devices/system/node";
+
+/// A logical CPU, and the physical core and NUMA node that it is on
+#[derive(Debug, Clone, Copy)]
+struct LogicalCpu {
+    id: usize,
+    package: usize,
+    core: usize,
+    node: usize,
+}
+
+/// Parse a sysfs CPU list, e.g. "0-3,8-11"
+fn parse_cpu_list(list: &str) -> Vec<usize> {
+    list.trim()
+        .split(',')
+        .filter(|range| !range.is_empty())
+        .filter_map(|range| {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            Some(start.parse::<usize>().ok()?..=end.parse::<usize>().ok()?)
+        })
+        .flatten()
+        .collect()
+}
+
+fn read_number(path: &Path) -> Option<usize> {
+    read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// The NUMA node of each CPU; without NUMA support, every CPU is on node 0
+fn read_cpu_nodes() -> BTreeMap<usize, usize> {
+    let mut nodes = BTreeMap::new();
+    let Ok(entries) = std::fs::read_dir(SYSFS_NODE_DIR) else {
+        return nodes;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(node) = name.strip_prefix("node").and_then(|n| n.parse().ok()) else {
+            continue;
+        };
+        if let Ok(list) = read_to_string(entry.path().join("cpulist")) {
+            for cpu in parse_cpu_list(&list) {
+                nodes.insert(cpu, node);
+            }
+        }
+    }
+    nodes
+}
+
+/// The logical CPUs that the process may run on, which may be fewer than the
+/// machine's, e.g. in a container or under taskset
+#[cfg(target_os = "linux")]
+fn allowed_cpus() -> Option<Vec<usize>> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    let result =
+        unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) };
+    (result == 0).then(|| {
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+            .collect()
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_cpus() -> Option<Vec<usize>> {
+    None
+}
+
+/// The logical CPUs of the machine, grouped into physical cores and NUMA nodes
+pub struct CpuTopology {
+    cpus: Vec<LogicalCpu>,
+}
+
+impl CpuTopology {
+    /// Read the topology of the CPUs that the process may run on from sysfs.
+    ///
+    /// Returns None where it isn't available, which is anywhere but Linux.
+    pub fn detect() -> Option<Self> {
+        let nodes = read_cpu_nodes();
+        let cpus: Vec<LogicalCpu> = allowed_cpus()?
+            .into_iter()
+            .filter_map(|id| {
+                let topology = Path::new(SYSFS_CPU_DIR).join(format!("cpu{id}/topology"));
+                Some(LogicalCpu {
+                    id,
+                    package: read_number(&topology.join("physical_package_id"))?,
+                    core: read_number(&topology.join("core_id"))?,
+                    node: nodes.get(&id).copied().unwrap_or(0),
+                })
+            })
+            .collect();
+
+        (!cpus.is_empty()).then_some(CpuTopology { cpus })
+    }
+
+    /// The logical CPUs of each physical core, by node, package, and core
+    fn cores(&self) -> BTreeMap<(usize, usize, usize), Vec<usize>> {
+        let mut cores: BTreeMap<(usize, usize, usize), Vec<usize>> = BTreeMap::new();
+        for cpu in &self.cpus {
+            cores
+                .entry((cpu.node, cpu.package, cpu.core))
+                .or_default()
+                .push(cpu.id);
+        }
+        cores
+    }
+
+    pub fn physical_core_count(&self) -> usize {
+        self.cores().len()
+    }
+
+    pub fn logical_cpu_count(&self) -> usize {
+        self.cpus.len()
+    }
+
+    pub fn node_count(&self) -> usize {
+        let mut nodes: Vec<usize> = self.cpus.iter().map(|cpu| cpu.node).collect();
+        nodes.sort();
+        nodes.dedup();
+        nodes.len()
+    }
+
+    /// The CPUs to pin threads to, in order: one logical CPU of each physical
+    /// core, filling a node before the next, and only then the hyperthread
+    /// siblings, which share a core's DP units with the first.
+    pub fn pinning_order(&self) -> Vec<usize> {
+        let cores = self.cores();
+        let max_siblings = cores.values().map(Vec::len).max().unwrap_or(0);
+        (0..max_siblings)
+            .flat_map(|sibling| {
+                cores
+                    .values()
+                    .filter_map(move |core_cpus| core_cpus.get(sibling).copied())
+            })
+            .collect()
+    }
+}
+
+/// Pin the calling thread to a CPU, and return the CPUs it was allowed to run on before.
+#[cfg(target_os = "linux")]
+fn set_affinity(cpu: usize) -> std::io::Result<libc::cpu_set_t> {
+    let size = std::mem::size_of::<libc::cpu_set_t>();
+    let mut previous: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    unsafe {
+        if libc::sched_getaffinity(0, size, &mut previous) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, size, &set) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(previous)
+}
+
+/// A thread that is pinned to a CPU until this is dropped
+pub struct PinnedThread {
+    #[cfg(target_os = "linux")]
+    previous: libc::cpu_set_t,
+}
+
+thread_local! {
+    /// The pin of a thread pool's thread, which is held until the thread ends
+    static POOL_THREAD_PIN: RefCell<Option<PinnedThread>> = const { RefCell::new(None) };
+}
+
+impl Drop for PinnedThread {
+    fn drop(&mut self) {
+        // the tools that are run after aligning would otherwise inherit the pin
+        #[cfg(target_os = "linux")]
+        unsafe {
+            libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &self.previous);
+        }
+    }
+}
+
+/// The CPUs that the align workers, and the threads that map the query
+/// profiles, are pinned to with --pin-threads
+#[derive(Debug, Clone)]
+pub struct CpuPinning {
+    order: Vec<usize>,
+}
+
+impl CpuPinning {
+    /// Detect the CPU topology if --pin-threads was given, and describe how the
+    /// --threads align workers will be placed.
+    pub fn detect(args: &Args) -> Option<Self> {
+        if !args.pin_threads {
+            return None;
+        }
+        let Some(topology) = CpuTopology::detect() else {
+            eprintln!(
+                "warning: the CPU topology isn't available on this platform; not pinning threads"
+            );
+            return None;
+        };
+
+        let workers = args.threads.max(1);
+        let core_count = topology.physical_core_count();
+        let cpu_count = topology.logical_cpu_count();
+        eprintln!(
+            "pinning {workers} align workers to the {cpu_count} logical CPUs on {core_count} \
+             physical cores in {} NUMA nodes",
+            topology.node_count()
+        );
+        // past one worker per core, the next go on the hyperthread siblings, and
+        // past one per logical CPU, they are pinned to the same CPUs again
+        if workers > cpu_count {
+            eprintln!(
+                "warning: {workers} align workers is more than the {cpu_count} logical CPUs, \
+                 so some will be pinned to the same CPU"
+            );
+        } else if workers > core_count {
+            eprintln!(
+                "warning: {workers} align workers is more than the {core_count} physical cores, \
+                 so some will share a core with a hyperthread sibling"
+            );
+        }
+
+        Some(CpuPinning {
+            order: topology.pinning_order(),
+        })
+    }
+
+    /// The CPU of a thread, by its index
+    fn cpu(&self, thread_idx: usize) -> usize {
+        self.order[thread_idx % self.order.len()]
+    }
+
+    /// Pin the calling thread to the CPU of its index, warning if it can't be.
+    #[cfg(target_os = "linux")]
+    pub fn pin_current_thread(&self, thread_idx: usize) -> Option<PinnedThread> {
+        let cpu = self.cpu(thread_idx);
+        match set_affinity(cpu) {
+            Ok(previous) => Some(PinnedThread { previous }),
+            Err(err) => {
+                eprintln!("warning: failed to pin a thread to CPU {cpu}: {err}");
+                None
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn pin_current_thread(&self, _thread_idx: usize) -> Option<PinnedThread> {
+        None
+    }
+
+    /// Pin a thread pool's thread to the CPU of its index, for as long as the
+    /// thread runs, e.g. from the pool's start handler.
+    pub fn pin_pool_thread(&self, thread_idx: usize) {
+        let pinned = self.pin_current_thread(thread_idx);
+        POOL_THREAD_PIN.with(|pin| *pin.borrow_mut() = pinned);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_lists_are_parsed_with_their_ranges() {
+        assert_eq!(parse_cpu_list("0-3,8-9,12\n"), [0, 1, 2, 3, 8, 9, 12]);
+        assert_eq!(parse_cpu_list(""), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn threads_fill_the_physical_cores_of_a_node_before_the_siblings() {
+        // two nodes of two cores, each with a hyperthread sibling numbered after the cores
+        let cpu = |id, core, node| LogicalCpu {
+            id,
+            package: node,
+            core,
+            node,
+        };
+        let topology = CpuTopology {
+            cpus: vec![
+                cpu(0, 0, 0),
+                cpu(1, 1, 0),
+                cpu(2, 0, 1),
+                cpu(3, 1, 1),
+                cpu(4, 0, 0),
+                cpu(5, 1, 0),
+                cpu(6, 0, 1),
+                cpu(7, 1, 1),
+            ],
+        };
+        assert_eq!(topology.physical_core_count(), 4);
+        assert_eq!(topology.node_count(), 2);
+        assert_eq!(topology.pinning_order(), [0, 1, 2, 3, 4, 5, 6, 7]);
+
+        let pinning = CpuPinning {
+            order: topology.pinning_order(),
+        };
+        // past one worker per logical CPU, the order starts again
+        assert_eq!(pinning.cpu(8), 0);
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::pipeline::{align_seeds, AlignOutput, QuerySet, TargetStore};
+use crate::schedule::Schedule;
+use crate::Args;
+
+/// The schedules that the verification runs cycle through
+const SCHEDULES: [Schedule; 3] = [Schedule::Fifo, Schedule::Lpt, Schedule::Random];
+
+#[derive(Error, Debug)]
+#[error(
+    "align run {run} (schedule {schedule:?}, {threads} threads) produced different results than run 1"
+)]
+pub struct NondeterministicResultsError {
+    run: usize,
+    schedule: Schedule,
+    threads: usize,
+}
+
+/// The parts of a hit that must be identical between runs, with the scores compared bit for bit
+type HitKey = (
+    String,
+    String,
+    usize,
+    usize,
+    usize,
+    usize,
+    u32,
+    u32,
+    usize,
+    usize,
+);
+
+fn sorted_hit_keys(output: &AlignOutput) -> Vec<HitKey> {
+    let mut keys: Vec<HitKey> = output
+        .hits
+        .iter()
+        .map(|(alignment, aligned_hit)| {
+            (
+                alignment.profile_name.clone(),
+                alignment.target_name.clone(),
+                alignment.target_start,
+                alignment.target_end,
+                alignment.profile_start,
+                alignment.profile_end,
+                alignment.bit_score.to_bits(),
+                alignment.evalue.to_bits(),
+                aligned_hit.envelope.start,
+                aligned_hit.envelope.end,
+            )
+        })
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// Align the seeds several times, with different schedules and thread counts,
+/// and check that every run produces exactly the same hits.
+///
+/// Any state that leaks from one seed to the next, such as a profile left
+/// configured for the wrong target length, or that is shared between the
+/// workers, shows up as a difference here.
+pub fn verify_deterministic(
+    args: &Args,
+    queries: &QuerySet,
+    targets: &TargetStore,
+    run_count: usize,
+) -> Result<()> {
+    let mut expected: Option<Vec<HitKey>> = None;
+
+    for run_idx in 0..run_count {
+        let mut run_args = args.clone();
+        run_args.schedule = SCHEDULES[run_idx % SCHEDULES.len()];
+        // every other run is on a single thread, so that a race
+        // between the workers shows up even with --threads 1
+        run_args.threads = match run_idx % 2 {
+            0 => 1,
+            _ => args.threads.max(2),
+        };
+
+        eprintln!(
+            "determinism check: run {} of {run_count} (schedule {:?}, {} threads)",
+            run_idx + 1,
+            run_args.schedule,
+            run_args.threads
+        );
+
+        let keys = sorted_hit_keys(&align_seeds(&run_args, queries, targets)?);
+        match &expected {
+            None => expected = Some(keys),
+            Some(expected) if *expected != keys => {
+                return Err(NondeterministicResultsError {
+                    run: run_idx + 1,
+                    schedule: run_args.schedule,
+                    threads: run_args.threads,
+                }
+                .into());
+            }
+            Some(_) => {}
+        }
+    }
+
+    eprintln!("determinism check: all {run_count} runs produced identical results");
+
+    Ok(())
+}
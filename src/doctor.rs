@@ -0,0 +1,333 @@
+use std::fmt;
+use std::fs::{create_dir_all, read_to_string, remove_dir_all, remove_file, write, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use thiserror::Error;
+
+use crate::cpu_topology::CpuTopology;
+use crate::tabular::Table;
+use crate::tools::{Tool, ToolPaths};
+use crate::Args;
+
+/// A tiny query MSA for the end to end run
+const DOCTOR_QUERY: &str = "# STOCKHOLM 1.0
+#=GF ID doctor
+#=GF AC doctor
+seq1 MKVLAAGIVGLPNVGKSTLFNALTKAGAEAANYPFCTIEPNVGV
+seq2 MKVLAAGIVGLPNVGKSTLFNALTRAGAEAANYPFCTIEPNVGI
+seq3 MKILAAGIVGLPNVGKSTLFNALTKSGAEAANYPFCTIEPNTGV
+//
+";
+
+/// A tiny target for the end to end run, one of which contains the query
+const DOCTOR_TARGET: &str = ">hit
+MSTEKRKVLAAGIVGLPNVGKSTLFNALTKAGAEAANYPFCTIEPNVGVVPLPDE
+>decoy
+MDDQWERTYPASDFGHKLCVNMQWERTYPASDFGHKLCVNMQWERTYPASDFGH
+";
+
+#[derive(Error, Debug)]
+#[error("search failed: {0}")]
+pub struct DoctorSearchFailedError(String);
+
+#[derive(Error, Debug)]
+#[error("{0} of the doctor's checks failed")]
+pub struct DoctorChecksFailedError(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "FAIL",
+        };
+        write!(f, "{status}")
+    }
+}
+
+/// The outcome of one of the doctor's checks
+struct Check {
+    name: String,
+    status: CheckStatus,
+    detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Run a tool, and return the first line of its output that contains the pattern.
+pub fn discover_version(
+    tools: &ToolPaths,
+    name: &str,
+    tool: &Tool,
+    args: &[&str],
+    pattern: &str,
+) -> Result<String> {
+    let output = tools
+        .direct_command(tool)?
+        .args(args)
+        .output()
+        .context(tools.failed_to_run(name, tool))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout
+        .lines()
+        .find(|line| line.contains(pattern))
+        .unwrap_or_else(|| stdout.lines().next().unwrap_or_default())
+        .trim_start_matches('#')
+        .trim()
+        .to_string())
+}
+
+/// The version of a tool, and where it was given, if it wasn't found on the PATH
+fn tool_version(
+    tools: &ToolPaths,
+    name: &str,
+    tool: &Tool,
+    args: &[&str],
+    pattern: &str,
+) -> Result<String> {
+    let version = discover_version(tools, name, tool, args, pattern)?;
+    match tool.given_by {
+        Some(given_by) => Ok(format!(
+            "{version} ({}, from {given_by})",
+            tool.path.to_string_lossy()
+        )),
+        None => Ok(version),
+    }
+}
+
+/// Check for a tool; a missing optional tool is only a warning.
+fn check_tool(
+    tools: &ToolPaths,
+    name: &str,
+    tool: &Tool,
+    args: &[&str],
+    pattern: &str,
+    required: bool,
+) -> Check {
+    let missing_status = match required {
+        true => CheckStatus::Fail,
+        false => CheckStatus::Warn,
+    };
+    match tool_version(tools, name, tool, args, pattern) {
+        Ok(version) => Check::new(name, CheckStatus::Ok, version),
+        Err(err) => Check::new(name, missing_status, format!("{err:#}")),
+    }
+}
+
+/// The total and available memory, read from /proc on Linux
+fn memory_info() -> Option<(u64, u64)> {
+    let meminfo = read_to_string("/proc/meminfo").ok()?;
+    let kilobytes = |key: &str| -> Option<u64> {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(key))?
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse()
+            .ok()
+    };
+    Some((kilobytes("MemTotal:")?, kilobytes("MemAvailable:")?))
+}
+
+fn check_resources() -> Vec<Check> {
+    let cpus = match std::thread::available_parallelism() {
+        Ok(cpus) => Check::new("cpus", CheckStatus::Ok, cpus.to_string()),
+        Err(err) => Check::new("cpus", CheckStatus::Warn, err.to_string()),
+    };
+
+    let gib = |kilobytes: u64| kilobytes as f64 / (1024.0 * 1024.0);
+    let memory = match memory_info() {
+        Some((total, available)) => Check::new(
+            "memory",
+            CheckStatus::Ok,
+            format!(
+                "{:.1} GiB total, {:.1} GiB available",
+                gib(total),
+                gib(available)
+            ),
+        ),
+        None => Check::new("memory", CheckStatus::Warn, "unknown on this platform"),
+    };
+
+    let topology = match CpuTopology::detect() {
+        Some(topology) => Check::new(
+            "cpu topology",
+            CheckStatus::Ok,
+            format!(
+                "{} logical CPUs on {} physical cores in {} NUMA nodes",
+                topology.logical_cpu_count(),
+                topology.physical_core_count(),
+                topology.node_count()
+            ),
+        ),
+        None => Check::new(
+            "cpu topology",
+            CheckStatus::Warn,
+            "unknown on this platform, so --pin-threads has no effect",
+        ),
+    };
+
+    vec![cpus, topology, memory]
+}
+
+/// Check that a directory can be written to, or created if it doesn't exist yet.
+fn check_writable(name: &str, dir: &Path) -> Check {
+    // a directory that doesn't exist yet is created in its nearest existing ancestor
+    let Some(existing) = dir.ancestors().find(|a| a.is_dir()) else {
+        return Check::new(
+            name,
+            CheckStatus::Fail,
+            format!("{} has no existing parent", dir.to_string_lossy()),
+        );
+    };
+
+    let probe = existing.join(format!(".mmoreseqs-doctor-{}", std::process::id()));
+    let outcome = write(&probe, b"probe").and_then(|_| remove_file(&probe));
+
+    let detail = match existing == dir {
+        true => dir.to_string_lossy().to_string(),
+        false => format!("{} (will be created)", dir.to_string_lossy()),
+    };
+    match outcome {
+        Ok(_) => Check::new(name, CheckStatus::Ok, detail),
+        Err(err) => Check::new(
+            name,
+            CheckStatus::Fail,
+            format!("{detail} is not writable: {err}"),
+        ),
+    }
+}
+
+/// Search the tiny query against the tiny target in a directory with
+/// this mmoreseqs binary, and return the number of hits.
+fn run_doctor_search(dir: &Path, tools: &ToolPaths) -> Result<usize> {
+    create_dir_all(dir)?;
+    let query = dir.join("query.sto");
+    let target = dir.join("target.fa");
+    let results = dir.join("results.tsv");
+    write(&query, DOCTOR_QUERY)?;
+    write(&target, DOCTOR_TARGET)?;
+
+    let output = Command::new(std::env::current_exe()?)
+        .arg("search")
+        .arg(&query)
+        .arg(&target)
+        .arg("--work-dir")
+        .arg(dir.join("work"))
+        .arg("-o")
+        .arg(&results)
+        .arg("--skipped-file")
+        .arg(dir.join("skipped.tsv"))
+        .args(["--summary", "0"])
+        .args(tools.cli_args())
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(DoctorSearchFailedError(stderr.trim().to_string()).into());
+    }
+
+    let table = Table::read(BufReader::new(File::open(&results)?))?;
+    Ok(table.rows.len())
+}
+
+fn check_end_to_end(tools: &ToolPaths) -> Check {
+    let name = "end to end run";
+    let dir = std::env::temp_dir().join(format!("mmoreseqs-doctor-{}", std::process::id()));
+
+    let outcome = run_doctor_search(&dir, tools);
+    let _ = remove_dir_all(&dir);
+
+    match outcome {
+        Ok(0) => Check::new(name, CheckStatus::Warn, "the search ran, but found no hits"),
+        Ok(hit_count) => Check::new(
+            name,
+            CheckStatus::Ok,
+            format!("the search ran, and found {hit_count} hits"),
+        ),
+        Err(err) => Check::new(name, CheckStatus::Fail, format!("{err:#}")),
+    }
+}
+
+/// Check the environment that mmoreseqs runs in, and print a report to paste into bug reports.
+pub fn doctor(args: &Args) -> Result<()> {
+    let tools = &args.tools;
+    let mut checks = vec![
+        check_tool(tools, "mmseqs", &tools.mmseqs, &["version"], "", true),
+        check_tool(tools, "hmmbuild", &tools.hmmbuild, &["-h"], "HMMER", true),
+        // only needed for hmmpress-ed queries
+        check_tool(
+            tools,
+            "hmmconvert",
+            &tools.hmmconvert,
+            &["-h"],
+            "HMMER",
+            false,
+        ),
+    ];
+    checks.extend(check_resources());
+    checks.push(check_writable("prep dir", &args.workspace.dir));
+    checks.push(check_writable("output dir", &doctor_output_dir(args)));
+
+    let tools_found = checks[..2].iter().all(|c| c.status == CheckStatus::Ok);
+    match (args.doctor_run, tools_found) {
+        (false, _) => {}
+        (true, true) => checks.push(check_end_to_end(&args.tools)),
+        (true, false) => checks.push(Check::new(
+            "end to end run",
+            CheckStatus::Warn,
+            "skipped, since mmseqs or hmmbuild is missing",
+        )),
+    }
+
+    println!("mmoreseqs doctor report");
+    println!("```");
+    println!("mmoreseqs   {}", env!("CARGO_PKG_VERSION"));
+    println!(
+        "platform    {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    let width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    for check in &checks {
+        println!("{:width$}  [{}] {}", check.name, check.status, check.detail);
+    }
+    println!("```");
+
+    match checks
+        .iter()
+        .filter(|c| c.status == CheckStatus::Fail)
+        .count()
+    {
+        0 => Ok(()),
+        failed_count => Err(DoctorChecksFailedError(failed_count).into()),
+    }
+}
+
+/// The directory that the results would be written to
+fn doctor_output_dir(args: &Args) -> PathBuf {
+    match args.paths.results.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use nale::structs::{Alignment, Profile};
+
+use crate::alignment_output::AlignedHit;
+
+/// The statistics of a hit as one of the domains of its target.
+///
+/// A hit's own E-value is an independent (HMMER's i-Evalue) domain E-value. These
+/// add the conditional (c-Evalue) domain E-value, which only counts the targets
+/// that have hits to the profile, since the domain is only looked for in a target
+/// that is a hit, and a score and E-value for the whole target. The target's score
+/// is the sum of its domains' scores; it isn't HMMER's sequence score, which comes
+/// from a Forward score over the whole target, less its null2 correction.
+#[derive(Debug, Clone, Copy)]
+pub struct DomainStats {
+    /// The summed bit score of the target's non-overlapping domains
+    pub seq_bit_score: f32,
+    pub seq_evalue: f32,
+    pub conditional_evalue: f32,
+    /// The number of domains reported for the target
+    pub domain_count: usize,
+}
+
+fn pvalue(profile: &Profile, bit_score: f32) -> f32 {
+    (-profile.forward_lambda * (bit_score - profile.forward_tau)).exp()
+}
+
+/// The bit score of a set of domains, counting only the best of any that overlap.
+fn seq_bit_score(domains: &[&(Alignment, AlignedHit)]) -> f32 {
+    let mut by_score: Vec<&(Alignment, AlignedHit)> = domains.to_vec();
+    by_score.sort_by(|(a, _), (b, _)| b.bit_score.total_cmp(&a.bit_score));
+
+    let mut counted: Vec<&AlignedHit> = vec![];
+    let mut bit_score: f32 = 0.0;
+    for (alignment, hit) in by_score {
+        let overlaps = counted
+            .iter()
+            .any(|c| hit.envelope.start <= c.envelope.end && c.envelope.start <= hit.envelope.end);
+        if !overlaps {
+            bit_score += alignment.bit_score;
+            counted.push(hit);
+        }
+    }
+    bit_score
+}
+
+/// Set the domain statistics of each of the reported hits.
+///
+/// The profiles are looked up by accession, and any hit whose profile is missing is left without.
+pub fn assign_domain_stats(
+    hits: &mut [(Alignment, AlignedHit)],
+    profiles_by_accession: &HashMap<String, Arc<Profile>>,
+    target_count: usize,
+) {
+    let mut hit_idxs_by_pair: HashMap<(&str, &str), Vec<usize>> = HashMap::new();
+    for (hit_idx, (alignment, hit)) in hits.iter().enumerate() {
+        hit_idxs_by_pair
+            .entry((&hit.profile_accession, &alignment.target_name))
+            .or_default()
+            .push(hit_idx);
+    }
+
+    let mut hit_target_counts: HashMap<&str, usize> = HashMap::new();
+    for (profile_accession, _) in hit_idxs_by_pair.keys() {
+        *hit_target_counts.entry(profile_accession).or_default() += 1;
+    }
+
+    let mut stats: Vec<(usize, DomainStats)> = vec![];
+    for ((profile_accession, _), hit_idxs) in &hit_idxs_by_pair {
+        let Some(profile) = profiles_by_accession.get(*profile_accession) else {
+            continue;
+        };
+        let domains: Vec<&(Alignment, AlignedHit)> =
+            hit_idxs.iter().map(|hit_idx| &hits[*hit_idx]).collect();
+        let seq_bit_score = seq_bit_score(&domains);
+        let seq_evalue = pvalue(profile, seq_bit_score) * target_count as f32;
+        let hit_target_count = hit_target_counts[profile_accession];
+
+        for hit_idx in hit_idxs {
+            let (alignment, _) = &hits[*hit_idx];
+            stats.push((
+                *hit_idx,
+                DomainStats {
+                    seq_bit_score,
+                    seq_evalue,
+                    conditional_evalue: pvalue(profile, alignment.bit_score)
+                        * hit_target_count as f32,
+                    domain_count: hit_idxs.len(),
+                },
+            ));
+        }
+    }
+
+    for (hit_idx, domain_stats) in stats {
+        hits[hit_idx].1.domain_stats = Some(domain_stats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Envelope;
+    use crate::test_files::fixture_profile;
+
+    /// The fixture profile, with its E-value parameters set so that
+    /// the P-value of a bit score of s is 2^-s
+    fn profile() -> Arc<Profile> {
+        let mut profile = fixture_profile();
+        profile.forward_lambda = std::f32::consts::LN_2;
+        profile.forward_tau = 0.0;
+        Arc::new(profile)
+    }
+
+    /// A hit of a profile to a target, with the given bit score and envelope
+    fn hit(
+        profile_accession: &str,
+        target_name: &str,
+        bit_score: f32,
+        envelope: (usize, usize),
+    ) -> (Alignment, AlignedHit) {
+        let (start, end) = envelope;
+        let alignment = Alignment {
+            profile_name: "prof".to_string(),
+            target_name: target_name.to_string(),
+            bit_score,
+            evalue: 1.0,
+            length: end - start + 1,
+            profile_start: 1,
+            profile_end: 24,
+            profile_string: String::new(),
+            target_start: start,
+            target_end: end,
+            target_string: String::new(),
+            middle_string: String::new(),
+            posterior_probability_string: String::new(),
+        };
+        let hit = AlignedHit {
+            id: "hit".to_string(),
+            profile_name: "prof".to_string(),
+            profile_accession: profile_accession.to_string(),
+            target_name: target_name.to_string(),
+            target_start: start,
+            target_end: end,
+            envelope: Envelope { start, end },
+            reference: String::new(),
+            target: String::new(),
+            posterior: String::new(),
+            identity: 0.5,
+            similarity: 0.75,
+            gap_fraction: 0.0,
+            bias: 0.0,
+            profile_coverage: 1.0,
+            target_coverage: 0.5,
+            seed: None,
+            supporting_seeds: 1,
+            domain_stats: None,
+            included: None,
+            alternatives: vec![],
+            bound_stats: None,
+            trace: None,
+        };
+        (alignment, hit)
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() <= expected * 1e-5,
+            "{actual} isn't {expected}"
+        );
+    }
+
+    #[test]
+    fn the_seq_score_sums_the_best_of_overlapping_domains() {
+        let mut hits = vec![
+            hit("PF1", "tgt1", 10.0, (40, 70)),
+            hit("PF1", "tgt1", 30.0, (1, 50)),
+            hit("PF1", "tgt1", 20.0, (60, 100)),
+            hit("PF1", "tgt2", 10.0, (1, 30)),
+        ];
+        let profiles = HashMap::from([("PF1".to_string(), profile())]);
+        assign_domain_stats(&mut hits, &profiles, 1000);
+
+        // the 10 bit domain overlaps both of the others, so it isn't counted
+        for (_, hit) in &hits[..3] {
+            let stats = hit.domain_stats.unwrap();
+            assert_eq!(stats.seq_bit_score, 50.0);
+            assert_close(stats.seq_evalue, 1000.0 / 2f32.powi(50));
+            assert_eq!(stats.domain_count, 3);
+        }
+
+        let stats = hits[3].1.domain_stats.unwrap();
+        assert_eq!(stats.seq_bit_score, 10.0);
+        assert_close(stats.seq_evalue, 1000.0 / 1024.0);
+        assert_eq!(stats.domain_count, 1);
+    }
+
+    #[test]
+    fn the_c_evalue_only_counts_the_targets_with_hits() {
+        let mut hits = vec![
+            hit("PF1", "tgt1", 30.0, (1, 50)),
+            hit("PF1", "tgt1", 20.0, (60, 100)),
+            hit("PF1", "tgt2", 10.0, (1, 30)),
+            hit("PF1", "tgt3", 10.0, (1, 30)),
+            hit("PF2", "tgt1", 10.0, (1, 30)),
+        ];
+        let profiles = HashMap::from([
+            ("PF1".to_string(), profile()),
+            ("PF2".to_string(), profile()),
+        ]);
+        assign_domain_stats(&mut hits, &profiles, 1000);
+
+        // PF1 hits three targets, whatever their number of domains, and PF2 hits one
+        let c_evalues: Vec<f32> = hits
+            .iter()
+            .map(|(_, hit)| hit.domain_stats.unwrap().conditional_evalue)
+            .collect();
+        let expected = [
+            3.0 / 2f32.powi(30),
+            3.0 / 2f32.powi(20),
+            3.0 / 1024.0,
+            3.0 / 1024.0,
+            1.0 / 1024.0,
+        ];
+        for (actual, expected) in c_evalues.into_iter().zip(expected) {
+            assert_close(actual, expected);
+        }
+
+        let domain_counts: Vec<usize> = hits
+            .iter()
+            .map(|(_, hit)| hit.domain_stats.unwrap().domain_count)
+            .collect();
+        assert_eq!(domain_counts, vec![2, 2, 1, 1, 1]);
+    }
+
+    #[test]
+    fn hits_whose_profile_is_missing_are_left_without_stats() {
+        let mut hits = vec![
+            hit("PF1", "tgt1", 30.0, (1, 50)),
+            hit("missing", "tgt1", 30.0, (1, 50)),
+        ];
+        let profiles = HashMap::from([("PF1".to_string(), profile())]);
+        assign_domain_stats(&mut hits, &profiles, 1000);
+
+        assert_eq!(hits[0].1.domain_stats.unwrap().domain_count, 1);
+        assert!(hits[1].1.domain_stats.is_none());
+    }
+}
@@ -0,0 +1,100 @@
+use nale::structs::DpMatrixFlat;
+
+/// The number of bytes in a single DP matrix cell
+const CELL_BYTES: usize = std::mem::size_of::<f32>();
+
+/// The core states of a DP matrix at each target and profile position: match, insert, and delete
+const CORE_STATES: usize = 3;
+
+/// The special states of a DP matrix at each target position: N, B, E, C, and J
+const SPECIAL_STATES: usize = 5;
+
+/// The set of DP matrices that are used to align a single seed.
+///
+/// The matrices grow on demand to fit the largest seed they have serviced. If a memory cap
+/// is set, the matrices are released after servicing a seed that pushed them past the cap,
+/// so that a single outlier target doesn't permanently inflate memory usage.
+pub struct DpMatrices {
+    pub forward: DpMatrixFlat,
+    pub backward: DpMatrixFlat,
+    pub posterior: DpMatrixFlat,
+    pub optimal: DpMatrixFlat,
+    /// The target length that the matrices were allocated for
+    allocated_target_length: usize,
+    /// The profile length that the matrices were allocated for
+    allocated_profile_length: usize,
+    /// The maximum number of bytes the matrices may hold on to between seeds
+    max_retained_bytes: Option<usize>,
+}
+
+impl DpMatrices {
+    pub fn new(max_retained_bytes: Option<usize>) -> Self {
+        DpMatrices {
+            forward: DpMatrixFlat::new(0, 0),
+            backward: DpMatrixFlat::new(0, 0),
+            posterior: DpMatrixFlat::new(0, 0),
+            optimal: DpMatrixFlat::new(0, 0),
+            allocated_target_length: 0,
+            allocated_profile_length: 0,
+            max_retained_bytes,
+        }
+    }
+
+    /// An upper bound on the number of bytes that the matrices take up when they
+    /// are allocated for a target and profile, without allocating them.
+    pub fn bytes_for(target_length: usize, profile_length: usize) -> usize {
+        // with a padding row and column either side of the target and profile
+        let rows = target_length + 2;
+        let core_cells = rows * (profile_length + 2) * CORE_STATES;
+        4 * (core_cells + rows * SPECIAL_STATES) * CELL_BYTES
+    }
+
+    /// The number of bytes currently allocated across all of the matrices.
+    pub fn allocated_bytes(&self) -> usize {
+        [
+            &self.forward,
+            &self.backward,
+            &self.posterior,
+            &self.optimal,
+        ]
+        .iter()
+        .map(|m| (m.core_data.len() + m.special_data.len()) * CELL_BYTES)
+        .sum()
+    }
+
+    /// Prepare the matrices for a target and profile, growing them if they are too small.
+    pub fn reuse(&mut self, target_length: usize, profile_length: usize) {
+        if target_length > self.allocated_target_length
+            || profile_length > self.allocated_profile_length
+        {
+            let new_target_length = target_length.max(self.allocated_target_length);
+            let new_profile_length = profile_length.max(self.allocated_profile_length);
+            self.allocate(new_target_length, new_profile_length);
+        }
+
+        self.forward.reuse(target_length, profile_length);
+        self.backward.reuse(target_length, profile_length);
+        self.posterior.reuse(target_length, profile_length);
+        self.optimal.reuse(target_length, profile_length);
+    }
+
+    /// Release the matrices if they have grown past the memory cap.
+    ///
+    /// This should be called after each seed is finished with the matrices.
+    pub fn shrink_to_cap(&mut self) {
+        if let Some(max_retained_bytes) = self.max_retained_bytes {
+            if self.allocated_bytes() > max_retained_bytes {
+                self.allocate(0, 0);
+            }
+        }
+    }
+
+    fn allocate(&mut self, target_length: usize, profile_length: usize) {
+        self.forward = DpMatrixFlat::new(target_length, profile_length);
+        self.backward = DpMatrixFlat::new(target_length, profile_length);
+        self.posterior = DpMatrixFlat::new(target_length, profile_length);
+        self.optimal = DpMatrixFlat::new(target_length, profile_length);
+        self.allocated_target_length = target_length;
+        self.allocated_profile_length = profile_length;
+    }
+}
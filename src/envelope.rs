@@ -0,0 +1,48 @@
+use nale::align::bounded::structs::RowBounds;
+use nale::structs::dp_matrix::DpMatrix;
+
+/// The minimum posterior probability that a target position is emitted by the
+/// core model for it to be included in a hit's envelope (HMMER's rt2).
+const ENVELOPE_THRESHOLD: f32 = 0.25;
+
+/// The region of the target that is likely to be homologous to the profile.
+///
+/// The envelope always contains the optimal accuracy alignment, and extends
+/// past it into any flanking positions that carry appreciable posterior mass.
+#[derive(Debug, Clone, Copy)]
+pub struct Envelope {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Envelope {
+    /// Extend the alignment endpoints into the flanking target positions that
+    /// have at least ENVELOPE_THRESHOLD posterior mass in match or insert states.
+    pub fn from_posterior(
+        posterior: &impl DpMatrix,
+        row_bounds: &RowBounds,
+        alignment_start: usize,
+        alignment_end: usize,
+    ) -> Self {
+        let row_mass = |target_idx: usize| -> f32 {
+            (row_bounds.left_row_bounds[target_idx]..=row_bounds.right_row_bounds[target_idx])
+                .map(|profile_idx| {
+                    posterior.get_match(target_idx, profile_idx)
+                        + posterior.get_insert(target_idx, profile_idx)
+                })
+                .sum()
+        };
+
+        let mut start = alignment_start;
+        while start > row_bounds.target_start && row_mass(start - 1) >= ENVELOPE_THRESHOLD {
+            start -= 1;
+        }
+
+        let mut end = alignment_end;
+        while end < row_bounds.target_end && row_mass(end + 1) >= ENVELOPE_THRESHOLD {
+            end += 1;
+        }
+
+        Envelope { start, end }
+    }
+}
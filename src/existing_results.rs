@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::pipeline::MismatchedResultsError;
+use crate::tabular::{Table, EVALUE_COLUMN_IDX, PROFILE_NAME_COLUMN_IDX, TARGET_NAME_COLUMN_IDX};
+
+/// The hits of an earlier run, whose profile-target pairs aren't aligned again
+pub struct ExistingResults {
+    table: Table,
+    pairs: HashSet<(String, String)>,
+}
+
+impl ExistingResults {
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = File::open(path).context(format!(
+            "failed to open existing results: {}",
+            path.to_string_lossy()
+        ))?;
+        let table = Table::read(BufReader::new(file)).context(format!(
+            "failed to parse existing results: {}",
+            path.to_string_lossy()
+        ))?;
+
+        let pairs = table
+            .rows
+            .iter()
+            .map(|row| {
+                (
+                    row[PROFILE_NAME_COLUMN_IDX].clone(),
+                    row[TARGET_NAME_COLUMN_IDX].clone(),
+                )
+            })
+            .collect();
+
+        Ok(ExistingResults { table, pairs })
+    }
+
+    /// Whether a profile and target pair was already reported.
+    pub fn contains(&self, profile_name: &str, target_name: &str) -> bool {
+        self.pairs
+            .contains(&(profile_name.to_string(), target_name.to_string()))
+    }
+
+    pub fn hit_count(&self) -> usize {
+        self.table.rows.len()
+    }
+
+    /// Keep only the hits that the keep function accepts, given the
+    /// profile name, target name, and E-value of each hit.
+    pub fn retain(&mut self, keep: impl Fn(&str, &str, f32) -> bool) {
+        self.table.rows.retain(|row| {
+            // an unparsable E-value can't pass a threshold
+            let evalue = row[EVALUE_COLUMN_IDX].parse().unwrap_or(f32::INFINITY);
+            keep(
+                &row[PROFILE_NAME_COLUMN_IDX],
+                &row[TARGET_NAME_COLUMN_IDX],
+                evalue,
+            )
+        });
+    }
+
+    /// Add the existing hits to a results table with the same columns.
+    ///
+    /// The existing rows follow this run's, in their own order, so that the
+    /// caller sorts the table as the run's hits were sorted.
+    pub fn merge_into(self, table: &mut Table) -> Result<()> {
+        if self.table.rows.is_empty() {
+            return Ok(());
+        }
+        if self.table.version != table.version || self.table.headers != table.headers {
+            return Err(MismatchedResultsError)
+                .context("the existing results have different columns than this run");
+        }
+
+        table.rows.extend(self.table.rows);
+        Ok(())
+    }
+}
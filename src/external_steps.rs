@@ -1,122 +1,463 @@
-use crate::command_ext::CommandExt;
+use crate::mmseqs_profile::{
+    CompressedProfileDbError, ConsensusSource, ProfileLayout, UnsupportedProfileLayoutError,
+};
+use crate::sensitivity::QueryStats;
 use crate::Args;
 use anyhow::{Context, Result};
 use nale::structs::Sequence;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
-use std::process::Command;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
-#[derive(Error, Debug)]
-#[error("no profile to profile map")]
-pub struct ProfilesNotMappedError;
-
-pub fn check_hmmer_installed() -> Result<()> {
-    Command::new("hmmbuild")
-        .arg("-h")
-        .run()
-        .context("hmmbuild does not appear to be in the system path")
+pub fn check_hmmer_installed(args: &Args) -> Result<()> {
+    args.runner
+        .run(args.tools.hmmbuild().arg("-h"))
+        .context(args.tools.failed_to_run("hmmbuild", &args.tools.hmmbuild))
 }
 
-pub fn check_mmseqs_installed() -> Result<()> {
-    Command::new("mmseqs")
-        .arg("-h")
-        .run()
-        .context("mmseqs2 does not appear to be in the system path")
+pub fn check_mmseqs_installed(args: &Args) -> Result<()> {
+    args.runner
+        .run(args.tools.mmseqs().arg("-h"))
+        .context(args.tools.failed_to_run("mmseqs2", &args.tools.mmseqs))
 }
 
+/// hmmbuild barely speeds up past this many threads
+pub const HMMBUILD_MAX_THREADS: usize = 2;
+
 pub fn run_hmmbuild(args: &Args) -> Result<()> {
-    Command::new("hmmbuild")
-        .args(["--cpu", &args.threads.to_string()])
-        .arg(&args.paths.query_hmm)
-        .arg(&args.paths.query_msa)
-        .run()
+    // when prep overlaps the query and target, args.threads is already the query's share
+    let threads = args.threads.min(HMMBUILD_MAX_THREADS);
+    args.runner.run(
+        args.tools
+            .hmmbuild()
+            .args(["--cpu", &threads.to_string()])
+            .arg(&args.paths.query_hmm)
+            .arg(&args.paths.query),
+    )
+}
+
+/// Convert a (possibly hmmpress-ed) HMM database into a flat ASCII HMMER3 file.
+pub fn run_hmmconvert(args: &Args, hmm_db: &Path, out: &Path) -> Result<()> {
+    args.runner
+        .run_to_file(args.tools.hmmconvert().arg("-a").arg(hmm_db), out)
 }
 
 pub fn run_mmseqs_convertmsa(args: &Args) -> Result<()> {
-    Command::new("mmseqs")
-        .arg("convertmsa")
-        .arg(&args.paths.query_msa)
-        .arg(&args.paths.query_msa_db)
-        .run()
+    args.runner.run(
+        args.tools
+            .mmseqs()
+            .arg("convertmsa")
+            .arg(&args.paths.query)
+            .arg(&args.paths.query_msa_db),
+    )
 }
 
 pub fn run_mmseqs_msa2profile(args: &Args) -> Result<()> {
-    Command::new("mmseqs")
-        .arg("msa2profile")
-        .arg(&args.paths.query_msa_db)
-        .arg(&args.paths.query_db)
-        .args(["--threads", &args.threads.to_string()])
-        // --match-mode INT       0: Columns that have a residue in the first sequence are kept,
-        //                        1: columns that have a residue in --match-ratio of all sequences
-        //                           are kept [0]
-        .args(["--match-mode", "1"])
-        .run()
+    args.runner.run(
+        args.tools
+            .mmseqs()
+            .arg("msa2profile")
+            .arg(&args.paths.query_msa_db)
+            .arg(&args.paths.query_db)
+            .args(["--threads", &args.threads.to_string()])
+            // --match-mode INT       0: Columns that have a residue in the first sequence are kept,
+            //                        1: columns that have a residue in --match-ratio of all sequences
+            //                           are kept [0]
+            .args(["--match-mode", "1"]),
+    )
 }
 
 pub fn run_mmseqs_createdb(args: &Args) -> Result<()> {
-    Command::new("mmseqs")
-        .arg("createdb")
-        .arg(&args.paths.target_fasta)
-        .arg(&args.paths.target_db)
-        .run()
-}
-
-pub fn run_mmseqs_prefilter(args: &Args) -> Result<()> {
-    Command::new("mmseqs")
-        .arg("prefilter")
-        .arg(&args.paths.query_db)
-        .arg(&args.paths.target_db)
-        .arg(&args.paths.prefilter_db)
-        .args(["--threads", &args.threads.to_string()])
+    // if we are filtering by taxonomy, the
+    // prep step has written a filtered fasta
+    let target_fasta = match args.paths.taxdb {
+        Some(_) => &args.paths.filtered_target_fasta,
+        None => args.paths.prepped_target_fasta(),
+    };
+
+    args.runner.run(
+        args.tools
+            .mmseqs()
+            .arg("createdb")
+            .arg(target_fasta)
+            .arg(&args.paths.target_db),
+    )
+}
+
+pub fn run_mmseqs_createdb_query_consensus(args: &Args) -> Result<()> {
+    args.runner.run(
+        args.tools
+            .mmseqs()
+            .arg("createdb")
+            .arg(&args.paths.query_consensus_fasta)
+            .arg(&args.paths.query_db),
+    )
+}
+
+/// Copy the target database entries whose keys are listed into the target subset database.
+pub fn run_mmseqs_createsubdb(args: &Args) -> Result<()> {
+    let with_headers = |db: &Path| {
+        let mut headers = db.as_os_str().to_owned();
+        headers.push("_h");
+        PathBuf::from(headers)
+    };
+
+    for (db, subset_db) in [
+        (
+            args.paths.target_db.clone(),
+            args.paths.target_subset_db.clone(),
+        ),
+        (
+            with_headers(&args.paths.target_db),
+            with_headers(&args.paths.target_subset_db),
+        ),
+    ] {
+        args.runner.run(
+            args.tools
+                .mmseqs()
+                .arg("createsubdb")
+                .arg(&args.paths.target_subset_keys)
+                .arg(db)
+                .arg(subset_db),
+        )?;
+    }
+    Ok(())
+}
+
+/// Build the precomputed k-mer index of the target database, which the prefilter
+/// reads in place of indexing the targets on every search.
+pub fn run_mmseqs_createindex(args: &Args, tmp_dir: &Path) -> Result<()> {
+    // -k INT  k-mer length (0: automatically set to optimum) [0]
+    let kmer_args = match args.kmer_length {
+        Some(kmer_length) => vec!["-k".to_string(), kmer_length.to_string()],
+        None => vec![],
+    };
+    args.runner.run(
+        args.tools
+            .mmseqs()
+            .arg("createindex")
+            .arg(&args.paths.target_db)
+            .arg(tmp_dir)
+            .args(["--threads", &args.threads.to_string()])
+            .args(kmer_args)
+            .args(memory_args(args, true)),
+    )
+}
+
+/// Read an mmseqs database into the page cache, so that the first search doesn't pay for it.
+pub fn run_mmseqs_touchdb(args: &Args, db: &Path) -> Result<()> {
+    args.runner.run(args.tools.mmseqs().arg("touchdb").arg(db))
+}
+
+/// The arguments that make mmseqs compress the database that it writes
+fn compression_args(args: &Args) -> Vec<&'static str> {
+    match args.compress_intermediates {
+        true => vec!["--compressed", "1"],
+        false => vec![],
+    }
+}
+
+/// The arguments that fit mmseqs to the --max-ram budget
+fn memory_args(args: &Args, split_memory: bool) -> Vec<String> {
+    let Some(budget) = args.max_ram else {
+        return vec![];
+    };
+
+    let mut memory_args = vec![
+        "--db-load-mode".to_string(),
+        budget.db_load_mode(&args.paths.target_db).to_string(),
+    ];
+    if split_memory {
+        memory_args.push("--split-memory-limit".to_string());
+        memory_args.push(budget.split_memory_limit());
+    }
+    memory_args
+}
+
+/// The prefilter parameters of a run, after tuning to --sensitivity and the query
+/// models, with the ones given on the command line in place of the tuned ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedPrefilter {
+    /// The k-mer length, or None to let mmseqs pick it
+    pub kmer_length: Option<u32>,
+    /// The k-mer score threshold, or None when mmseqs picks it for its own sensitivity
+    pub k_score: Option<u32>,
+    pub mmseqs_sensitivity: f32,
+    pub max_seqs: usize,
+}
+
+impl ResolvedPrefilter {
+    pub fn new(args: &Args, query_db_type: MmseqsDbType) -> Result<Self> {
+        // the query HMM doesn't exist yet in a dry run, so the untuned parameters are used
+        let query_stats = match query_db_type {
+            MmseqsDbType::Profile => QueryStats::read(&args.paths.query_hmm)?,
+            MmseqsDbType::Sequence => None,
+        };
+        let tuned = args.sensitivity.prefilter_params(query_stats.as_ref());
+
+        // for sequence queries, mmseqs picks a k-mer score threshold for its own sensitivity
+        let k_score = match query_db_type {
+            MmseqsDbType::Profile => args.k_score.or(Some(tuned.k_score)),
+            MmseqsDbType::Sequence => args.k_score,
+        };
+
+        Ok(ResolvedPrefilter {
+            kmer_length: args.kmer_length.or(tuned.kmer_length),
+            k_score,
+            mmseqs_sensitivity: tuned.mmseqs_sensitivity,
+            max_seqs: args.max_seqs.unwrap_or(tuned.max_seqs),
+        })
+    }
+
+    /// The search parameters of the mmseqs prefilter command
+    pub fn mmseqs_args(&self) -> Vec<String> {
+        let mut mmseqs_args = vec![];
         // -k INT                    k-mer length (0: automatically set to optimum) [0]
-        // .args(["-k", "7"])
+        if let Some(kmer_length) = self.kmer_length {
+            mmseqs_args.extend(["-k".to_string(), kmer_length.to_string()]);
+        }
         // --k-score INT             k-mer threshold for generating similar k-mer lists [2147483647]
-        .args(["--k-score", "80"])
+        // -s FLOAT                  Sensitivity: 1.0 faster; 4.0 fast; 7.5 sensitive [5.700]
+        match self.k_score {
+            Some(k_score) => mmseqs_args.extend(["--k-score".to_string(), k_score.to_string()]),
+            None => mmseqs_args.extend(["-s".to_string(), self.mmseqs_sensitivity.to_string()]),
+        }
         // --min-ungapped-score INT  Accept only matches with ungapped alignment score above
         //                             threshold [15]
-        .args(["--min-ungapped-score", "15"])
+        mmseqs_args.extend(["--min-ungapped-score".to_string(), "15".to_string()]);
         // --max-seqs INT            Maximum results per query sequence allowed to pass the
         //                             prefilter (affects sensitivity) [300]
-        .args(["--max-seqs", "1000"])
-        .run()
+        mmseqs_args.extend(["--max-seqs".to_string(), self.max_seqs.to_string()]);
+        mmseqs_args
+    }
 }
 
-pub fn run_mmseqs_align(args: &Args) -> Result<()> {
-    Command::new("mmseqs")
-        .arg("align")
-        .arg(&args.paths.query_db)
-        .arg(&args.paths.target_db)
-        .arg(&args.paths.prefilter_db)
-        .arg(&args.paths.align_db)
-        .args(["--threads", &args.threads.to_string()])
+/// The search parameters of the mmseqs align command
+pub fn mmseqs_align_args() -> Vec<&'static str> {
+    vec![
         // -e DOUBLE      List matches below this E-value (range 0.0-inf) [1.000E-03]
-        .args(["-e", "1e-2"])
+        "-e",
+        "1e-2",
         // --alt-ali INT  Show up to this many alternative alignments [0]
-        .args(["--alt-ali", "0"])
-        .args(["-a", "1"])
-        .run()
+        "--alt-ali",
+        "0",
+        "-a",
+        "1",
+    ]
+}
+
+pub fn run_mmseqs_prefilter(args: &Args, query_db_type: MmseqsDbType) -> Result<()> {
+    let prefilter = ResolvedPrefilter::new(args, query_db_type)?;
+
+    let (threshold_flag, threshold) = match prefilter.k_score {
+        Some(k_score) => ("--k-score", k_score.to_string()),
+        None => ("-s", prefilter.mmseqs_sensitivity.to_string()),
+    };
+    eprintln!(
+        "prefilter parameters: {threshold_flag} {threshold} -k {} --max-seqs {}",
+        prefilter
+            .kmer_length
+            .map_or("auto".to_string(), |k| k.to_string()),
+        prefilter.max_seqs,
+    );
+
+    args.runner.run(
+        args.tools
+            .mmseqs()
+            .arg("prefilter")
+            .arg(&args.paths.query_db)
+            .arg(&args.paths.target_db)
+            .arg(&args.paths.prefilter_db)
+            .args(["--threads", &args.threads.to_string()])
+            .args(prefilter.mmseqs_args())
+            .args(memory_args(args, true))
+            .args(compression_args(args)),
+    )
+}
+
+pub fn run_mmseqs_align(args: &Args) -> Result<()> {
+    args.runner.run(
+        args.tools
+            .mmseqs()
+            .arg("align")
+            .arg(&args.paths.query_db)
+            .arg(&args.paths.target_db)
+            .arg(&args.paths.prefilter_db)
+            .arg(&args.paths.align_db)
+            .args(["--threads", &args.threads.to_string()])
+            .args(mmseqs_align_args())
+            .args(memory_args(args, false))
+            .args(compression_args(args)),
+    )
 }
 
 pub fn run_mmseqs_convertalis(args: &Args) -> Result<()> {
-    Command::new("mmseqs")
-        .arg("convertalis")
-        .arg(&args.paths.query_db)
-        .arg(&args.paths.target_db)
-        .arg(&args.paths.align_db)
-        .arg(&args.paths.seeds)
-        .args(["--threads", &args.threads.to_string()])
-        .args([
-            "--format-output",
-            "query,target,qstart,qend,tstart,tend,evalue",
-        ])
-        .run()
+    args.runner.run(
+        args.tools
+            .mmseqs()
+            .arg("convertalis")
+            .arg(&args.paths.query_db)
+            .arg(&args.paths.target_db)
+            .arg(&args.paths.align_db)
+            .arg(&args.paths.seeds)
+            .args(["--threads", &args.threads.to_string()])
+            .args([
+                "--format-output",
+                "query,target,qstart,qend,tstart,tend,evalue,bits",
+            ]),
+    )
+}
+
+#[derive(Error, Debug)]
+#[error("unsupported mmseqs database type: {0}")]
+pub struct UnsupportedDbTypeError(u32);
+
+#[derive(Error, Debug)]
+#[error(
+    "the target database is a profile database, but mmseqs only takes profiles on the \
+     query side; rebuild it from the target fasta with mmoreseqs prep"
+)]
+pub struct ProfileTargetDbError;
+
+/// The types of mmseqs databases that can be used as the query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmseqsDbType {
+    Sequence,
+    Profile,
+}
+
+/// The extended dbtype flag that marks a compressed database
+const DBTYPE_EXTENDED_COMPRESSED: u32 = 1 << 16;
+
+/// Read the raw contents of an mmseqs database's .dbtype file.
+fn read_raw_db_type(db: &Path) -> Result<u32> {
+    let mut dbtype_path = db.as_os_str().to_owned();
+    dbtype_path.push(".dbtype");
+
+    let mut buffer = [0u8; 4];
+    File::open(&dbtype_path)
+        .context("failed to open mmseqs dbtype file")?
+        .read_exact(&mut buffer)?;
+
+    Ok(u32::from_le_bytes(buffer))
+}
+
+/// Read the type of an mmseqs database from its .dbtype file.
+pub fn read_mmseqs_db_type(db: &Path) -> Result<MmseqsDbType> {
+    // the upper bytes hold extended flags (e.g. compression)
+    match read_raw_db_type(db)? & 0xFFFF {
+        0 => Ok(MmseqsDbType::Sequence),
+        2 => Ok(MmseqsDbType::Profile),
+        other => Err(UnsupportedDbTypeError(other).into()),
+    }
+}
+
+fn dbtype_exists(db: &Path) -> bool {
+    let mut dbtype_path = db.as_os_str().to_owned();
+    dbtype_path.push(".dbtype");
+    Path::new(&dbtype_path).exists()
 }
 
+/// Check that the seed step can search the query database against the target
+/// database, and return the query database type that the prefilter is set up for.
+///
+/// A query database built from an MSA holds profiles, and one built from an HMM
+/// holds consensus sequences; the target must always hold sequences.
+pub fn check_seed_db_types(args: &Args) -> Result<MmseqsDbType> {
+    if dbtype_exists(&args.paths.target_db) {
+        let target_db_type =
+            read_mmseqs_db_type(&args.paths.target_db).context("unusable target database")?;
+        if target_db_type == MmseqsDbType::Profile {
+            return Err(ProfileTargetDbError.into());
+        }
+    }
+
+    // a dry run may not have the databases that prep would build, which hold profiles for an MSA
+    if args.dry_run && !dbtype_exists(&args.paths.query_db) {
+        return Ok(MmseqsDbType::Profile);
+    }
+    read_mmseqs_db_type(&args.paths.query_db).context("unusable query database")
+}
+
+/// Get the consensus sequence of each query profile, keyed by accession.
 pub fn extract_mmseqs_profile_consensus_sequences(
     args: &Args,
 ) -> Result<HashMap<String, Sequence>> {
+    // a sequence database already holds the consensus sequences
+    if read_mmseqs_db_type(&args.paths.query_db)? == MmseqsDbType::Sequence {
+        return parse_query_db_consensus_sequences(args);
+    }
+
+    match args.consensus_source {
+        ConsensusSource::Parse => parse_query_db_consensus_sequences(args),
+        ConsensusSource::Mmseqs => mmseqs_profile_consensus_sequences(args),
+        ConsensusSource::Auto => match parse_query_db_consensus_sequences(args) {
+            Err(err)
+                if err.is::<UnsupportedProfileLayoutError>()
+                    || err.is::<CompressedProfileDbError>() =>
+            {
+                eprintln!("{err}; getting the consensus sequences from mmseqs instead");
+                mmseqs_profile_consensus_sequences(args)
+            }
+            result => result,
+        },
+    }
+}
+
+/// Have mmseqs compute the profile consensus sequences and write them as fasta, then read them.
+fn mmseqs_profile_consensus_sequences(args: &Args) -> Result<HashMap<String, Sequence>> {
+    args.runner.run(
+        args.tools
+            .mmseqs()
+            .arg("profile2consensus")
+            .arg(&args.paths.query_db)
+            .arg(&args.paths.query_profile_consensus_db),
+    )?;
+    args.runner.run(
+        args.tools
+            .mmseqs()
+            .arg("convert2fasta")
+            .arg(&args.paths.query_profile_consensus_db)
+            .arg(&args.paths.query_profile_consensus_fasta),
+    )?;
+
+    let fasta = File::open(&args.paths.query_profile_consensus_fasta)
+        .context("failed to open the mmseqs consensus fasta")?;
+
+    let mut sequence_map: HashMap<String, Sequence> = HashMap::new();
+    let mut current: Option<(String, Vec<u8>)> = None;
+    let mut finish = |record: Option<(String, Vec<u8>)>| -> Result<()> {
+        if let Some((accession, residues)) = record {
+            sequence_map.insert(accession, Sequence::from_utf8(&residues)?);
+        }
+        Ok(())
+    };
+
+    for line in BufReader::new(fasta).lines() {
+        let line = line?;
+        match line.strip_prefix('>') {
+            // the headers are copied from the query database, and start with the accession
+            Some(header) => {
+                finish(current.take())?;
+                let accession = header.split_whitespace().next().unwrap_or_default();
+                current = Some((accession.to_string(), vec![]));
+            }
+            None => {
+                if let Some((_, residues)) = current.as_mut() {
+                    residues.extend(line.trim().bytes());
+                }
+            }
+        }
+    }
+    finish(current.take())?;
+
+    Ok(sequence_map)
+}
+
+/// Read the consensus sequences straight out of the query database.
+fn parse_query_db_consensus_sequences(args: &Args) -> Result<HashMap<String, Sequence>> {
     let mut offsets_and_lengths: Vec<(usize, usize)> = vec![];
     let mut accession_numbers: Vec<String> = vec![];
 
@@ -189,25 +530,43 @@ pub fn extract_mmseqs_profile_consensus_sequences(
 
     let mut sequence_map: HashMap<String, Sequence> = HashMap::new();
 
+    let query_db_type = read_mmseqs_db_type(&args.paths.query_db)?;
+
     let mut query_db_file = File::open(&args.paths.query_db).context("failed to open queryDB")?;
 
-    for (seq_idx, (offset, length)) in offsets_and_lengths.iter().enumerate() {
+    let mut entries: Vec<Vec<u8>> = vec![];
+    for (offset, length) in &offsets_and_lengths {
         let mut buffer = vec![0; *length];
         query_db_file.seek(SeekFrom::Start(*offset as u64))?;
         query_db_file.read_exact(&mut buffer)?;
+        entries.push(buffer);
+    }
 
-        let mut consensus_digital_bytes: Vec<u8> = vec![];
-
-        for byte_chunk in buffer.chunks(23) {
-            if byte_chunk.len() == 23 {
-                consensus_digital_bytes.push(byte_chunk[21]);
+    let profile_layout = match query_db_type {
+        MmseqsDbType::Profile => {
+            if read_raw_db_type(&args.paths.query_db)? & DBTYPE_EXTENDED_COMPRESSED != 0 {
+                return Err(CompressedProfileDbError.into());
             }
+            Some(ProfileLayout::detect(&entries, &args.tools)?)
         }
+        MmseqsDbType::Sequence => None,
+    };
+
+    for (seq_idx, buffer) in entries.into_iter().enumerate() {
+        let consensus = match profile_layout {
+            Some(layout) => layout.consensus(&buffer)?,
+            None => {
+                // sequence database entries are terminated by a newline and a null byte
+                let sequence_bytes: Vec<u8> = buffer
+                    .into_iter()
+                    .take_while(|b| *b != b'\n' && *b != 0)
+                    .collect();
+
+                Sequence::from_utf8(&sequence_bytes)?
+            }
+        };
 
-        sequence_map.insert(
-            accession_numbers[seq_idx].clone(),
-            Sequence::from_digital(&consensus_digital_bytes)?,
-        );
+        sequence_map.insert(accession_numbers[seq_idx].clone(), consensus);
     }
 
     Ok(sequence_map)
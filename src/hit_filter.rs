@@ -0,0 +1,653 @@
+use std::fmt;
+use std::str::FromStr;
+
+use nale::structs::Alignment;
+use thiserror::Error;
+
+use crate::alignment_output::AlignedHit;
+
+#[derive(Error, Debug)]
+#[error("invalid filter at column {column}: {message}")]
+pub struct FilterSyntaxError {
+    column: usize,
+    message: String,
+}
+
+/// A field of a hit that a filter can refer to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Evalue,
+    Bits,
+    Bias,
+    Length,
+    TargetStart,
+    TargetEnd,
+    ProfileStart,
+    ProfileEnd,
+    EnvStart,
+    EnvEnd,
+    TargetCov,
+    ProfileCov,
+    Identity,
+    Similarity,
+    GapFraction,
+    Seeds,
+    SeedEvalue,
+    SeedBits,
+}
+
+/// The fields by name
+const FIELDS: [(&str, Field); 18] = [
+    ("evalue", Field::Evalue),
+    ("bits", Field::Bits),
+    ("bias", Field::Bias),
+    ("length", Field::Length),
+    ("target_start", Field::TargetStart),
+    ("target_end", Field::TargetEnd),
+    ("profile_start", Field::ProfileStart),
+    ("profile_end", Field::ProfileEnd),
+    ("env_start", Field::EnvStart),
+    ("env_end", Field::EnvEnd),
+    ("target_cov", Field::TargetCov),
+    ("profile_cov", Field::ProfileCov),
+    ("identity", Field::Identity),
+    ("similarity", Field::Similarity),
+    ("gap_fraction", Field::GapFraction),
+    ("seeds", Field::Seeds),
+    ("seed_evalue", Field::SeedEvalue),
+    ("seed_bits", Field::SeedBits),
+];
+
+impl Field {
+    /// The field's value for a hit; the seed fields of a hit without a seed are NaN
+    fn value(&self, alignment: &Alignment, hit: &AlignedHit) -> f64 {
+        match self {
+            Field::Evalue => alignment.evalue as f64,
+            Field::Bits => alignment.bit_score as f64,
+            Field::Bias => hit.bias as f64,
+            Field::Length => alignment.length as f64,
+            Field::TargetStart => alignment.target_start as f64,
+            Field::TargetEnd => alignment.target_end as f64,
+            Field::ProfileStart => alignment.profile_start as f64,
+            Field::ProfileEnd => alignment.profile_end as f64,
+            Field::EnvStart => hit.envelope.start as f64,
+            Field::EnvEnd => hit.envelope.end as f64,
+            Field::TargetCov => hit.target_coverage as f64,
+            Field::ProfileCov => hit.profile_coverage as f64,
+            Field::Identity => hit.identity as f64,
+            Field::Similarity => hit.similarity as f64,
+            Field::GapFraction => hit.gap_fraction as f64,
+            Field::Seeds => hit.supporting_seeds as f64,
+            Field::SeedEvalue => hit.seed.as_ref().map_or(f64::NAN, |s| s.evalue as f64),
+            Field::SeedBits => hit
+                .seed
+                .as_ref()
+                .and_then(|s| s.bit_score)
+                .map_or(f64::NAN, |b| b as f64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Number(f64),
+    Field(Field),
+    Or,
+    And,
+    Not,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Open,
+    Close,
+}
+
+/// Split a filter into tokens, each with its 1-based column.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, FilterSyntaxError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<(Token, usize)> = vec![];
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        let column = idx + 1;
+        let c = chars[idx];
+        let next = chars.get(idx + 1).copied();
+
+        if c.is_whitespace() {
+            idx += 1;
+            continue;
+        }
+
+        if c.is_ascii_digit() || (c == '.' && next.is_some_and(|n| n.is_ascii_digit())) {
+            let start = idx;
+            while idx < chars.len() && (chars[idx].is_ascii_digit() || chars[idx] == '.') {
+                idx += 1;
+            }
+            // an exponent, e.g. 1e-5
+            if idx < chars.len() && matches!(chars[idx], 'e' | 'E') {
+                let mut end = idx + 1;
+                if end < chars.len() && matches!(chars[end], '+' | '-') {
+                    end += 1;
+                }
+                if end < chars.len() && chars[end].is_ascii_digit() {
+                    idx = end;
+                    while idx < chars.len() && chars[idx].is_ascii_digit() {
+                        idx += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..idx].iter().collect();
+            let number = text.parse().map_err(|_| FilterSyntaxError {
+                column,
+                message: format!("invalid number {text}"),
+            })?;
+            tokens.push((Token::Number(number), column));
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            let start = idx;
+            while idx < chars.len() && (chars[idx].is_ascii_alphanumeric() || chars[idx] == '_') {
+                idx += 1;
+            }
+            let name: String = chars[start..idx].iter().collect();
+            let Some((_, field)) = FIELDS.iter().find(|(field_name, _)| *field_name == name) else {
+                return Err(FilterSyntaxError {
+                    column,
+                    message: format!(
+                        "unknown field {name} (the fields are {})",
+                        FIELDS.map(|(name, _)| name).join(", ")
+                    ),
+                });
+            };
+            tokens.push((Token::Field(*field), column));
+            continue;
+        }
+
+        let (token, length) = match (c, next) {
+            ('|', Some('|')) => (Token::Or, 2),
+            ('&', Some('&')) => (Token::And, 2),
+            ('<', Some('=')) => (Token::Le, 2),
+            ('>', Some('=')) => (Token::Ge, 2),
+            ('=', Some('=')) => (Token::Eq, 2),
+            ('!', Some('=')) => (Token::Ne, 2),
+            ('!', _) => (Token::Not, 1),
+            ('<', _) => (Token::Lt, 1),
+            ('>', _) => (Token::Gt, 1),
+            ('+', _) => (Token::Add, 1),
+            ('-', _) => (Token::Sub, 1),
+            ('*', _) => (Token::Mul, 1),
+            ('/', _) => (Token::Div, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            _ => {
+                return Err(FilterSyntaxError {
+                    column,
+                    message: format!("unexpected character '{c}'"),
+                })
+            }
+        };
+        tokens.push((token, column));
+        idx += length;
+    }
+
+    Ok(tokens)
+}
+
+/// An expression that evaluates to a number
+#[derive(Debug, Clone)]
+enum NumberExpr {
+    Number(f64),
+    Field(Field),
+    Neg(Box<NumberExpr>),
+    Arithmetic(Token, Box<NumberExpr>, Box<NumberExpr>),
+}
+
+impl NumberExpr {
+    fn eval(&self, alignment: &Alignment, hit: &AlignedHit) -> f64 {
+        match self {
+            NumberExpr::Number(number) => *number,
+            NumberExpr::Field(field) => field.value(alignment, hit),
+            NumberExpr::Neg(expr) => -expr.eval(alignment, hit),
+            NumberExpr::Arithmetic(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(alignment, hit), rhs.eval(alignment, hit));
+                match op {
+                    Token::Add => lhs + rhs,
+                    Token::Sub => lhs - rhs,
+                    Token::Mul => lhs * rhs,
+                    _ => lhs / rhs,
+                }
+            }
+        }
+    }
+}
+
+/// An expression that is true or false for a hit
+#[derive(Debug, Clone)]
+enum BoolExpr {
+    Compare(Token, NumberExpr, NumberExpr),
+    Not(Box<BoolExpr>),
+    And(Box<BoolExpr>, Box<BoolExpr>),
+    Or(Box<BoolExpr>, Box<BoolExpr>),
+}
+
+impl BoolExpr {
+    fn eval(&self, alignment: &Alignment, hit: &AlignedHit) -> bool {
+        match self {
+            BoolExpr::Compare(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(alignment, hit), rhs.eval(alignment, hit));
+                match op {
+                    Token::Lt => lhs < rhs,
+                    Token::Le => lhs <= rhs,
+                    Token::Gt => lhs > rhs,
+                    Token::Ge => lhs >= rhs,
+                    Token::Eq => lhs == rhs,
+                    _ => lhs != rhs,
+                }
+            }
+            BoolExpr::Not(expr) => !expr.eval(alignment, hit),
+            BoolExpr::And(lhs, rhs) => lhs.eval(alignment, hit) && rhs.eval(alignment, hit),
+            BoolExpr::Or(lhs, rhs) => lhs.eval(alignment, hit) || rhs.eval(alignment, hit),
+        }
+    }
+}
+
+/// A parenthesized expression may be either kind, until it is used
+enum Expr {
+    Number(NumberExpr),
+    Bool(BoolExpr),
+}
+
+/// A recursive descent parser over the tokens of a filter, from the loosest
+/// binding operator (||) to the tightest (unary minus)
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    /// The column just past the end of the filter, for errors at the end
+    end_column: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.position).map(|(token, _)| *token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.end_column, |(_, column)| *column)
+    }
+
+    fn error(&self, message: &str) -> FilterSyntaxError {
+        FilterSyntaxError {
+            column: self.column(),
+            message: message.to_string(),
+        }
+    }
+
+    fn expect_bool(&self, expr: Expr, column: usize) -> Result<BoolExpr, FilterSyntaxError> {
+        match expr {
+            Expr::Bool(expr) => Ok(expr),
+            Expr::Number(_) => Err(FilterSyntaxError {
+                column,
+                message: "expected a comparison, e.g. evalue < 1e-5".to_string(),
+            }),
+        }
+    }
+
+    fn expect_number(&self, expr: Expr, column: usize) -> Result<NumberExpr, FilterSyntaxError> {
+        match expr {
+            Expr::Number(expr) => Ok(expr),
+            Expr::Bool(_) => Err(FilterSyntaxError {
+                column,
+                message: "expected a number, not a comparison".to_string(),
+            }),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, FilterSyntaxError> {
+        let column = self.column();
+        let mut lhs = self.and()?;
+        while self.peek() == Some(Token::Or) {
+            let lhs_bool = self.expect_bool(lhs, column)?;
+            self.position += 1;
+            let rhs_column = self.column();
+            let rhs = self.and()?;
+            let rhs_bool = self.expect_bool(rhs, rhs_column)?;
+            lhs = Expr::Bool(BoolExpr::Or(Box::new(lhs_bool), Box::new(rhs_bool)));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, FilterSyntaxError> {
+        let column = self.column();
+        let mut lhs = self.not()?;
+        while self.peek() == Some(Token::And) {
+            let lhs_bool = self.expect_bool(lhs, column)?;
+            self.position += 1;
+            let rhs_column = self.column();
+            let rhs = self.not()?;
+            let rhs_bool = self.expect_bool(rhs, rhs_column)?;
+            lhs = Expr::Bool(BoolExpr::And(Box::new(lhs_bool), Box::new(rhs_bool)));
+        }
+        Ok(lhs)
+    }
+
+    fn not(&mut self) -> Result<Expr, FilterSyntaxError> {
+        if self.peek() != Some(Token::Not) {
+            return self.comparison();
+        }
+        self.position += 1;
+        let column = self.column();
+        let expr = self.not()?;
+        let expr = self.expect_bool(expr, column)?;
+        Ok(Expr::Bool(BoolExpr::Not(Box::new(expr))))
+    }
+
+    fn comparison(&mut self) -> Result<Expr, FilterSyntaxError> {
+        let column = self.column();
+        let lhs = self.additive()?;
+        let Some(op) = self.peek().filter(|token| {
+            [
+                Token::Lt,
+                Token::Le,
+                Token::Gt,
+                Token::Ge,
+                Token::Eq,
+                Token::Ne,
+            ]
+            .contains(token)
+        }) else {
+            return Ok(lhs);
+        };
+        let lhs = self.expect_number(lhs, column)?;
+        self.position += 1;
+        let rhs_column = self.column();
+        let rhs = self.additive()?;
+        let rhs = self.expect_number(rhs, rhs_column)?;
+        Ok(Expr::Bool(BoolExpr::Compare(op, lhs, rhs)))
+    }
+
+    fn additive(&mut self) -> Result<Expr, FilterSyntaxError> {
+        self.arithmetic(&[Token::Add, Token::Sub], Self::multiplicative)
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, FilterSyntaxError> {
+        self.arithmetic(&[Token::Mul, Token::Div], Self::unary)
+    }
+
+    /// A chain of left associative arithmetic operators of the same precedence
+    fn arithmetic(
+        &mut self,
+        ops: &[Token],
+        operand: fn(&mut Self) -> Result<Expr, FilterSyntaxError>,
+    ) -> Result<Expr, FilterSyntaxError> {
+        let column = self.column();
+        let mut lhs = operand(self)?;
+        while let Some(op) = self.peek().filter(|token| ops.contains(token)) {
+            let lhs_number = self.expect_number(lhs, column)?;
+            self.position += 1;
+            let rhs_column = self.column();
+            let rhs = operand(self)?;
+            let rhs_number = self.expect_number(rhs, rhs_column)?;
+            lhs = Expr::Number(NumberExpr::Arithmetic(
+                op,
+                Box::new(lhs_number),
+                Box::new(rhs_number),
+            ));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, FilterSyntaxError> {
+        if self.peek() != Some(Token::Sub) {
+            return self.primary();
+        }
+        self.position += 1;
+        let column = self.column();
+        let expr = self.unary()?;
+        let expr = self.expect_number(expr, column)?;
+        Ok(Expr::Number(NumberExpr::Neg(Box::new(expr))))
+    }
+
+    fn primary(&mut self) -> Result<Expr, FilterSyntaxError> {
+        let expr = match self.peek() {
+            Some(Token::Number(number)) => Expr::Number(NumberExpr::Number(number)),
+            Some(Token::Field(field)) => Expr::Number(NumberExpr::Field(field)),
+            Some(Token::Open) => {
+                self.position += 1;
+                let expr = self.or()?;
+                if self.peek() != Some(Token::Close) {
+                    return Err(self.error("expected ')'"));
+                }
+                expr
+            }
+            Some(_) => return Err(self.error("expected a field, a number, or '('")),
+            None => return Err(self.error("unexpected end of the filter")),
+        };
+        self.position += 1;
+        Ok(expr)
+    }
+}
+
+/// A condition on the fields of a hit, given with --filter, that the hits must
+/// meet to be reported
+#[derive(Debug, Clone)]
+pub struct HitFilter {
+    source: String,
+    expr: BoolExpr,
+}
+
+impl FromStr for HitFilter {
+    type Err = FilterSyntaxError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            end_column: source.chars().count() + 1,
+        };
+        let expr = parser.or()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("expected && or || between conditions"));
+        }
+        let expr = parser.expect_bool(expr, 1)?;
+
+        Ok(HitFilter {
+            source: source.to_string(),
+            expr,
+        })
+    }
+}
+
+impl fmt::Display for HitFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+pub fn parse_hit_filter(source: &str) -> Result<HitFilter, FilterSyntaxError> {
+    source.parse()
+}
+
+impl HitFilter {
+    /// Whether a hit meets the filter. The seed fields of a hit without a seed
+    /// are missing, and every comparison with them but != is false.
+    pub fn allows(&self, alignment: &Alignment, hit: &AlignedHit) -> bool {
+        self.expr.eval(alignment, hit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment_output::SeedProvenance;
+    use crate::envelope::Envelope;
+
+    /// A hit with the given E-value and bit score, and the seed it was aligned from
+    fn hit(evalue: f32, bit_score: f32, seed: Option<SeedProvenance>) -> (Alignment, AlignedHit) {
+        let alignment = Alignment {
+            profile_name: "prof".to_string(),
+            target_name: "tgt".to_string(),
+            bit_score,
+            evalue,
+            length: 40,
+            profile_start: 1,
+            profile_end: 40,
+            profile_string: String::new(),
+            target_start: 11,
+            target_end: 50,
+            target_string: String::new(),
+            middle_string: String::new(),
+            posterior_probability_string: String::new(),
+        };
+        let hit = AlignedHit {
+            id: "hit".to_string(),
+            profile_name: "prof".to_string(),
+            profile_accession: "prof".to_string(),
+            target_name: "tgt".to_string(),
+            target_start: 11,
+            target_end: 50,
+            envelope: Envelope { start: 9, end: 52 },
+            reference: String::new(),
+            target: String::new(),
+            posterior: String::new(),
+            identity: 0.5,
+            similarity: 0.75,
+            gap_fraction: 0.0,
+            bias: 0.0,
+            profile_coverage: 1.0,
+            target_coverage: 0.5,
+            seed,
+            supporting_seeds: 1,
+            domain_stats: None,
+            included: None,
+            alternatives: vec![],
+            bound_stats: None,
+            trace: None,
+        };
+        (alignment, hit)
+    }
+
+    fn seed(bit_score: Option<f32>) -> SeedProvenance {
+        SeedProvenance {
+            evalue: 1e-3,
+            bit_score,
+            profile_start: 1,
+            profile_end: 40,
+            target_start: 11,
+            target_end: 50,
+        }
+    }
+
+    fn allows(filter: &str, (alignment, hit): &(Alignment, AlignedHit)) -> bool {
+        parse_hit_filter(filter).unwrap().allows(alignment, hit)
+    }
+
+    fn error(filter: &str) -> String {
+        parse_hit_filter(filter).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn operators_bind_by_precedence() {
+        let hit = hit(1e-10, 50.0, None);
+
+        // && binds tighter than ||, so this isn't (evalue < 1e-5 || bits > 100) && bits < 10
+        assert!(allows("evalue < 1e-5 || bits > 100 && bits < 10", &hit));
+        assert!(!allows("(evalue < 1e-5 || bits > 100) && bits < 10", &hit));
+        // ! applies to the comparison, not to the field
+        assert!(allows("!bits > 100", &hit));
+        assert!(allows("!!(bits > 1)", &hit));
+
+        assert!(allows("bits + 10 * 2 == 70", &hit));
+        assert!(allows("(bits + 10) * 2 == 120", &hit));
+        // arithmetic is left associative
+        assert!(allows("bits - 10 - 20 == 20", &hit));
+        assert!(allows("bits / 5 / 2 == 5", &hit));
+    }
+
+    #[test]
+    fn unary_minus_binds_tightest() {
+        let hit = hit(1e-10, 50.0, None);
+
+        assert!(allows("-bits == -50", &hit));
+        assert!(allows("- -bits == 50", &hit));
+        assert!(allows("-2 * 3 == -6", &hit));
+        assert!(allows("10 - -5 == 15", &hit));
+        assert!(allows("bits * -1 < 0", &hit));
+        assert!(allows("-1e-5 < evalue", &hit));
+    }
+
+    #[test]
+    fn missing_seed_fields_only_pass_not_equal() {
+        for hit in [hit(1e-10, 50.0, None), hit(1e-10, 50.0, Some(seed(None)))] {
+            assert!(!allows("seed_bits < 1", &hit));
+            assert!(!allows("seed_bits >= 1", &hit));
+            assert!(!allows("seed_bits == seed_bits", &hit));
+            assert!(allows("seed_bits != 1", &hit));
+            assert!(allows("!(seed_bits > 1)", &hit));
+        }
+
+        let without_seed = hit(1e-10, 50.0, None);
+        assert!(!allows("seed_evalue < 1", &without_seed));
+        assert!(!allows("seed_evalue >= 1", &without_seed));
+
+        let with_seed = hit(1e-10, 50.0, Some(seed(Some(30.0))));
+        assert!(allows("seed_evalue < 1", &with_seed));
+        assert!(allows("seed_bits == 30", &with_seed));
+    }
+
+    #[test]
+    fn errors_point_at_their_column() {
+        assert_eq!(
+            error("bogus > 1"),
+            "invalid filter at column 1: unknown field bogus (the fields are evalue, bits, \
+             bias, length, target_start, target_end, profile_start, profile_end, env_start, \
+             env_end, target_cov, profile_cov, identity, similarity, gap_fraction, seeds, \
+             seed_evalue, seed_bits)"
+        );
+        assert_eq!(
+            error("bits > 10 # x"),
+            "invalid filter at column 11: unexpected character '#'"
+        );
+        assert_eq!(
+            error("evalue << 1"),
+            "invalid filter at column 9: expected a field, a number, or '('"
+        );
+        assert_eq!(
+            error("evalue < 1e-5 &&"),
+            "invalid filter at column 17: unexpected end of the filter"
+        );
+        assert_eq!(
+            error("(bits > 1"),
+            "invalid filter at column 10: expected ')'"
+        );
+        assert_eq!(
+            error("bits > 10 evalue < 1"),
+            "invalid filter at column 11: expected && or || between conditions"
+        );
+        assert_eq!(
+            error("evalue"),
+            "invalid filter at column 1: expected a comparison, e.g. evalue < 1e-5"
+        );
+        assert_eq!(
+            error("bits > 10 && 3"),
+            "invalid filter at column 14: expected a comparison, e.g. evalue < 1e-5"
+        );
+        assert_eq!(
+            error("(bits > 1) + 1 > 2"),
+            "invalid filter at column 1: expected a number, not a comparison"
+        );
+        assert_eq!(
+            error("-(bits > 1) < 0"),
+            "invalid filter at column 2: expected a number, not a comparison"
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use nale::alphabet::UTF8_TO_DIGITAL_AMINO;
+use nale::structs::Sequence;
+
+use crate::tabular::{
+    Table, TARGET_END_COLUMN_IDX, TARGET_NAME_COLUMN_IDX, TARGET_START_COLUMN_IDX,
+};
+
+/// The residue that masked positions are replaced with
+const MASK_RESIDUE: u8 = b'X';
+
+/// The target regions covered by the hits of a previous run, which are masked
+/// so that a new search can find the weaker hits elsewhere on the targets
+pub struct HitMask {
+    /// The 1-based, inclusive target ranges of the hits, by target name
+    regions: HashMap<String, Vec<(usize, usize)>>,
+}
+
+impl HitMask {
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = File::open(path).context(format!(
+            "failed to open previous results: {}",
+            path.to_string_lossy()
+        ))?;
+        let table = Table::read(BufReader::new(file)).context(format!(
+            "failed to parse previous results: {}",
+            path.to_string_lossy()
+        ))?;
+
+        let mut regions: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+        for (row_idx, row) in table.rows.iter().enumerate() {
+            let coordinate = |column_idx: usize| -> Result<usize> {
+                row[column_idx].parse().context(format!(
+                    "invalid target coordinate '{}' in row {} of previous results: {}",
+                    row[column_idx],
+                    row_idx + 1,
+                    path.to_string_lossy()
+                ))
+            };
+            let start = coordinate(TARGET_START_COLUMN_IDX)?;
+            let end = coordinate(TARGET_END_COLUMN_IDX)?;
+            regions
+                .entry(row[TARGET_NAME_COLUMN_IDX].clone())
+                .or_default()
+                .push((start.min(end), start.max(end)));
+        }
+
+        Ok(HitMask { regions })
+    }
+
+    /// The number of hit regions to mask
+    pub fn region_count(&self) -> usize {
+        self.regions.values().map(Vec::len).sum()
+    }
+
+    /// Whether a 1-based position of a target is covered by a previous hit
+    fn covers(&self, target_name: &str, position: usize) -> bool {
+        self.regions.get(target_name).is_some_and(|regions| {
+            regions
+                .iter()
+                .any(|&(start, end)| (start..=end).contains(&position))
+        })
+    }
+
+    /// Copy a fasta file with the residues of the previous hits replaced by X,
+    /// keeping its line layout, and return the number of residues masked.
+    pub fn mask_fasta(&self, fasta: &Path, out: &mut impl Write) -> Result<usize> {
+        let fasta_file = File::open(fasta).context(format!(
+            "failed to open target fasta: {}",
+            fasta.to_string_lossy()
+        ))?;
+
+        let mut target_name = String::new();
+        let mut position: usize = 0;
+        let mut masked_count: usize = 0;
+        for line in BufReader::new(fasta_file).lines() {
+            let line = line?;
+            if let Some(header) = line.strip_prefix('>') {
+                target_name = header
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                position = 0;
+                writeln!(out, "{line}")?;
+                continue;
+            }
+            if !self.regions.contains_key(&target_name) {
+                writeln!(out, "{line}")?;
+                continue;
+            }
+
+            let mut masked_line = line.into_bytes();
+            for byte in masked_line.iter_mut() {
+                if byte.is_ascii_whitespace() {
+                    continue;
+                }
+                position += 1;
+                if self.covers(&target_name, position) {
+                    if !byte.eq_ignore_ascii_case(&MASK_RESIDUE) {
+                        masked_count += 1;
+                    }
+                    *byte = MASK_RESIDUE;
+                }
+            }
+            out.write_all(&masked_line)?;
+            writeln!(out)?;
+        }
+
+        Ok(masked_count)
+    }
+
+    /// Mask the residues of the previous hits in the loaded targets, and
+    /// return the number of residues masked.
+    pub fn mask_sequences(&self, sequences: &mut HashMap<String, Sequence>) -> usize {
+        let digital_mask = UTF8_TO_DIGITAL_AMINO[&MASK_RESIDUE];
+
+        let mut masked_count: usize = 0;
+        for (target_name, regions) in &self.regions {
+            let Some(sequence) = sequences.get_mut(target_name) else {
+                continue;
+            };
+            for &(start, end) in regions {
+                // the sequences are 1-based, with a padding byte at 0
+                for position in start.max(1)..=end.min(sequence.length) {
+                    if !sequence.utf8_bytes[position].eq_ignore_ascii_case(&MASK_RESIDUE) {
+                        masked_count += 1;
+                    }
+                    sequence.utf8_bytes[position] = MASK_RESIDUE;
+                    sequence.digital_bytes[position] = digital_mask;
+                }
+            }
+        }
+        masked_count
+    }
+}
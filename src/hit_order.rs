@@ -0,0 +1,99 @@
+use std::cmp::Ordering;
+
+use clap::ValueEnum;
+use nale::structs::Alignment;
+
+use crate::alignment_output::AlignedHit;
+use crate::tabular::{
+    Table, BIT_SCORE_COLUMN_IDX, EVALUE_COLUMN_IDX, PROFILE_NAME_COLUMN_IDX, TARGET_NAME_COLUMN_IDX,
+};
+
+/// The keys that the hits can be sorted by, with --sort-by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+    /// The E-value, smallest first
+    Evalue,
+    /// The bit score, largest first
+    Bits,
+    /// The target name
+    Target,
+    /// The query (profile) name
+    Query,
+    /// The target coordinates, then the profile coordinates
+    Coords,
+}
+
+/// The fields of a hit that it can be sorted on, from either an alignment or a results table row
+struct SortFields<'a> {
+    evalue: f32,
+    bit_score: f32,
+    target_name: &'a str,
+    profile_name: &'a str,
+    coords: [usize; 4],
+}
+
+impl<'a> SortFields<'a> {
+    fn from_alignment(alignment: &'a Alignment) -> Self {
+        SortFields {
+            evalue: alignment.evalue,
+            bit_score: alignment.bit_score,
+            target_name: &alignment.target_name,
+            profile_name: &alignment.profile_name,
+            coords: [
+                alignment.target_start,
+                alignment.target_end,
+                alignment.profile_start,
+                alignment.profile_end,
+            ],
+        }
+    }
+
+    /// The fields of a results table row, whose first columns are always the default columns.
+    fn from_row(row: &'a [String]) -> Self {
+        let number = |column_idx: usize| row[column_idx].parse::<usize>().unwrap_or_default();
+        SortFields {
+            evalue: row[EVALUE_COLUMN_IDX].parse().unwrap_or(f32::INFINITY),
+            bit_score: row[BIT_SCORE_COLUMN_IDX]
+                .parse()
+                .unwrap_or(f32::NEG_INFINITY),
+            target_name: &row[TARGET_NAME_COLUMN_IDX],
+            profile_name: &row[PROFILE_NAME_COLUMN_IDX],
+            // the target start and end, then the profile start and end
+            coords: [number(2), number(3), number(4), number(5)],
+        }
+    }
+
+    fn compare(&self, other: &Self, keys: &[SortKey]) -> Ordering {
+        keys.iter()
+            .map(|key| match key {
+                SortKey::Evalue => self.evalue.total_cmp(&other.evalue),
+                SortKey::Bits => other.bit_score.total_cmp(&self.bit_score),
+                SortKey::Target => self.target_name.cmp(other.target_name),
+                SortKey::Query => self.profile_name.cmp(other.profile_name),
+                SortKey::Coords => self.coords.cmp(&other.coords),
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Sort the hits by each of the keys in turn. The sort is stable, so with no
+/// keys, or between hits that tie on all of them, the seeds file order stays.
+pub fn sort_hits(hits: &mut [(Alignment, AlignedHit)], keys: &[SortKey]) {
+    if keys.is_empty() {
+        return;
+    }
+    hits.sort_by(|(a, _), (b, _)| {
+        SortFields::from_alignment(a).compare(&SortFields::from_alignment(b), keys)
+    });
+}
+
+/// Sort the rows of a results table by each of the keys in turn, as sort_hits does.
+pub fn sort_rows(table: &mut Table, keys: &[SortKey]) {
+    if keys.is_empty() {
+        return;
+    }
+    table
+        .rows
+        .sort_by(|a, b| SortFields::from_row(a).compare(&SortFields::from_row(b), keys));
+}
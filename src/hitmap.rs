@@ -0,0 +1,88 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+
+use anyhow::Result;
+use nale::structs::{Alignment, Sequence};
+
+/// A run of target residues that are covered by the same set of profiles
+struct CoverageRun<'a> {
+    length: usize,
+    profiles: BTreeSet<&'a str>,
+}
+
+/// Run-length encode the coverage of a target by its hits.
+///
+/// Each run is written as LENGTH:PROFILES, where PROFILES is the '+' separated
+/// names of the profiles with a hit covering the run, or '-' if there are none.
+fn encode_coverage(runs: &[CoverageRun]) -> String {
+    runs.iter()
+        .map(|run| {
+            let profiles = match run.profiles.is_empty() {
+                true => "-".to_string(),
+                false => run.profiles.iter().copied().collect::<Vec<_>>().join("+"),
+            };
+            format!("{}:{profiles}", run.length)
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Write a coverage mask for each target that has at least one hit.
+///
+/// Each line holds the target name, its length, the number of
+/// residues covered by any hit, and the run-length encoded mask.
+pub fn write_hitmap(
+    alignments: &[Alignment],
+    target_map: &HashMap<String, Sequence>,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut alignments_by_target: BTreeMap<&str, Vec<&Alignment>> = BTreeMap::new();
+    for alignment in alignments {
+        alignments_by_target
+            .entry(&alignment.target_name)
+            .or_default()
+            .push(alignment);
+    }
+
+    writeln!(out, "target\tlength\tcovered\tmask")?;
+
+    for (target_name, target_alignments) in alignments_by_target {
+        let Some(target) = target_map.get(target_name) else {
+            continue;
+        };
+
+        // the set of covering profiles for each residue, 1-indexed
+        let mut coverage: Vec<BTreeSet<&str>> = vec![BTreeSet::new(); target.length + 1];
+        for alignment in target_alignments {
+            for profiles in &mut coverage[alignment.target_start..=alignment.target_end] {
+                profiles.insert(&alignment.profile_name);
+            }
+        }
+
+        let mut runs: Vec<CoverageRun> = vec![];
+        for profiles in coverage.into_iter().skip(1) {
+            match runs.last_mut() {
+                Some(run) if run.profiles == profiles => run.length += 1,
+                _ => runs.push(CoverageRun {
+                    length: 1,
+                    profiles,
+                }),
+            }
+        }
+
+        let covered: usize = runs
+            .iter()
+            .filter(|run| !run.profiles.is_empty())
+            .map(|run| run.length)
+            .sum();
+
+        writeln!(
+            out,
+            "{target_name}\t{}\t{covered}\t{}",
+            target.length,
+            encode_coverage(&runs)
+        )?;
+    }
+
+    Ok(())
+}
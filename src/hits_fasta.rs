@@ -0,0 +1,119 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+use anyhow::Result;
+use mmoreseqs::seq_io::{write_fasta, SeqRecord, WriteOptions};
+use nale::structs::{Alignment, Sequence};
+
+use crate::alignment_output::AlignedHit;
+
+/// How much of the target is written for each hit
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HitContext {
+    /// The number of target residues to add on each side of a hit
+    pub flank: usize,
+    /// Write each target with hits whole, instead of a region per hit
+    pub full_target: bool,
+}
+
+/// The residues of a target from start to end (1-based, inclusive), with the
+/// residues outside of the core in lower case, so that the hit stands out.
+fn target_region(
+    target: &Sequence,
+    start: usize,
+    end: usize,
+    core_start: usize,
+    core_end: usize,
+) -> String {
+    (start..=end)
+        .map(|target_idx| {
+            let residue = target.utf8_bytes[target_idx] as char;
+            match (core_start..=core_end).contains(&target_idx) {
+                true => residue.to_ascii_uppercase(),
+                false => residue.to_ascii_lowercase(),
+            }
+        })
+        .collect()
+}
+
+/// The description of a hit on a FASTA header line
+fn hit_description(alignment: &Alignment, hit: &AlignedHit) -> String {
+    format!(
+        "{}:{}-{} hit-id={}",
+        alignment.profile_name, alignment.target_start, alignment.target_end, hit.id
+    )
+}
+
+/// Write the target sequence of each hit in FASTA format.
+///
+/// Each hit's record is named after its region of the target, e.g. "seq1/10-90",
+/// and its description gives the profile, the hit's own coordinates, and its
+/// identifier. With
+/// the full targets, each target with hits is written once, and its
+/// description lists all of them.
+pub fn write_hits_fasta(
+    alignments: &[Alignment],
+    aligned_hits: &[AlignedHit],
+    target_map: &HashMap<String, Sequence>,
+    context: HitContext,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut records: Vec<SeqRecord> = vec![];
+
+    match context.full_target {
+        true => {
+            let mut alignments_by_target: BTreeMap<&str, Vec<(&Alignment, &AlignedHit)>> =
+                BTreeMap::new();
+            for (alignment, hit) in alignments.iter().zip(aligned_hits) {
+                alignments_by_target
+                    .entry(&alignment.target_name)
+                    .or_default()
+                    .push((alignment, hit));
+            }
+
+            for (target_name, target_alignments) in alignments_by_target {
+                let Some(target) = target_map.get(target_name) else {
+                    continue;
+                };
+                records.push(SeqRecord {
+                    name: target_name.to_string(),
+                    description: Some(
+                        target_alignments
+                            .iter()
+                            .map(|(alignment, hit)| hit_description(alignment, hit))
+                            .collect::<Vec<String>>()
+                            .join(" "),
+                    ),
+                    sequence: target_region(target, 1, target.length, 1, target.length),
+                });
+            }
+        }
+        false => {
+            for (alignment, hit) in alignments.iter().zip(aligned_hits) {
+                let Some(target) = target_map.get(&alignment.target_name) else {
+                    continue;
+                };
+                let start = alignment.target_start.saturating_sub(context.flank).max(1);
+                let end = (alignment.target_end + context.flank).min(target.length);
+                records.push(SeqRecord {
+                    name: format!("{}/{start}-{end}", alignment.target_name),
+                    description: Some(hit_description(alignment, hit)),
+                    sequence: target_region(
+                        target,
+                        start,
+                        end,
+                        alignment.target_start,
+                        alignment.target_end,
+                    ),
+                });
+            }
+        }
+    }
+
+    // the target names are written as they are, so that they match the results
+    let options = WriteOptions {
+        sanitize_names: false,
+        ..WriteOptions::default()
+    };
+    write_fasta(&records, &options, out)
+}
@@ -0,0 +1,8 @@
+//! The mmoreseqs library.
+//!
+//! The search itself is run by the mmoreseqs binary. The library exposes the
+//! sequence and alignment writers that the pipeline uses for its intermediate
+//! artifacts (query consensus sequences, hit MSAs), so that downstream tools
+//! can write the same files.
+
+pub mod seq_io;
@@ -0,0 +1,72 @@
+use std::io::Write;
+
+use anyhow::Result;
+use nale::alphabet::{AMINO_ALPHABET, AMINO_BACKGROUND_FREQUENCIES};
+use nale::output::path_buf_ext::PathBufExt;
+use nale::structs::hmm::Hmm;
+
+use crate::pipeline::QuerySet;
+use crate::Args;
+
+/// The information content of a match state, in bits above the background,
+/// which is the height of its column in a logo
+fn information_content(probabilities: &[f32]) -> f32 {
+    probabilities
+        .iter()
+        .zip(AMINO_BACKGROUND_FREQUENCIES)
+        .filter(|(&probability, _)| probability > 0.0)
+        .map(|(&probability, background)| probability * (probability / background).log2())
+        .sum()
+}
+
+/// The most probable residue of a match state
+fn consensus_residue(probabilities: &[f32]) -> &'static str {
+    probabilities
+        .iter()
+        .zip(AMINO_ALPHABET)
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map_or("-", |(_, residue)| residue)
+}
+
+/// Write the match state emission probabilities and information content of each
+/// position of each model as a tidy TSV, one position per row, so that the models
+/// can be drawn as logos. The height of a residue's letter is its probability
+/// times the information content of its position.
+fn write_logo_data(hmms: &[Hmm], out: &mut impl Write) -> Result<()> {
+    writeln!(
+        out,
+        "profile\tposition\tconsensus\tinformation\t{}",
+        AMINO_ALPHABET.join("\t")
+    )?;
+
+    for hmm in hmms {
+        // the first row of the match probabilities is the begin state
+        for (position, probabilities) in hmm.model.match_probabilities.iter().enumerate().skip(1) {
+            let probabilities = &probabilities[..AMINO_ALPHABET.len()];
+            let emissions: Vec<String> = probabilities.iter().map(|p| format!("{p:.4}")).collect();
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{:.4}\t{}",
+                hmm.header.name,
+                position,
+                consensus_residue(probabilities),
+                information_content(probabilities),
+                emissions.join("\t")
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the logo data of the query HMMs.
+pub fn view_hmm(args: &Args) -> Result<()> {
+    let queries = QuerySet::load(args)?;
+    write_logo_data(queries.hmms(), &mut args.paths.logo_data.open(true)?)?;
+    eprintln!(
+        "wrote the logo data of {} query models to {}",
+        queries.hmms().len(),
+        args.paths.logo_data.display()
+    );
+    Ok(())
+}
@@ -1,22 +1,142 @@
+mod alignment_output;
+mod alphabet;
+mod alt_alignments;
+mod anchor;
+mod banded_nw;
+mod batch;
+mod bench;
+mod bound_stats;
 mod command_ext;
+mod comp_bias;
+mod container;
+mod cpu_topology;
+mod determinism;
+mod doctor;
+mod domain_stats;
+mod dp_matrices;
+mod envelope;
+mod existing_results;
 mod external_steps;
+mod hit_filter;
+mod hit_mask;
+mod hit_order;
+mod hitmap;
+mod hits_fasta;
+mod logo;
+mod manifest;
+mod memory_budget;
+mod mmseqs_profile;
+mod model_overrides;
+mod msv_filter;
+mod network;
+mod notify;
+mod null2;
+mod number_format;
+mod object_store;
+mod orfs;
 mod pipeline;
+mod profile_config;
+mod profile_keys;
+mod progress;
+mod provenance;
+mod query_filter;
+mod query_format;
+mod rbh;
+mod remote_target;
+mod run_params;
+mod run_summary;
+mod runner;
+mod schedule;
+mod seed_chain;
+mod seed_expansion;
+mod seed_files;
+mod seed_plot;
+mod seed_schema;
+mod seed_stats;
+mod seeds_header;
+mod sensitivity;
+mod server;
+mod shard;
+mod shutdown;
+mod skipped;
+mod strict;
+mod summary;
+mod tabular;
+mod target_sources;
+mod target_subset;
+mod taxonomy;
+#[cfg(test)]
+mod test_files;
+mod thresholds;
+mod time_limit;
+mod tools;
+mod trace_output;
+mod version_info;
+mod workspace;
 
+use crate::alignment_output::AliFormat;
+use crate::alphabet::AmbiguityPolicy;
+use crate::anchor::{AnchorRegion, CoordRange};
+use crate::bench::{bench_dense, bench_parallel, DenseBenchParams, ParallelBenchParams};
+use crate::container::{Container, ContainerRunner};
+use crate::doctor::doctor;
 use crate::external_steps::{check_hmmer_installed, check_mmseqs_installed};
-use crate::pipeline::{align, prep, search, seed};
+use crate::hit_filter::{parse_hit_filter, HitFilter};
+use crate::hit_order::SortKey;
+use crate::hits_fasta::HitContext;
+use crate::logo::view_hmm;
+use crate::memory_budget::{parse_gigabytes, MemoryBudget};
+use crate::mmseqs_profile::ConsensusSource;
+use crate::model_overrides::{parse_fraction, parse_probability, ModelOverrides};
+use crate::notify::{notify_completion, WebhookUrl};
+use crate::number_format::{EvalueFormat, NumberFormat};
+use crate::object_store::{parse_output_location, ObjectUploads};
+use crate::orfs::{parse_genetic_code, GeneticCode, OrfParams, TargetType};
+use crate::pipeline::{
+    align, align_region, merge, prep, run_stages, seed, view_seeds, SeedsFormat,
+    DEFAULT_DENSE_CELLS,
+};
+use crate::progress::{top, ProgressMonitor};
+use crate::query_filter::{parse_query_patterns, QueryFilter, QueryPatterns};
+use crate::query_format::QueryFormat;
+use crate::rbh::rbh;
+use crate::remote_target::{is_remote_target, parse_target_location, RemoteTarget};
+use crate::run_params::expand_config_args;
+use crate::runner::{ProcessRunner, RecordingRunner, Runner};
+use crate::schedule::Schedule;
+use crate::seed_expansion::{parse_seed_expansion, SeedExpansion};
+use crate::seed_stats::SeedFilter;
+use crate::sensitivity::Sensitivity;
+use crate::server::serve;
+use crate::shard::Shard;
+use crate::shutdown::CancelledError;
+use crate::tabular::OutputVersion;
+use crate::taxonomy::TaxonFilter;
+use crate::time_limit::{parse_duration, TimeLimitReachedError, TimeLimits, TIME_LIMIT_EXIT_CODE};
+use crate::tools::ToolPaths;
+use crate::trace_output::TraceFormat;
+use crate::version_info::print_version_json;
+use crate::workspace::{set_query_db, set_target_db, Stage, Workspace};
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
 use std::fs::create_dir_all;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 #[derive(Debug, Parser)]
 #[command(name = "mmoreseqs")]
 #[command(
     about = "Using MMseqs2 to find rough alignment seeds, perform bounded profile HMM sequence alignment"
 )]
+#[command(arg_required_else_help = true, args_conflicts_with_subcommands = true)]
 pub struct Cli {
+    /// Print the versions of mmoreseqs, its build, and the mmseqs and HMMER that it finds, as JSON
+    #[arg(long, exclusive = true)]
+    version_json: bool,
     #[command(subcommand)]
-    command: SubCommands,
+    command: Option<SubCommands>,
 }
 
 #[derive(Debug, Parser)]
@@ -25,37 +145,452 @@ struct CommonArgs {
     /// The number of threads to use
     #[arg(long, default_value_t = 1usize)]
     threads: usize,
+    /// A memory budget in gigabytes that mmseqs' memory limit and database loading, the
+    /// aligner's loading of the targets, and the number of align workers and the DP matrices
+    /// they keep between seeds, are fit to
+    #[arg(long, value_name = "GB", value_parser = parse_gigabytes)]
+    max_ram: Option<f64>,
+    /// Stop with an error on any problem with the inputs that would otherwise only be a warning
+    #[arg(long)]
+    strict: bool,
+    #[command(flatten)]
+    tools: ToolArgs,
+}
+
+#[derive(Debug, Parser)]
+struct ToolArgs {
+    /// The mmseqs binary to run, instead of the one in the PATH [env: MMORESEQS_MMSEQS]
+    #[arg(long, value_name = "PATH")]
+    mmseqs_path: Option<String>,
+    /// The hmmbuild binary, or the directory of HMMER binaries, to run instead of those in the PATH [env: MMORESEQS_HMMBUILD]
+    #[arg(long, value_name = "PATH")]
+    hmmer_path: Option<String>,
+    /// Run mmseqs and HMMER inside this docker image, with the directories they use mounted
+    #[arg(long, value_name = "IMAGE", conflicts_with = "use_singularity")]
+    use_docker: Option<String>,
+    /// Run mmseqs and HMMER inside this singularity image, with the directories they use mounted
+    #[arg(long, value_name = "IMG")]
+    use_singularity: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct TaxonomyArgs {
+    /// Directory containing nodes.dmp, names.dmp, and a target name to taxid mapping file,
+    /// or an mmseqs taxonomy database (the sequence database that createtaxdb was run on)
+    #[arg(long)]
+    taxdb: Option<String>,
+    /// Only search targets under these taxids (comma separated)
+    #[arg(long, value_delimiter = ',', requires = "taxdb")]
+    taxon_include: Vec<u32>,
+    /// Don't search targets under these taxids (comma separated)
+    #[arg(long, value_delimiter = ',', requires = "taxdb")]
+    taxon_exclude: Vec<u32>,
+}
+
+#[derive(Debug, Parser)]
+struct MaskArgs {
+    /// Mask the target regions covered by the hits in this results file (e.g. from
+    /// a previous run) with X, so that only weaker hits elsewhere are found
+    #[arg(long, value_name = "RESULTS.tsv")]
+    mask_previous: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct TargetTypeArgs {
+    /// What the target fasta holds
+    #[arg(long, value_enum, default_value_t = TargetType::Protein)]
+    target_type: TargetType,
+    /// The fewest residues an ORF called on the contigs must have to be searched
+    #[arg(long, value_name = "N", default_value_t = 30)]
+    min_orf_length: usize,
+    /// The NCBI translation table to call the ORFs of the contigs with, e.g. 2 for
+    /// vertebrate mitochondria, or 4 for Mycoplasma
+    #[arg(long, value_name = "TABLE", value_parser = parse_genetic_code, default_value = "11")]
+    genetic_code: GeneticCode,
+}
+
+#[derive(Debug, Parser)]
+struct AlignArgs {
+    /// Only report hits with an E-value above this value
+    #[arg(short = 'E', default_value_t = 10.0)]
+    evalue_cutoff: f32,
+    /// Per-query E-value thresholds (accession, E-value, and optionally inclusion E-value per
+    /// line); unlisted queries use -E and --incE
+    #[arg(long, value_name = "FILE")]
+    threshold_table: Option<String>,
+    /// Mark the hits with an E-value at or below this value as included
+    #[arg(long = "incE", value_name = "EVALUE")]
+    inclusion_evalue: Option<f32>,
+    /// Mark the hits with a bit score at or above this value as included
+    #[arg(long = "incT", value_name = "BITS")]
+    inclusion_bits: Option<f32>,
+    /// Only align these query profiles: accessions or names (globs allowed), comma separated or in a file
+    #[arg(long, value_name = "LIST/FILE", value_parser = parse_query_patterns)]
+    query_include: Option<QueryPatterns>,
+    /// Don't align these query profiles: accessions or names (globs allowed), comma separated or in a file
+    #[arg(long, value_name = "LIST/FILE", value_parser = parse_query_patterns)]
+    query_exclude: Option<QueryPatterns>,
+    /// Reuse the hits in this results file, and only align the profile-target pairs that it lacks
+    #[arg(long, value_name = "RESULTS.tsv")]
+    skip_existing: Option<String>,
+    /// What to do when the cloud search fails to produce valid bounds for a seed
+    #[arg(long, value_enum, default_value_t = OnBoundFail::Skip)]
+    on_bound_fail: OnBoundFail,
+    /// Only align the i-th of n shards of the query profiles (sorted by accession)
+    #[arg(long, value_name = "i/n")]
+    shard: Option<Shard>,
+    /// Release a worker's DP matrices after any seed that grows them past this many megabytes
+    #[arg(long, value_name = "MB")]
+    max_matrix_mb: Option<usize>,
+    /// Align the seeds whose whole DP matrix (profile length times target length) has at
+    /// most this many cells without the cloud search, so that each finds the best hit on its
+    /// target; 0 always runs the cloud search
+    #[arg(long, value_name = "CELLS", default_value_t = DEFAULT_DENSE_CELLS)]
+    dense_cells: usize,
+    /// Filter seeds on their forward score before computing the full alignment; the seeds
+    /// that are dropped are listed as `fast_pass` in the skipped seeds file
+    #[arg(long)]
+    fast_pass: bool,
+    /// Filter seeds on an ungapped (MSV) score before the cloud search, keeping P-values up to P;
+    /// the seeds that are dropped are listed as `msv_filter` in the skipped seeds file
+    #[arg(long, value_name = "P", value_parser = parse_probability)]
+    msv_pvalue: Option<f32>,
+    /// Pad the seeds on each side before the cloud search: N residues, or a fraction of the seed length
+    #[arg(long, value_name = "N", value_parser = parse_seed_expansion)]
+    seed_expand: Option<SeedExpansion>,
+    /// Chain collinear seeds of the same query and target into one seed before the cloud
+    /// search, when each starts within GAP positions of the last on both the profile and target
+    #[arg(long, value_name = "GAP")]
+    chain_seeds: Option<usize>,
+    /// Skip seeds that lie mostly in low complexity or compositionally biased target sequence
+    #[arg(long)]
+    comp_bias_filter: bool,
+    /// Drop hits whose null2 bias correction is more than this fraction of their score
+    #[arg(long, value_name = "FRACTION")]
+    max_bias_fraction: Option<f32>,
+    /// Drop hits whose alignment has fewer than this many columns
+    #[arg(long, value_name = "N")]
+    min_aligned_length: Option<usize>,
+    /// Drop hits whose alignment spans less than this fraction of the profile
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction)]
+    min_profile_cov: Option<f32>,
+    /// Drop hits whose alignment spans less than this fraction of the target
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction)]
+    min_target_cov: Option<f32>,
+    /// Drop hits that don't meet a condition on their fields, e.g.
+    /// 'evalue < 1e-5 && target_cov > 0.5 && bias < 0.2*bits'
+    #[arg(long, value_name = "EXPR", value_parser = parse_hit_filter)]
+    filter: Option<HitFilter>,
+    /// Before aligning, check that N runs with different schedules and thread counts agree exactly
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(2..))]
+    verify_deterministic: Option<u32>,
+    /// The order to align the seeds in
+    #[arg(long, value_enum, default_value_t = Schedule::Fifo)]
+    schedule: Schedule,
+    /// Stop aligning once the run has taken this long (e.g. 3600, 90m, or 11:30:00), and write
+    /// the partial results, with the unfinished queries marked in the run summary
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    time_limit: Option<Duration>,
+    /// Stop aligning a query's seeds once they have taken this long, and mark it as incomplete
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    per_query_time_limit: Option<Duration>,
+    /// How to get the consensus sequences of the mmseqs query profiles
+    #[arg(long, value_enum, default_value_t = ConsensusSource::Auto)]
+    consensus_source: ConsensusSource,
+    /// Pin the align workers to CPUs, one per physical core and filling a NUMA node
+    /// before the next, so that each worker's DP matrices stay on its local node
+    #[arg(long)]
+    pin_threads: bool,
+    /// The format of the per-hit alignments
+    #[arg(long, value_enum, default_value_t = AliFormat::Pairwise)]
+    ali_format: AliFormat,
+    /// How to treat targets with ambiguous residues (B, Z, J, U, O, X, or *), or with characters
+    /// that aren't amino acids at all, which are also warned about [default: score-as-x, or
+    /// error with --strict]
+    #[arg(long, value_enum)]
+    ambiguity: Option<AmbiguityPolicy>,
+    /// Score matches against the background residue frequencies in this file
+    #[arg(long, value_name = "FILE")]
+    bg_freqs: Option<String>,
+    /// Override the probability of opening an insertion after a match
+    #[arg(long, value_parser = parse_probability)]
+    popen: Option<f32>,
+    /// Override the probability of extending an insertion
+    #[arg(long, value_parser = parse_probability)]
+    pext: Option<f32>,
+    /// Drop seeds with an mmseqs E-value above this value
+    #[arg(long, value_name = "E")]
+    seed_max_evalue: Option<f32>,
+    /// Only align the K seeds with the best mmseqs E-values for each profile
+    #[arg(long, value_name = "K")]
+    seed_keep_top: Option<usize>,
+}
+
+#[derive(Debug, Parser)]
+struct SeedArgs {
+    /// The mmseqs prefilter k-mer score threshold (lower finds more seeds, more slowly)
+    /// [default: tuned to --sensitivity and the query models, or chosen by mmseqs for sequence queries]
+    #[arg(long)]
+    k_score: Option<u32>,
+    /// The mmseqs prefilter k-mer length [default: tuned to --sensitivity]
+    #[arg(long, value_name = "K")]
+    kmer_length: Option<u32>,
+    /// The most prefilter hits that mmseqs keeps per query [default: tuned to --sensitivity and the query set]
+    #[arg(long)]
+    max_seqs: Option<usize>,
+    /// Have mmseqs compress the intermediate prefilter and alignment databases
+    #[arg(long)]
+    compress_intermediates: bool,
+    /// Only seed (and so align) the target sequences named in this file, one per line
+    #[arg(long, value_name = "FILE")]
+    target_include_ids: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct SensitivityArgs {
+    /// The trade-off between speed and sensitivity that the seed and align steps are tuned for
+    #[arg(long, value_enum, default_value_t = Sensitivity::Default)]
+    sensitivity: Sensitivity,
+}
+
+#[derive(Debug, Parser)]
+struct NotifyArgs {
+    /// Run this shell command when the run finishes or fails (see MMORESEQS_STATUS)
+    #[arg(long, value_name = "CMD")]
+    on_complete: Option<String>,
+    /// Post the run's status and summary (JSON) to this http(s):// URL when it finishes or fails
+    #[arg(long, value_name = "URL")]
+    webhook: Option<WebhookUrl>,
+}
+
+#[derive(Debug, Parser)]
+struct MonitorArgs {
+    /// Show a live dashboard of the run's progress on stderr, with its messages, until it finishes or q is pressed
+    #[arg(long)]
+    tui: bool,
+    /// Keep a snapshot of the run's progress (JSON) in this file, for mmoreseqs top
+    #[arg(long, value_name = "FILE")]
+    progress_file: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct NumberFormatArgs {
+    /// How to write E-values
+    #[arg(long, value_enum, default_value_t = EvalueFormat::Scientific)]
+    evalue_format: EvalueFormat,
+    /// The digits after the point of E-values (1 for scientific E-values, and all
+    /// of those needed for the exact value for decimal ones, by default)
+    #[arg(long, value_name = "N")]
+    evalue_precision: Option<usize>,
+    /// The digits after the point of bit scores and bias corrections (2 for the
+    /// hit's bit score, and 1 for the bias and seed bit score, by default)
+    #[arg(long, value_name = "N")]
+    score_precision: Option<usize>,
+}
+
+#[derive(Debug, Parser)]
+struct OutputArgs {
+    /// Where to place the results: a path, or an s3:// or gs:// URI
+    #[arg(short, long, default_value = "results.tsv", value_parser = parse_output_location)]
+    output_file: String,
+    /// Where to write the seeds that were skipped during alignment [default: skipped.tsv next to the results]
+    #[arg(long)]
+    skipped_file: Option<String>,
+    /// Where to write the per-hit alignments
+    #[arg(long)]
+    ali_file: Option<String>,
+    /// Also write each query's hits, alignments, and summary to a directory
+    /// named after its accession, next to the results file
+    #[arg(long)]
+    per_query_dirs: bool,
+    /// Write a run-length encoded mask of the residues covered by hits, for each target with a hit
+    #[arg(long, value_name = "FILE")]
+    hitmap_file: Option<String>,
+    /// Also report up to N suboptimal alignments of each hit's seed, traced back
+    /// without the residue pairs of the better ones
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    alt_alignments: usize,
+    /// Where to write the suboptimal alignments of --alt-alignments
+    #[arg(long, value_name = "FILE", default_value = "alt-alignments.tsv")]
+    alt_file: String,
+    /// Write the target sequence of each hit in FASTA format
+    #[arg(long, value_name = "FILE")]
+    hits_fasta: Option<String>,
+    /// Write a query, target, and bit score line for each query and target with hits,
+    /// in MCL's ABC format, for clustering
+    #[arg(long, value_name = "FILE")]
+    abc_output: Option<String>,
+    /// Write the query and target pairs with hits as an edge list (TSV) with their E-values
+    #[arg(long, value_name = "FILE")]
+    edge_list: Option<String>,
+    /// Write the size and shape of the DP bounds of each hit, next to the size of the full
+    /// matrix, for comparing bounded and full DP
+    #[arg(long, value_name = "FILE")]
+    bound_stats: Option<String>,
+    /// Write the state path (M/I/D/N/C/J states, with their positions and posteriors)
+    /// of each hit, for rebuilding or rescoring the alignments downstream
+    #[arg(long, value_name = "FILE")]
+    trace_output: Option<String>,
+    /// The format of the --trace-output file
+    #[arg(long, value_enum, default_value_t = TraceFormat::Json, requires = "trace_output")]
+    trace_format: TraceFormat,
+    /// Add this many residues of flanking target context on each side of the hits
+    /// in the --hits-fasta file, in lower case
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "hits_fasta")]
+    flank: usize,
+    /// Write the whole of each target with hits to the --hits-fasta file
+    #[arg(long, requires = "hits_fasta", conflicts_with = "flank")]
+    full_target: bool,
+    /// The keys to sort the hits by, in order (comma separated); by default, the
+    /// hits are in the order of their seeds
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "KEY")]
+    sort_by: Vec<SortKey>,
+    /// Print a summary of this many of the top hits to stderr (0 to disable)
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    summary: usize,
+    /// Don't colorize the summary
+    #[arg(long)]
+    no_color: bool,
+    /// Add the mmseqs scores and coordinates of each hit's seed to the results
+    #[arg(long)]
+    seed_provenance: bool,
+    /// The results table schema to write (v1 is the legacy column layout)
+    #[arg(long, value_enum, default_value_t = OutputVersion::V3)]
+    output_version: OutputVersion,
+    #[command(flatten)]
+    number_format: NumberFormatArgs,
+    /// Write machine-readable run totals (JSON) to this file
+    #[arg(long, value_name = "FILE")]
+    run_summary: Option<String>,
+    /// Exit with an error if the search produces no hits
+    #[arg(long)]
+    fail_if_no_hits: bool,
+}
+
+#[derive(Debug, Parser)]
+struct SearchArgs {
+    /// Query MSA (stockholm) or HMM file
+    #[arg(required_unless_present = "batch")]
+    query: Option<String>,
+    /// Target fasta file, a directory of fasta files, or the http(s)://, s3://, or gs:// URI of a fasta file
+    #[arg(value_parser = parse_target_location)]
+    target: String,
+    /// Another target fasta file to combine with TARGET (may be repeated)
+    #[arg(long = "target", value_name = "FASTA")]
+    extra_targets: Vec<String>,
+    /// Where to place intermediate files
+    #[arg(long, default_value = "./tmp/")]
+    work_dir: String,
+    /// Place all of the run's files in this directory, using a standard layout; an s3:// or
+    /// gs:// URI is uploaded to as the results are written, and synced when the run ends,
+    /// without the intermediate files
+    #[arg(
+        long,
+        value_name = "RUN_DIR",
+        value_parser = parse_output_location,
+        conflicts_with_all = [
+            "output_file",
+            "skipped_file",
+            "ali_file",
+            "run_summary",
+            "progress_file",
+            "work_dir"
+        ]
+    )]
+    output_dir: Option<String>,
+    /// The format of the query (detected from its contents by default)
+    #[arg(long, value_enum)]
+    query_format: Option<QueryFormat>,
+    #[command(flatten)]
+    seed: SeedArgs,
+    #[command(flatten)]
+    sensitivity: SensitivityArgs,
+    /// Run each of the queries listed in this batch file (TSV, or JSON with a .json extension)
+    #[arg(long, conflicts_with = "query")]
+    batch: Option<String>,
+    #[command(flatten)]
+    output: OutputArgs,
+    #[command(flatten)]
+    align: AlignArgs,
+    #[command(flatten)]
+    target_type: TargetTypeArgs,
+    #[command(flatten)]
+    taxonomy: TaxonomyArgs,
+    #[command(flatten)]
+    mask: MaskArgs,
+    #[command(flatten)]
+    notify: NotifyArgs,
+    #[command(flatten)]
+    monitor: MonitorArgs,
+    #[command(flatten)]
+    common: CommonArgs,
+    /// Read the options in this file, as written to run-params.toml by a run with --output-dir;
+    /// the options given on the command line take precedence
+    #[arg(long, value_name = "FILE")]
+    config: Option<String>,
 }
 
 /// Doc comment
 #[derive(Debug, Subcommand)]
 enum SubCommands {
-    #[command(about = "Prepare a query (MSA) file and target (fasta) file for the seed step")]
+    #[command(
+        about = "Prepare a query (MSA or HMM) file and target (fasta) file for the seed step"
+    )]
     Prep {
-        /// Query MSA file
+        /// Query MSA (stockholm) or HMM file
         query: String,
-        /// Target fasta file
+        /// Target fasta file, a directory of fasta files, or the http(s)://, s3://, or gs:// URI of a fasta file
+        #[arg(value_parser = parse_target_location)]
         target: String,
+        /// Another target fasta file to combine with TARGET (may be repeated)
+        #[arg(long = "target", value_name = "FASTA")]
+        extra_targets: Vec<String>,
         /// Where to place output files
         #[arg(short, long, default_value = "./prep/")]
         output_dir: String,
+        /// Don't build the query HMM (it must then be provided to the align step)
+        #[arg(long)]
+        skip_hmmbuild: bool,
+        /// The format of the query (detected from its contents by default)
+        #[arg(long, value_enum)]
+        query_format: Option<QueryFormat>,
+        /// Print the external commands instead of running them
+        #[arg(long)]
+        dry_run: bool,
+        #[command(flatten)]
+        target_type: TargetTypeArgs,
+        #[command(flatten)]
+        taxonomy: TaxonomyArgs,
+        #[command(flatten)]
+        mask: MaskArgs,
         #[command(flatten)]
         common: CommonArgs,
     },
     #[command(about = "Use MMseqs2 to create a set of alignment seeds for the align step")]
     Seed {
-        /// Query MMseqs2 profile database
-        query_db: String,
-        /// Query P7 profile HMM
-        query_hmm: String,
-        /// Target MMseqs 2 sequence database
-        target: String,
+        /// Directory produced by mmoreseqs prep, or, with the deprecated
+        /// QUERY_HMM and TARGET_DB arguments, the query MMseqs2 profile database
+        #[arg(value_name = "PREP_DIR")]
+        prep_dir: String,
+        /// Deprecated: the query P7 profile HMM, for a query database given in place of PREP_DIR
+        #[arg(requires = "target_db")]
+        query_hmm: Option<String>,
+        /// Deprecated: the target MMseqs2 sequence database, for a query database given in place of PREP_DIR
+        #[arg(requires = "query_hmm")]
+        target_db: Option<String>,
+        /// Deprecated: where to place intermediate files, with the query and target databases
+        #[arg(short, long, requires = "target_db", default_value = "./tmp/")]
+        work_dir: String,
         /// Where to place the seeds output
         #[arg(short, long, default_value = "seeds.tsv")]
         output_file: String,
-        /// Where to place intermediate files
-        #[arg(short, long, default_value = "./tmp/")]
-        work_dir: String,
+        #[command(flatten)]
+        seed: SeedArgs,
+        #[command(flatten)]
+        sensitivity: SensitivityArgs,
+        /// Print the external commands instead of running them
+        #[arg(long)]
+        dry_run: bool,
         #[command(flatten)]
         common: CommonArgs,
     },
@@ -63,33 +598,259 @@ enum SubCommands {
         about = "Search with the query (HMM) against the target (fasta), using alignment seeds"
     )]
     Align {
-        /// Query P7 HMM file
+        /// Query P7 HMM file, or an hmmpress-ed HMM database (converted with hmmconvert first, so slower to load)
         query: String,
-        /// Target fasta file
+        /// Target fasta file, or the http(s)://, s3://, or gs:// URI of one, from which only the seeded targets are fetched
+        #[arg(value_parser = parse_target_location)]
         target: String,
-        /// Seed file (result of mmoreseqs seed)
-        seeds: String,
-        /// Only report hits with an E-value above this value
-        #[arg(short = 'E', default_value_t = 10.0)]
-        evalue_cutoff: f32,
+        /// Seed files (results of mmoreseqs seed, or of mmseqs convertalis), possibly gzipped
+        #[arg(required_unless_present = "seeds_dir")]
+        seeds: Vec<String>,
+        /// A directory of seed files to read, in name order, e.g. the chunks of a split seed search
+        #[arg(long, value_name = "DIR")]
+        seeds_dir: Option<String>,
+        /// The format of the seed files
+        #[arg(long, value_enum, default_value_t = SeedsFormat::Mmoreseqs)]
+        seeds_format: SeedsFormat,
+        /// Query MSA file to build the query HMM from, if the HMM doesn't exist
+        #[arg(long)]
+        query_msa: Option<String>,
+        /// Directory produced by mmoreseqs prep
+        #[arg(long, default_value = "./prep/")]
+        prep_dir: String,
+        /// Only warn if the seeds were generated from a different query HMM or target
+        #[arg(long)]
+        force: bool,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        align: AlignArgs,
+        #[command(flatten)]
+        sensitivity: SensitivityArgs,
+        #[command(flatten)]
+        taxonomy: TaxonomyArgs,
+        #[command(flatten)]
+        mask: MaskArgs,
+        #[command(flatten)]
+        notify: NotifyArgs,
+        #[command(flatten)]
+        monitor: MonitorArgs,
         #[command(flatten)]
         common: CommonArgs,
     },
-    #[command(about = "Search a query (MSA) file and target (fasta) file")]
-    Search {
-        /// Query MSA file
+    #[command(about = "Align one profile to a given region of one target, without seeds")]
+    AlignRegion {
+        /// Query P7 HMM file, or an hmmpress-ed HMM database (converted with hmmconvert first, so slower to load)
         query: String,
         /// Target fasta file
         target: String,
+        /// The name or accession of the profile to align
+        #[arg(long = "profile", value_name = "NAME")]
+        profile_name: String,
+        /// The name of the target to align to
+        #[arg(long = "target", value_name = "NAME")]
+        target_name: String,
+        /// The target region to align, e.g. 120-310 (1-based, inclusive)
+        #[arg(long, value_name = "START-END")]
+        target_range: CoordRange,
+        /// The profile region to anchor the alignment to (the whole profile by default)
+        #[arg(long, value_name = "START-END")]
+        profile_range: Option<CoordRange>,
+        /// What to do when the cloud search fails to produce valid bounds for the region
+        #[arg(long, value_enum, default_value_t = OnBoundFail::Skip)]
+        on_bound_fail: OnBoundFail,
+        /// The format of the alignment
+        #[arg(long, value_enum, default_value_t = AliFormat::Pairwise)]
+        ali_format: AliFormat,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    #[command(
+        about = "Search a query (MSA or HMM) file and target (fasta) file",
+        allow_missing_positional = true
+    )]
+    Search {
+        #[command(flatten)]
+        search: SearchArgs,
+    },
+    #[command(
+        about = "Run a subset of the pipeline stages on a query and target",
+        allow_missing_positional = true
+    )]
+    Pipeline {
+        /// The stages to run (comma separated)
+        #[arg(long, value_enum, value_delimiter = ',', required = true)]
+        stages: Vec<Stage>,
+        #[command(flatten)]
+        search: SearchArgs,
+    },
+    #[command(about = "Serve searches against a prepared target over an HTTP/JSON API")]
+    Serve {
+        /// Target fasta file (the one the target index was prepared from)
+        target: String,
+        /// Directory produced by mmoreseqs prep that holds the target database
+        #[arg(long)]
+        target_index: String,
+        /// The address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        /// The port to listen on
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        /// Where to place the per-query intermediate files
+        #[arg(long, default_value = "./serve/")]
+        work_dir: String,
+        /// Load the target database into memory before accepting queries
+        #[arg(long)]
+        preload: bool,
+        /// Answer 408 and close a connection that stalls this long while its request is
+        /// read or its response is written (e.g. 30 or 2m), or 0 to wait forever
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "30")]
+        request_timeout: Duration,
+        #[command(flatten)]
+        seed: SeedArgs,
+        #[command(flatten)]
+        align: AlignArgs,
+        #[command(flatten)]
+        sensitivity: SensitivityArgs,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    #[command(about = "Export data for inspecting a run")]
+    View {
+        #[command(subcommand)]
+        view: ViewCommands,
+    },
+    #[command(about = "Show a live dashboard of a run that was started with --progress-file")]
+    Top {
+        /// The run's progress file, or its --output-dir
+        progress_file: String,
+    },
+    #[command(about = "Measure the align step's --schedule and --threads on a synthetic workload")]
+    BenchParallel {
+        /// Query P7 HMM file, whose models the synthetic seeds are drawn for
+        query: String,
+        /// The number of seeds in the workload
+        #[arg(long, default_value_t = 500usize)]
+        seeds: usize,
+        /// How uneven the workload is, in the number of seeds of each model and the
+        /// lengths of their targets; 0 makes it even
+        #[arg(long, default_value_t = 1.0)]
+        skew: f64,
+        /// The most threads to measure [default: the number of CPUs]
+        #[arg(long)]
+        max_threads: Option<usize>,
+        /// The number of times to measure each configuration, keeping the fastest
+        #[arg(long, default_value_t = 3usize)]
+        repeats: usize,
+    },
+    #[command(
+        about = "Measure the model and target sizes below which seeds are faster to align without the cloud search"
+    )]
+    BenchDense {
+        /// Query P7 HMM file, whose models are aligned to synthetic targets
+        query: String,
+        /// The longest synthetic target; the lengths double from 8 up to this
+        #[arg(long, default_value_t = 1024usize)]
+        max_target_length: usize,
+        /// The number of seeds to align at each size
+        #[arg(long, default_value_t = 100usize)]
+        seeds: usize,
+        /// The number of times to measure each size, keeping the fastest
+        #[arg(long, default_value_t = 3usize)]
+        repeats: usize,
+    },
+    #[command(about = "Check the environment, and print a report to include in bug reports")]
+    Doctor {
+        /// The prep directory to check
+        #[arg(long, default_value = "./prep/")]
+        prep_dir: String,
+        /// The directory to check that results can be written to
+        #[arg(long, default_value = ".")]
+        output_dir: String,
+        /// Don't try a tiny search
+        #[arg(long)]
+        no_run: bool,
+        #[command(flatten)]
+        tools: ToolArgs,
+    },
+    #[command(about = "Merge the results files of several sharded runs")]
+    Merge {
+        /// Results files to merge
+        #[arg(required = true)]
+        results: Vec<String>,
         /// Only report hits with an E-value above this value
         #[arg(short = 'E', default_value_t = 10.0)]
         evalue_cutoff: f32,
-        /// Where to place the results
-        #[arg(short, long, default_value = "results.tsv")]
+        /// Where to place the merged results: a path, or an s3:// or gs:// URI
+        #[arg(short, long, default_value = "results.tsv", value_parser = parse_output_location)]
         output_file: String,
+        /// The keys to sort the hits by, in order (comma separated); by default, the
+        /// hits are sorted by E-value
+        #[arg(long, value_enum, value_delimiter = ',', value_name = "KEY")]
+        sort_by: Vec<SortKey>,
+    },
+    #[command(
+        about = "Search the queries against the targets and back, and report the reciprocal best hits"
+    )]
+    Rbh {
+        /// Query MSA (stockholm) or HMM file
+        query: String,
+        /// Target fasta file
+        target: String,
         /// Where to place intermediate files
         #[arg(long, default_value = "./tmp/")]
         work_dir: String,
+        /// Where to place the reciprocal best hits: a path, or an s3:// or gs:// URI
+        #[arg(short, long, default_value = "rbh.tsv", value_parser = parse_output_location)]
+        output_file: String,
+        /// The format of the query (detected from its contents by default)
+        #[arg(long, value_enum)]
+        query_format: Option<QueryFormat>,
+        #[command(flatten)]
+        seed: SeedArgs,
+        #[command(flatten)]
+        sensitivity: SensitivityArgs,
+        #[command(flatten)]
+        align: AlignArgs,
+        #[command(flatten)]
+        number_format: NumberFormatArgs,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ViewCommands {
+    #[command(about = "Export the seeds and the final hits as rectangles for plotting")]
+    Seeds {
+        /// Query P7 HMM file, or an hmmpress-ed HMM database (converted with hmmconvert first, so slower to load)
+        query: String,
+        /// Seed file (result of mmoreseqs seed, or of mmseqs convertalis), possibly gzipped
+        seeds: String,
+        /// Results file written by mmoreseqs align
+        results: String,
+        /// The format of the seed file
+        #[arg(long, value_enum, default_value_t = SeedsFormat::Mmoreseqs)]
+        seeds_format: SeedsFormat,
+        /// Directory produced by mmoreseqs prep
+        #[arg(long, default_value = "./prep/")]
+        prep_dir: String,
+        /// Where to place the plot data (TSV)
+        #[arg(long)]
+        plot_data: String,
+        #[command(flatten)]
+        number_format: NumberFormatArgs,
+        #[command(flatten)]
+        common: CommonArgs,
+    },
+    #[command(about = "Export the emission probabilities and information content of query HMMs")]
+    Hmm {
+        /// Query P7 HMM file, or an hmmpress-ed HMM database (converted with hmmconvert first, so slower to load)
+        query: String,
+        /// Where to place the per-position logo data (TSV)
+        #[arg(long)]
+        logo_data: String,
         #[command(flatten)]
         common: CommonArgs,
     },
@@ -98,107 +859,303 @@ enum SubCommands {
 impl Cli {
     fn args(self) -> Args {
         let mut args = Args::default();
-        match self.command {
+        // without --version-json, clap requires a subcommand
+        let Some(command) = self.command else {
+            return args;
+        };
+        match command {
             SubCommands::Prep {
                 query,
                 target,
+                extra_targets,
                 output_dir,
+                skip_hmmbuild,
+                query_format,
+                dry_run,
+                target_type,
+                taxonomy,
+                mask,
                 common,
             } => {
+                args.dry_run = dry_run;
                 args.set_common(&common);
+                args.set_taxonomy(&taxonomy);
+                args.set_mask(&mask);
                 args.command = Command::Prep;
-                args.paths.query_msa = PathBuf::from(query);
-                args.paths.target_fasta = PathBuf::from(target);
+                args.paths.query = PathBuf::from(query);
 
-                let output_dir = PathBuf::from(output_dir);
-
-                create_dir_all(&output_dir).expect("failed to create output directory");
-                args.paths.query_msa_db = output_dir.join("msaDB");
-                args.paths.query_db = output_dir.join("queryDB");
-                args.paths.target_db = output_dir.join("targetDB");
-                args.paths.query_hmm = output_dir.join("query.hmm");
+                args.set_workspace(Workspace::new(output_dir));
+                args.set_target_fasta(target, extra_targets);
+                args.set_target_type(&target_type);
+                args.skip_hmmbuild = skip_hmmbuild;
+                args.query_format = query_format;
             }
             SubCommands::Seed {
-                query_db,
+                prep_dir,
                 query_hmm,
-                target,
-                output_file,
+                target_db,
                 work_dir,
+                output_file,
+                seed,
+                sensitivity,
+                dry_run,
                 common,
             } => {
+                args.dry_run = dry_run;
+                args.set_seed(&seed);
+                args.set_sensitivity(&sensitivity);
                 args.set_common(&common);
                 args.command = Command::Seed;
-
-                args.paths.query_db = PathBuf::from(&query_db);
-                args.paths.query_db_index = PathBuf::from(format!("{}.index", query_db));
-                args.paths.query_db_h = PathBuf::from(format!("{}_h", query_db));
-                args.paths.query_db_h_index = PathBuf::from(format!("{}_h.index", query_db));
-                args.paths.query_hmm = PathBuf::from(query_hmm);
-                args.paths.target_db = PathBuf::from(target);
-
-                let work_dir = PathBuf::from(work_dir);
-                create_dir_all(&work_dir).expect("failed to create working directory");
-
-                args.paths.prefilter_db = work_dir.join("prefilterDB");
-                args.paths.align_db = work_dir.join("alignDB");
-
+                match (query_hmm, target_db) {
+                    (Some(query_hmm), Some(target_db)) => {
+                        eprintln!("warning: `mmoreseqs seed QUERY_DB QUERY_HMM TARGET_DB` is deprecated; use `mmoreseqs seed PREP_DIR` on the directory of mmoreseqs prep");
+                        args.set_workspace(Workspace::new(work_dir));
+                        set_query_db(&mut args.paths, PathBuf::from(prep_dir));
+                        set_target_db(&mut args.paths, PathBuf::from(target_db));
+                        args.paths.query_hmm = PathBuf::from(query_hmm);
+                    }
+                    _ => args.set_workspace(Workspace::new(prep_dir)),
+                }
                 args.paths.seeds = PathBuf::from(output_file);
             }
             SubCommands::Align {
                 query,
                 target,
                 seeds,
-                evalue_cutoff,
+                seeds_dir,
+                seeds_format,
+                query_msa,
+                prep_dir,
+                force,
+                output,
+                align,
+                sensitivity,
+                taxonomy,
+                mask,
+                notify,
+                monitor,
                 common,
             } => {
                 args.set_common(&common);
+                args.set_taxonomy(&taxonomy);
+                args.set_mask(&mask);
+                args.set_align(&align);
+                args.set_sensitivity(&sensitivity);
+                args.set_notify(&notify);
+                args.set_monitor(&monitor);
                 args.command = Command::Align;
+                args.set_workspace(Workspace::new(prep_dir));
+                args.paths.query_hmm = PathBuf::from(query);
+                args.paths.target_fasta = args.target_path(target);
+                args.paths.seed_files = seeds.iter().map(PathBuf::from).collect();
+                args.paths.seeds_dir = seeds_dir.map(PathBuf::from);
+                args.seeds_format = seeds_format;
+                args.force = force;
+                if let Some(query_msa) = query_msa {
+                    args.paths.query = PathBuf::from(query_msa);
+                }
+                args.set_output_options(&output);
+                args.paths.results = args.uploads.output_path(&output.output_file);
+                args.paths.skipped = args.skipped_path(&output);
+                args.paths.alignments = output.ali_file.map(PathBuf::from);
+            }
+            SubCommands::AlignRegion {
+                query,
+                target,
+                profile_name,
+                target_name,
+                target_range,
+                profile_range,
+                on_bound_fail,
+                ali_format,
+                common,
+            } => {
+                args.set_common(&common);
+                args.command = Command::AlignRegion;
                 args.paths.query_hmm = PathBuf::from(query);
                 args.paths.target_fasta = PathBuf::from(target);
+                args.region = AnchorRegion {
+                    profile_name,
+                    target_name,
+                    target_range,
+                    profile_range,
+                };
+                args.on_bound_fail = on_bound_fail;
+                args.ali_format = ali_format;
+            }
+            SubCommands::Search { search } => {
+                args.command = Command::Search;
+                args.stages = vec![Stage::Prep, Stage::Seed, Stage::Align];
+                args.set_search(search);
+            }
+            SubCommands::Pipeline { stages, search } => {
+                args.command = Command::Pipeline;
+                args.stages = stages;
+                args.set_search(search);
+            }
+            SubCommands::Serve {
+                target,
+                target_index,
+                host,
+                port,
+                work_dir,
+                preload,
+                request_timeout,
+                seed,
+                align,
+                sensitivity,
+                common,
+            } => {
+                args.set_common(&common);
+                args.set_seed(&seed);
+                args.set_align(&align);
+                args.set_sensitivity(&sensitivity);
+                // each request is only held to --per-query-time-limit
+                args.time_limits.clear_deadline();
+                args.command = Command::Serve;
+                args.set_workspace(Workspace::new(work_dir));
+                Workspace::new(target_index).fill_target_paths(&mut args.paths);
+                args.paths.target_fasta = PathBuf::from(target);
+                args.host = host;
+                args.port = port;
+                args.preload = preload;
+                args.request_timeout = request_timeout;
+            }
+            SubCommands::View {
+                view:
+                    ViewCommands::Seeds {
+                        query,
+                        seeds,
+                        results,
+                        seeds_format,
+                        prep_dir,
+                        plot_data,
+                        number_format,
+                        common,
+                    },
+            } => {
+                args.set_common(&common);
+                args.set_number_format(&number_format);
+                args.command = Command::ViewSeeds;
+                args.set_workspace(Workspace::new(prep_dir));
+                args.paths.query_hmm = PathBuf::from(query);
                 args.paths.seeds = PathBuf::from(seeds);
-                args.evalue_cutoff = evalue_cutoff;
+                args.seeds_format = seeds_format;
+                args.paths.results = PathBuf::from(results);
+                args.paths.plot_data = PathBuf::from(plot_data);
+            }
+            SubCommands::View {
+                view:
+                    ViewCommands::Hmm {
+                        query,
+                        logo_data,
+                        common,
+                    },
+            } => {
+                args.set_common(&common);
+                args.command = Command::ViewHmm;
+                args.paths.query_hmm = PathBuf::from(query);
+                args.paths.logo_data = PathBuf::from(logo_data);
+            }
+            SubCommands::Doctor {
+                prep_dir,
+                output_dir,
+                no_run,
+                tools,
+            } => {
+                args.command = Command::Doctor;
+                args.set_tools(&tools);
+                args.workspace = Workspace::new(prep_dir);
+                args.paths.results = PathBuf::from(output_dir).join("results.tsv");
+                args.doctor_run = !no_run;
             }
-            SubCommands::Search {
+            SubCommands::BenchParallel {
                 query,
-                target,
+                seeds,
+                skew,
+                max_threads,
+                repeats,
+            } => {
+                args.command = Command::BenchParallel;
+                args.paths.query_hmm = PathBuf::from(query);
+                // the workload is aligned as a search would, tiny seeds densely
+                args.dense_cells = DEFAULT_DENSE_CELLS;
+                args.parallel_bench = ParallelBenchParams {
+                    seeds,
+                    skew,
+                    max_threads: max_threads.unwrap_or_else(|| {
+                        std::thread::available_parallelism().map_or(1, |cpus| cpus.get())
+                    }),
+                    repeats,
+                };
+            }
+            SubCommands::BenchDense {
+                query,
+                max_target_length,
+                seeds,
+                repeats,
+            } => {
+                args.command = Command::BenchDense;
+                args.paths.query_hmm = PathBuf::from(query);
+                args.dense_bench = DenseBenchParams {
+                    max_target_length,
+                    seeds,
+                    repeats,
+                };
+            }
+            SubCommands::Top { progress_file } => {
+                args.command = Command::Top;
+                let mut progress_file = PathBuf::from(progress_file);
+                if progress_file.is_dir() {
+                    progress_file = progress_file.join("progress.json");
+                }
+                args.paths.progress = Some(progress_file);
+            }
+            SubCommands::Merge {
+                results,
                 evalue_cutoff,
                 output_file,
+                sort_by,
+            } => {
+                args.command = Command::Merge;
+                args.paths.merge_inputs = results.iter().map(PathBuf::from).collect();
+                args.paths.results = args.uploads.output_path(&output_file);
+                args.evalue_cutoff = evalue_cutoff;
+                args.sort_by = sort_by;
+            }
+            SubCommands::Rbh {
+                query,
+                target,
                 work_dir,
+                output_file,
+                query_format,
+                seed,
+                sensitivity,
+                align,
+                number_format,
                 common,
             } => {
                 args.set_common(&common);
-
-                args.command = Command::Search;
-                args.paths.query_msa = PathBuf::from(query);
+                args.set_seed(&seed);
+                args.set_align(&align);
+                args.set_sensitivity(&sensitivity);
+                args.set_number_format(&number_format);
+                args.command = Command::Rbh;
+                args.set_workspace(Workspace::new(work_dir));
+                args.query_format = query_format;
+                args.paths.query = PathBuf::from(query);
                 args.paths.target_fasta = PathBuf::from(target);
-
-                let work_dir = PathBuf::from(work_dir);
-
-                create_dir_all(&work_dir).expect("failed to create working directory");
-
-                args.paths.query_msa_db = work_dir.join("msaDB");
-                args.paths.query_db = work_dir.join("queryDB");
-                args.paths.query_db_index = work_dir.join("queryDB.index");
-                args.paths.query_db_h = work_dir.join("queryDB_h");
-                args.paths.query_db_h_index = work_dir.join("queryDB_h.index");
-                args.paths.target_db = work_dir.join("targetDB");
-                args.paths.prefilter_db = work_dir.join("prefilterDB");
-                args.paths.align_db = work_dir.join("alignDB");
-                args.paths.seeds = work_dir.join("seeds.tsv");
-                args.paths.query_hmm = work_dir.join("query.hmm");
-
-                args.evalue_cutoff = evalue_cutoff;
-                args.paths.results = PathBuf::from(output_file);
+                args.paths.results = args.uploads.output_path(&output_file);
             }
         }
         args
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct FilePaths {
     pub query_hmm: PathBuf,
-    pub query_msa: PathBuf,
+    pub query: PathBuf,
     pub target_fasta: PathBuf,
     pub query_msa_db: PathBuf,
     pub query_db: PathBuf,
@@ -206,54 +1163,592 @@ pub struct FilePaths {
     pub query_db_h: PathBuf,
     pub query_db_h_index: PathBuf,
     pub target_db: PathBuf,
+    pub target_db_index: PathBuf,
+    pub target_db_lookup: PathBuf,
+    /// The precomputed k-mer index of the target database, which the server builds once
+    pub target_db_kmer_index: PathBuf,
+    /// The list of target names that the seed step is restricted to
+    pub target_include_ids: Option<PathBuf>,
+    pub target_subset_keys: PathBuf,
+    pub target_subset_db: PathBuf,
+    pub target_checksum: PathBuf,
     pub prefilter_db: PathBuf,
     pub align_db: PathBuf,
     pub seeds: PathBuf,
+    /// The seed files given to align, read in place of the seeds path
+    pub seed_files: Vec<PathBuf>,
+    pub seeds_dir: Option<PathBuf>,
     pub results: PathBuf,
+    pub skipped: PathBuf,
+    pub alignments: Option<PathBuf>,
+    pub merge_inputs: Vec<PathBuf>,
+    pub taxdb: Option<PathBuf>,
+    pub filtered_target_fasta: PathBuf,
+    /// Where the target fasta is read from, when it is a URL or an object store URI
+    pub target_uri: Option<RemoteTarget>,
+    pub remote_target_fasta: PathBuf,
+    pub target_index: PathBuf,
+    pub fetched_target_fasta: PathBuf,
+    /// The results of a previous run, whose hit regions are masked in the targets
+    pub mask_previous: Option<PathBuf>,
+    pub masked_target_fasta: PathBuf,
+    /// The target fasta files (or directories) to combine, when there is more than one
+    pub target_fasta_sources: Vec<PathBuf>,
+    pub combined_target_fasta: PathBuf,
+    /// The nucleotide contigs that the target ORFs are called on, with --target-type contigs
+    pub target_contigs: Option<PathBuf>,
+    pub target_orfs: PathBuf,
+    pub orf_map: PathBuf,
+    pub target_sources: PathBuf,
+    pub query_stockholm: PathBuf,
+    pub query_keyed_stockholm: PathBuf,
+    pub query_consensus_fasta: PathBuf,
+    pub query_profile_consensus_db: PathBuf,
+    pub query_profile_consensus_fasta: PathBuf,
+    pub logs_dir: Option<PathBuf>,
+    pub manifest: Option<PathBuf>,
+    /// Where the run's resolved parameters are written
+    pub run_params: Option<PathBuf>,
+    /// The config file that the options were read from
+    pub config: Option<PathBuf>,
+    pub batch: Option<PathBuf>,
+    pub run_summary: Option<PathBuf>,
+    pub hitmap: Option<PathBuf>,
+    pub hits_fasta: Option<PathBuf>,
+    pub abc: Option<PathBuf>,
+    pub bound_stats: Option<PathBuf>,
+    pub trace_output: Option<PathBuf>,
+    pub edge_list: Option<PathBuf>,
+    pub alt_alignments: Option<PathBuf>,
+    pub progress: Option<PathBuf>,
+    pub threshold_table: Option<PathBuf>,
+    pub skip_existing: Option<PathBuf>,
+    pub plot_data: PathBuf,
+    pub logo_data: PathBuf,
+}
+
+impl FilePaths {
+    /// The target fasta that prep builds the target database from, after masking
+    pub fn prepped_target_fasta(&self) -> &PathBuf {
+        match self.mask_previous {
+            Some(_) => &self.masked_target_fasta,
+            None => &self.target_fasta,
+        }
+    }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub enum Command {
     Prep,
     Seed,
     Align,
+    AlignRegion,
     Search,
+    Pipeline,
+    Merge,
+    Rbh,
+    Serve,
+    ViewSeeds,
+    ViewHmm,
+    Top,
+    Doctor,
+    BenchParallel,
+    BenchDense,
     #[default]
     CommandNotSet,
 }
 
-#[derive(Default)]
+/// The strategy used when the cloud search fails for a seed
+#[derive(Debug, Default, Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnBoundFail {
+    /// Drop the seed
+    #[default]
+    Skip,
+    /// Align the seed's profile and target with full (unbounded) DP
+    FullDp,
+    /// Retry the cloud search with relaxed pruning parameters
+    WidenRetry,
+}
+
+#[derive(Default, Clone)]
 pub struct Args {
     pub command: Command,
+    pub workspace: Workspace,
     pub paths: FilePaths,
     pub threads: usize,
     pub evalue_cutoff: f32,
+    pub inclusion_evalue: Option<f32>,
+    pub inclusion_bits: Option<f32>,
+    pub on_bound_fail: OnBoundFail,
+    pub shard: Option<Shard>,
+    pub ali_format: AliFormat,
+    pub ambiguity: AmbiguityPolicy,
+    pub model_overrides: ModelOverrides,
+    pub seed_filter: SeedFilter,
+    pub seeds_format: SeedsFormat,
+    pub dry_run: bool,
+    pub runner: Runner,
+    pub tools: ToolPaths,
+    pub taxon_filter: TaxonFilter,
+    pub query_filter: QueryFilter,
+    pub max_matrix_bytes: Option<usize>,
+    pub dense_cells: usize,
+    pub max_ram: Option<MemoryBudget>,
+    pub skip_hmmbuild: bool,
+    pub force: bool,
+    pub strict: bool,
+    pub comp_bias_filter: bool,
+    pub max_bias_fraction: Option<f32>,
+    pub min_aligned_length: Option<usize>,
+    pub min_profile_cov: Option<f32>,
+    pub min_target_cov: Option<f32>,
+    pub hit_filter: Option<HitFilter>,
+    pub fail_if_no_hits: bool,
+    pub output_version: OutputVersion,
+    pub number_format: NumberFormat,
+    pub compress_intermediates: bool,
+    pub k_score: Option<u32>,
+    pub kmer_length: Option<u32>,
+    pub max_seqs: Option<usize>,
+    pub sensitivity: Sensitivity,
+    pub target_type: TargetType,
+    pub orf_params: OrfParams,
+    pub schedule: Schedule,
+    pub time_limits: TimeLimits,
+    pub pin_threads: bool,
+    pub consensus_source: ConsensusSource,
+    pub verify_deterministic: Option<usize>,
+    pub per_query_dirs: bool,
+    pub on_complete: Option<String>,
+    pub webhook: Option<WebhookUrl>,
+    pub progress: ProgressMonitor,
+    pub region: AnchorRegion,
+    pub doctor_run: bool,
+    pub parallel_bench: ParallelBenchParams,
+    pub dense_bench: DenseBenchParams,
+    pub uploads: ObjectUploads,
+    /// The runner for the downloads and uploads, which run on the host even with --container
+    pub host_runner: Runner,
+    pub query_format: Option<QueryFormat>,
+    pub fast_pass: bool,
+    pub msv_pvalue: Option<f32>,
+    pub seed_expand: Option<SeedExpansion>,
+    pub chain_seeds: Option<usize>,
+    pub hit_context: HitContext,
+    pub alt_alignments: usize,
+    pub trace_format: TraceFormat,
+    pub sort_by: Vec<SortKey>,
+    pub stages: Vec<Stage>,
+    pub summary_count: usize,
+    pub color: bool,
+    pub seed_provenance: bool,
+    pub host: String,
+    pub port: u16,
+    pub preload: bool,
+    pub request_timeout: Duration,
 }
 
 impl Args {
     fn set_common(&mut self, args: &CommonArgs) {
         self.threads = args.threads;
+        self.max_ram = args.max_ram.map(MemoryBudget::from_gb);
+        self.strict = args.strict;
+        self.set_tools(&args.tools);
+    }
+
+    fn set_tools(&mut self, args: &ToolArgs) {
+        self.tools = ToolPaths::new(args.mmseqs_path.as_deref(), args.hmmer_path.as_deref());
+        self.tools.container = match (&args.use_docker, &args.use_singularity) {
+            (Some(image), _) => Some(Container::Docker(image.clone())),
+            (None, Some(image)) => Some(Container::Singularity(image.clone())),
+            (None, None) => None,
+        };
+    }
+
+    fn set_align(&mut self, args: &AlignArgs) {
+        self.evalue_cutoff = args.evalue_cutoff;
+        self.paths.threshold_table = args.threshold_table.as_ref().map(PathBuf::from);
+        self.inclusion_evalue = args.inclusion_evalue;
+        self.inclusion_bits = args.inclusion_bits;
+        self.paths.skip_existing = args.skip_existing.as_ref().map(PathBuf::from);
+        self.on_bound_fail = args.on_bound_fail;
+        self.shard = args.shard;
+        self.query_filter = QueryFilter {
+            include: args.query_include.clone().map(|p| p.0).unwrap_or_default(),
+            exclude: args.query_exclude.clone().map(|p| p.0).unwrap_or_default(),
+        };
+        self.max_matrix_bytes = args.max_matrix_mb.map(|mb| mb * 1024 * 1024);
+        self.dense_cells = args.dense_cells;
+        self.fast_pass = args.fast_pass;
+        self.msv_pvalue = args.msv_pvalue;
+        self.seed_expand = args.seed_expand;
+        self.chain_seeds = args.chain_seeds;
+        self.comp_bias_filter = args.comp_bias_filter;
+        self.max_bias_fraction = args.max_bias_fraction;
+        self.min_aligned_length = args.min_aligned_length;
+        self.min_profile_cov = args.min_profile_cov;
+        self.min_target_cov = args.min_target_cov;
+        self.hit_filter = args.filter.clone();
+        self.schedule = args.schedule;
+        self.time_limits = TimeLimits::new(args.time_limit, args.per_query_time_limit);
+        self.pin_threads = args.pin_threads;
+        self.consensus_source = args.consensus_source;
+        self.verify_deterministic = args.verify_deterministic.map(|n| n as usize);
+        self.ali_format = args.ali_format;
+        // set_common has already set strict
+        self.ambiguity = args.ambiguity.unwrap_or(match self.strict {
+            true => AmbiguityPolicy::Error,
+            false => AmbiguityPolicy::ScoreAsX,
+        });
+        self.model_overrides = ModelOverrides {
+            background_path: args.bg_freqs.as_ref().map(PathBuf::from),
+            gap_open: args.popen,
+            gap_extend: args.pext,
+        };
+        self.seed_filter = SeedFilter {
+            max_evalue: args.seed_max_evalue,
+            keep_top: args.seed_keep_top,
+        };
+    }
+
+    fn set_output_options(&mut self, args: &OutputArgs) {
+        self.summary_count = args.summary;
+        self.color = !args.no_color;
+        self.seed_provenance = args.seed_provenance;
+        self.per_query_dirs = args.per_query_dirs;
+        self.paths.hitmap = args.hitmap_file.as_ref().map(PathBuf::from);
+        self.paths.hits_fasta = args.hits_fasta.as_ref().map(PathBuf::from);
+        self.paths.abc = args.abc_output.as_ref().map(PathBuf::from);
+        self.paths.edge_list = args.edge_list.as_ref().map(PathBuf::from);
+        self.paths.bound_stats = args.bound_stats.as_ref().map(PathBuf::from);
+        self.paths.trace_output = args.trace_output.as_ref().map(PathBuf::from);
+        self.trace_format = args.trace_format;
+        self.alt_alignments = args.alt_alignments;
+        self.paths.alt_alignments =
+            (args.alt_alignments > 0).then(|| PathBuf::from(&args.alt_file));
+        self.sort_by = args.sort_by.clone();
+        self.hit_context = HitContext {
+            flank: args.flank,
+            full_target: args.full_target,
+        };
+        self.output_version = args.output_version;
+        self.set_number_format(&args.number_format);
+        self.paths.run_summary = args.run_summary.as_ref().map(PathBuf::from);
+        self.fail_if_no_hits = args.fail_if_no_hits;
+    }
+
+    /// The path to write the skipped seeds to: the one given, or else skipped.tsv
+    /// in the directory (or under the object store prefix) of the results.
+    fn skipped_path(&mut self, args: &OutputArgs) -> PathBuf {
+        if let Some(skipped_file) = &args.skipped_file {
+            return PathBuf::from(skipped_file);
+        }
+        match args.output_file.rsplit_once('/') {
+            Some((dir, _)) => self.uploads.output_path(&format!("{dir}/skipped.tsv")),
+            None => PathBuf::from("skipped.tsv"),
+        }
+    }
+
+    fn set_seed(&mut self, args: &SeedArgs) {
+        self.compress_intermediates = args.compress_intermediates;
+        self.k_score = args.k_score;
+        self.kmer_length = args.kmer_length;
+        self.max_seqs = args.max_seqs;
+        self.paths.target_include_ids = args.target_include_ids.as_ref().map(PathBuf::from);
+    }
+
+    fn set_sensitivity(&mut self, args: &SensitivityArgs) {
+        self.sensitivity = args.sensitivity;
+    }
+
+    fn set_notify(&mut self, args: &NotifyArgs) {
+        self.on_complete = args.on_complete.clone();
+        self.webhook = args.webhook.clone();
+    }
+
+    fn set_monitor(&mut self, args: &MonitorArgs) {
+        if let Some(progress_file) = &args.progress_file {
+            self.paths.progress = Some(PathBuf::from(progress_file));
+        }
+        self.progress = ProgressMonitor::new(self.paths.progress.clone(), args.tui);
+    }
+
+    fn set_search(&mut self, args: SearchArgs) {
+        self.set_common(&args.common);
+        self.set_taxonomy(&args.taxonomy);
+        self.set_mask(&args.mask);
+        self.set_align(&args.align);
+        self.set_notify(&args.notify);
+
+        if let Some(query) = args.query {
+            self.paths.query = PathBuf::from(query);
+        }
+        self.paths.batch = args.batch.map(PathBuf::from);
+        self.paths.config = args.config.map(PathBuf::from);
+        self.query_format = args.query_format;
+        self.set_seed(&args.seed);
+        self.set_sensitivity(&args.sensitivity);
+
+        let output = args.output;
+        self.set_output_options(&output);
+        let skipped_file = self.skipped_path(&output);
+        let (work_dir, output_file, skipped_file, ali_file) = match args.output_dir {
+            Some(output_dir) => {
+                // the standard run directory layout:
+                //   RUN_DIR/
+                //     prep/             intermediate files
+                //     results/          results, skipped seeds, and alignments
+                //     logs/             logs
+                //     run.json          the run manifest
+                //     run-params.toml   the run's parameters, after tuning and defaulting
+                //     run-summary.json  the run totals
+                //     progress.json     the run's progress, for mmoreseqs top
+                let run_dir = self.uploads.output_path(&output_dir);
+                let results_dir = run_dir.join("results");
+                let logs_dir = run_dir.join("logs");
+
+                create_dir_all(&results_dir).expect("failed to create results directory");
+                create_dir_all(&logs_dir).expect("failed to create logs directory");
+
+                self.paths.logs_dir = Some(logs_dir);
+                self.paths.manifest = Some(run_dir.join("run.json"));
+                self.paths.run_params = Some(run_dir.join("run-params.toml"));
+                self.paths.run_summary = Some(run_dir.join("run-summary.json"));
+                self.paths.progress = Some(run_dir.join("progress.json"));
+
+                (
+                    run_dir.join("prep"),
+                    results_dir.join("results.tsv"),
+                    results_dir.join("skipped.tsv"),
+                    Some(results_dir.join(format!("alignments.{}", self.ali_format.extension()))),
+                )
+            }
+            None => (
+                PathBuf::from(args.work_dir),
+                self.uploads.output_path(&output.output_file),
+                skipped_file,
+                output.ali_file.map(PathBuf::from),
+            ),
+        };
+
+        self.set_workspace(Workspace::new(work_dir));
+        self.set_target_fasta(args.target, args.extra_targets);
+        self.set_target_type(&args.target_type);
+
+        self.paths.results = output_file;
+        self.paths.skipped = skipped_file;
+        self.paths.alignments = ali_file;
+
+        self.set_monitor(&args.monitor);
+    }
+
+    /// The path to read a target fasta from, which for a URL or an object store
+    /// URI is the workspace's local copy, and the URI is kept to fetch from.
+    fn target_path(&mut self, target: String) -> PathBuf {
+        if !is_remote_target(&target) {
+            return PathBuf::from(target);
+        }
+        // clap has already checked the URI with parse_target_location
+        self.paths.target_uri = Some(target.parse().expect("invalid target URI"));
+        self.paths.remote_target_fasta.clone()
+    }
+
+    /// Set the target fasta, which is the workspace's combined
+    /// targets if there are several files or a directory of them.
+    fn set_target_fasta(&mut self, target: String, extra_targets: Vec<String>) {
+        let target = match extra_targets.is_empty() {
+            true => self.target_path(target),
+            false => PathBuf::from(target),
+        };
+        match extra_targets.is_empty() && !target.is_dir() {
+            true => self.paths.target_fasta = target,
+            false => {
+                self.paths.target_fasta_sources = std::iter::once(target)
+                    .chain(extra_targets.into_iter().map(PathBuf::from))
+                    .collect();
+                self.paths.target_fasta = self.paths.combined_target_fasta.clone();
+            }
+        }
+    }
+
+    /// With --target-type contigs, the target fasta is the workspace's ORFs, which are called on the contigs.
+    fn set_target_type(&mut self, args: &TargetTypeArgs) {
+        self.target_type = args.target_type;
+        self.orf_params = OrfParams {
+            min_length: args.min_orf_length,
+            genetic_code: args.genetic_code,
+        };
+        if args.target_type == TargetType::Contigs {
+            self.paths.target_contigs = Some(self.paths.target_fasta.clone());
+            self.paths.target_fasta = self.paths.target_orfs.clone();
+        }
+    }
+
+    fn set_workspace(&mut self, workspace: Workspace) {
+        workspace.fill_paths(&mut self.paths);
+        self.workspace = workspace;
+    }
+
+    fn set_number_format(&mut self, args: &NumberFormatArgs) {
+        self.number_format = NumberFormat {
+            evalue_format: args.evalue_format,
+            evalue_precision: args.evalue_precision,
+            score_precision: args.score_precision,
+        };
+    }
+
+    fn set_mask(&mut self, args: &MaskArgs) {
+        self.paths.mask_previous = args.mask_previous.as_ref().map(PathBuf::from);
+    }
+
+    fn set_taxonomy(&mut self, args: &TaxonomyArgs) {
+        self.paths.taxdb = args.taxdb.as_ref().map(PathBuf::from);
+        self.taxon_filter = TaxonFilter {
+            include: args.taxon_include.clone(),
+            exclude: args.taxon_exclude.clone(),
+        };
     }
 }
 
 fn main() -> Result<()> {
-    let args = Cli::parse().args();
+    let cli = Cli::parse_from(expand_config_args(std::env::args().collect())?);
+    if cli.version_json {
+        return print_version_json();
+    }
+    let mut args = cli.args();
+
+    let recording_runner = Arc::new(RecordingRunner::new());
+    if args.dry_run {
+        args.runner = Runner::shared(recording_runner.clone());
+    } else {
+        args.runner = Runner::new(ProcessRunner::new(Some(logs_dir(&args))));
+    }
+
+    // the downloads and uploads are run on the host, and not in the tools' container
+    args.host_runner = args.runner.clone();
+    args.uploads.set_runner(args.host_runner.clone());
+    if let Some(container) = &args.tools.container {
+        args.runner = Runner::new(ContainerRunner::new(container.clone(), args.runner.clone()));
+    }
+
+    // a search stops between seeds and stages when interrupted, and writes what it
+    // has; a server or `top` has nothing to write, so it is left to exit at once
+    if matches!(
+        args.command,
+        Command::Prep
+            | Command::Seed
+            | Command::Align
+            | Command::Search
+            | Command::Pipeline
+            | Command::Rbh
+    ) && !args.dry_run
+    {
+        shutdown::install_signal_handlers();
+    }
 
-    check_hmmer_installed()?;
-    check_mmseqs_installed()?;
+    let started = SystemTime::now();
+    let result = run_command(&args);
+    args.progress.finish(result.is_ok());
+    // the outputs bound for object storage are uploaded however the run ended,
+    // so that the partial results of a preempted job aren't lost
+    let result = match (result, args.uploads.upload()) {
+        (Ok(()), Err(upload_err)) => Err(upload_err),
+        (result, _) => result,
+    };
+    notify_completion(&args, started, &result);
+
+    // the partial results are written, so this is reported with its own exit status
+    if let Err(err) = &result {
+        if err.downcast_ref::<TimeLimitReachedError>().is_some() {
+            eprintln!("Error: {err}");
+            std::process::exit(TIME_LIMIT_EXIT_CODE);
+        }
+        if let Some(cancelled) = err.downcast_ref::<CancelledError>() {
+            eprintln!("Error: {err}");
+            std::process::exit(cancelled.exit_code());
+        }
+    }
+    result?;
+
+    if args.dry_run {
+        for command in recording_runner.commands() {
+            println!("{command}");
+        }
+    }
+
+    Ok(())
+}
+
+/// The directory of the external tools' logs: the run directory's logs with
+/// --output-dir, or else a logs directory next to what the command writes.
+fn logs_dir(args: &Args) -> PathBuf {
+    if let Some(logs_dir) = &args.paths.logs_dir {
+        return logs_dir.clone();
+    }
+    let output_dir = match args.command {
+        // prep writes to the prep dir, and a server or an align-region only to stdout
+        Command::Prep | Command::Serve | Command::AlignRegion => &args.workspace.dir,
+        Command::Seed => args.paths.seeds.parent().unwrap_or(Path::new("")),
+        _ => args.paths.results.parent().unwrap_or(Path::new("")),
+    };
+    output_dir.join("logs")
+}
+
+/// Check that the external tools are installed, and run the command.
+fn run_command(args: &Args) -> Result<()> {
+    // watching another run or merging results doesn't need the tools, and the
+    // doctor checks for them itself
+    match (&args.command, &args.paths.progress) {
+        (Command::Top, Some(progress_file)) => return top(progress_file, Duration::from_secs(1)),
+        (Command::Merge, _) => return merge(args),
+        (Command::Doctor, _) => return doctor(args),
+        (Command::BenchParallel, _) => return bench_parallel(args),
+        (Command::BenchDense, _) => return bench_dense(args),
+        _ => {}
+    }
+
+    args.tools.validate()?;
+    // a dry run doesn't run the tools, so the checks would only clutter the commands it prints
+    if !args.dry_run {
+        check_hmmer_installed(args)?;
+        check_mmseqs_installed(args)?;
+    }
 
     match args.command {
         Command::Prep => {
-            prep(&args)?;
+            prep(args)?;
         }
         Command::Seed => {
-            seed(&args)?;
+            seed(args)?;
         }
         Command::Align => {
-            align(&args)?;
+            align(args)?;
+        }
+        Command::AlignRegion => {
+            align_region(args)?;
+        }
+        Command::Search | Command::Pipeline => {
+            run_stages(args)?;
+        }
+        Command::Rbh => {
+            rbh(args)?;
         }
-        Command::Search => {
-            search(&args)?;
+        Command::Serve => {
+            serve(args)?;
+        }
+        Command::ViewSeeds => {
+            view_seeds(args)?;
+        }
+        Command::ViewHmm => {
+            view_hmm(args)?;
+        }
+        Command::Top
+        | Command::Merge
+        | Command::Doctor
+        | Command::BenchParallel
+        | Command::BenchDense => {
+            unreachable!()
         }
         Command::CommandNotSet => {
             unreachable!()
@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use nale::output::path_buf_ext::PathBufExt;
+use serde::Serialize;
+
+use crate::Args;
+
+/// How a run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Complete,
+    /// The time limit was reached before every query was fully searched
+    Partial,
+    /// The run was stopped by SIGINT or SIGTERM
+    Cancelled,
+}
+
+/// A description of a run, written as run.json in the run directory.
+#[derive(Serialize)]
+pub struct RunManifest {
+    pub version: &'static str,
+    pub status: RunStatus,
+    pub arguments: Vec<String>,
+    pub query: PathBuf,
+    pub batch: Option<PathBuf>,
+    pub target: PathBuf,
+    /// The fasta files (or directories) that were combined into the target
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub target_sources: Vec<PathBuf>,
+    pub threads: usize,
+    pub evalue_cutoff: f32,
+    pub results: PathBuf,
+    pub skipped: PathBuf,
+    pub alignments: Option<PathBuf>,
+    pub logs: Option<PathBuf>,
+}
+
+impl RunManifest {
+    pub fn new(args: &Args, status: RunStatus) -> Self {
+        RunManifest {
+            version: env!("CARGO_PKG_VERSION"),
+            status,
+            arguments: std::env::args().collect(),
+            query: args.paths.query.clone(),
+            batch: args.paths.batch.clone(),
+            target: args.paths.target_fasta.clone(),
+            target_sources: args.paths.target_fasta_sources.clone(),
+            threads: args.threads,
+            evalue_cutoff: args.evalue_cutoff,
+            results: args.paths.results.clone(),
+            skipped: args.paths.skipped.clone(),
+            alignments: args.paths.alignments.clone(),
+            logs: args.paths.logs_dir.clone(),
+        }
+    }
+
+    pub fn write(&self, path: &PathBuf) -> Result<()> {
+        serde_json::to_writer_pretty(&mut path.open(true)?, self)?;
+        Ok(())
+    }
+}
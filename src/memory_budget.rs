@@ -0,0 +1,172 @@
+use std::fs::metadata;
+use std::path::Path;
+
+use crate::dp_matrices::DpMatrices;
+use crate::pipeline::TargetStore;
+
+/// The bytes held per target residue once the targets are loaded (text and digital)
+const BYTES_PER_TARGET_RESIDUE: usize = 2;
+
+/// The fraction of the budget that a target database may take up and still be loaded whole
+const MAX_LOADED_DB_FRACTION: f64 = 0.5;
+
+/// The least memory that a worker's DP matrices are allowed to keep between seeds
+const MIN_MATRIX_BYTES: usize = 64 * 1024 * 1024;
+
+pub fn parse_gigabytes(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(gb) if gb > 0.0 => Ok(gb),
+        _ => Err(format!("{value} is not a positive number of gigabytes")),
+    }
+}
+
+/// A single memory budget, set with --max-ram, that each stage derives its own limits from.
+///
+/// It isn't a cap on the whole run: it sets how mmseqs splits its prefilter and loads its
+/// databases, whether the aligner loads the whole target fasta or only the seeded targets,
+/// how many align workers run at once, and how much their DP matrices may keep between
+/// seeds. A seed whose matrices need more than the budget is still aligned.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryBudget {
+    bytes: usize,
+}
+
+impl MemoryBudget {
+    pub fn from_gb(gb: f64) -> Self {
+        MemoryBudget {
+            bytes: (gb * 1024.0 * 1024.0 * 1024.0) as usize,
+        }
+    }
+
+    /// The value of mmseqs prefilter's --split-memory-limit
+    pub fn split_memory_limit(&self) -> String {
+        format!("{}M", self.bytes / (1024 * 1024))
+    }
+
+    /// The value of mmseqs' --db-load-mode for a database.
+    ///
+    /// A database that fits comfortably in the budget is mapped and touched,
+    /// so that it is read into memory up front; otherwise it is only mapped,
+    /// and the pages are read as they are needed.
+    pub fn db_load_mode(&self, db: &Path) -> &'static str {
+        match self.loads_whole(db) {
+            true => "3",
+            false => "2",
+        }
+    }
+
+    /// Whether a file fits comfortably enough in the budget to be read into memory whole.
+    ///
+    /// This decides how mmseqs loads a database, and whether the aligner loads the
+    /// whole target fasta, or only the targets that have seeds.
+    pub fn loads_whole(&self, path: &Path) -> bool {
+        let file_bytes = metadata(path).map(|m| m.len()).unwrap_or(0);
+        file_bytes as f64 <= self.bytes as f64 * MAX_LOADED_DB_FRACTION
+    }
+
+    /// The most align workers whose DP matrices fit in what the budget has left after
+    /// the targets are loaded, each with matrices for the largest seed of the run,
+    /// and at least one.
+    pub fn max_workers(
+        &self,
+        targets: &TargetStore,
+        max_target_length: usize,
+        max_profile_length: usize,
+    ) -> usize {
+        let worker_bytes = DpMatrices::bytes_for(max_target_length, max_profile_length);
+        (self.bytes.saturating_sub(target_bytes(targets)) / worker_bytes.max(1)).max(1)
+    }
+
+    /// The most memory each worker's DP matrices may keep between seeds, which is an
+    /// even share of whatever the budget has left after the targets are loaded. The
+    /// matrices still grow past it for a larger seed, and are released after it.
+    pub fn matrix_bytes(&self, targets: &TargetStore, workers: usize) -> usize {
+        (self.bytes.saturating_sub(target_bytes(targets)) / workers.max(1)).max(MIN_MATRIX_BYTES)
+    }
+}
+
+/// The memory that the loaded targets take up
+fn target_bytes(targets: &TargetStore) -> usize {
+    targets
+        .sequences
+        .values()
+        .map(|s| s.length * BYTES_PER_TARGET_RESIDUE)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::temp_file;
+    use nale::structs::Sequence;
+    use std::collections::{HashMap, HashSet};
+    use std::fs::remove_file;
+
+    /// A target store of one target of the given length
+    fn one_target(length: usize) -> TargetStore {
+        let mut target = Sequence::from_utf8(&b"A".repeat(length)).unwrap();
+        target.name = "tgt".to_string();
+        TargetStore {
+            sequences: HashMap::from([(target.name.clone(), target)]),
+            count: 1,
+            taxonomy: None,
+            ambiguous_names: HashSet::new(),
+            sources: None,
+            orfs: None,
+        }
+    }
+
+    #[test]
+    fn budgets_must_be_positive() {
+        assert_eq!(parse_gigabytes("1.5"), Ok(1.5));
+        assert!(parse_gigabytes("0").is_err());
+        assert!(parse_gigabytes("-2").is_err());
+        assert!(parse_gigabytes("lots").is_err());
+    }
+
+    #[test]
+    fn the_split_memory_limit_is_in_megabytes() {
+        assert_eq!(MemoryBudget::from_gb(2.0).split_memory_limit(), "2048M");
+        assert_eq!(MemoryBudget::from_gb(0.5).split_memory_limit(), "512M");
+    }
+
+    #[test]
+    fn only_files_well_inside_the_budget_are_loaded_whole() {
+        let path = temp_file("targets.fa", &">tgt\nACDEF\n".repeat(100));
+        let small = MemoryBudget { bytes: 1000 };
+        let large = MemoryBudget { bytes: 10_000 };
+        let (loaded_by_small, loaded_by_large) =
+            (small.loads_whole(&path), large.loads_whole(&path));
+        let modes = (small.db_load_mode(&path), large.db_load_mode(&path));
+        remove_file(&path).unwrap();
+
+        assert!(!loaded_by_small);
+        assert!(loaded_by_large);
+        assert_eq!(modes, ("2", "3"));
+    }
+
+    #[test]
+    fn the_workers_are_capped_by_their_largest_matrices() {
+        let targets = one_target(1000);
+        let worker_bytes = DpMatrices::bytes_for(1000, 200);
+        let budget = MemoryBudget {
+            bytes: target_bytes(&targets) + 3 * worker_bytes + 1,
+        };
+        assert_eq!(budget.max_workers(&targets, 1000, 200), 3);
+
+        // a budget too small for even one worker's matrices still aligns on one
+        let budget = MemoryBudget { bytes: 1 };
+        assert_eq!(budget.max_workers(&targets, 1000, 200), 1);
+    }
+
+    #[test]
+    fn the_matrix_estimate_covers_the_allocated_matrices() {
+        for (target_length, profile_length) in [(40, 24), (300, 120)] {
+            let mut matrices = DpMatrices::new(None);
+            matrices.reuse(target_length, profile_length);
+            let estimate = DpMatrices::bytes_for(target_length, profile_length);
+            assert!(estimate >= matrices.allocated_bytes());
+            assert!(estimate <= 2 * matrices.allocated_bytes().max(1));
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Reading the consensus sequences out of an mmseqs profile database.
+//!
+//! Each profile entry is a run of fixed-size records, one per profile column,
+//! followed by a null terminator. Every record starts with the 20 amino acid
+//! scores, then the query residue and the consensus residue, and mmseqs
+//! releases have appended more fields since:
+//!
+//! | layout         | record                                          | bytes |
+//! |----------------|-------------------------------------------------|-------|
+//! | scores         | 20 scores, query, consensus                     | 22    |
+//! | neff           | 20 scores, query, consensus, neff               | 23    |
+//! | gap penalties  | 20 scores, query, consensus, neff, gDel, gIns   | 25    |
+//!
+//! mmseqs doesn't record the layout in the database, so it is detected from
+//! the entries: a layout fits if every entry is a whole number of records and
+//! every query and consensus residue is a valid residue code. The detection
+//! fails, rather than guessing, unless exactly one layout fits.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use nale::structs::Sequence;
+use thiserror::Error;
+
+use crate::tools::ToolPaths;
+
+/// The number of amino acid scores at the start of each record
+const SCORE_COUNT: usize = 20;
+
+/// The residue codes that mmseqs uses: the 20 amino acids, in nale's order, then X
+const MMSEQS_X: u8 = 20;
+
+/// nale's digital code for X
+const NALE_X: u8 = 22;
+
+#[derive(Error, Debug)]
+#[error(
+    "the mmseqs profile database {fit} (the installed mmseqs is version \
+     {mmseqs_version}); its entries are {entry_sizes} bytes long"
+)]
+pub struct UnsupportedProfileLayoutError {
+    /// How the database fails to fit exactly one of the known layouts
+    fit: String,
+    mmseqs_version: String,
+    entry_sizes: String,
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "an mmseqs profile entry of {length} bytes isn't a whole number of {record_size} byte records"
+)]
+pub struct TruncatedProfileRecordError {
+    length: usize,
+    record_size: usize,
+}
+
+#[derive(Error, Debug)]
+#[error("compressed mmseqs profile databases aren't supported; rebuild the query database without --compressed")]
+pub struct CompressedProfileDbError;
+
+/// Where the consensus sequences of the mmseqs query profiles come from
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+pub enum ConsensusSource {
+    /// Parse the profile database, and fall back to mmseqs if its layout is unknown
+    #[default]
+    Auto,
+    /// Only parse the profile database
+    Parse,
+    /// Have mmseqs write the consensus sequences (profile2consensus and convert2fasta)
+    Mmseqs,
+}
+
+/// The record layouts of mmseqs profile databases, oldest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileLayout {
+    Scores,
+    Neff,
+    GapPenalties,
+}
+
+impl ProfileLayout {
+    /// The layouts to try, newest first
+    const DETECTION_ORDER: [ProfileLayout; 3] = [
+        ProfileLayout::GapPenalties,
+        ProfileLayout::Neff,
+        ProfileLayout::Scores,
+    ];
+
+    pub fn record_size(&self) -> usize {
+        match self {
+            ProfileLayout::Scores => SCORE_COUNT + 2,
+            ProfileLayout::Neff => SCORE_COUNT + 3,
+            ProfileLayout::GapPenalties => SCORE_COUNT + 5,
+        }
+    }
+
+    fn query_offset(&self) -> usize {
+        SCORE_COUNT
+    }
+
+    fn consensus_offset(&self) -> usize {
+        SCORE_COUNT + 1
+    }
+
+    /// Whether an entry (without its terminator) is made of records in this layout.
+    fn fits(&self, entry: &[u8]) -> bool {
+        let is_residue = |byte: u8| byte <= MMSEQS_X;
+        !entry.is_empty()
+            && entry.len().is_multiple_of(self.record_size())
+            && entry.chunks(self.record_size()).all(|record| {
+                is_residue(record[self.query_offset()])
+                    && is_residue(record[self.consensus_offset()])
+            })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ProfileLayout::Scores => "scores",
+            ProfileLayout::Neff => "neff",
+            ProfileLayout::GapPenalties => "gap penalties",
+        }
+    }
+
+    /// Detect the layout of a profile database from its entries.
+    ///
+    /// It is an error if no layout fits, or if more than one does.
+    pub fn detect(entries: &[Vec<u8>], tools: &ToolPaths) -> Result<Self> {
+        let entries: Vec<&[u8]> = entries.iter().map(|e| strip_terminator(e)).collect();
+
+        let fitting: Vec<ProfileLayout> = Self::DETECTION_ORDER
+            .into_iter()
+            .filter(|layout| entries.iter().all(|entry| layout.fits(entry)))
+            .collect();
+
+        match fitting[..] {
+            [layout] => Ok(layout),
+            _ => {
+                let fit = match fitting.is_empty() {
+                    true => "doesn't fit any known profile layout".to_string(),
+                    false => format!(
+                        "fits more than one profile layout ({})",
+                        fitting
+                            .iter()
+                            .map(|layout| layout.name())
+                            .collect::<Vec<&str>>()
+                            .join(", ")
+                    ),
+                };
+                let mut entry_sizes: Vec<usize> = entries.iter().map(|e| e.len()).collect();
+                entry_sizes.sort();
+                entry_sizes.dedup();
+                entry_sizes.truncate(5);
+                Err(UnsupportedProfileLayoutError {
+                    fit,
+                    mmseqs_version: mmseqs_version(tools),
+                    entry_sizes: entry_sizes
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                }
+                .into())
+            }
+        }
+    }
+
+    /// Read the consensus sequence of a profile entry.
+    pub fn consensus(&self, entry: &[u8]) -> Result<Sequence> {
+        let entry = strip_terminator(entry);
+        if !entry.len().is_multiple_of(self.record_size()) {
+            return Err(TruncatedProfileRecordError {
+                length: entry.len(),
+                record_size: self.record_size(),
+            }
+            .into());
+        }
+
+        let consensus: Vec<u8> = entry
+            .chunks_exact(self.record_size())
+            .map(|record| match record[self.consensus_offset()] {
+                MMSEQS_X => NALE_X,
+                residue => residue,
+            })
+            .collect();
+
+        Sequence::from_digital(&consensus)
+    }
+}
+
+fn strip_terminator(entry: &[u8]) -> &[u8] {
+    entry.strip_suffix(&[0]).unwrap_or(entry)
+}
+
+/// The version that mmseqs reports, for error messages
+fn mmseqs_version(tools: &ToolPaths) -> String {
+    tools
+        .mmseqs()
+        .arg("version")
+        .output()
+        .ok()
+        .and_then(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .map(|line| line.trim().to_string())
+        })
+        .filter(|version| !version.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tool paths whose mmseqs doesn't exist, so that its version is "unknown"
+    fn no_tools() -> ToolPaths {
+        let mut tools = ToolPaths::default();
+        tools.mmseqs.path = "/nonexistent/mmseqs".into();
+        tools
+    }
+
+    /// A null-terminated profile entry in a layout, with a record for each
+    /// (query, consensus) residue pair. The scores and any trailing fields
+    /// aren't residue codes, so that a record only fits its own layout.
+    fn entry(layout: ProfileLayout, residues: &[(u8, u8)]) -> Vec<u8> {
+        let mut entry = vec![];
+        for &(query, consensus) in residues {
+            let mut record = vec![0xf0; layout.record_size()];
+            record[SCORE_COUNT] = query;
+            record[SCORE_COUNT + 1] = consensus;
+            entry.extend(record);
+        }
+        entry.push(0);
+        entry
+    }
+
+    #[test]
+    fn each_layout_is_detected_from_its_record_size() {
+        for layout in ProfileLayout::DETECTION_ORDER {
+            let entries = vec![
+                entry(layout, &[(0, 0), (1, 1), (2, MMSEQS_X)]),
+                entry(layout, &[(3, 3)]),
+            ];
+            assert_eq!(
+                ProfileLayout::detect(&entries, &no_tools()).unwrap(),
+                layout
+            );
+        }
+        assert_eq!(ProfileLayout::Scores.record_size(), 22);
+        assert_eq!(ProfileLayout::Neff.record_size(), 23);
+        assert_eq!(ProfileLayout::GapPenalties.record_size(), 25);
+    }
+
+    #[test]
+    fn the_consensus_is_read_from_each_layout() {
+        for layout in ProfileLayout::DETECTION_ORDER {
+            let entry = entry(layout, &[(1, 0), (1, 1), (0, 2), (0, MMSEQS_X)]);
+            let consensus = layout.consensus(&entry).unwrap();
+            assert_eq!(&consensus.utf8_bytes[1..], b"ACDX");
+        }
+    }
+
+    #[test]
+    fn a_truncated_record_is_an_error() {
+        let mut truncated = entry(ProfileLayout::GapPenalties, &[(0, 0), (1, 1)]);
+        truncated.remove(0);
+        let whole = entry(ProfileLayout::GapPenalties, &[(0, 0)]);
+
+        let err = ProfileLayout::detect(&[whole, truncated.clone()], &no_tools()).unwrap_err();
+        assert!(err.is::<UnsupportedProfileLayoutError>());
+        assert!(err
+            .to_string()
+            .contains("doesn't fit any known profile layout"));
+        assert!(err.to_string().contains("version unknown"));
+        assert!(err.to_string().contains("25, 49 bytes"));
+
+        let err = ProfileLayout::GapPenalties
+            .consensus(&truncated)
+            .unwrap_err();
+        assert!(err.is::<TruncatedProfileRecordError>());
+    }
+
+    #[test]
+    fn an_unknown_record_size_is_an_error() {
+        let entries = vec![vec![0xf0; 24], vec![0xf0; 48]];
+        let err = ProfileLayout::detect(&entries, &no_tools()).unwrap_err();
+        assert!(err.is::<UnsupportedProfileLayoutError>());
+        assert!(err.to_string().contains("24, 48 bytes"));
+        assert!(err
+            .to_string()
+            .contains("doesn't fit any known profile layout"));
+
+        let err = ProfileLayout::detect(&[vec![0]], &no_tools()).unwrap_err();
+        assert!(err.is::<UnsupportedProfileLayoutError>());
+    }
+
+    #[test]
+    fn entries_that_fit_more_than_one_layout_are_an_error() {
+        // 22 records of 25 bytes are 25 records of 22 bytes, and zero is a residue code
+        let entries = vec![vec![0; 22 * 25 + 1]];
+        let err = ProfileLayout::detect(&entries, &no_tools()).unwrap_err();
+        assert!(err.is::<UnsupportedProfileLayoutError>());
+        assert!(err
+            .to_string()
+            .contains("fits more than one profile layout (gap penalties, scores)"));
+    }
+}
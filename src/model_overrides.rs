@@ -0,0 +1,173 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use nale::alphabet::{AMINO_ALPHABET, AMINO_BACKGROUND_FREQUENCIES};
+use nale::structs::Profile;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("invalid background frequency file: {0}")]
+pub struct InvalidBackgroundError(String);
+
+/// Parse a probability that is strictly between 0 and 1
+pub fn parse_probability(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(p) if p > 0.0 && p < 1.0 => Ok(p),
+        _ => Err(format!("{value} is not a probability between 0 and 1")),
+    }
+}
+
+/// Parse a fraction between 0 and 1 (inclusive).
+pub fn parse_fraction(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(f) if (0.0..=1.0).contains(&f) => Ok(f),
+        _ => Err(format!("{value} is not a fraction between 0 and 1")),
+    }
+}
+
+/// Read background residue frequencies from a file.
+///
+/// Each line holds a residue and its frequency, separated by whitespace, and
+/// lines starting with '#' are ignored. All 20 standard residues must be listed.
+/// The frequencies are normalized to sum to 1.
+fn read_background(path: &Path) -> Result<[f32; 20]> {
+    let file = File::open(path).context(format!(
+        "failed to open background frequency file: {}",
+        path.to_string_lossy()
+    ))?;
+
+    let mut frequencies: [Option<f32>; 20] = [None; 20];
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let (residue, frequency) = match tokens[..] {
+            [residue, frequency] => (residue.to_ascii_uppercase(), frequency),
+            _ => return Err(InvalidBackgroundError(format!("malformed line: {line}")).into()),
+        };
+
+        let residue_idx = AMINO_ALPHABET
+            .iter()
+            .position(|&r| r == residue)
+            .ok_or_else(|| InvalidBackgroundError(format!("unknown residue: {residue}")))?;
+
+        let frequency = frequency
+            .parse::<f32>()
+            .ok()
+            .filter(|f| *f > 0.0)
+            .ok_or_else(|| InvalidBackgroundError(format!("invalid frequency: {frequency}")))?;
+
+        frequencies[residue_idx] = Some(frequency);
+    }
+
+    let mut background = [0.0; 20];
+    for (residue_idx, frequency) in frequencies.iter().enumerate() {
+        background[residue_idx] = frequency.ok_or_else(|| {
+            InvalidBackgroundError(format!("missing residue: {}", AMINO_ALPHABET[residue_idx]))
+        })?;
+    }
+
+    let total: f32 = background.iter().sum();
+    background.iter_mut().for_each(|f| *f /= total);
+
+    Ok(background)
+}
+
+/// Adjustments made to the query profiles after they are built from the HMMs.
+///
+/// Note that the profiles' E-value calibration (lambda and tau) is not
+/// recomputed, so E-values become approximate when these are used.
+#[derive(Debug, Default, Clone)]
+pub struct ModelOverrides {
+    /// A file of background residue frequencies to score matches against
+    pub background_path: Option<PathBuf>,
+    /// The probability of opening an insertion after a match
+    pub gap_open: Option<f32>,
+    /// The probability of extending an insertion
+    pub gap_extend: Option<f32>,
+}
+
+impl ModelOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.background_path.is_none() && self.gap_open.is_none() && self.gap_extend.is_none()
+    }
+
+    /// Apply the overrides to each of the profiles.
+    pub fn apply(&self, profiles: &mut [Profile]) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let background = match &self.background_path {
+            Some(path) => Some(read_background(path)?),
+            None => None,
+        };
+
+        for profile in profiles.iter_mut() {
+            if let Some(background) = &background {
+                rescore_matches(profile, background);
+            }
+            self.set_insert_transitions(profile);
+        }
+
+        Ok(())
+    }
+
+    /// Replace the match to insert and insert to insert transitions, and rescale
+    /// the remaining transitions so that each state's transitions still sum to 1.
+    fn set_insert_transitions(&self, profile: &mut Profile) {
+        // there is no insert state after the last match state
+        for profile_idx in 1..profile.length {
+            let transitions = &mut profile.transitions[profile_idx];
+
+            if let Some(gap_open) = self.gap_open {
+                let old_open = transitions[Profile::MATCH_TO_INSERT_IDX].exp();
+                let scale = ((1.0 - gap_open) / (1.0 - old_open)).ln();
+                transitions[Profile::MATCH_TO_MATCH_IDX] += scale;
+                transitions[Profile::MATCH_TO_DELETE_IDX] += scale;
+                transitions[Profile::MATCH_TO_INSERT_IDX] = gap_open.ln();
+            }
+
+            if let Some(gap_extend) = self.gap_extend {
+                transitions[Profile::INSERT_TO_INSERT_IDX] = gap_extend.ln();
+                transitions[Profile::INSERT_TO_MATCH_IDX] = (1.0 - gap_extend).ln();
+            }
+        }
+    }
+}
+
+/// Rescore the match emissions of a profile against a new background.
+///
+/// The match scores are log-odds ratios against the default background, so
+/// each one is shifted by the log ratio of the old and new frequencies.
+fn rescore_matches(profile: &mut Profile, background: &[f32; 20]) {
+    for profile_idx in 1..=profile.length {
+        let scores = &mut profile.match_scores[profile_idx];
+
+        for residue_idx in 0..Profile::MAX_ALPHABET_SIZE {
+            scores[residue_idx] +=
+                (AMINO_BACKGROUND_FREQUENCIES[residue_idx] / background[residue_idx]).ln();
+        }
+
+        // the degenerate residues are scored with the
+        // background-weighted average of the residue scores
+        let degenerate_score: f32 = (0..Profile::MAX_ALPHABET_SIZE)
+            .map(|residue_idx| scores[residue_idx] * background[residue_idx])
+            .sum();
+
+        for score in scores
+            .iter_mut()
+            .take(Profile::MAX_DEGENERATE_ALPHABET_SIZE - 3)
+            .skip(Profile::MAX_ALPHABET_SIZE)
+        {
+            *score = degenerate_score;
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use nale::align::bounded::structs::Seed;
+use nale::structs::hmm::Hmm;
+use nale::structs::{Profile, Sequence};
+
+/// The Gumbel parameters of a profile's MSV score distribution, from the HMM's STATS LOCAL MSV line
+#[derive(Debug, Clone, Copy)]
+pub struct MsvStats {
+    pub mu: f32,
+    pub lambda: f32,
+}
+
+impl MsvStats {
+    pub fn new(hmm: &Hmm) -> Self {
+        MsvStats {
+            mu: hmm.stats.msv_gumble_mu,
+            lambda: hmm.stats.msv_gumble_lambda,
+        }
+    }
+
+    /// The P-value of an MSV bit score
+    pub fn pvalue(&self, bit_score: f32) -> f32 {
+        1.0 - (-(-self.lambda * (bit_score - self.mu)).exp()).exp()
+    }
+}
+
+/// Compute an MSV-style score for a seed: the best ungapped diagonal segment that
+/// lies within the seed's profile and target ranges, with local entry and exit.
+///
+/// The profile must already be configured for the target's length.
+pub fn msv_bit_score(profile: &Profile, target: &Sequence, seed: &Seed) -> f32 {
+    let mut best_segment_score = f32::NEG_INFINITY;
+    let mut best_segment_length: usize = 0;
+
+    // each diagonal that crosses the seed box starts on its top or left edge
+    let starts = (seed.profile_start..=seed.profile_end)
+        .map(|profile_idx| (profile_idx, seed.target_start))
+        .chain(
+            (seed.target_start + 1..=seed.target_end)
+                .map(|target_idx| (seed.profile_start, target_idx)),
+        );
+
+    for (profile_start, target_start) in starts {
+        let mut segment_score: f32 = 0.0;
+        let mut segment_length: usize = 0;
+
+        let mut profile_idx = profile_start;
+        let mut target_idx = target_start;
+        while profile_idx <= seed.profile_end && target_idx <= seed.target_end {
+            let residue = target.digital_bytes[target_idx] as usize;
+            let match_score = profile.match_score(residue, profile_idx);
+
+            // Kadane's maximum subarray along the diagonal
+            if segment_score > 0.0 {
+                segment_score += match_score;
+                segment_length += 1;
+            } else {
+                segment_score = match_score;
+                segment_length = 1;
+            }
+
+            if segment_score > best_segment_score {
+                best_segment_score = segment_score;
+                best_segment_length = segment_length;
+            }
+
+            profile_idx += 1;
+            target_idx += 1;
+        }
+    }
+
+    let profile_length = profile.length as f32;
+    let target_length = target.length as f32;
+    let flanking_length = (target.length - best_segment_length.min(target.length)) as f32;
+
+    // local entry is uniform over the model positions
+    let entry_score = (2.0 / (profile_length * (profile_length + 1.0))).ln();
+
+    let score = best_segment_score
+        + entry_score
+        + flanking_length
+            * profile.special_transition_score(Profile::SPECIAL_N_IDX, Profile::SPECIAL_LOOP_IDX)
+        + profile.special_transition_score(Profile::SPECIAL_N_IDX, Profile::SPECIAL_MOVE_IDX)
+        + profile.special_transition_score(Profile::SPECIAL_E_IDX, Profile::SPECIAL_MOVE_IDX)
+        + profile.special_transition_score(Profile::SPECIAL_C_IDX, Profile::SPECIAL_MOVE_IDX);
+
+    // the null model emits the target with a single geometric length distribution
+    let null_score = target_length * (target_length / (target_length + 1.0)).ln()
+        + (1.0 / (target_length + 1.0)).ln();
+
+    (score - null_score) / std::f32::consts::LN_2
+}
+
+/// The number of seeds that entered and passed each of the filters
+#[derive(Debug, Default)]
+pub struct FilterCounts {
+    pub msv_in: usize,
+    pub msv_passed: usize,
+    pub forward_in: usize,
+    pub forward_passed: usize,
+}
+
+impl FilterCounts {
+    /// Add in the counts of another worker.
+    pub fn add(&mut self, other: &FilterCounts) {
+        self.msv_in += other.msv_in;
+        self.msv_passed += other.msv_passed;
+        self.forward_in += other.forward_in;
+        self.forward_passed += other.forward_passed;
+    }
+
+    /// A line for each filter that seeds went through, with the share of them that passed.
+    pub fn summary_lines(&self) -> Vec<String> {
+        let rate = |passed: usize, total: usize| match total {
+            0 => 0.0,
+            _ => 100.0 * passed as f32 / total as f32,
+        };
+
+        let mut lines = vec![];
+        if self.msv_in > 0 {
+            lines.push(format!(
+                "msv filter: {} of {} seeds passed ({:.1}%)",
+                self.msv_passed,
+                self.msv_in,
+                rate(self.msv_passed, self.msv_in)
+            ));
+        }
+        if self.forward_in > 0 {
+            lines.push(format!(
+                "forward filter: {} of {} seeds passed ({:.1}%)",
+                self.forward_passed,
+                self.forward_in,
+                rate(self.forward_passed, self.forward_in)
+            ));
+        }
+        lines
+    }
+
+    /// Print the summary to stderr, so that it stays out of any results written to stdout.
+    pub fn print(&self) {
+        for line in self.summary_lines() {
+            eprintln!("{line}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_counts_of_the_workers_are_added_up() {
+        let mut counts = FilterCounts::default();
+        counts.add(&FilterCounts {
+            msv_in: 10,
+            msv_passed: 4,
+            forward_in: 4,
+            forward_passed: 1,
+        });
+        counts.add(&FilterCounts {
+            msv_in: 6,
+            msv_passed: 4,
+            forward_in: 4,
+            forward_passed: 2,
+        });
+
+        assert_eq!(
+            counts.summary_lines(),
+            [
+                "msv filter: 8 of 16 seeds passed (50.0%)",
+                "forward filter: 3 of 8 seeds passed (37.5%)",
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_that_no_seeds_went_through_are_left_out() {
+        assert!(FilterCounts::default().summary_lines().is_empty());
+
+        let forward_only = FilterCounts {
+            forward_in: 3,
+            forward_passed: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            forward_only.summary_lines(),
+            ["forward filter: 3 of 3 seeds passed (100.0%)"]
+        );
+    }
+}
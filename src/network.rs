@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Result;
+use nale::structs::Alignment;
+
+use crate::alignment_output::AlignedHit;
+use crate::number_format::NumberFormat;
+
+/// The score of a query and a target, over all of the query's hits to the target
+struct Edge {
+    bit_score: f32,
+    evalue: f32,
+    domain_count: usize,
+}
+
+/// One edge for each query and target with hits, keyed by their names.
+///
+/// An edge takes the full sequence statistics of the target, or the best of
+/// its hits where they haven't been computed.
+fn edges<'a>(
+    alignments: &'a [Alignment],
+    aligned_hits: &[AlignedHit],
+) -> BTreeMap<(&'a str, &'a str), Edge> {
+    let mut edges: BTreeMap<(&str, &str), Edge> = BTreeMap::new();
+    for (alignment, hit) in alignments.iter().zip(aligned_hits) {
+        let (bit_score, evalue) = match hit.domain_stats {
+            Some(stats) => (stats.seq_bit_score, stats.seq_evalue),
+            None => (alignment.bit_score, alignment.evalue),
+        };
+        let edge = edges
+            .entry((&alignment.profile_name, &alignment.target_name))
+            .or_insert(Edge {
+                bit_score,
+                evalue,
+                domain_count: 0,
+            });
+        edge.bit_score = edge.bit_score.max(bit_score);
+        edge.evalue = edge.evalue.min(evalue);
+        edge.domain_count += 1;
+    }
+    edges
+}
+
+/// Write a query, target, and bit score line for each query and target with
+/// hits, in the ABC format that MCL's mcxload reads.
+pub fn write_abc(
+    alignments: &[Alignment],
+    aligned_hits: &[AlignedHit],
+    format: &NumberFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    for ((query_name, target_name), edge) in edges(alignments, aligned_hits) {
+        writeln!(
+            out,
+            "{query_name}\t{target_name}\t{}",
+            format.score(edge.bit_score, 2)
+        )?;
+    }
+    Ok(())
+}
+
+/// Write the edges of the ABC file as a TSV with a header, with their E-values
+/// and the number of hits that each is made of, for graph tools that filter on them.
+pub fn write_edge_list(
+    alignments: &[Alignment],
+    aligned_hits: &[AlignedHit],
+    format: &NumberFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    writeln!(out, "query\ttarget\tbit score\te-value\tdomains")?;
+    for ((query_name, target_name), edge) in edges(alignments, aligned_hits) {
+        writeln!(
+            out,
+            "{query_name}\t{target_name}\t{}\t{}\t{}",
+            format.score(edge.bit_score, 2),
+            format.evalue(edge.evalue),
+            edge.domain_count
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use std::fs::{metadata, read_to_string};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::remote_target::curl;
+use crate::shutdown::CancelledError;
+use crate::time_limit::TimeLimitReachedError;
+use crate::Args;
+
+/// How long to wait on the webhook server before giving up
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Error, Debug)]
+#[error("invalid webhook URL (only http:// and https:// URLs are supported): {0}")]
+pub struct InvalidWebhookUrlError(String);
+
+#[derive(Error, Debug)]
+#[error("{0}")]
+pub struct WebhookFailedError(String);
+
+#[derive(Error, Debug)]
+#[error("on-complete command exited with: {0}")]
+pub struct OnCompleteFailedError(std::process::ExitStatus);
+
+/// An http:// or https:// URL to post the run's outcome to
+#[derive(Debug, Clone)]
+pub struct WebhookUrl(String);
+
+impl FromStr for WebhookUrl {
+    type Err = InvalidWebhookUrlError;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let host = url
+            .strip_prefix("http://")
+            .or_else(|| url.strip_prefix("https://"))
+            .and_then(|rest| rest.split('/').next())
+            .unwrap_or_default();
+        if host.is_empty() {
+            return Err(InvalidWebhookUrlError(url.to_string()));
+        }
+
+        Ok(WebhookUrl(url.to_string()))
+    }
+}
+
+impl WebhookUrl {
+    /// Post a JSON body to the URL with curl, and fail unless the response status is 2xx.
+    fn post(&self, body: &Value) -> Result<()> {
+        let body = serde_json::to_vec(body)?;
+
+        let mut child = curl()
+            .args(["--max-time", &WEBHOOK_TIMEOUT.as_secs().to_string()])
+            .args(["--header", "Content-Type: application/json"])
+            .args(["--data-binary", "@-"])
+            .arg(&self.0)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("failed to run curl")?;
+        // the stdin handle is dropped once the body is written, which ends curl's input
+        child.stdin.take().unwrap().write_all(&body)?;
+
+        let output = child.wait_with_output()?;
+        match output.status.success() {
+            true => Ok(()),
+            // curl explains the failure on stderr, with --show-error
+            false => Err(WebhookFailedError(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            )
+            .into()),
+        }
+    }
+}
+
+/// Run the user's completion hooks with the outcome of the run.
+///
+/// The on-complete command is run through the shell, with MMORESEQS_STATUS set to
+/// "success", "partial" (stopped at the time limit), "cancelled" (interrupted), or
+/// "failure", and MMORESEQS_RUN_SUMMARY set to the run summary's path if this run
+/// wrote one. The webhook is posted the status, the error if there was one, and the
+/// run summary, with curl.
+///
+/// A failing hook is reported, but never replaces the outcome of the run.
+pub fn notify_completion(args: &Args, started: SystemTime, result: &Result<()>) {
+    if args.on_complete.is_none() && args.webhook.is_none() {
+        return;
+    }
+
+    let status = match result {
+        Ok(_) => "success",
+        Err(err) if err.downcast_ref::<TimeLimitReachedError>().is_some() => "partial",
+        Err(err) if err.is::<CancelledError>() => "cancelled",
+        Err(_) => "failure",
+    };
+
+    // the summary is only on disk if the run got far enough to write it,
+    // and one that is older than the run was left by a previous run
+    let summary_path = args.paths.run_summary.as_ref().filter(|path| {
+        metadata(path)
+            .and_then(|m| m.modified())
+            .is_ok_and(|modified| modified >= started)
+    });
+
+    if let Some(on_complete) = &args.on_complete {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(on_complete)
+            .env("MMORESEQS_STATUS", status);
+        if let Some(path) = summary_path {
+            command.env("MMORESEQS_RUN_SUMMARY", path);
+        }
+
+        let outcome = command
+            .status()
+            .context("failed to run on-complete command")
+            .and_then(|exit_status| match exit_status.success() {
+                true => Ok(()),
+                false => Err(OnCompleteFailedError(exit_status).into()),
+            });
+        if let Err(err) = outcome {
+            eprintln!("warning: {err:#}");
+        }
+    }
+
+    if let Some(webhook) = &args.webhook {
+        let summary = summary_path
+            .and_then(|path| read_to_string(path).ok())
+            .and_then(|summary| serde_json::from_str::<Value>(&summary).ok());
+
+        let body = json!({
+            "status": status,
+            "error": result.as_ref().err().map(|err| format!("{err:#}")),
+            "summary": summary,
+        });
+
+        if let Err(err) = webhook.post(&body) {
+            eprintln!("warning: webhook failed: {err:#}");
+        }
+    }
+}
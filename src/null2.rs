@@ -0,0 +1,158 @@
+use nale::structs::trace::constants::{TRACE_B, TRACE_E, TRACE_I, TRACE_M};
+use nale::structs::{Profile, Sequence, Trace};
+
+/// The prior probability of the null2 model (HMMER's omega)
+const NULL2_OMEGA: f32 = 1.0 / 256.0;
+
+/// Compute a hit's null2 bias correction, in bits like its score.
+///
+/// As in HMMER, the null2 model is the hit's own expected composition: the emission
+/// odds of the aligned match and insert states, averaged with the posterior probability
+/// of each state as its weight. A hit whose residues are explained about as well by that
+/// composition as by the profile gets a large correction.
+pub fn null2_bias(trace: &Trace, profile: &Profile, target: &Sequence) -> f32 {
+    let mut expected_odds = [0.0f32; Profile::MAX_ALPHABET_SIZE];
+    let mut total_weight = 0.0f32;
+    let mut residues: Vec<usize> = vec![];
+
+    let mut in_alignment = false;
+    for trace_idx in 0..trace.length {
+        let state = trace.states[trace_idx];
+        let profile_idx = trace.profile_idx[trace_idx];
+
+        match state {
+            TRACE_B => {
+                in_alignment = true;
+                continue;
+            }
+            TRACE_E => break,
+            _ if !in_alignment => continue,
+            _ => {}
+        }
+
+        let emission_score: fn(&Profile, usize, usize) -> f32 = match state {
+            TRACE_M => Profile::match_score,
+            TRACE_I => Profile::insert_score,
+            _ => continue,
+        };
+
+        let weight = trace.posterior_probabilities[trace_idx];
+        for (residue, odds) in expected_odds.iter_mut().enumerate() {
+            *odds += weight * emission_score(profile, residue, profile_idx).exp();
+        }
+        total_weight += weight;
+
+        residues.push(target.digital_bytes[trace.target_idx[trace_idx]] as usize);
+    }
+
+    if total_weight <= 0.0 {
+        return 0.0;
+    }
+
+    // degenerate residues are left out, since the null2 model has no odds for them
+    let correction: f32 = residues
+        .iter()
+        .filter(|&&residue| residue < Profile::MAX_ALPHABET_SIZE)
+        .map(|&residue| (expected_odds[residue] / total_weight).ln())
+        .sum();
+
+    // ln(1 + omega * e^correction), computed without overflowing, and then
+    // converted from nats to the bits of the bit score
+    let x = NULL2_OMEGA.ln() + correction;
+    (x.max(0.0) + (-x.abs()).exp().ln_1p()) / std::f32::consts::LN_2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::fixture_profile;
+    use nale::structs::trace::constants::{TRACE_C, TRACE_S, TRACE_T};
+
+    /// A trace of one domain, with the (state, target index, profile index,
+    /// posterior probability) of each of its steps between B and E
+    fn domain_trace(
+        target: &Sequence,
+        profile: &Profile,
+        steps: &[(usize, usize, usize, f32)],
+    ) -> Trace {
+        let mut trace = Trace::new(target.length, profile.length);
+        let mut push = |(state, target_idx, profile_idx, posterior): (usize, usize, usize, f32)| {
+            trace.states.push(state);
+            trace.target_idx.push(target_idx);
+            trace.profile_idx.push(profile_idx);
+            trace.posterior_probabilities.push(posterior);
+        };
+
+        push((TRACE_S, 0, 0, 0.0));
+        push((TRACE_B, 0, 0, 0.0));
+        steps.iter().copied().for_each(&mut push);
+        push((TRACE_E, 0, 0, 0.0));
+        push((TRACE_C, 0, 0, 0.0));
+        push((TRACE_T, 0, 0, 0.0));
+
+        trace.length = trace.states.len();
+        trace
+    }
+
+    /// log2(1 + omega * e^correction), without the care for overflow
+    fn bias_bits(correction: f64) -> f64 {
+        (1.0 + NULL2_OMEGA as f64 * correction.exp()).log2()
+    }
+
+    #[test]
+    fn a_single_match_is_corrected_by_its_own_emission_odds() {
+        let profile = fixture_profile();
+        let target = Sequence::from_utf8(b"W").unwrap();
+        let trace = domain_trace(&target, &profile, &[(TRACE_M, 1, 3, 1.0)]);
+
+        // the null2 model is the match state's emissions, so the
+        // correction is the log odds of the residue's own emission
+        let residue = target.digital_bytes[1] as usize;
+        let expected = bias_bits(profile.match_score(residue, 3) as f64);
+
+        let bias = null2_bias(&trace, &profile, &target);
+        assert!((bias as f64 - expected).abs() < 1e-4, "{bias} {expected}");
+    }
+
+    #[test]
+    fn the_null2_model_averages_the_states_by_their_posterior_probability() {
+        let profile = fixture_profile();
+        let target = Sequence::from_utf8(b"ACDW").unwrap();
+        let steps = [
+            (TRACE_M, 1, 1, 0.5),
+            (TRACE_M, 2, 2, 1.0),
+            (TRACE_I, 3, 2, 0.25),
+            (TRACE_M, 4, 3, 0.75),
+        ];
+        let trace = domain_trace(&target, &profile, &steps);
+
+        let odds = |residue: usize| -> f64 {
+            let weighted_odds: f64 = steps
+                .iter()
+                .map(|&(state, _, profile_idx, posterior)| {
+                    let score = match state {
+                        TRACE_M => profile.match_score(residue, profile_idx),
+                        _ => profile.insert_score(residue, profile_idx),
+                    };
+                    posterior as f64 * (score as f64).exp()
+                })
+                .sum();
+            weighted_odds / 2.5
+        };
+        let correction: f64 = (1..=4)
+            .map(|target_idx| odds(target.digital_bytes[target_idx] as usize).ln())
+            .sum();
+
+        let bias = null2_bias(&trace, &profile, &target);
+        assert!((bias as f64 - bias_bits(correction)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn a_trace_without_aligned_residues_has_no_bias() {
+        let profile = fixture_profile();
+        let target = Sequence::from_utf8(b"ACDW").unwrap();
+        let trace = domain_trace(&target, &profile, &[]);
+
+        assert_eq!(null2_bias(&trace, &profile, &target), 0.0);
+    }
+}
@@ -0,0 +1,48 @@
+use clap::ValueEnum;
+
+/// The number of mantissa digits after the point of a scientific E-value, unless overridden
+const DEFAULT_EVALUE_PRECISION: usize = 1;
+
+/// How E-values are written
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EvalueFormat {
+    /// e.g. 2.1e-10
+    #[default]
+    Scientific,
+    /// e.g. 0.00000000021, without an exponent; with no precision given, every
+    /// digit needed to read back the exact value is written
+    Decimal,
+}
+
+/// How the E-values and scores in the outputs are written
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NumberFormat {
+    pub evalue_format: EvalueFormat,
+    /// The digits after the point of E-values
+    pub evalue_precision: Option<usize>,
+    /// The digits after the point of scores; each score column has its own default
+    pub score_precision: Option<usize>,
+}
+
+impl NumberFormat {
+    pub fn evalue(&self, value: f32) -> String {
+        match (self.evalue_format, self.evalue_precision) {
+            (EvalueFormat::Scientific, precision) => {
+                format!(
+                    "{value:.precision$e}",
+                    precision = precision.unwrap_or(DEFAULT_EVALUE_PRECISION)
+                )
+            }
+            (EvalueFormat::Decimal, Some(precision)) => format!("{value:.precision$}"),
+            (EvalueFormat::Decimal, None) => format!("{value}"),
+        }
+    }
+
+    /// Write a score (in bits) with the score precision, or else with the default precision of its column.
+    pub fn score(&self, value: f32, default_precision: usize) -> String {
+        format!(
+            "{value:.precision$}",
+            precision = self.score_precision.unwrap_or(default_precision)
+        )
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::runner::Runner;
+
+/// The environment variable that gives the path to the AWS CLI, which uploads to and reads s3:// URIs
+pub const AWS_ENV_VAR: &str = "MMORESEQS_AWS";
+
+/// The environment variable that gives the path to the gcloud CLI, which uploads to and reads gs:// URIs
+pub const GCLOUD_ENV_VAR: &str = "MMORESEQS_GCLOUD";
+
+/// The prefix of the directory that a run writes the outputs bound for an object
+/// store to before they are uploaded, which is named for the run's process and
+/// start time, so that runs from the same directory don't upload each other's files
+const STAGING_DIR_PREFIX: &str = "mmoreseqs-staging";
+
+/// The files of a run directory that aren't uploaded: the intermediate files,
+/// which include the mmseqs databases, and can be rebuilt
+const RUN_DIR_EXCLUDED: &str = "prep";
+
+#[derive(Error, Debug)]
+#[error("invalid object store URI (expected s3://BUCKET/KEY or gs://BUCKET/KEY): {0}")]
+pub struct InvalidObjectUriError(String);
+
+#[derive(Error, Debug)]
+#[error("failed to upload {failed} of {total} outputs to object storage; the local copies are in {}", staging_dir.display())]
+pub struct UploadFailedError {
+    failed: usize,
+    total: usize,
+    staging_dir: PathBuf,
+}
+
+/// The object stores that outputs can be uploaded to, and targets read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ObjectScheme {
+    S3,
+    Gs,
+}
+
+impl ObjectScheme {
+    fn prefix(&self) -> &'static str {
+        match self {
+            ObjectScheme::S3 => "s3://",
+            ObjectScheme::Gs => "gs://",
+        }
+    }
+
+    /// The CLI that uploads to and downloads from the store, from its environment variable or else the PATH
+    fn cli(&self) -> PathBuf {
+        let (name, env_var) = match self {
+            ObjectScheme::S3 => ("aws", AWS_ENV_VAR),
+            ObjectScheme::Gs => ("gcloud", GCLOUD_ENV_VAR),
+        };
+        std::env::var_os(env_var)
+            .filter(|path| !path.is_empty())
+            .map_or_else(|| PathBuf::from(name), PathBuf::from)
+    }
+}
+
+/// An object, or a prefix of objects, in S3 or Google Cloud Storage
+#[derive(Debug, Clone)]
+pub struct ObjectUri {
+    scheme: ObjectScheme,
+    bucket: String,
+    key: String,
+}
+
+/// Whether an output path is an object store URI rather than a local path
+pub fn is_object_uri(path: &str) -> bool {
+    [ObjectScheme::S3, ObjectScheme::Gs]
+        .iter()
+        .any(|scheme| path.starts_with(scheme.prefix()))
+}
+
+impl FromStr for ObjectUri {
+    type Err = InvalidObjectUriError;
+
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidObjectUriError(uri.to_string());
+
+        let (scheme, rest) = [ObjectScheme::S3, ObjectScheme::Gs]
+            .into_iter()
+            .find_map(|scheme| Some((scheme, uri.strip_prefix(scheme.prefix())?)))
+            .ok_or_else(invalid)?;
+        let (bucket, key) = rest.split_once('/').ok_or_else(invalid)?;
+        let key = key.trim_end_matches('/');
+        if bucket.is_empty() || key.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(ObjectUri {
+            scheme,
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ObjectUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}/{}", self.scheme.prefix(), self.bucket, self.key)
+    }
+}
+
+/// Check that an output path is a well formed URI, if it is one, for clap.
+pub fn parse_output_location(path: &str) -> Result<String, InvalidObjectUriError> {
+    if is_object_uri(path) {
+        path.parse::<ObjectUri>()?;
+    }
+    Ok(path.to_string())
+}
+
+impl ObjectUri {
+    /// The local path that the output is written to before it is uploaded,
+    /// which mirrors the URI under the staging directory
+    fn staging_path(&self, staging_dir: &Path) -> PathBuf {
+        staging_dir
+            .join(self.scheme.prefix().trim_end_matches("://"))
+            .join(&self.bucket)
+            .join(&self.key)
+    }
+
+    /// The URI of a file under this prefix
+    fn join(&self, relative: &Path) -> ObjectUri {
+        ObjectUri {
+            key: format!("{}/{}", self.key, relative.to_string_lossy()),
+            ..self.clone()
+        }
+    }
+
+    /// The command that uploads a file to the URI, or a run directory under it
+    fn upload_command(&self, local: &Path) -> Command {
+        let mut command = Command::new(self.scheme.cli());
+        let uri = self.to_string();
+        match (self.scheme, local.is_dir()) {
+            (ObjectScheme::S3, false) => {
+                command
+                    .args(["s3", "cp", "--only-show-errors"])
+                    .arg(local)
+                    .arg(uri);
+            }
+            (ObjectScheme::S3, true) => {
+                command
+                    .args(["s3", "sync", "--only-show-errors"])
+                    .arg(local)
+                    .arg(uri)
+                    .args(["--exclude", &format!("{RUN_DIR_EXCLUDED}/*")]);
+            }
+            (ObjectScheme::Gs, false) => {
+                command.args(["storage", "cp"]).arg(local).arg(uri);
+            }
+            (ObjectScheme::Gs, true) => {
+                command
+                    .args(["storage", "rsync", "--recursive"])
+                    .arg(local)
+                    .arg(uri)
+                    .args(["--exclude", &format!("^{RUN_DIR_EXCLUDED}/")]);
+            }
+        }
+        command
+    }
+}
+
+/// A command that downloads an object, or a byte range of it
+pub struct Download {
+    pub command: Command,
+    /// Whether the command writes the bytes to stdout, rather than to the local path
+    pub to_stdout: bool,
+}
+
+impl ObjectUri {
+    /// The command that writes the whole object to stdout, to be read as it downloads
+    pub fn stream_command(&self) -> Command {
+        let mut command = Command::new(self.scheme.cli());
+        match self.scheme {
+            ObjectScheme::S3 => command
+                .args(["s3", "cp", "--only-show-errors"])
+                .arg(self.to_string())
+                .arg("-"),
+            ObjectScheme::Gs => command.args(["storage", "cat"]).arg(self.to_string()),
+        };
+        command
+    }
+
+    /// The command that prints the size of the object in bytes
+    pub fn size_command(&self) -> Command {
+        let mut command = Command::new(self.scheme.cli());
+        match self.scheme {
+            ObjectScheme::S3 => command
+                .args([
+                    "s3api",
+                    "head-object",
+                    "--bucket",
+                    &self.bucket,
+                    "--key",
+                    &self.key,
+                ])
+                .args(["--query", "ContentLength", "--output", "text"]),
+            ObjectScheme::Gs => command
+                .args(["storage", "objects", "describe"])
+                .arg(self.to_string())
+                .arg("--format=value(size)"),
+        };
+        command
+    }
+
+    /// The command that downloads the object, or the inclusive byte range of it, to a local path
+    pub fn download_command(&self, range: Option<(u64, u64)>, local: &Path) -> Download {
+        let mut command = Command::new(self.scheme.cli());
+        let to_stdout = match (self.scheme, range) {
+            (ObjectScheme::S3, None) => {
+                command
+                    .args(["s3", "cp", "--only-show-errors"])
+                    .arg(self.to_string())
+                    .arg(local);
+                false
+            }
+            // get-object prints the object's metadata to stdout, so it writes the bytes to the path
+            (ObjectScheme::S3, Some((start, end))) => {
+                command
+                    .args([
+                        "s3api",
+                        "get-object",
+                        "--bucket",
+                        &self.bucket,
+                        "--key",
+                        &self.key,
+                    ])
+                    .args(["--range", &format!("bytes={start}-{end}")])
+                    .arg(local);
+                false
+            }
+            (ObjectScheme::Gs, None) => {
+                command
+                    .args(["storage", "cp"])
+                    .arg(self.to_string())
+                    .arg(local);
+                false
+            }
+            (ObjectScheme::Gs, Some((start, end))) => {
+                command
+                    .args(["storage", "cat", "--range", &format!("{start}-{end}")])
+                    .arg(self.to_string());
+                true
+            }
+        };
+        Download { command, to_stdout }
+    }
+}
+
+/// The outputs that are bound for object storage: each is written to a local
+/// staging path during the run, and uploaded as soon as it's written, and again
+/// when the run ends, however it ends, if it has changed or its upload failed.
+#[derive(Default, Clone)]
+pub struct ObjectUploads {
+    /// The run's staging directory, once an output has been staged
+    staging_dir: Option<PathBuf>,
+    staged: Vec<(PathBuf, ObjectUri)>,
+    /// The runner of the uploads, which runs them on the host, and not in the tools' container
+    runner: Runner,
+    /// The modification time of each file when it was uploaded, shared with the copies of the args
+    uploaded: Arc<Mutex<HashMap<PathBuf, SystemTime>>>,
+}
+
+impl ObjectUploads {
+    /// The local path to write an output to: the path itself, or, for an object
+    /// store URI, its staging path, which is uploaded to the URI.
+    pub fn output_path(&mut self, path: &str) -> PathBuf {
+        if !is_object_uri(path) {
+            return PathBuf::from(path);
+        }
+        // clap has already checked the URI with parse_output_location
+        let uri: ObjectUri = path.parse().expect("invalid object store URI");
+        let staging_path = uri.staging_path(self.staging_dir());
+        if let Some(parent) = staging_path.parent() {
+            create_dir_all(parent).expect("failed to create the staging directory");
+        }
+        self.staged.push((staging_path.clone(), uri));
+        staging_path
+    }
+
+    fn staging_dir(&mut self) -> &Path {
+        self.staging_dir.get_or_insert_with(|| {
+            let started = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            PathBuf::from(format!(
+                "{STAGING_DIR_PREFIX}-{started}-{}",
+                std::process::id()
+            ))
+        })
+    }
+
+    /// Whether a local path is the staging path of an output
+    pub fn is_staged(&self, path: &Path) -> bool {
+        self.staged.iter().any(|(local, _)| local == path)
+    }
+
+    /// Set the runner of the uploads.
+    pub fn set_runner(&mut self, runner: Runner) {
+        self.runner = runner;
+    }
+
+    /// Upload a file that has been written and closed, if it's bound for object
+    /// storage, as an output or a file of an output directory. A failed upload is
+    /// reported, and tried again when the run ends.
+    pub fn closed(&self, path: &Path) {
+        let uri = self
+            .staged
+            .iter()
+            .find_map(|(local, uri)| match path.strip_prefix(local) {
+                Ok(relative) if relative.as_os_str().is_empty() => Some(uri.clone()),
+                Ok(relative) => Some(uri.join(relative)),
+                Err(_) => None,
+            });
+        if let Some(uri) = uri {
+            if let Err(err) = self.upload_file(path, &uri) {
+                eprintln!(
+                    "failed to upload {} to {uri}, which will be tried again when the run ends: {err:#}",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    /// Upload a file or a directory, and note the time that it was last modified.
+    fn upload_file(&self, local: &Path, uri: &ObjectUri) -> Result<()> {
+        let modified = local.metadata()?.modified()?;
+        self.runner.run(&mut uri.upload_command(local))?;
+        self.uploaded
+            .lock()
+            .unwrap()
+            .insert(local.to_path_buf(), modified);
+        eprintln!("uploaded {} to {uri}", local.display());
+        Ok(())
+    }
+
+    /// Whether a file was uploaded as it is now
+    fn is_uploaded(&self, local: &Path) -> bool {
+        let modified = local.metadata().and_then(|metadata| metadata.modified());
+        self.uploaded
+            .lock()
+            .unwrap()
+            .get(local)
+            .is_some_and(|uploaded| modified.is_ok_and(|modified| modified == *uploaded))
+    }
+
+    /// Upload each staged output that was written and not already uploaded as it
+    /// is, and sync the output directories, and fail after trying them all if any
+    /// upload failed, so that no output is silently left behind.
+    pub fn upload(&self) -> Result<()> {
+        let mut failed: usize = 0;
+        let mut total: usize = 0;
+        for (local, uri) in &self.staged {
+            // an optional output may not have been written, e.g. after an early failure
+            if !local.exists() {
+                continue;
+            }
+            total += 1;
+            if local.is_file() && self.is_uploaded(local) {
+                continue;
+            }
+            if let Err(err) = self.upload_file(local, uri) {
+                failed += 1;
+                eprintln!("failed to upload {} to {uri}: {err:#}", local.display());
+            }
+        }
+
+        match failed {
+            0 => Ok(()),
+            _ => Err(UploadFailedError {
+                failed,
+                total,
+                staging_dir: self.staging_dir.clone().unwrap_or_default(),
+            }
+            .into()),
+        }
+    }
+}
@@ -0,0 +1,613 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use mmoreseqs::seq_io::{sanitize_name, write_fasta, SeqRecord, WriteOptions};
+use nale::output::path_buf_ext::PathBufExt;
+
+/// The NCBI translation tables, with their amino acids for the 64 codons in TCAG
+/// order and '*' for the stop codons. In the tables where a stop codon is only read
+/// through in some contexts (27, 28, and 31), it is always read through.
+const GENETIC_CODES: [GeneticCode; 27] = [
+    GeneticCode {
+        id: 1,
+        name: "Standard",
+        amino_acids: b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 2,
+        name: "Vertebrate Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 3,
+        name: "Yeast Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWTTTTPPPPHHQQRRRRIIMMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 4,
+        name: "Mold, Protozoan, and Coelenterate Mitochondrial; Mycoplasma; Spiroplasma",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 5,
+        name: "Invertebrate Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSSSVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 6,
+        name: "Ciliate, Dasycladacean and Hexamita Nuclear",
+        amino_acids: b"FFLLSSSSYYQQCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 9,
+        name: "Echinoderm and Flatworm Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 10,
+        name: "Euplotid Nuclear",
+        amino_acids: b"FFLLSSSSYY**CCCWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 11,
+        name: "Bacterial, Archaeal and Plant Plastid",
+        amino_acids: b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 12,
+        name: "Alternative Yeast Nuclear",
+        amino_acids: b"FFLLSSSSYY**CC*WLLLSPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 13,
+        name: "Ascidian Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSGGVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 14,
+        name: "Alternative Flatworm Mitochondrial",
+        amino_acids: b"FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 15,
+        name: "Blepharisma Nuclear",
+        amino_acids: b"FFLLSSSSYY*QCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 16,
+        name: "Chlorophycean Mitochondrial",
+        amino_acids: b"FFLLSSSSYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 21,
+        name: "Trematode Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 22,
+        name: "Scenedesmus obliquus Mitochondrial",
+        amino_acids: b"FFLLSS*SYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 23,
+        name: "Thraustochytrium Mitochondrial",
+        amino_acids: b"FF*LSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 24,
+        name: "Rhabdopleuridae Mitochondrial",
+        amino_acids: b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 25,
+        name: "Candidate Division SR1 and Gracilibacteria",
+        amino_acids: b"FFLLSSSSYY**CCGWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 26,
+        name: "Pachysolen tannophilus Nuclear",
+        amino_acids: b"FFLLSSSSYY**CC*WLLLAPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 27,
+        name: "Karyorelict Nuclear",
+        amino_acids: b"FFLLSSSSYYQQCCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 28,
+        name: "Condylostoma Nuclear",
+        amino_acids: b"FFLLSSSSYYQQCCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 29,
+        name: "Mesodinium Nuclear",
+        amino_acids: b"FFLLSSSSYYYYCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 30,
+        name: "Peritrich Nuclear",
+        amino_acids: b"FFLLSSSSYYEECC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 31,
+        name: "Blastocrithidia Nuclear",
+        amino_acids: b"FFLLSSSSYYEECCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 32,
+        name: "Balanophoraceae Plastid",
+        amino_acids: b"FFLLSSSSYY*WCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+    },
+    GeneticCode {
+        id: 33,
+        name: "Cephalodiscidae Mitochondrial",
+        amino_acids: b"FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+    },
+];
+
+/// What the target fasta holds
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TargetType {
+    /// Protein sequences
+    #[default]
+    Protein,
+    /// Nucleotide contigs, which are searched through the ORFs called on them
+    Contigs,
+}
+
+/// An NCBI translation table
+#[derive(Debug, Clone, Copy)]
+pub struct GeneticCode {
+    pub id: u8,
+    pub name: &'static str,
+    amino_acids: &'static [u8; 64],
+}
+
+impl Default for GeneticCode {
+    /// The bacterial, archaeal, and plant plastid code, which suits most metagenomes
+    fn default() -> Self {
+        parse_genetic_code("11").unwrap()
+    }
+}
+
+/// Parse the number of an NCBI translation table, for --genetic-code
+pub fn parse_genetic_code(value: &str) -> Result<GeneticCode, String> {
+    let id = value.parse::<u8>().ok();
+    GENETIC_CODES
+        .iter()
+        .find(|code| Some(code.id) == id)
+        .copied()
+        .ok_or_else(|| {
+            let supported: Vec<String> = GENETIC_CODES
+                .iter()
+                .map(|code| code.id.to_string())
+                .collect();
+            format!(
+                "{value} is not an NCBI genetic code (one of {})",
+                supported.join(", ")
+            )
+        })
+}
+
+/// The index of a nucleotide in TCAG order
+fn nucleotide_idx(nucleotide: u8) -> Option<usize> {
+    match nucleotide.to_ascii_uppercase() {
+        b'T' | b'U' => Some(0),
+        b'C' => Some(1),
+        b'A' => Some(2),
+        b'G' => Some(3),
+        _ => None,
+    }
+}
+
+fn complement(nucleotide: u8) -> u8 {
+    match nucleotide.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'T' | b'U' => b'A',
+        b'C' => b'G',
+        b'G' => b'C',
+        _ => b'N',
+    }
+}
+
+impl GeneticCode {
+    /// The amino acid of a codon, or 'X' if it has an ambiguous nucleotide
+    fn translate(&self, codon: &[u8]) -> u8 {
+        match (
+            nucleotide_idx(codon[0]),
+            nucleotide_idx(codon[1]),
+            nucleotide_idx(codon[2]),
+        ) {
+            (Some(first), Some(second), Some(third)) => {
+                self.amino_acids[first * 16 + second * 4 + third]
+            }
+            _ => b'X',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strand {
+    Forward,
+    Reverse,
+}
+
+impl Strand {
+    pub fn symbol(&self) -> char {
+        match self {
+            Strand::Forward => '+',
+            Strand::Reverse => '-',
+        }
+    }
+}
+
+/// Where an ORF lies on its contig
+#[derive(Debug, Clone)]
+pub struct Orf {
+    pub contig: String,
+    pub strand: Strand,
+    /// The reading frame, 1 to 3, counted from the start of the strand
+    pub frame: usize,
+    /// The first nucleotide of the ORF on the forward strand (1-based, inclusive)
+    pub start: usize,
+    /// The last nucleotide of the ORF on the forward strand (1-based, inclusive)
+    pub end: usize,
+}
+
+impl Orf {
+    /// The contig range (1-based, inclusive, on the forward strand) of a range
+    /// of the ORF's residues, e.g. of a hit to the ORF
+    pub fn contig_range(&self, residue_start: usize, residue_end: usize) -> (usize, usize) {
+        match self.strand {
+            Strand::Forward => (
+                self.start + (residue_start - 1) * 3,
+                self.start + residue_end * 3 - 1,
+            ),
+            Strand::Reverse => (
+                self.end + 1 - residue_end * 3,
+                self.end + 1 - (residue_start - 1) * 3 - 1,
+            ),
+        }
+    }
+}
+
+/// The ORF calling parameters for --target-type contigs
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OrfParams {
+    /// The fewest residues an ORF must have to be searched
+    pub min_length: usize,
+    pub genetic_code: GeneticCode,
+}
+
+/// Call the ORFs of one strand of a contig: the runs between stop codons in
+/// each frame, so that genes cut off by the ends of the contig are kept.
+fn strand_orfs(
+    contig: &str,
+    strand_nucleotides: &[u8],
+    strand: Strand,
+    params: &OrfParams,
+    orfs: &mut Vec<(Orf, Vec<u8>)>,
+) {
+    let length = strand_nucleotides.len();
+    for frame in 1..=3 {
+        let mut residues: Vec<u8> = vec![];
+        // the strand position of the ORF's first nucleotide (0-based)
+        let mut orf_start = frame - 1;
+
+        let Some(frame_nucleotides) = strand_nucleotides.get(frame - 1..) else {
+            continue;
+        };
+        let codons = frame_nucleotides.chunks(3);
+        let codon_count = codons.len();
+        for (codon_idx, codon) in codons.enumerate() {
+            let is_last = codon_idx + 1 == codon_count;
+            let amino_acid = match codon.len() {
+                3 => Some(params.genetic_code.translate(codon)),
+                _ => None,
+            };
+            let is_stop = amino_acid == Some(b'*');
+            if let Some(amino_acid) = amino_acid.filter(|_| !is_stop) {
+                residues.push(amino_acid);
+            }
+
+            if !(is_stop || is_last) {
+                continue;
+            }
+            if !residues.is_empty() && residues.len() >= params.min_length {
+                let strand_start = orf_start;
+                let strand_end = orf_start + residues.len() * 3 - 1;
+                let (start, end) = match strand {
+                    Strand::Forward => (strand_start + 1, strand_end + 1),
+                    Strand::Reverse => (length - strand_end, length - strand_start),
+                };
+                orfs.push((
+                    Orf {
+                        contig: contig.to_string(),
+                        strand,
+                        frame,
+                        start,
+                        end,
+                    },
+                    std::mem::take(&mut residues),
+                ));
+            }
+            residues.clear();
+            orf_start = frame - 1 + (codon_idx + 1) * 3;
+        }
+    }
+}
+
+/// Call the ORFs of a contig on both strands, in the order of their start on the contig.
+fn contig_orfs(contig: &str, nucleotides: &[u8], params: &OrfParams) -> Vec<(Orf, Vec<u8>)> {
+    let mut orfs: Vec<(Orf, Vec<u8>)> = vec![];
+    strand_orfs(contig, nucleotides, Strand::Forward, params, &mut orfs);
+
+    let reverse_complement: Vec<u8> = nucleotides.iter().rev().map(|&n| complement(n)).collect();
+    strand_orfs(
+        contig,
+        &reverse_complement,
+        Strand::Reverse,
+        params,
+        &mut orfs,
+    );
+
+    orfs.sort_by_key(|(orf, _)| (orf.start, orf.end));
+    orfs
+}
+
+/// Call the ORFs of each nucleotide contig, and write their translations as the
+/// target fasta, and where each lies on its contig to the ORF map.
+///
+/// Each ORF is named after its contig and its number on it, e.g. contig_3. The ORF
+/// map has the ORF's name, contig, strand, frame, start, and end on each line.
+/// Returns the number of contigs and of ORFs.
+pub fn call_orfs(
+    contigs: &Path,
+    orf_fasta: &Path,
+    orf_map: &Path,
+    params: &OrfParams,
+) -> Result<(usize, usize)> {
+    let file = File::open(contigs).context(format!(
+        "failed to open target contigs: {}",
+        contigs.to_string_lossy()
+    ))?;
+    let mut fasta_out = BufWriter::new(orf_fasta.to_path_buf().open(true)?);
+    let mut map_out = BufWriter::new(orf_map.to_path_buf().open(true)?);
+    let options = WriteOptions {
+        wrap_width: None,
+        ..WriteOptions::default()
+    };
+
+    let mut contig_count: usize = 0;
+    let mut orf_count: usize = 0;
+    let mut finish = |record: Option<(String, Vec<u8>)>| -> Result<()> {
+        let Some((contig, nucleotides)) = record else {
+            return Ok(());
+        };
+        contig_count += 1;
+        for (orf_idx, (orf, residues)) in contig_orfs(&contig, &nucleotides, params)
+            .into_iter()
+            .enumerate()
+        {
+            let name = format!("{contig}_{}", orf_idx + 1);
+            write_fasta(
+                &[SeqRecord::new(
+                    &name,
+                    String::from_utf8_lossy(&residues).to_string(),
+                )],
+                &options,
+                &mut fasta_out,
+            )?;
+            writeln!(
+                map_out,
+                "{name}\t{}\t{}\t{}\t{}\t{}",
+                orf.contig,
+                orf.strand.symbol(),
+                orf.frame,
+                orf.start,
+                orf.end
+            )?;
+            orf_count += 1;
+        }
+        Ok(())
+    };
+
+    let mut current: Option<(String, Vec<u8>)> = None;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        match line.strip_prefix('>') {
+            Some(header) => {
+                finish(current.take())?;
+                let name = header.split_whitespace().next().unwrap_or_default();
+                current = Some((sanitize_name(name), vec![]));
+            }
+            None => {
+                if let Some((_, nucleotides)) = current.as_mut() {
+                    nucleotides.extend(line.bytes().filter(|b| !b.is_ascii_whitespace()));
+                }
+            }
+        }
+    }
+    finish(current.take())?;
+
+    Ok((contig_count, orf_count))
+}
+
+/// The contig location of each ORF, for the ORFs called with --target-type contigs.
+pub struct OrfMap {
+    orfs_by_name: HashMap<String, Orf>,
+}
+
+impl OrfMap {
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = File::open(path).context(format!(
+            "failed to open ORF map: {}",
+            path.to_string_lossy()
+        ))?;
+
+        let mut orfs_by_name = HashMap::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [name, contig, strand, frame, start, end] = fields[..] else {
+                continue;
+            };
+            let orf = Orf {
+                contig: contig.to_string(),
+                strand: match strand {
+                    "-" => Strand::Reverse,
+                    _ => Strand::Forward,
+                },
+                frame: frame.parse()?,
+                start: start.parse()?,
+                end: end.parse()?,
+            };
+            orfs_by_name.insert(name.to_string(), orf);
+        }
+
+        Ok(OrfMap { orfs_by_name })
+    }
+
+    pub fn orf(&self, orf_name: &str) -> Option<&Orf> {
+        self.orfs_by_name.get(orf_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::temp_path;
+    use std::fs::{read_to_string, remove_file, write};
+
+    /// A contig with a stop codon in each forward frame: TAG in frame 1, and
+    /// TGA in frame 2 under the standard code
+    const CONTIG: &[u8] = b"ATGAAATAGCCC";
+
+    fn params(min_length: usize, genetic_code: &str) -> OrfParams {
+        OrfParams {
+            min_length,
+            genetic_code: parse_genetic_code(genetic_code).unwrap(),
+        }
+    }
+
+    /// The strand, frame, contig range, and translation of each ORF
+    fn summarize(orfs: &[(Orf, Vec<u8>)]) -> Vec<(char, usize, usize, usize, String)> {
+        orfs.iter()
+            .map(|(orf, residues)| {
+                (
+                    orf.strand.symbol(),
+                    orf.frame,
+                    orf.start,
+                    orf.end,
+                    String::from_utf8_lossy(residues).to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn orfs_are_called_in_six_frames() {
+        let orfs = contig_orfs("contig", CONTIG, &params(1, "11"));
+        assert_eq!(
+            summarize(&orfs),
+            [
+                ('+', 1, 1, 6, "MK".to_string()),
+                ('-', 1, 1, 12, "GLFH".to_string()),
+                ('-', 3, 2, 10, "AIS".to_string()),
+                ('+', 3, 3, 11, "EIA".to_string()),
+                ('-', 2, 3, 11, "GYF".to_string()),
+                ('+', 2, 5, 10, "NS".to_string()),
+                ('+', 1, 10, 12, "P".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reverse_strand_hits_are_placed_on_the_forward_strand() {
+        let orfs = contig_orfs("contig", CONTIG, &params(1, "11"));
+        let (reverse, _) = &orfs[1];
+        assert_eq!(reverse.strand, Strand::Reverse);
+        // the G of GLFH is the reverse complement of the last codon, CCC
+        assert_eq!(reverse.contig_range(1, 1), (10, 12));
+        assert_eq!(reverse.contig_range(2, 4), (1, 9));
+
+        let (forward, _) = &orfs[0];
+        assert_eq!(forward.contig_range(2, 2), (4, 6));
+    }
+
+    #[test]
+    fn short_orfs_are_dropped() {
+        let orfs = contig_orfs("contig", CONTIG, &params(3, "11"));
+        let lengths: Vec<usize> = orfs.iter().map(|(_, residues)| residues.len()).collect();
+        assert_eq!(lengths, [4, 3, 3, 3]);
+    }
+
+    #[test]
+    fn the_genetic_code_decides_the_stop_codons() {
+        // TGA codes for tryptophan in the mycoplasma code, table 4
+        let orfs = contig_orfs("contig", CONTIG, &params(1, "4"));
+        let forward_frame_2: Vec<_> = summarize(&orfs)
+            .into_iter()
+            .filter(|(strand, frame, ..)| (*strand, *frame) == ('+', 2))
+            .collect();
+        assert_eq!(forward_frame_2, [('+', 2, 2, 10, "WNS".to_string())]);
+
+        assert!(parse_genetic_code("7").is_err());
+        assert_eq!(GeneticCode::default().id, 11);
+    }
+
+    #[test]
+    fn every_ncbi_table_is_supported() {
+        // the numbers that NCBI has retired or never used
+        let unused = [7, 8, 17, 18, 19, 20];
+        for id in 1..=33 {
+            assert_eq!(
+                parse_genetic_code(&id.to_string()).is_ok(),
+                !unused.contains(&id),
+                "table {id}"
+            );
+        }
+
+        // the Balanophoraceae plastid code reads TAG as tryptophan
+        let orfs = contig_orfs("contig", CONTIG, &params(1, "32"));
+        assert_eq!(summarize(&orfs)[0], ('+', 1, 1, 12, "MKWP".to_string()));
+    }
+
+    #[test]
+    fn each_contig_gets_its_orfs_named_and_mapped() {
+        let (contigs, orf_fasta, orf_map) = (
+            temp_path("contigs.fa"),
+            temp_path("orfs.fa"),
+            temp_path("orfs.map"),
+        );
+        write(&contigs, ">c1 a contig\nATGAAA\nTAGCCC\n>c2\nATG\n").unwrap();
+
+        let counts = call_orfs(&contigs, &orf_fasta, &orf_map, &params(1, "11")).unwrap();
+        let fasta = read_to_string(&orf_fasta).unwrap();
+        let map = OrfMap::read(&orf_map).unwrap();
+        for path in [contigs, orf_fasta, orf_map] {
+            remove_file(path).unwrap();
+        }
+
+        assert_eq!(counts, (2, 9));
+        assert!(fasta.starts_with(">c1_1\nMK\n>c1_2\nGLFH\n"));
+        assert!(fasta.ends_with(">c2_1\nM\n>c2_2\nH\n"));
+
+        let orf = map.orf("c1_2").unwrap();
+        assert_eq!(
+            (
+                orf.contig.as_str(),
+                orf.strand,
+                orf.frame,
+                orf.start,
+                orf.end
+            ),
+            ("c1", Strand::Reverse, 1, 1, 12)
+        );
+        assert!(map.orf("c3_1").is_none());
+    }
+}
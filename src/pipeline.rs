@@ -1,13 +1,77 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{copy, create_dir_all, remove_file, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::alignment_output::{
+    write_a2m, write_pairwise, write_stockholm, AliFormat, AlignedHit, SeedProvenance,
+};
+use crate::alphabet::{read_amino_fasta, ParsedTargets};
+use crate::alt_alignments::{trace_alternatives, write_alt_alignments};
+use crate::banded_nw::banded_needleman_wunsch;
+use crate::batch::run_batch;
+use crate::bound_stats::{print_bound_summary, write_bound_stats, BoundSource, BoundStats};
+use crate::comp_bias::is_biased;
+use crate::cpu_topology::CpuPinning;
+use crate::determinism::verify_deterministic;
+use crate::domain_stats::{assign_domain_stats, DomainStats};
+use crate::dp_matrices::DpMatrices;
+use crate::envelope::Envelope;
+use crate::existing_results::ExistingResults;
 use crate::external_steps::{
-    extract_mmseqs_profile_consensus_sequences, run_hmmbuild, run_mmseqs_align,
-    run_mmseqs_convertalis, run_mmseqs_convertmsa, run_mmseqs_createdb, run_mmseqs_msa2profile,
-    run_mmseqs_prefilter, ProfilesNotMappedError,
+    check_seed_db_types, extract_mmseqs_profile_consensus_sequences, run_hmmbuild, run_hmmconvert,
+    run_mmseqs_align, run_mmseqs_convertalis, run_mmseqs_convertmsa, run_mmseqs_createdb,
+    run_mmseqs_createdb_query_consensus, run_mmseqs_msa2profile, run_mmseqs_prefilter,
+    HMMBUILD_MAX_THREADS,
+};
+use crate::hit_mask::HitMask;
+use crate::hit_order::{sort_hits, sort_rows, SortKey};
+use crate::hitmap::write_hitmap;
+use crate::hits_fasta::write_hits_fasta;
+use crate::manifest::RunManifest;
+use crate::msv_filter::{msv_bit_score, FilterCounts, MsvStats};
+use crate::network::{write_abc, write_edge_list};
+use crate::number_format::NumberFormat;
+use crate::orfs::{call_orfs, Orf, OrfMap};
+use crate::profile_config::{group_by_length_config, WorkerProfile};
+use crate::profile_keys::{
+    assign_profile_keys, stockholm_lacks_accessions, write_keyed_stockholm, KeyMatch,
+    ProfileLookup, ProfileNotFoundError,
 };
-use crate::Args;
+use crate::provenance::Provenance;
+use crate::query_filter::NoQueriesSelectedError;
+use crate::query_format::{convert_afa_to_stockholm, QueryFormat};
+use crate::remote_target::{download_target, fetch_targets, TargetIndex};
+use crate::run_params::RunParams;
+use crate::run_summary::{AlignStats, RunSummary};
+use crate::schedule::WorkQueue;
+use crate::seed_chain::chain_seeds;
+use crate::seed_expansion::ExpansionCounts;
+use crate::seed_files::{open_seeds_file, seed_files};
+use crate::seed_plot::{write_plot_data, PlotFeature, PlotRect};
+use crate::seed_schema::{SeedRecord, SeedRecords};
+use crate::seed_stats::{print_seed_stats, SeedsByAccession};
+use crate::seeds_header::{checksum_file, prepend_seeds_header, SeedsHeader};
+use crate::shutdown::{self, CancelledError};
+use crate::skipped::{print_skipped_summary, write_skipped_seeds, SkipReason, SkippedSeed};
+use crate::strict::warn_or_fail;
+use crate::summary::print_hit_summary;
+use crate::tabular::{
+    OutputVersion, Table, BIT_SCORE_COLUMN_IDX, EVALUE_COLUMN_IDX, PROFILE_NAME_COLUMN_IDX,
+    TARGET_END_COLUMN_IDX, TARGET_NAME_COLUMN_IDX, TARGET_START_COLUMN_IDX,
+};
+use crate::target_sources::{combine_targets, expand_target_sources, TargetSources};
+use crate::target_subset::select_target_subset;
+use crate::taxonomy::{filter_fasta, Taxonomy};
+use crate::thresholds::{InclusionNotEnabledError, ThresholdTable};
+use crate::trace_output::{write_traces, HitTrace};
+use crate::workspace::{safe_file_name, Stage};
+use crate::{Args, OnBoundFail};
 
 use nale::align::bounded::structs::{
     CloudBoundGroup, CloudMatrixLinear, CloudSearchParams, RowBounds, Seed,
@@ -16,245 +80,3116 @@ use nale::align::bounded::{
     backward_bounded, cloud_search_backward, cloud_search_forward, forward_bounded,
     optimal_accuracy_bounded, posterior_bounded, traceback_bounded,
 };
-use nale::align::needleman_wunsch::{needleman_wunsch, SimpleTraceStep};
-use nale::output::output_tabular::write_tabular_output;
+use nale::align::needleman_wunsch::SimpleTraceStep;
 use nale::output::path_buf_ext::PathBufExt;
-use nale::structs::hmm::parse_hmms_from_p7hmm_file;
-use nale::structs::{Alignment, DpMatrixFlat, Profile, Sequence, Trace};
+use nale::structs::dp_matrix::DpMatrix;
+use nale::structs::hmm::{parse_hmms_from_p7hmm_file, Hmm};
+use nale::structs::{Alignment, Profile, Sequence, Trace};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use csv::StringRecord;
+use mmoreseqs::seq_io::{write_fasta, SeqRecord, WriteOptions};
+use rayon::prelude::*;
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error(
+    "query HMM not found: {0}; either run prep without --skip-hmmbuild, \
+     or provide the query MSA with --query-msa to build it"
+)]
+pub struct MissingQueryHmmError(PathBuf);
+
+/// The most DP cells (profile length times target length) of a seed that is aligned
+/// over its whole matrix, skipping the cloud search, unless --dense-cells is given;
+/// measured with bench-dense
+pub const DEFAULT_DENSE_CELLS: usize = 256;
+
+/// What to tell the user when the seed step finds no seeds
+pub const NO_SEEDS_MESSAGE: &str =
+    "0 seeds generated; consider a higher --sensitivity, or lowering --k-score or raising --max-seqs";
+
+#[derive(Error, Debug)]
+#[error("results files have different columns")]
+pub struct MismatchedResultsError;
 
-use anyhow::Result;
+#[derive(Error, Debug)]
+#[error("no profile named {0} in the query")]
+pub struct MissingRegionProfileError(String);
+
+#[derive(Error, Debug)]
+#[error("no target named {0} in the target fasta")]
+pub struct MissingRegionTargetError(String);
+
+#[derive(Error, Debug)]
+#[error("the region could not be aligned: {0}")]
+pub struct RegionNotAlignedError(SkipReason);
+
+/// A profile's accession, and its map from mmseqs to p7 consensus positions
+/// if it has an mmseqs consensus sequence
+type ConsensusMapping<'a> = (&'a str, Option<Vec<usize>>);
 
 fn map_p7_to_mmseqs_profiles(
     p7_profiles: &[Profile],
     args: &Args,
+    pinning: Option<&CpuPinning>,
 ) -> Result<HashMap<String, Vec<usize>>> {
     let mmseqs_consensus_map = extract_mmseqs_profile_consensus_sequences(args)?;
 
+    // the profiles are mapped independently, so they are spread over the threads
+    let mut thread_pool = rayon::ThreadPoolBuilder::new().num_threads(args.threads);
+    if let Some(pinning) = pinning.cloned() {
+        thread_pool =
+            thread_pool.start_handler(move |thread_idx| pinning.pin_pool_thread(thread_idx));
+    }
+    let thread_pool = thread_pool.build()?;
+    let mappings: Vec<ConsensusMapping> = thread_pool.install(|| {
+        p7_profiles
+            .par_iter()
+            .map(|p7_profile| {
+                let accession = &p7_profile.accession[..];
+                match mmseqs_consensus_map.get(accession) {
+                    Some(mmseqs_consensus) => Ok((
+                        accession,
+                        Some(map_mmseqs_to_p7(mmseqs_consensus, p7_profile)?),
+                    )),
+                    None => Ok((accession, None)),
+                }
+            })
+            .collect::<Result<_>>()
+    })?;
+
     let mut profile_to_profile_idx_maps_by_accession: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut unmatched_accessions: Vec<&str> = vec![];
 
-    for p7_profile in p7_profiles {
-        let accession = &p7_profile.accession;
-        let mmseqs_consensus = mmseqs_consensus_map.get(accession).unwrap();
-        let p7_consensus = Sequence::from_utf8(&p7_profile.consensus_sequence[1..])?;
-        let trace = needleman_wunsch(mmseqs_consensus, &p7_consensus);
-
-        let mut mmseqs_to_p7: Vec<usize> = vec![0; mmseqs_consensus.length + 1];
-
-        let mut mmseqs_idx: usize = 0;
-        let mut p7_idx: usize = 0;
-        for step in &trace {
-            match step {
-                SimpleTraceStep::Diagonal => {
-                    mmseqs_idx += 1;
-                    p7_idx += 1;
-                }
-                SimpleTraceStep::Up => {
-                    mmseqs_idx += 1;
-                }
-                SimpleTraceStep::Left => {
-                    p7_idx += 1;
-                }
+    for (accession, mmseqs_to_p7) in mappings {
+        match mmseqs_to_p7 {
+            Some(mmseqs_to_p7) => {
+                profile_to_profile_idx_maps_by_accession
+                    .insert(accession.to_string(), mmseqs_to_p7);
             }
-            mmseqs_to_p7[mmseqs_idx] = p7_idx;
+            None => unmatched_accessions.push(accession),
         }
+    }
 
-        // this debug assert should guarantee that the NW
-        // alignment fully covered both consensus sequences
-        debug_assert_eq!(mmseqs_idx, mmseqs_consensus.length);
-        debug_assert_eq!(p7_idx, p7_consensus.length);
-
-        profile_to_profile_idx_maps_by_accession.insert(accession.clone(), mmseqs_to_p7);
+    if !unmatched_accessions.is_empty() {
+        warn_or_fail(
+            args,
+            format!(
+                "{} query profiles have no mmseqs consensus sequence, so their seeds can't be mapped: {}",
+                unmatched_accessions.len(),
+                unmatched_accessions.join(", ")
+            ),
+        )?;
     }
 
     Ok(profile_to_profile_idx_maps_by_accession)
 }
 
+/// Map each position of a profile's mmseqs consensus to its position in the p7 consensus.
+fn map_mmseqs_to_p7(mmseqs_consensus: &Sequence, p7_profile: &Profile) -> Result<Vec<usize>> {
+    let p7_consensus = Sequence::from_utf8(&p7_profile.consensus_sequence[1..])?;
+    let trace = banded_needleman_wunsch(mmseqs_consensus, &p7_consensus);
+
+    let mut mmseqs_to_p7: Vec<usize> = vec![0; mmseqs_consensus.length + 1];
+
+    let mut mmseqs_idx: usize = 0;
+    let mut p7_idx: usize = 0;
+    for step in &trace {
+        match step {
+            SimpleTraceStep::Diagonal => {
+                mmseqs_idx += 1;
+                p7_idx += 1;
+            }
+            SimpleTraceStep::Up => {
+                mmseqs_idx += 1;
+            }
+            SimpleTraceStep::Left => {
+                p7_idx += 1;
+            }
+        }
+        mmseqs_to_p7[mmseqs_idx] = p7_idx;
+    }
+
+    // this debug assert should guarantee that the NW
+    // alignment fully covered both consensus sequences
+    debug_assert_eq!(mmseqs_idx, mmseqs_consensus.length);
+    debug_assert_eq!(p7_idx, p7_consensus.length);
+
+    Ok(mmseqs_to_p7)
+}
+
+/// Run the forward and backward cloud searches for a seed and join them into a set of row bounds.
+///
+/// Returns None if the cloud search fails or produces invalid bounds.
+pub(crate) fn cloud_search_row_bounds(
+    profile: &Profile,
+    target: &Sequence,
+    seed: &Seed,
+    params: &CloudSearchParams,
+    cloud_matrix: &mut CloudMatrixLinear,
+    forward_bounds: &mut CloudBoundGroup,
+    backward_bounds: &mut CloudBoundGroup,
+) -> Option<RowBounds> {
+    cloud_matrix.reuse(profile.length);
+    forward_bounds.reuse(target.length, profile.length);
+    backward_bounds.reuse(target.length, profile.length);
+
+    cloud_search_forward(profile, target, seed, cloud_matrix, params, forward_bounds).ok()?;
+    cloud_search_backward(profile, target, seed, cloud_matrix, params, backward_bounds).ok()?;
+
+    CloudBoundGroup::join_bounds(forward_bounds, backward_bounds).ok()?;
+
+    forward_bounds.trim_wings();
+
+    let row_bounds = RowBounds::new(forward_bounds);
+
+    match row_bounds.valid() {
+        true => Some(row_bounds),
+        false => None,
+    }
+}
+
+/// Compute the E-value of the bounded forward score of a seed.
+///
+/// Since the forward score sums over all of the alignments in the bounds, this
+/// is used to cheaply filter seeds before the rest of the DP is computed.
+fn forward_evalue(
+    profile: &Profile,
+    forward_matrix: &impl DpMatrix,
+    row_bounds: &RowBounds,
+    target_count: usize,
+) -> f32 {
+    let forward_score = forward_matrix.get_special(row_bounds.target_end, Profile::SPECIAL_C_IDX)
+        + profile.special_transition_score(Profile::SPECIAL_C_IDX, Profile::SPECIAL_MOVE_IDX);
+    let pvalue = (-profile.forward_lambda * (forward_score - profile.forward_tau)).exp();
+    pvalue * target_count as f32
+}
+
+/// Build a set of row bounds that covers the entire DP matrix, which
+/// turns the bounded DP routines into full (unbounded) DP.
+pub(crate) fn full_row_bounds(profile: &Profile, target: &Sequence) -> RowBounds {
+    RowBounds {
+        target_start: 1,
+        target_end: target.length,
+        row_capacity: target.length + 1,
+        left_row_bounds: vec![1; target.length + 1],
+        right_row_bounds: vec![profile.length; target.length + 1],
+    }
+}
+
+/// The formats that a seeds file may be given in
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+pub enum SeedsFormat {
+    /// The seeds file written by mmoreseqs seed
+    #[default]
+    Mmoreseqs,
+    /// The default (BLAST tab) output of mmseqs convertalis
+    MmseqsTsv,
+    /// JSON Lines in the mmoreseqs-seeds schema, from another seed finder
+    Json,
+}
+
+/// The column indices of the seed fields in a seeds file
+struct SeedColumns {
+    query: usize,
+    target: usize,
+    query_start: usize,
+    query_end: usize,
+    target_start: usize,
+    target_end: usize,
+    evalue: usize,
+    bit_score: usize,
+}
+
+impl SeedsFormat {
+    fn columns(&self) -> SeedColumns {
+        match self {
+            // query,target,qstart,qend,tstart,tend,evalue,bits
+            SeedsFormat::Mmoreseqs => SeedColumns {
+                query: 0,
+                target: 1,
+                query_start: 2,
+                query_end: 3,
+                target_start: 4,
+                target_end: 5,
+                evalue: 6,
+                bit_score: 7,
+            },
+            // query,target,fident,alnlen,mismatch,gapopen,qstart,qend,tstart,tend,evalue,bits
+            SeedsFormat::MmseqsTsv => SeedColumns {
+                query: 0,
+                target: 1,
+                query_start: 6,
+                query_end: 7,
+                target_start: 8,
+                target_end: 9,
+                evalue: 10,
+                bit_score: 11,
+            },
+            SeedsFormat::Json => unreachable!("JSON seeds files don't have columns"),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("seeds file line {line} has too few columns for the {format:?} format")]
+pub struct MalformedSeedsError {
+    line: usize,
+    format: SeedsFormat,
+}
+
+#[derive(Error, Debug)]
+#[error("seeds file line {line} has an invalid value in column {column}: {value:?}")]
+pub struct InvalidSeedFieldError {
+    line: usize,
+    column: usize,
+    value: String,
+}
+
+/// A reader of the records of a TSV seeds file.
+///
+/// Seeds files are tab-delimited, as mmseqs writes them, so the names may contain
+/// spaces. A field that contains a tab must be quoted with '"', and a '"' in a
+/// quoted field is written twice. Lines starting with '#' are comments.
+fn seeds_tsv_reader(reader: Box<dyn BufRead>) -> csv::Reader<Box<dyn BufRead>> {
+    csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        // seeds files written before the bit score column was added only have 7 columns
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_reader(reader)
+}
+
+/// Parse the field of a seeds file record in the given column (0-indexed).
+fn parse_seed_field<T: FromStr>(record: &StringRecord, column: usize, line: usize) -> Result<T> {
+    record[column].trim().parse::<T>().map_err(|_| {
+        InvalidSeedFieldError {
+            line,
+            column: column + 1,
+            value: record[column].to_string(),
+        }
+        .into()
+    })
+}
+
+/// A seed as it is read from a seeds file, before it is mapped onto the query HMM
+struct SeedRow {
+    query: String,
+    target: String,
+    query_start: usize,
+    query_end: usize,
+    target_start: usize,
+    target_end: usize,
+    evalue: f32,
+    bit_score: Option<f32>,
+}
+
+impl From<SeedRecord> for SeedRow {
+    fn from(record: SeedRecord) -> Self {
+        SeedRow {
+            query: record.query,
+            target: record.target,
+            query_start: record.query_start.get(),
+            query_end: record.query_end.get(),
+            target_start: record.target_start.get(),
+            target_end: record.target_end.get(),
+            evalue: record.evalue,
+            bit_score: record.bit_score,
+        }
+    }
+}
+
+/// Parse a record of a TSV seeds file, or None if it is blank.
+fn parse_seed_record(
+    record: csv::Result<StringRecord>,
+    format: SeedsFormat,
+) -> Result<Option<SeedRow>> {
+    let record = record.context("failed to read seeds file")?;
+    let line_number = record.position().map_or(0, |p| p.line() as usize);
+    if record.iter().all(|field| field.trim().is_empty()) {
+        return Ok(None);
+    }
+
+    let columns = format.columns();
+    if record.len() <= columns.evalue {
+        return Err(MalformedSeedsError {
+            line: line_number,
+            format,
+        }
+        .into());
+    }
+
+    Ok(Some(SeedRow {
+        query: record[columns.query].to_string(),
+        target: record[columns.target].to_string(),
+        query_start: parse_seed_field(&record, columns.query_start, line_number)?,
+        query_end: parse_seed_field(&record, columns.query_end, line_number)?,
+        target_start: parse_seed_field(&record, columns.target_start, line_number)?,
+        target_end: parse_seed_field(&record, columns.target_end, line_number)?,
+        evalue: parse_seed_field(&record, columns.evalue, line_number)?,
+        bit_score: match record.get(columns.bit_score) {
+            Some(_) => Some(parse_seed_field(&record, columns.bit_score, line_number)?),
+            None => None,
+        },
+    }))
+}
+
+/// The seeds of a seeds file in the format given by --seeds-format, parsed as
+/// they are read, so that the file is never held in memory whole.
+fn seed_rows(
+    reader: Box<dyn BufRead>,
+    format: SeedsFormat,
+) -> Box<dyn Iterator<Item = Result<SeedRow>>> {
+    match format {
+        SeedsFormat::Json => Box::new(SeedRecords::new(reader).map(|r| r.map(SeedRow::from))),
+        _ => Box::new(
+            seeds_tsv_reader(reader)
+                .into_records()
+                .filter_map(move |record| parse_seed_record(record, format).transpose()),
+        ),
+    }
+}
+
+/// The names of the targets that the seeds files have seeds on
+fn seeded_target_names(args: &Args) -> Result<HashSet<String>> {
+    let mut names: HashSet<String> = HashSet::new();
+    for seeds_path in &seed_files(args)? {
+        for row in seed_rows(open_seeds_file(seeds_path)?, args.seeds_format) {
+            let row = row.with_context(|| format!("in seeds file {}", seeds_path.display()))?;
+            names.insert(row.target);
+        }
+    }
+    Ok(names)
+}
+
+/// A seed with its coordinates in order and mapped onto the query HMM
+struct MappedSeed {
+    seed: Seed,
+    /// Whether the seed's target or profile coordinates had start > end
+    reversed: bool,
+    /// Whether the seed's profile coordinates were clamped onto the profile
+    clamped: bool,
+}
+
+/// Put a seed's (start, end) coordinates in order, and map its profile coordinates
+/// from the mmseqs consensus onto the query HMM's match states.
+fn map_seed(
+    target_name: String,
+    (target_start, target_end): (usize, usize),
+    (profile_start, profile_end): (usize, usize),
+    profile_idx_map: &[usize],
+) -> MappedSeed {
+    // mmseqs reports some alignments with start > end; the seed covers the
+    // same span either way, and cloud search needs the ends in order
+    let reversed = target_start > target_end || profile_start > profile_end;
+    let (target_start, target_end) = (target_start.min(target_end), target_start.max(target_end));
+    let (profile_start, profile_end) = (
+        profile_start.min(profile_end),
+        profile_start.max(profile_end),
+    );
+
+    // a seed that runs past the consensus, or starts in an insertion
+    // before the first match state, is clamped onto the profile
+    let last_idx = profile_idx_map.len() - 1;
+    let mapped_start = profile_idx_map[profile_start.min(last_idx)];
+    let mapped_end = profile_idx_map[profile_end.min(last_idx)];
+    let clamped = profile_end > last_idx || mapped_start == 0;
+
+    MappedSeed {
+        seed: Seed {
+            target_name,
+            target_start,
+            target_end,
+            profile_start: mapped_start.max(1),
+            profile_end: mapped_end,
+        },
+        reversed,
+        clamped,
+    }
+}
+
+/// Read the seeds, and map them onto the query HMM profiles that their queries name.
 pub fn build_alignment_seeds(
     profile_to_profile_idx_maps_by_accession: &HashMap<String, Vec<usize>>,
+    lookup: &ProfileLookup,
     args: &Args,
-) -> Result<HashMap<String, Vec<Seed>>> {
-    let mut profile_seeds_by_accession: HashMap<String, Vec<Seed>> = HashMap::new();
+) -> Result<SeedsByAccession> {
+    let mut profile_seeds_by_accession = SeedsByAccession::new();
 
-    let seeds_file = File::open(&args.paths.seeds)?;
-    let seeds_buf_reader = BufReader::new(seeds_file);
+    let mut reversed_count: usize = 0;
+    let mut clamped_count: usize = 0;
+    let mut unmatched_seed_counts: BTreeMap<String, usize> = BTreeMap::new();
+    let mut fallback_count: usize = 0;
 
-    for line in seeds_buf_reader.lines().flatten() {
-        let line_tokens: Vec<&str> = line.split_whitespace().collect();
-        let accession = line_tokens[0];
+    let seed_files = seed_files(args)?;
+    for seeds_path in &seed_files {
+        if let SeedsFormat::Mmoreseqs = args.seeds_format {
+            SeedsHeader::read(seeds_path)?.check(args)?;
+        }
 
-        let seeds = match profile_seeds_by_accession.get_mut(accession) {
-            Some(seeds) => seeds,
-            None => {
-                profile_seeds_by_accession.insert(accession.to_string(), vec![]);
-                profile_seeds_by_accession.get_mut(accession).unwrap()
+        for row in seed_rows(open_seeds_file(seeds_path)?, args.seeds_format) {
+            // the line numbers of the errors are only meaningful with the file
+            let row = row.with_context(|| format!("in seeds file {}", seeds_path.display()))?;
+            let Some((key, key_match)) = lookup.find(&row.query) else {
+                // the seeds of the profiles that were filtered out aren't mapped
+                if !args.query_filter.is_active() {
+                    *unmatched_seed_counts.entry(row.query).or_default() += 1;
+                }
+                continue;
+            };
+            // the profiles without an mmseqs consensus have already been reported
+            let Some(profile_idx_map) = profile_to_profile_idx_maps_by_accession.get(key) else {
+                continue;
+            };
+            if key_match != KeyMatch::Key {
+                fallback_count += 1;
             }
-        };
 
-        let profile_idx_map = profile_to_profile_idx_maps_by_accession
-            .get(accession)
-            .ok_or(ProfilesNotMappedError)?;
+            let seeds = profile_seeds_by_accession
+                .entry(key.to_string())
+                .or_default();
 
-        let target_name = line_tokens[1].to_string();
-        let target_start = line_tokens[4].parse::<usize>()?;
-        let target_end = line_tokens[5].parse::<usize>()?;
-        let profile_start = line_tokens[2].parse::<usize>()?;
-        let profile_end = line_tokens[3].parse::<usize>()?;
+            let SeedRow {
+                target: target_name,
+                query_start: profile_start,
+                query_end: profile_end,
+                target_start,
+                target_end,
+                evalue,
+                bit_score,
+                ..
+            } = row;
 
-        seeds.push(Seed {
-            target_name,
-            target_start,
-            target_end,
-            profile_start: profile_idx_map[profile_start].max(1),
-            profile_end: profile_idx_map[profile_end],
-        })
+            let MappedSeed {
+                seed,
+                reversed,
+                clamped,
+            } = map_seed(
+                target_name,
+                (target_start, target_end),
+                (profile_start, profile_end),
+                profile_idx_map,
+            );
+            if reversed {
+                reversed_count += 1;
+            }
+            if clamped {
+                clamped_count += 1;
+            }
+
+            let provenance = SeedProvenance {
+                evalue,
+                bit_score,
+                profile_start: seed.profile_start,
+                profile_end: seed.profile_end,
+                target_start: seed.target_start,
+                target_end: seed.target_end,
+            };
+
+            seeds.push((seed, provenance))
+        }
+    }
+
+    if reversed_count > 0 {
+        warn_or_fail(
+            args,
+            format!("normalized {reversed_count} seeds with reversed coordinates"),
+        )?;
+    }
+    if clamped_count > 0 {
+        warn_or_fail(
+            args,
+            format!("clamped {clamped_count} seeds that extend past the query profile"),
+        )?;
+    }
+    if fallback_count > 0 {
+        eprintln!(
+            "matched {fallback_count} seeds to their query profiles by name or ignoring case, rather than by accession"
+        );
+    }
+    if !unmatched_seed_counts.is_empty() {
+        warn_or_fail(
+            args,
+            ProfileNotFoundError::new(&unmatched_seed_counts).to_string(),
+        )?;
+    }
+
+    print_seed_stats(&profile_seeds_by_accession);
+
+    if let Some(max_gap) = args.chain_seeds {
+        let chained = chain_seeds(&mut profile_seeds_by_accession, max_gap);
+        if chained.chains > 0 {
+            eprintln!(
+                "chained {} collinear seeds into {} longer seeds",
+                chained.seeds, chained.chains
+            );
+        }
     }
+
+    let dropped_count = args.seed_filter.apply(&mut profile_seeds_by_accession);
+    if dropped_count > 0 {
+        eprintln!("dropped {dropped_count} seeds with the seed filters");
+    }
+
     Ok(profile_seeds_by_accession)
 }
 
+/// Write the consensus sequence of each query HMM to a fasta file, so that
+/// mmseqs can use them as a stand-in for the HMMs in the seed step.
+fn write_query_consensus_fasta(args: &Args) -> Result<()> {
+    let hmms = parse_hmms_from_p7hmm_file(args.paths.query.to_str().unwrap())?;
+
+    let mut profiles: Vec<Profile> = hmms.iter().map(Profile::new).collect();
+    assign_profile_keys(&mut profiles)?;
+
+    let mut records: Vec<SeqRecord> = vec![];
+    for profile in &profiles {
+        records.push(SeqRecord::new(
+            profile.accession.clone(),
+            std::str::from_utf8(&profile.consensus_sequence[1..])?.to_ascii_uppercase(),
+        ));
+    }
+
+    // the keys must match the query names that mmseqs reports, so they are left as is
+    let options = WriteOptions {
+        wrap_width: None,
+        sanitize_names: false,
+    };
+    write_fasta(
+        &records,
+        &options,
+        &mut args.paths.query_consensus_fasta.open(true)?,
+    )?;
+
+    Ok(())
+}
+
 pub fn prep(args: &Args) -> Result<()> {
-    run_mmseqs_convertmsa(args)?;
-    run_mmseqs_msa2profile(args)?;
-    run_mmseqs_createdb(args)?;
-    run_hmmbuild(args)?;
+    discard_if_cancelled(args, Stage::Prep, prep_steps(args))
+}
+
+/// Run the steps of a stage that writes to the workspace. If the run was
+/// cancelled while they ran, the outputs they were writing are removed and the
+/// cancellation is returned instead of the steps' own error.
+pub fn discard_if_cancelled(args: &Args, stage: Stage, result: Result<()>) -> Result<()> {
+    match shutdown::caught_signal() {
+        Some(signal) if result.is_err() => {
+            args.workspace.discard_partial(stage, &args.paths)?;
+            Err(CancelledError(signal).into())
+        }
+        _ => result,
+    }
+}
+
+fn prep_steps(args: &Args) -> Result<()> {
+    // a dry run keeps the commands in order, so that they read as a script,
+    // and a single thread can't be split between the query and target
+    if args.dry_run || args.threads < 2 {
+        prep_query(args)?;
+        return prep_target(args);
+    }
+
+    // the query and target are independent, so hmmbuild overlaps the target
+    // createdb, and --threads is split between them so the total stays within it
+    let (query_threads, target_threads) = split_prep_threads(args.threads);
+    let query_args = Args {
+        threads: query_threads,
+        ..args.clone()
+    };
+    let target_args = Args {
+        threads: target_threads,
+        ..args.clone()
+    };
+    std::thread::scope(|scope| {
+        let query = scope.spawn(|| prep_query(&query_args));
+        let target_result = prep_target(&target_args);
+        let query_result = query.join().expect("the query prep thread panicked");
+        query_result.and(target_result)
+    })
+}
+
+/// Split at least two threads between the query and target prep, which run at the same time.
+///
+/// The query gets as many as hmmbuild can use, up to half, and the target gets the rest.
+fn split_prep_threads(threads: usize) -> (usize, usize) {
+    let query_threads = (threads / 2).clamp(1, HMMBUILD_MAX_THREADS);
+    (query_threads, threads - query_threads)
+}
+
+/// Build the query mmseqs database and HMM in the workspace.
+pub fn prep_query(args: &Args) -> Result<()> {
+    args.workspace.create()?;
+
+    let query_format = match args.query_format {
+        Some(query_format) => query_format,
+        None => QueryFormat::detect(&args.paths.query)?,
+    };
+
+    match query_format {
+        QueryFormat::Stockholm if stockholm_lacks_accessions(&args.paths.query)? => {
+            // mmseqs names the profiles by accession, so every alignment needs one
+            let keyed = args.paths.query_keyed_stockholm.clone();
+            write_keyed_stockholm(&args.paths.query, &mut keyed.open(true)?)?;
+
+            let mut keyed_args = args.clone();
+            keyed_args.paths.query = keyed;
+            prep_query(&keyed_args)?;
+        }
+        QueryFormat::Stockholm => {
+            run_mmseqs_convertmsa(args)?;
+            run_mmseqs_msa2profile(args)?;
+            if !args.skip_hmmbuild {
+                run_hmmbuild(args)?;
+            }
+        }
+        QueryFormat::Afa => {
+            let stockholm = args.paths.query_stockholm.clone();
+            convert_afa_to_stockholm(&args.paths.query, &mut stockholm.open(true)?)?;
+
+            let mut stockholm_args = args.clone();
+            stockholm_args.paths.query = stockholm;
+            stockholm_args.query_format = Some(QueryFormat::Stockholm);
+            prep_query(&stockholm_args)?;
+        }
+        QueryFormat::Hmm => {
+            write_query_consensus_fasta(args)?;
+            run_mmseqs_createdb_query_consensus(args)?;
+            copy(&args.paths.query, &args.paths.query_hmm).context("failed to copy query HMM")?;
+        }
+    }
+
     Ok(())
 }
 
+/// Build the target mmseqs database in the workspace.
+pub fn prep_target(args: &Args) -> Result<()> {
+    args.workspace.create()?;
+
+    // mmseqs builds the target database from a local file, so it is downloaded whole
+    if let Some(target) = &args.paths.target_uri {
+        download_target(args, target)?;
+    }
+
+    if !args.paths.target_fasta_sources.is_empty() {
+        let sources = expand_target_sources(&args.paths.target_fasta_sources)?;
+        combine_targets(
+            &sources,
+            &args.paths.combined_target_fasta,
+            &args.paths.target_sources,
+        )?;
+        eprintln!("combined {} target fasta files", sources.len());
+    }
+
+    if let Some(contigs) = &args.paths.target_contigs {
+        let (contig_count, orf_count) = call_orfs(
+            contigs,
+            &args.paths.target_orfs,
+            &args.paths.orf_map,
+            &args.orf_params,
+        )?;
+        let genetic_code = &args.orf_params.genetic_code;
+        eprintln!(
+            "called {orf_count} ORFs on {contig_count} contigs with genetic code {} ({})",
+            genetic_code.id, genetic_code.name
+        );
+    }
+
+    if let Some(previous_results) = &args.paths.mask_previous {
+        let mask = HitMask::read(previous_results)?;
+        let masked_count = mask.mask_fasta(
+            &args.paths.target_fasta,
+            &mut BufWriter::new(args.paths.masked_target_fasta.open(true)?),
+        )?;
+        eprintln!(
+            "masked {masked_count} target residues covered by {} previous hits",
+            mask.region_count()
+        );
+    }
+
+    if let Some(taxdb) = &args.paths.taxdb {
+        let taxonomy = Taxonomy::load(taxdb)?;
+        let kept_count = filter_fasta(
+            args.paths.prepped_target_fasta(),
+            &taxonomy,
+            &args.taxon_filter,
+            &mut args.paths.filtered_target_fasta.open(true)?,
+        )?;
+        eprintln!("{kept_count} targets passed the taxon filter");
+    }
+
+    // the seed step records this in the seeds file, so that align can check its target
+    writeln!(
+        args.paths.target_checksum.open(true)?,
+        "{}",
+        checksum_file(&args.paths.target_fasta)?
+    )?;
+
+    run_mmseqs_createdb(args)
+}
+
 pub fn seed(args: &Args) -> Result<()> {
-    run_mmseqs_prefilter(args)?;
+    args.workspace.validate(Stage::Seed, &args.paths)?;
+    // the legacy seed arguments name a work directory that may not exist yet
+    args.workspace.create()?;
+    discard_if_cancelled(args, Stage::Seed, run_seed_steps(args))
+}
+
+/// Run the mmseqs steps that produce the alignment seeds.
+pub fn run_seed_steps(args: &Args) -> Result<()> {
+    // with an ID list, the mmseqs steps search a subset of the target database
+    let subset_args: Args;
+    let args = match &args.paths.target_include_ids {
+        Some(ids_path) => {
+            select_target_subset(args, ids_path)?;
+            let mut args = args.clone();
+            args.paths.target_db = args.paths.target_subset_db.clone();
+            subset_args = args;
+            &subset_args
+        }
+        None => args,
+    };
+
+    let query_db_type = check_seed_db_types(args)?;
+    run_mmseqs_prefilter(args, query_db_type)?;
     run_mmseqs_align(args)?;
     run_mmseqs_convertalis(args)?;
+    if !args.dry_run {
+        // the seeds file is written even if there are no seeds, so that align finds it
+        if !args.paths.seeds.exists() {
+            args.paths.seeds.open(true)?;
+        }
+        prepend_seeds_header(args)?;
+
+        if count_seeds(&args.paths.seeds)? == 0 {
+            eprintln!("{NO_SEEDS_MESSAGE}");
+        }
+    }
     Ok(())
 }
 
-pub fn align(args: &Args) -> Result<()> {
-    let hmms = parse_hmms_from_p7hmm_file(args.paths.query_hmm.to_str().unwrap())?;
-    let p7_profiles: Vec<Profile> = hmms.iter().map(Profile::new).collect();
+/// Count the seed lines in a seeds file.
+fn count_seeds(seeds: &Path) -> Result<usize> {
+    let file = File::open(seeds)?;
+    let mut count: usize = 0;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.starts_with('#') && !line.trim().is_empty() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
 
-    let profile_to_profile_idx_maps_by_accession = map_p7_to_mmseqs_profiles(&p7_profiles, args)?;
+/// The extensions of the files written by hmmpress
+const PRESSED_HMM_EXTENSIONS: [&str; 4] = ["h3m", "h3i", "h3f", "h3p"];
 
-    let profile_seeds_by_accession =
-        build_alignment_seeds(&profile_to_profile_idx_maps_by_accession, args)?;
+/// The number of pressed HMM databases converted so far, which keeps the temporary
+/// files of the queries loaded at the same time (by a server or a batch) apart
+static CONVERTED_HMM_COUNT: AtomicUsize = AtomicUsize::new(0);
 
-    let mut profile_map: HashMap<String, Profile> = HashMap::new();
-    for profile in p7_profiles {
-        profile_map.insert(profile.accession.clone(), profile);
+/// If the path points to an hmmpress-ed HMM database (and not a flat HMM file),
+/// return the base path of the database that HMMER expects.
+pub fn pressed_hmm_db_base(path: &Path) -> Option<PathBuf> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) if PRESSED_HMM_EXTENSIONS.contains(&extension) => {
+            Some(path.with_extension(""))
+        }
+        _ => {
+            let mut h3m = path.as_os_str().to_owned();
+            h3m.push(".h3m");
+            match !path.exists() && Path::new(&h3m).exists() {
+                true => Some(path.to_path_buf()),
+                false => None,
+            }
+        }
     }
+}
 
-    let targets = Sequence::amino_from_fasta(&args.paths.target_fasta)?;
-    let target_count = targets.len();
-    let mut target_map: HashMap<String, Sequence> = HashMap::new();
-    for target in targets {
-        target_map.insert(target.name.clone(), target);
-    }
+/// The query HMMs, parsed once and shared by everything that aligns against them.
+///
+/// The profiles are reconfigured for each target length as they are aligned,
+/// so the set holds the HMMs, and each user builds its own working profiles.
+pub struct QuerySet {
+    hmms: Vec<Hmm>,
+    /// The profile key of each HMM
+    keys: Vec<String>,
+    /// The MSV score statistics of each profile, by key
+    pub msv_stats: HashMap<String, MsvStats>,
+}
+
+impl QuerySet {
+    /// Load the query HMMs, building them from the query MSA if prep didn't.
+    pub fn load(args: &Args) -> Result<Self> {
+        if !args.paths.query_hmm.exists() && pressed_hmm_db_base(&args.paths.query_hmm).is_none() {
+            // if the HMM wasn't built during the prep step,
+            // we can build it now if we were given the MSA
+            match args.paths.query.as_os_str().is_empty() {
+                true => {
+                    return Err(MissingQueryHmmError(args.paths.query_hmm.clone()).into());
+                }
+                false => run_hmmbuild(args)?,
+            }
+        }
+
+        let hmms = match pressed_hmm_db_base(&args.paths.query_hmm) {
+            Some(db_base) => {
+                // nale can only read flat HMM files, so we let HMMER convert the
+                // pressed database for us; this is only a compatibility path, and
+                // loads more slowly than the flat file would, since the models
+                // are written out and parsed again rather than read from the .h3m
+                let converted_hmm = std::env::temp_dir().join(format!(
+                    "mmoreseqs-{}-{}.hmm",
+                    std::process::id(),
+                    CONVERTED_HMM_COUNT.fetch_add(1, Ordering::Relaxed)
+                ));
+                run_hmmconvert(args, &db_base, &converted_hmm)?;
+                let hmms = parse_hmms_from_p7hmm_file(converted_hmm.to_str().unwrap());
+                remove_file(&converted_hmm)?;
+                hmms?
+            }
+            None => parse_hmms_from_p7hmm_file(args.paths.query_hmm.to_str().unwrap())?,
+        };
 
-    let max_profile_length = profile_map
-        .values()
-        .fold(0usize, |acc: usize, p: &Profile| acc.max(p.length));
+        let mut profiles: Vec<Profile> = hmms.iter().map(Profile::new).collect();
+        assign_profile_keys(&mut profiles)?;
+        let keys: Vec<String> = profiles.into_iter().map(|p| p.accession).collect();
 
-    let max_target_length = target_map
-        .values()
-        .fold(0usize, |acc: usize, s: &Sequence| acc.max(s.length));
+        let msv_stats = hmms
+            .iter()
+            .zip(&keys)
+            .map(|(hmm, key)| (key.clone(), MsvStats::new(hmm)))
+            .collect();
+
+        Ok(QuerySet {
+            hmms,
+            keys,
+            msv_stats,
+        })
+    }
 
-    let mut cloud_matrix = CloudMatrixLinear::new(max_profile_length);
+    /// The query HMMs, in the order of the query file
+    pub fn hmms(&self) -> &[Hmm] {
+        &self.hmms
+    }
 
-    let mut forward_bounds = CloudBoundGroup::new(max_target_length, max_profile_length);
-    let mut backward_bounds = CloudBoundGroup::new(max_target_length, max_profile_length);
+    /// Build the working profiles of the queries that pass the query filter,
+    /// keyed and with the model overrides applied.
+    pub fn profiles(&self, args: &Args) -> Result<Vec<Profile>> {
+        let mut profiles: Vec<Profile> = self
+            .hmms
+            .iter()
+            .zip(&self.keys)
+            .map(|(hmm, key)| {
+                let mut profile = Profile::new(hmm);
+                profile.accession = key.clone();
+                profile
+            })
+            .collect();
 
-    let mut forward_matrix = DpMatrixFlat::new(max_target_length, max_profile_length);
-    let mut backward_matrix = DpMatrixFlat::new(max_target_length, max_profile_length);
-    let mut posterior_matrix = DpMatrixFlat::new(max_target_length, max_profile_length);
-    let mut optimal_matrix = DpMatrixFlat::new(max_target_length, max_profile_length);
+        if args.query_filter.is_active() {
+            let profile_count = profiles.len();
+            profiles.retain(|p| args.query_filter.allows(p));
+            if profiles.is_empty() {
+                return Err(NoQueriesSelectedError.into());
+            }
+            eprintln!(
+                "selected {} of {profile_count} query profiles",
+                profiles.len()
+            );
+        }
 
-    let mut alignments: Vec<Alignment> = vec![];
+        args.model_overrides.apply(&mut profiles)?;
 
-    let mut profile_names: Vec<&String> = profile_seeds_by_accession.keys().collect();
-    profile_names.sort();
+        Ok(profiles)
+    }
+}
 
-    for profile_accession in profile_names {
-        let profile = profile_map.get_mut(profile_accession).unwrap();
-        let seeds = profile_seeds_by_accession.get(profile_accession).unwrap();
-        for seed in seeds {
-            let target = target_map.get(&seed.target_name[..]).unwrap();
+/// The target sequences that seeds are aligned against.
+pub struct TargetStore {
+    pub sequences: HashMap<String, Sequence>,
+    /// The number of targets, which is used as the E-value search space
+    pub count: usize,
+    pub taxonomy: Option<Taxonomy>,
+    /// The targets that were dropped by the ambiguity policy
+    pub ambiguous_names: HashSet<String>,
+    /// The source fasta of each target, if the targets were combined from several
+    pub sources: Option<TargetSources>,
+    /// The contig location of each target, if the targets are ORFs called on contigs
+    pub orfs: Option<OrfMap>,
+}
 
-            profile.configure_for_target_length(target.length);
+impl TargetStore {
+    /// Load the target fasta, dropping any targets that don't pass the taxon filter.
+    ///
+    /// A remote target fasta without a local copy is downloaded into the workspace first.
+    pub fn load(args: &Args) -> Result<Self> {
+        if let Some(target) = &args.paths.target_uri {
+            if !args.paths.target_fasta.exists() {
+                download_target(args, target)?;
+            }
+        }
+        Self::load_from(args, &args.paths.target_fasta, None, None)
+    }
 
-            cloud_matrix.reuse(profile.length);
-            forward_bounds.reuse(target.length, profile.length);
-            backward_bounds.reuse(target.length, profile.length);
+    /// Load the targets that have seeds. For a remote target fasta without a
+    /// local copy, only their records are fetched, rather than the whole file,
+    /// and for a target fasta too large for --max-ram, only their sequences are
+    /// kept as it is read.
+    pub fn load_seeded(args: &Args) -> Result<Self> {
+        match &args.paths.target_uri {
+            Some(target) if !args.paths.target_fasta.exists() => {
+                let index = fetch_targets(args, target, &seeded_target_names(args)?)?;
+                Self::load_from(args, &args.paths.fetched_target_fasta, Some(&index), None)
+            }
+            _ => match args.max_ram {
+                Some(budget) if !budget.loads_whole(&args.paths.target_fasta) => {
+                    let seeded_names = seeded_target_names(args)?;
+                    eprintln!(
+                        "the target fasta is too large to load whole under --max-ram; \
+                         loading the {} targets with seeds",
+                        seeded_names.len()
+                    );
+                    Self::load_from(args, &args.paths.target_fasta, None, Some(&seeded_names))
+                }
+                _ => Self::load(args),
+            },
+        }
+    }
 
-            cloud_search_forward(
-                profile,
-                target,
-                seed,
-                &mut cloud_matrix,
-                &CloudSearchParams::default(),
-                &mut forward_bounds,
-            )?;
+    /// Load the targets from a fasta, or only the targets to keep, if they are given.
+    /// With the index of the targets that they were fetched from, or the targets
+    /// to keep, the E-value search space is all of the indexed or read targets that
+    /// pass the taxon filter, and not only the ones that were fetched or kept.
+    fn load_from(
+        args: &Args,
+        target_fasta: &Path,
+        index: Option<&TargetIndex>,
+        keep: Option<&HashSet<String>>,
+    ) -> Result<Self> {
+        let ParsedTargets {
+            sequences: mut targets,
+            mut unloaded_names,
+            skipped_names,
+            unknown_residues,
+        } = read_amino_fasta(target_fasta, args.ambiguity, keep)?;
 
-            cloud_search_backward(
-                profile,
-                target,
-                seed,
-                &mut cloud_matrix,
-                &CloudSearchParams::default(),
-                &mut backward_bounds,
+        if let Some(first_unknown) = unknown_residues.first() {
+            warn_or_fail(
+                args,
+                format!(
+                    "{} targets have characters that aren't amino acids, which are treated as \
+                     ambiguous residues (--ambiguity); e.g. {first_unknown}",
+                    unknown_residues.len()
+                ),
             )?;
+        }
 
-            CloudBoundGroup::join_bounds(&mut forward_bounds, &backward_bounds)?;
+        if !skipped_names.is_empty() {
+            eprintln!(
+                "skipped {} targets with ambiguous residues",
+                skipped_names.len()
+            );
+        }
 
-            forward_bounds.trim_wings();
+        let taxonomy = match &args.paths.taxdb {
+            Some(taxdb) => Some(Taxonomy::load(taxdb)?),
+            None => None,
+        };
 
-            let row_bounds = RowBounds::new(&forward_bounds);
+        if let Some(taxonomy) = &taxonomy {
+            targets.retain(|t| args.taxon_filter.allows(taxonomy, &t.name));
+            unloaded_names.retain(|name| args.taxon_filter.allows(taxonomy, name));
+        }
 
-            forward_matrix.reuse(target.length, profile.length);
-            backward_matrix.reuse(target.length, profile.length);
-            posterior_matrix.reuse(target.length, profile.length);
-            optimal_matrix.reuse(target.length, profile.length);
+        // the source map is only for the combined targets
+        let sources = match args.paths.target_fasta == args.paths.combined_target_fasta
+            && args.paths.target_sources.exists()
+        {
+            true => Some(TargetSources::read(&args.paths.target_sources)?),
+            false => None,
+        };
 
-            forward_bounded(profile, target, &mut forward_matrix, &row_bounds);
+        // and the ORF map is only for the ORFs
+        let orfs = match args.paths.target_fasta == args.paths.target_orfs
+            && args.paths.orf_map.exists()
+        {
+            true => Some(OrfMap::read(&args.paths.orf_map)?),
+            false => None,
+        };
 
-            backward_bounded(profile, target, &mut backward_matrix, &row_bounds);
+        let count = match (index, &taxonomy) {
+            (Some(index), Some(taxonomy)) => index
+                .names()
+                .filter(|name| args.taxon_filter.allows(taxonomy, name))
+                .count(),
+            (Some(index), None) => index.len(),
+            (None, _) => targets.len() + unloaded_names.len(),
+        };
+        let mut sequences: HashMap<String, Sequence> = HashMap::new();
+        let mut duplicate_count: usize = 0;
+        for target in targets {
+            if sequences.insert(target.name.clone(), target).is_some() {
+                duplicate_count += 1;
+            }
+        }
+        if duplicate_count > 0 {
+            warn_or_fail(
+                args,
+                format!("{duplicate_count} target names are repeated; the last sequence of each name is used"),
+            )?;
+        }
 
-            posterior_bounded(
-                profile,
-                &forward_matrix,
-                &backward_matrix,
-                &mut posterior_matrix,
-                &row_bounds,
+        if let Some(previous_results) = &args.paths.mask_previous {
+            let mask = HitMask::read(previous_results)?;
+            let masked_count = mask.mask_sequences(&mut sequences);
+            eprintln!(
+                "masked {masked_count} target residues covered by {} previous hits",
+                mask.region_count()
             );
+        }
+
+        Ok(TargetStore {
+            sequences,
+            count,
+            taxonomy,
+            ambiguous_names: skipped_names.into_iter().collect(),
+            sources,
+            orfs,
+        })
+    }
+}
 
-            optimal_accuracy_bounded(profile, &posterior_matrix, &mut optimal_matrix, &row_bounds);
+/// The outcome of aligning a set of seeds
+pub struct AlignOutput {
+    /// The hits that passed the E-value cutoff
+    pub hits: Vec<(Alignment, AlignedHit)>,
+    pub skipped: Vec<SkippedSeed>,
+    /// The number of query profiles that were aligned
+    pub query_count: usize,
+    /// The number of seeds that were aligned, including the skipped seeds
+    pub seed_count: usize,
+    /// The hits reused from --skip-existing, which still pass the E-value thresholds
+    pub existing: Option<ExistingResults>,
+    /// The names of the queries with seeds left unaligned at the time limit
+    pub incomplete_queries: Vec<String>,
+}
 
-            let mut trace = Trace::new(target.length, profile.length);
-            traceback_bounded(
-                profile,
-                &posterior_matrix,
-                &optimal_matrix,
-                &mut trace,
-                row_bounds.target_end,
-            );
+/// Merge the hits that different seeds of the same profile and target converged on.
+///
+/// Hits are duplicates when they share a profile, target, and envelope. The best
+/// scoring of the duplicates is kept, and it records how many seeds produced it.
+fn deduplicate_hits(hits: Vec<(Alignment, AlignedHit)>) -> Vec<(Alignment, AlignedHit)> {
+    let mut unique_hits: Vec<(Alignment, AlignedHit)> = vec![];
+    let mut hit_idx_by_key: HashMap<(String, String, usize, usize), usize> = HashMap::new();
 
-            alignments.push(Alignment::new(&trace, profile, target, target_count));
+    for (alignment, mut aligned_hit) in hits {
+        let key = (
+            alignment.profile_name.clone(),
+            alignment.target_name.clone(),
+            aligned_hit.envelope.start,
+            aligned_hit.envelope.end,
+        );
+
+        match hit_idx_by_key.get(&key) {
+            Some(&hit_idx) => {
+                let (kept_alignment, kept_hit) = &mut unique_hits[hit_idx];
+                let supporting_seeds = kept_hit.supporting_seeds + aligned_hit.supporting_seeds;
+                // the alternatives of every seed that converged on the hit are kept
+                let mut alternatives = std::mem::take(&mut kept_hit.alternatives);
+                alternatives.append(&mut aligned_hit.alternatives);
+                if alignment.bit_score > kept_alignment.bit_score {
+                    *kept_alignment = alignment;
+                    *kept_hit = aligned_hit;
+                }
+                kept_hit.supporting_seeds = supporting_seeds;
+                kept_hit.alternatives = alternatives;
+            }
+            None => {
+                hit_idx_by_key.insert(key, unique_hits.len());
+                unique_hits.push((alignment, aligned_hit));
+            }
         }
     }
 
-    alignments = alignments
-        .drain(..)
-        .filter(|a| a.evalue <= args.evalue_cutoff)
-        .collect();
+    unique_hits
+}
 
-    write_tabular_output(&alignments, &mut args.paths.results.open(true)?)?;
+/// What became of a single seed
+enum SeedOutcome {
+    Hit(Box<(Alignment, AlignedHit)>),
+    Skipped(SkipReason),
+    /// Dropped by the MSV filter or the fast pass
+    Filtered(SkipReason),
+}
 
-    Ok(())
+/// Runs the filters and the bounded DP for one seed at a time, one aligner to each worker.
+///
+/// The aligner only holds memory that is reused between seeds, which every DP
+/// routine overwrites before reading. The profile is borrowed immutably, and
+/// each seed's scores are computed into the SeedOutcome that align() returns,
+/// so no value computed for one seed can leak into the result of another.
+struct SeedAligner<'a> {
+    args: &'a Args,
+    target_count: usize,
+    default_params: CloudSearchParams,
+    relaxed_params: CloudSearchParams,
+    cloud_matrix: CloudMatrixLinear,
+    forward_bounds: CloudBoundGroup,
+    backward_bounds: CloudBoundGroup,
+    matrices: DpMatrices,
+    filter_counts: FilterCounts,
+    expansion_counts: ExpansionCounts,
 }
 
-pub fn search(args: &Args) -> Result<()> {
-    {
-        // quickly make sure we can write the results
-        args.paths.results.open(true)?;
+impl<'a> SeedAligner<'a> {
+    fn new(
+        args: &'a Args,
+        max_profile_length: usize,
+        max_target_length: usize,
+        target_count: usize,
+        max_matrix_bytes: Option<usize>,
+    ) -> Self {
+        let default_params = args.sensitivity.cloud_search_params();
+        // the relaxed parameters are only used to retry
+        // seeds that failed to produce valid bounds
+        let relaxed_params = CloudSearchParams {
+            gamma: default_params.gamma * 2,
+            alpha: default_params.alpha * 2.0,
+            beta: default_params.beta * 2.0,
+        };
+
+        SeedAligner {
+            args,
+            target_count,
+            default_params,
+            relaxed_params,
+            cloud_matrix: CloudMatrixLinear::new(max_profile_length),
+            forward_bounds: CloudBoundGroup::new(max_target_length, max_profile_length),
+            backward_bounds: CloudBoundGroup::new(max_target_length, max_profile_length),
+            matrices: DpMatrices::new(max_matrix_bytes),
+            filter_counts: FilterCounts::default(),
+            expansion_counts: ExpansionCounts::default(),
+        }
+    }
+
+    /// Find the row bounds for a seed: the whole matrix if it's small enough,
+    /// or else the cloud search's, falling back as set by --on-bound-fail.
+    fn row_bounds(
+        &mut self,
+        profile: &Profile,
+        target: &Sequence,
+        seed: &Seed,
+    ) -> Option<(RowBounds, BoundSource)> {
+        // below the cutoff, building the cloud bounds costs more than the whole matrix
+        if profile.length * target.length <= self.args.dense_cells {
+            return Some((full_row_bounds(profile, target), BoundSource::Dense));
+        }
+
+        let row_bounds = cloud_search_row_bounds(
+            profile,
+            target,
+            seed,
+            &self.default_params,
+            &mut self.cloud_matrix,
+            &mut self.forward_bounds,
+            &mut self.backward_bounds,
+        );
+
+        match (row_bounds, self.args.on_bound_fail) {
+            (Some(row_bounds), _) => Some((row_bounds, BoundSource::Cloud)),
+            (None, OnBoundFail::Skip) => None,
+            (None, OnBoundFail::FullDp) => {
+                Some((full_row_bounds(profile, target), BoundSource::Full))
+            }
+            (None, OnBoundFail::WidenRetry) => cloud_search_row_bounds(
+                profile,
+                target,
+                seed,
+                &self.relaxed_params,
+                &mut self.cloud_matrix,
+                &mut self.forward_bounds,
+                &mut self.backward_bounds,
+            )
+            .map(|row_bounds| (row_bounds, BoundSource::Widened)),
+        }
+    }
+
+    /// Align a seed. The profile must already be configured for the target's length.
+    fn align(
+        &mut self,
+        profile: &Profile,
+        target: &Sequence,
+        seed: &Seed,
+        provenance: &SeedProvenance,
+        msv_stats: &MsvStats,
+        evalue_cutoff: f32,
+    ) -> SeedOutcome {
+        if let Some(msv_pvalue) = self.args.msv_pvalue {
+            self.filter_counts.msv_in += 1;
+            let bit_score = msv_bit_score(profile, target, seed);
+            if msv_stats.pvalue(bit_score) > msv_pvalue {
+                return SeedOutcome::Filtered(SkipReason::MsvFilter);
+            }
+            self.filter_counts.msv_passed += 1;
+        }
+
+        // the cloud search starts from the padded seed, if there is one
+        let expanded_seed = self
+            .args
+            .seed_expand
+            .map(|expansion| expansion.expand(seed, profile.length, target.length));
+        let search_seed = expanded_seed.as_ref().unwrap_or(seed);
+
+        let (row_bounds, bound_source) = match self.row_bounds(profile, target, search_seed) {
+            Some(row_bounds) => row_bounds,
+            None => return SeedOutcome::Skipped(SkipReason::InvalidBounds),
+        };
+
+        let mut outcome =
+            self.align_bounded(profile, target, provenance, &row_bounds, evalue_cutoff);
+        self.matrices.shrink_to_cap();
+
+        if let (SeedOutcome::Hit(hit), Some(_)) = (&mut outcome, &self.args.paths.bound_stats) {
+            hit.1.bound_stats = Some(BoundStats::new(
+                &row_bounds,
+                bound_source,
+                profile.length,
+                target.length,
+            ));
+        }
+
+        if let (SeedOutcome::Hit(hit), Some(expanded_seed)) = (&outcome, &expanded_seed) {
+            self.expansion_counts
+                .count(&hit.0, expanded_seed, profile.length, target.length);
+        }
+        outcome
+    }
+
+    fn align_bounded(
+        &mut self,
+        profile: &Profile,
+        target: &Sequence,
+        provenance: &SeedProvenance,
+        row_bounds: &RowBounds,
+        evalue_cutoff: f32,
+    ) -> SeedOutcome {
+        let matrices = &mut self.matrices;
+        matrices.reuse(target.length, profile.length);
+
+        forward_bounded(profile, target, &mut matrices.forward, row_bounds);
+
+        if self.args.fast_pass {
+            self.filter_counts.forward_in += 1;
+            if forward_evalue(profile, &matrices.forward, row_bounds, self.target_count)
+                > evalue_cutoff
+            {
+                return SeedOutcome::Filtered(SkipReason::FastPass);
+            }
+            self.filter_counts.forward_passed += 1;
+        }
+
+        backward_bounded(profile, target, &mut matrices.backward, row_bounds);
+
+        posterior_bounded(
+            profile,
+            &matrices.forward,
+            &matrices.backward,
+            &mut matrices.posterior,
+            row_bounds,
+        );
+
+        optimal_accuracy_bounded(
+            profile,
+            &matrices.posterior,
+            &mut matrices.optimal,
+            row_bounds,
+        );
+
+        let mut trace = Trace::new(target.length, profile.length);
+        traceback_bounded(
+            profile,
+            &matrices.posterior,
+            &matrices.optimal,
+            &mut trace,
+            row_bounds.target_end,
+        );
+
+        let mut aligned_hit = AlignedHit::new(&trace, profile, target);
+        aligned_hit.envelope = Envelope::from_posterior(
+            &matrices.posterior,
+            row_bounds,
+            aligned_hit.target_start,
+            aligned_hit.target_end,
+        );
+        aligned_hit.seed = Some(provenance.clone());
+        if self.args.paths.trace_output.is_some() {
+            aligned_hit.trace = Some(HitTrace::new(&trace));
+        }
+
+        let alignment = Alignment::new(&trace, profile, target, self.target_count);
+        aligned_hit.assign_id(alignment.bit_score);
+
+        if self.args.alt_alignments > 0 {
+            aligned_hit.alternatives = trace_alternatives(
+                profile,
+                target,
+                matrices,
+                row_bounds,
+                &alignment,
+                self.args.alt_alignments,
+                self.target_count,
+            );
+        }
+
+        SeedOutcome::Hit(Box::new((alignment, aligned_hit)))
+    }
+}
+
+/// Whether a seed's coordinates fall within its target and profile. The seeds are
+/// 1-based, so a zero start means the coordinates are 0-based or garbled.
+fn seed_in_range(seed: &Seed, target_length: usize, profile_length: usize) -> bool {
+    seed.target_start > 0 && seed.target_end <= target_length && seed.profile_end <= profile_length
+}
+
+/// Align the seeds in the workspace against the targets.
+pub fn align_seeds(args: &Args, queries: &QuerySet, targets: &TargetStore) -> Result<AlignOutput> {
+    let p7_profiles = queries.profiles(args)?;
+    let msv_stats = &queries.msv_stats;
+
+    let pinning = CpuPinning::detect(args);
+    let profile_to_profile_idx_maps_by_accession =
+        map_p7_to_mmseqs_profiles(&p7_profiles, args, pinning.as_ref())?;
+
+    let profile_seeds_by_accession = build_alignment_seeds(
+        &profile_to_profile_idx_maps_by_accession,
+        &ProfileLookup::new(&p7_profiles),
+        args,
+    )?;
+
+    // the workers share the profiles, and each only copies the ones it configures
+    let mut profile_map: HashMap<String, Arc<Profile>> = HashMap::new();
+    for profile in p7_profiles {
+        profile_map.insert(profile.accession.clone(), Arc::new(profile));
+    }
+
+    let target_map = &targets.sequences;
+
+    let mut profile_accessions: Vec<String> = profile_map.keys().cloned().collect();
+    profile_accessions.sort();
+
+    let profile_accessions = match &args.shard {
+        Some(shard) => shard.select(&profile_accessions),
+        None => &profile_accessions[..],
+    };
+
+    let thresholds = match &args.paths.threshold_table {
+        Some(path) => ThresholdTable::read(path)?,
+        None => ThresholdTable::default(),
+    };
+    let inclusion = args.inclusion_evalue.is_some() || args.inclusion_bits.is_some();
+    if thresholds.has_inclusion_cutoffs() && !inclusion {
+        return Err(InclusionNotEnabledError.into());
+    }
+    // keyed by accession, as the table is, since profiles may share a name
+    let mut evalue_cutoffs_by_accession: HashMap<String, f32> = HashMap::new();
+    let mut inclusion_cutoffs_by_accession: HashMap<String, Option<f32>> = HashMap::new();
+
+    let mut existing = match &args.paths.skip_existing {
+        Some(path) => Some(ExistingResults::read(path)?),
+        None => None,
+    };
+    let mut reused_seed_count: usize = 0;
+
+    let mut seed_count: usize = 0;
+
+    // each seed is a unit of work, and the work is done in the order set by the
+    // schedule; the work index restores the seeds file order of the outputs
+    let mut work: Vec<WorkItem> = vec![];
+    for profile_accession in profile_accessions {
+        let seeds = match profile_seeds_by_accession.get(profile_accession) {
+            Some(seeds) => seeds,
+            None => continue,
+        };
+        let profile = &profile_map[profile_accession];
+        let evalue_cutoff = thresholds.evalue_cutoff(profile_accession, args.evalue_cutoff);
+        evalue_cutoffs_by_accession.insert(profile_accession.clone(), evalue_cutoff);
+        inclusion_cutoffs_by_accession.insert(
+            profile_accession.clone(),
+            thresholds.inclusion_cutoff(profile_accession, args.inclusion_evalue),
+        );
+        for (seed, provenance) in seeds {
+            if existing
+                .as_ref()
+                .is_some_and(|e| e.contains(&profile.name, &seed.target_name))
+            {
+                reused_seed_count += 1;
+                continue;
+            }
+            seed_count += 1;
+            work.push((work.len(), profile_accession, seed, provenance));
+        }
+    }
+
+    if let Some(existing) = &mut existing {
+        // the existing hits only have the profile name, so a name that several
+        // profiles share keeps the loosest of their thresholds
+        let mut evalue_cutoffs_by_name: HashMap<&str, f32> = HashMap::new();
+        for (profile_accession, evalue_cutoff) in &evalue_cutoffs_by_accession {
+            let cutoff = evalue_cutoffs_by_name
+                .entry(&profile_map[profile_accession].name)
+                .or_insert(*evalue_cutoff);
+            *cutoff = cutoff.max(*evalue_cutoff);
+        }
+        // the thresholds may have been tightened, and the targets may have changed since
+        existing.retain(|profile_name, target_name, evalue| {
+            let evalue_cutoff = evalue_cutoffs_by_name
+                .get(profile_name)
+                .copied()
+                .unwrap_or(args.evalue_cutoff);
+            evalue <= evalue_cutoff && target_map.contains_key(target_name)
+        });
+        eprintln!(
+            "reusing {} existing hits; skipped {reused_seed_count} seeds of pairs already reported",
+            existing.hit_count()
+        );
+    }
+
+    let WorkerOutput {
+        hits,
+        skipped,
+        filter_counts,
+        expansion_counts,
+        incomplete_queries,
+        length_configurations,
+    } = run_align_workers(
+        args,
+        &profile_map,
+        targets,
+        msv_stats,
+        &evalue_cutoffs_by_accession,
+        pinning.as_ref(),
+        work,
+    );
+
+    if seed_count > 0 {
+        eprintln!(
+            "configured the queries for {length_configurations} target lengths over {seed_count} seeds"
+        );
+    }
+    filter_counts.print();
+    expansion_counts.print();
+
+    // put the outputs back in seeds file order, so that they don't depend on the schedule
+    let hits: Vec<(Alignment, AlignedHit)> = in_work_order(hits);
+    let skipped: Vec<SkippedSeed> = in_work_order(skipped);
+
+    let mut hits = deduplicate_hits(hits);
+    hits.retain(|(a, h)| a.evalue <= evalue_cutoffs_by_accession[&h.profile_accession]);
+    // a target's sequence score and domain count are over all of its domains that pass the
+    // E-value cutoff, so they don't change with the filters on which of them are reported
+    assign_domain_stats(&mut hits, &profile_map, targets.count);
+    if let Some(max_bias_fraction) = args.max_bias_fraction {
+        hits.retain(|(a, h)| h.bias <= max_bias_fraction * a.bit_score);
+    }
+    if let Some(min_aligned_length) = args.min_aligned_length {
+        hits.retain(|(a, _)| a.length >= min_aligned_length);
+    }
+    if let Some(min_profile_cov) = args.min_profile_cov {
+        hits.retain(|(_, h)| h.profile_coverage >= min_profile_cov);
+    }
+    if let Some(min_target_cov) = args.min_target_cov {
+        hits.retain(|(_, h)| h.target_coverage >= min_target_cov);
+    }
+    if let Some(hit_filter) = &args.hit_filter {
+        let hit_count = hits.len();
+        hits.retain(|(a, h)| hit_filter.allows(a, h));
+        eprintln!(
+            "--filter removed {} of {hit_count} hits",
+            hit_count - hits.len()
+        );
+    }
+    if inclusion {
+        for (a, h) in hits.iter_mut() {
+            let meets_evalue = inclusion_cutoffs_by_accession[&h.profile_accession]
+                .is_none_or(|inclusion_evalue| a.evalue <= inclusion_evalue);
+            let meets_bits = args
+                .inclusion_bits
+                .is_none_or(|inclusion_bits| a.bit_score >= inclusion_bits);
+            h.included = Some(meets_evalue && meets_bits);
+        }
+    }
+
+    Ok(AlignOutput {
+        hits,
+        skipped,
+        query_count: profile_accessions.len(),
+        seed_count,
+        existing,
+        incomplete_queries: incomplete_queries.into_iter().collect(),
+    })
+}
+
+/// Align the seeds of the work on --threads workers, handing them out in groups
+/// of one profile and target length in the order of --schedule, and merge what
+/// the workers found.
+pub(crate) fn run_align_workers(
+    args: &Args,
+    profile_map: &HashMap<String, Arc<Profile>>,
+    targets: &TargetStore,
+    msv_stats: &HashMap<String, MsvStats>,
+    evalue_cutoffs_by_accession: &HashMap<String, f32>,
+    pinning: Option<&CpuPinning>,
+    work: Vec<WorkItem>,
+) -> WorkerOutput {
+    let target_map = &targets.sequences;
+    let seed_count = work.len();
+
+    let target_length = |seed: &Seed| target_map.get(&seed.target_name).map_or(0, |t| t.length);
+    // a group goes to a single worker, which configures the profile for the
+    // group's target length once, however many seeds the group has
+    let mut groups = group_by_length_config(work, |(_, profile_accession, seed, _)| {
+        (*profile_accession, target_length(seed))
+    });
+    args.schedule.order(&mut groups, |group| {
+        group
+            .iter()
+            .map(|(_, profile_accession, seed, _)| {
+                profile_map[*profile_accession].length * target_length(seed)
+            })
+            .sum()
+    });
+
+    let max_profile_length = profile_map.values().map(|p| p.length).max().unwrap_or(0);
+    let max_target_length = target_map.values().map(|s| s.length).max().unwrap_or(0);
+
+    let mut workers = args.threads.max(1);
+    if let Some(budget) = args.max_ram {
+        let max_workers = budget.max_workers(targets, max_target_length, max_profile_length);
+        if max_workers < workers {
+            eprintln!(
+                "aligning on {max_workers} of {workers} threads, so that the workers' DP \
+                 matrices fit in --max-ram"
+            );
+            workers = max_workers;
+        }
+    }
+    // an explicit --max-matrix-mb wins over the workers' share of --max-ram
+    let max_matrix_bytes = args.max_matrix_bytes.or_else(|| {
+        args.max_ram
+            .map(|budget| budget.matrix_bytes(targets, workers))
+    });
+
+    let context = AlignContext {
+        args,
+        profile_map,
+        targets,
+        msv_stats,
+        evalue_cutoffs_by_accession,
+        max_profile_length,
+        max_target_length,
+        max_matrix_bytes,
+        pinning,
+        align_time_by_accession: Mutex::new(HashMap::new()),
+    };
+    let queue = WorkQueue::new(&groups);
+
+    args.progress.start_align(seed_count, workers);
+
+    let outputs: Vec<WorkerOutput> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..workers)
+            .map(|worker_idx| {
+                let (context, queue) = (&context, &queue);
+                scope.spawn(move || align_worker(context, queue, worker_idx))
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("an align worker panicked"))
+            .collect()
+    });
+
+    let mut merged = WorkerOutput::default();
+    for output in outputs {
+        merged.hits.extend(output.hits);
+        merged.skipped.extend(output.skipped);
+        merged.filter_counts.add(&output.filter_counts);
+        merged.expansion_counts.add(&output.expansion_counts);
+        merged.incomplete_queries.extend(output.incomplete_queries);
+        merged.length_configurations += output.length_configurations;
+    }
+
+    args.progress.end_align(merged.hits.len());
+    merged
+}
+
+/// The outputs of the align workers in the order of their work indexes, which is
+/// the seeds file order, whichever worker each came from.
+fn in_work_order<T>(mut outputs: Vec<(usize, T)>) -> Vec<T> {
+    outputs.sort_by_key(|(work_idx, _)| *work_idx);
+    outputs.into_iter().map(|(_, output)| output).collect()
+}
+
+/// A seed to align: its work index, its profile's accession, and the seed itself
+pub(crate) type WorkItem<'a> = (usize, &'a String, &'a Seed, &'a SeedProvenance);
+
+/// What the align workers share, none of which they change but the align times
+struct AlignContext<'a> {
+    args: &'a Args,
+    profile_map: &'a HashMap<String, Arc<Profile>>,
+    targets: &'a TargetStore,
+    msv_stats: &'a HashMap<String, MsvStats>,
+    evalue_cutoffs_by_accession: &'a HashMap<String, f32>,
+    max_profile_length: usize,
+    max_target_length: usize,
+    /// The most memory that each worker's DP matrices may keep between seeds
+    max_matrix_bytes: Option<usize>,
+    /// The CPUs to pin the workers to, with --pin-threads
+    pinning: Option<&'a CpuPinning>,
+    /// The time spent aligning each query's seeds, over all of the workers,
+    /// for --per-query-time-limit
+    align_time_by_accession: Mutex<HashMap<&'a String, Duration>>,
+}
+
+/// The hits and skipped seeds of one align worker, or of all of them merged, by
+/// their work index
+#[derive(Default)]
+pub(crate) struct WorkerOutput {
+    pub(crate) hits: Vec<(usize, (Alignment, AlignedHit))>,
+    skipped: Vec<(usize, SkippedSeed)>,
+    filter_counts: FilterCounts,
+    expansion_counts: ExpansionCounts,
+    /// The names of the queries with seeds left unaligned at the time limit
+    incomplete_queries: BTreeSet<String>,
+    /// The times that a profile was configured for a new target length, at most
+    /// once for each group of seeds
+    length_configurations: usize,
+}
+
+/// Align groups of seeds from the queue until it runs out.
+///
+/// Each worker has its own DP matrices, and its own copy of each profile that it
+/// has to configure for a group's target length.
+fn align_worker<'a>(
+    context: &AlignContext<'a>,
+    queue: &WorkQueue<Vec<WorkItem<'a>>>,
+    worker_idx: usize,
+) -> WorkerOutput {
+    let AlignContext {
+        args,
+        profile_map,
+        targets,
+        msv_stats,
+        evalue_cutoffs_by_accession,
+        max_profile_length,
+        max_target_length,
+        max_matrix_bytes,
+        pinning,
+        ref align_time_by_accession,
+    } = *context;
+    let target_map = &targets.sequences;
+    let target_count = targets.count;
+
+    // the DP matrices are allocated once the worker is pinned, so
+    // that they are placed on the memory of the CPU's NUMA node
+    let _pinned = pinning.and_then(|pinning| pinning.pin_current_thread(worker_idx));
+    let mut aligner = SeedAligner::new(
+        args,
+        max_profile_length,
+        max_target_length,
+        target_count,
+        max_matrix_bytes,
+    );
+
+    let mut hits: Vec<(usize, (Alignment, AlignedHit))> = vec![];
+    let mut skipped: Vec<(usize, SkippedSeed)> = vec![];
+    let mut incomplete_queries: BTreeSet<String> = BTreeSet::new();
+    let mut length_configurations: usize = 0;
+
+    let mut profiles: HashMap<&String, WorkerProfile> = HashMap::new();
+
+    let seeds = std::iter::from_fn(|| queue.take()).flatten();
+    for &(work_idx, profile_accession, seed, provenance) in seeds {
+        let worker_profile = profiles
+            .entry(profile_accession)
+            .or_insert_with(|| WorkerProfile::new(Arc::clone(&profile_map[profile_accession])));
+        let profile = worker_profile.get();
+        let align_time = align_time_by_accession
+            .lock()
+            .unwrap()
+            .get(profile_accession)
+            .copied()
+            .unwrap_or_default();
+        // once cancelled or out of time, the seeds that are left are skipped, so
+        // that the hits so far are written out whole
+        let stop_reason = match () {
+            _ if shutdown::requested() => Some(SkipReason::Cancelled),
+            _ if args.time_limits.expired() || args.time_limits.query_expired(align_time) => {
+                Some(SkipReason::TimeLimit)
+            }
+            _ => None,
+        };
+        if let Some(reason) = stop_reason {
+            incomplete_queries.insert(profile.name.clone());
+            skipped.push((work_idx, SkippedSeed::new(&profile.name, seed, reason)));
+            continue;
+        }
+        args.progress
+            .next_seed(worker_idx, &profile.name, &seed.target_name, hits.len());
+        let evalue_cutoff = evalue_cutoffs_by_accession[profile_accession];
+
+        let target = match target_map.get(&seed.target_name[..]) {
+            Some(target) => target,
+            None => {
+                let reason = match targets.ambiguous_names.contains(&seed.target_name) {
+                    true => SkipReason::AmbiguousTarget,
+                    false => SkipReason::MissingTarget,
+                };
+                skipped.push((work_idx, SkippedSeed::new(&profile.name, seed, reason)));
+                continue;
+            }
+        };
+
+        if !seed_in_range(seed, target.length, profile.length) {
+            skipped.push((
+                work_idx,
+                SkippedSeed::new(&profile.name, seed, SkipReason::CoordinatesOutOfRange),
+            ));
+            continue;
+        }
+
+        if args.comp_bias_filter && is_biased(target, seed) {
+            skipped.push((
+                work_idx,
+                SkippedSeed::new(&profile.name, seed, SkipReason::LowComplexity),
+            ));
+            continue;
+        }
+
+        // the seeds of a group are taken in a row, so this
+        // only configures the profile for the first of them
+        if worker_profile.configure(target.length) {
+            length_configurations += 1;
+        }
+        let profile = worker_profile.get();
+
+        let seed_start = Instant::now();
+        let outcome = aligner.align(
+            profile,
+            target,
+            seed,
+            provenance,
+            &msv_stats[profile_accession],
+            evalue_cutoff,
+        );
+        *align_time_by_accession
+            .lock()
+            .unwrap()
+            .entry(profile_accession)
+            .or_default() += seed_start.elapsed();
+
+        match outcome {
+            SeedOutcome::Hit(hit) => hits.push((work_idx, *hit)),
+            SeedOutcome::Skipped(reason) | SeedOutcome::Filtered(reason) => {
+                skipped.push((work_idx, SkippedSeed::new(&profile.name, seed, reason)))
+            }
+        }
+    }
+
+    WorkerOutput {
+        hits,
+        skipped,
+        filter_counts: aligner.filter_counts,
+        expansion_counts: aligner.expansion_counts,
+        incomplete_queries,
+        length_configurations,
+    }
+}
+
+pub fn align(args: &Args) -> Result<()> {
+    let mut summary = RunSummary::new();
+
+    let start = Instant::now();
+    args.progress.start_stage(Stage::Align);
+    align_stage(args, &mut summary)?;
+    args.progress.end_stage();
+    summary.add_stage_time(Stage::Align, start.elapsed());
+
+    summary.finish(args)
+}
+
+fn align_stage(args: &Args, summary: &mut RunSummary) -> Result<()> {
+    args.workspace.validate(Stage::Align, &args.paths)?;
+
+    let queries = QuerySet::load(args)?;
+    let targets = TargetStore::load_seeded(args)?;
+    summary.add_align(&align_targets(args, &queries, &targets)?);
+    Ok(())
+}
+
+/// Align the seeds in the workspace against already loaded targets, and write the outputs.
+pub fn align_targets(args: &Args, queries: &QuerySet, targets: &TargetStore) -> Result<AlignStats> {
+    if let Some(run_count) = args.verify_deterministic {
+        verify_deterministic(args, queries, targets, run_count)?;
+    }
+
+    let AlignOutput {
+        mut hits,
+        skipped,
+        query_count,
+        seed_count,
+        existing,
+        incomplete_queries,
+    } = align_seeds(args, queries, targets)?;
+
+    let provenance = Provenance::new(args)?;
+
+    if seed_count == 0 {
+        eprintln!("no seeds to align, so the results will be empty");
+    }
+    if !incomplete_queries.is_empty() {
+        let cause = match shutdown::requested() {
+            true => "the run was cancelled",
+            false => "the time limit was reached",
+        };
+        eprintln!(
+            "warning: {cause} before {} queries were fully searched; their results are partial",
+            incomplete_queries.len()
+        );
+    }
+
+    if args.per_query_dirs {
+        // the hits of each query are written together, which the seeds file order
+        // already has, but sorting by other keys would break up
+        if !args.sort_by.is_empty() {
+            let mut query_sort_by = vec![SortKey::Query];
+            query_sort_by.extend(&args.sort_by);
+            sort_hits(&mut hits, &query_sort_by);
+        }
+        let (alignments, aligned_hits): (Vec<Alignment>, Vec<AlignedHit>) =
+            hits.into_iter().unzip();
+        write_per_query_dirs(
+            args,
+            targets,
+            &alignments,
+            &aligned_hits,
+            &incomplete_queries,
+            &provenance,
+        )?;
+        hits = alignments.into_iter().zip(aligned_hits).collect();
+    }
+
+    // every other output is written from the hits in this order
+    sort_hits(&mut hits, &args.sort_by);
+
+    let (alignments, aligned_hits): (Vec<Alignment>, Vec<AlignedHit>) = hits.into_iter().unzip();
+
+    let mut table = results_table(args, targets, &alignments, &aligned_hits, &provenance);
+    if let Some(existing) = existing {
+        existing.merge_into(&mut table)?;
+        sort_rows(&mut table, &args.sort_by);
+    }
+    table.write(&mut args.paths.results.open(true)?)?;
+    args.uploads.closed(&args.paths.results);
+
+    if let Some(path) = &args.paths.alignments {
+        write_alignments(
+            args,
+            &alignments,
+            &aligned_hits,
+            &provenance,
+            &mut path.open(true)?,
+        )?;
+    }
+
+    if let Some(path) = &args.paths.alt_alignments {
+        write_alt_alignments(
+            &aligned_hits,
+            &provenance,
+            &args.number_format,
+            &mut path.open(true)?,
+        )?;
+    }
+
+    if let Some(path) = &args.paths.hitmap {
+        write_hitmap(&alignments, &targets.sequences, &mut path.open(true)?)?;
+    }
+
+    if let Some(path) = &args.paths.hits_fasta {
+        write_hits_fasta(
+            &alignments,
+            &aligned_hits,
+            &targets.sequences,
+            args.hit_context,
+            &mut path.open(true)?,
+        )?;
+    }
+
+    if let Some(path) = &args.paths.abc {
+        write_abc(
+            &alignments,
+            &aligned_hits,
+            &args.number_format,
+            &mut path.open(true)?,
+        )?;
+    }
+
+    if let Some(path) = &args.paths.edge_list {
+        write_edge_list(
+            &alignments,
+            &aligned_hits,
+            &args.number_format,
+            &mut path.open(true)?,
+        )?;
+    }
+
+    if let Some(path) = &args.paths.bound_stats {
+        write_bound_stats(
+            &alignments,
+            &aligned_hits,
+            &provenance,
+            &args.number_format,
+            &mut path.open(true)?,
+        )?;
+        print_bound_summary(&aligned_hits);
+    }
+
+    if let Some(path) = &args.paths.trace_output {
+        write_traces(
+            &alignments,
+            &aligned_hits,
+            &provenance,
+            args.trace_format,
+            &mut path.open(true)?,
+        )?;
+    }
+
+    write_skipped_seeds(&skipped, &mut args.paths.skipped.open(true)?)?;
+    args.uploads.closed(&args.paths.skipped);
+
+    print_skipped_summary(&skipped);
+
+    print_hit_summary(&alignments, args.summary_count, args.color);
+
+    let source_stats = match &targets.sources {
+        Some(sources) => sources.stats(
+            targets.sequences.keys(),
+            alignments.iter().map(|a| &a.target_name),
+        ),
+        None => BTreeMap::new(),
+    };
+    for (source, stats) in &source_stats {
+        eprintln!("{source}: {} hits in {} targets", stats.hits, stats.targets);
+    }
+
+    Ok(AlignStats {
+        query_count,
+        target_count: targets.count,
+        seed_count,
+        hit_evalues: alignments.iter().map(|a| a.evalue).collect(),
+        skipped_counts: AlignStats::count_skipped(&skipped),
+        source_stats,
+        incomplete_queries,
+        provenance,
+    })
+}
+
+/// Build the results table for a set of hits.
+fn results_table(
+    args: &Args,
+    targets: &TargetStore,
+    alignments: &[Alignment],
+    aligned_hits: &[AlignedHit],
+    provenance: &Provenance,
+) -> Table {
+    let mut table = Table::from_alignments(alignments, &args.number_format);
+    table.version = args.output_version;
+    table.comments = provenance.comment_lines();
+
+    if args.output_version >= OutputVersion::V2 {
+        add_v2_columns(args, targets, alignments, aligned_hits, &mut table);
+    }
+
+    table
+}
+
+fn write_alignments(
+    args: &Args,
+    alignments: &[Alignment],
+    aligned_hits: &[AlignedHit],
+    provenance: &Provenance,
+    out: &mut impl Write,
+) -> Result<()> {
+    match args.ali_format {
+        AliFormat::Pairwise => write_pairwise(alignments, aligned_hits, provenance, out),
+        AliFormat::Stockholm => write_stockholm(aligned_hits, provenance, out),
+        // fasta has nowhere to put the provenance
+        AliFormat::A2m => write_a2m(aligned_hits, out),
+    }
+}
+
+/// The totals for one query, written as summary.json in its directory
+#[derive(Serialize)]
+struct QuerySummary<'a> {
+    accession: &'a str,
+    name: &'a str,
+    hits: usize,
+    best_evalue: Option<f32>,
+    best_bit_score: Option<f32>,
+    /// Whether all of the query's seeds were aligned before the time limit
+    complete: bool,
+    provenance: &'a Provenance,
+}
+
+/// The name of a query's directory under --per-query-dirs: its accession, or its
+/// name if it has none, made safe to use as a single path component.
+fn query_dir_name(accession: &str, name: &str) -> Result<String> {
+    safe_file_name(match accession.is_empty() {
+        true => name,
+        false => accession,
+    })
+}
+
+/// Write each query's hits, alignments, and summary to a directory named after its accession,
+/// next to the results file.
+///
+/// The hits of each query must be contiguous.
+fn write_per_query_dirs(
+    args: &Args,
+    targets: &TargetStore,
+    alignments: &[Alignment],
+    aligned_hits: &[AlignedHit],
+    incomplete_queries: &[String],
+    provenance: &Provenance,
+) -> Result<()> {
+    let base_dir = args
+        .paths
+        .results
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+
+    let mut query_start: usize = 0;
+    for query_hits in aligned_hits.chunk_by(|a, b| a.profile_accession == b.profile_accession) {
+        let query_end = query_start + query_hits.len();
+        let query_alignments = &alignments[query_start..query_end];
+        query_start = query_end;
+
+        let first_hit = &query_hits[0];
+        let query_dir = base_dir.join(query_dir_name(
+            &first_hit.profile_accession,
+            &first_hit.profile_name,
+        )?);
+        create_dir_all(&query_dir).context(format!(
+            "failed to create query directory: {}",
+            query_dir.to_string_lossy()
+        ))?;
+
+        results_table(args, targets, query_alignments, query_hits, provenance)
+            .write(&mut query_dir.join("results.tsv").open(true)?)?;
+
+        write_alignments(
+            args,
+            query_alignments,
+            query_hits,
+            provenance,
+            &mut query_dir
+                .join(format!("alignments.{}", args.ali_format.extension()))
+                .open(true)?,
+        )?;
+
+        let best = query_alignments
+            .iter()
+            .min_by(|a, b| a.evalue.total_cmp(&b.evalue));
+        let summary = QuerySummary {
+            accession: &first_hit.profile_accession,
+            name: &first_hit.profile_name,
+            hits: query_hits.len(),
+            best_evalue: best.map(|a| a.evalue),
+            best_bit_score: best.map(|a| a.bit_score),
+            complete: !incomplete_queries.contains(&first_hit.profile_name),
+            provenance,
+        };
+        serde_json::to_writer_pretty(&mut query_dir.join("summary.json").open(true)?, &summary)?;
+    }
+
+    Ok(())
+}
+
+/// Add the columns that follow the legacy columns in the v2 and later results tables.
+///
+/// The column order is part of the documented schema: the required columns of a
+/// version never change without a new version, and the optional columns always
+/// come after every required one.
+fn add_v2_columns(
+    args: &Args,
+    targets: &TargetStore,
+    alignments: &[Alignment],
+    aligned_hits: &[AlignedHit],
+    table: &mut Table,
+) {
+    table.add_column(
+        "env start",
+        aligned_hits
+            .iter()
+            .map(|h| h.envelope.start.to_string())
+            .collect(),
+    );
+    table.add_column(
+        "env end",
+        aligned_hits
+            .iter()
+            .map(|h| h.envelope.end.to_string())
+            .collect(),
+    );
+    table.add_column(
+        "seeds",
+        aligned_hits
+            .iter()
+            .map(|h| h.supporting_seeds.to_string())
+            .collect(),
+    );
+
+    table.add_column(
+        "% identity",
+        aligned_hits
+            .iter()
+            .map(|h| format!("{:.1}", h.identity * 100.0))
+            .collect(),
+    );
+    table.add_column(
+        "% similarity",
+        aligned_hits
+            .iter()
+            .map(|h| format!("{:.1}", h.similarity * 100.0))
+            .collect(),
+    );
+    table.add_column(
+        "gap fraction",
+        aligned_hits
+            .iter()
+            .map(|h| format!("{:.3}", h.gap_fraction))
+            .collect(),
+    );
+    let format = &args.number_format;
+    if args.output_version >= OutputVersion::V3 {
+        add_v3_columns(aligned_hits, format, table);
+    }
+
+    if args.seed_provenance {
+        let seed_column = |f: &dyn Fn(&SeedProvenance) -> String| -> Vec<String> {
+            aligned_hits
+                .iter()
+                .map(|h| h.seed.as_ref().map_or("-".to_string(), f))
+                .collect()
+        };
+
+        table.add_column("seed evalue", seed_column(&|s| format.evalue(s.evalue)));
+        table.add_column(
+            "seed bits",
+            seed_column(&|s| s.bit_score.map_or("-".to_string(), |b| format.score(b, 1))),
+        );
+        table.add_column(
+            "seed profile start",
+            seed_column(&|s| s.profile_start.to_string()),
+        );
+        table.add_column(
+            "seed profile end",
+            seed_column(&|s| s.profile_end.to_string()),
+        );
+        table.add_column(
+            "seed target start",
+            seed_column(&|s| s.target_start.to_string()),
+        );
+        table.add_column(
+            "seed target end",
+            seed_column(&|s| s.target_end.to_string()),
+        );
+    }
+
+    if let Some(taxonomy) = &targets.taxonomy {
+        let taxids: Vec<Option<u32>> = alignments
+            .iter()
+            .map(|a| taxonomy.taxid(&a.target_name))
+            .collect();
+
+        table.add_column(
+            "taxid",
+            taxids
+                .iter()
+                .map(|t| t.map_or("-".to_string(), |t| t.to_string()))
+                .collect(),
+        );
+        table.add_column(
+            "lineage",
+            taxids
+                .iter()
+                .map(|t| t.map_or("-".to_string(), |t| taxonomy.lineage_string(t)))
+                .collect(),
+        );
+    }
+
+    if let Some(sources) = &targets.sources {
+        table.add_column(
+            "target source",
+            alignments
+                .iter()
+                .map(|a| sources.source(&a.target_name).unwrap_or("-").to_string())
+                .collect(),
+        );
+    }
+
+    if let Some(orf_map) = &targets.orfs {
+        let orf_column = |f: &dyn Fn(&Orf, &Alignment) -> String| -> Vec<String> {
+            alignments
+                .iter()
+                .map(|a| {
+                    orf_map
+                        .orf(&a.target_name)
+                        .map_or("-".to_string(), |o| f(o, a))
+                })
+                .collect()
+        };
+
+        table.add_column("contig", orf_column(&|o, _| o.contig.clone()));
+        table.add_column(
+            "contig start",
+            orf_column(&|o, a| o.contig_range(a.target_start, a.target_end).0.to_string()),
+        );
+        table.add_column(
+            "contig end",
+            orf_column(&|o, a| o.contig_range(a.target_start, a.target_end).1.to_string()),
+        );
+        table.add_column("strand", orf_column(&|o, _| o.strand.symbol().to_string()));
+        table.add_column("frame", orf_column(&|o, _| o.frame.to_string()));
+    }
+
+    if args.inclusion_evalue.is_some() || args.inclusion_bits.is_some() {
+        table.add_column(
+            "included",
+            aligned_hits
+                .iter()
+                .map(|h| match h.included {
+                    Some(true) => "yes".to_string(),
+                    Some(false) => "no".to_string(),
+                    None => "-".to_string(),
+                })
+                .collect(),
+        );
+    }
+}
+
+/// Add the columns that v3 appends to the v2 columns.
+fn add_v3_columns(aligned_hits: &[AlignedHit], format: &NumberFormat, table: &mut Table) {
+    table.add_column(
+        "bias",
+        aligned_hits
+            .iter()
+            .map(|h| format.score(h.bias, 1))
+            .collect(),
+    );
+
+    let domain_column = |f: &dyn Fn(&DomainStats) -> String| -> Vec<String> {
+        aligned_hits
+            .iter()
+            .map(|h| h.domain_stats.as_ref().map_or("-".to_string(), f))
+            .collect()
+    };
+    table.add_column(
+        "c-evalue",
+        domain_column(&|d| format.evalue(d.conditional_evalue)),
+    );
+    table.add_column(
+        "seq bit score",
+        domain_column(&|d| format.score(d.seq_bit_score, 2)),
+    );
+    table.add_column(
+        "seq e-value",
+        domain_column(&|d| format.evalue(d.seq_evalue)),
+    );
+    table.add_column("domains", domain_column(&|d| d.domain_count.to_string()));
+    table.add_column(
+        "hit id",
+        aligned_hits.iter().map(|h| h.id.clone()).collect(),
+    );
+}
+
+/// Run the selected pipeline stages, in pipeline order.
+pub fn run_stages(args: &Args) -> Result<()> {
+    if args.paths.batch.is_some() {
+        return run_batch(args);
+    }
+
+    let mut stages = args.stages.clone();
+    stages.sort();
+    stages.dedup();
+
+    if stages.contains(&Stage::Align) {
+        // quickly make sure we can write the results
+        args.paths.results.open(true)?;
+    }
+
+    let mut summary = RunSummary::new();
+
+    for stage in stages {
+        // a cancelled run stops between stages, so the workspace holds whole stages
+        if shutdown::requested() {
+            break;
+        }
+        let start = Instant::now();
+        args.progress.start_stage(stage);
+        let result = match stage {
+            Stage::Prep => prep(args),
+            Stage::Seed => seed(args),
+            Stage::Align => align_stage(args, &mut summary),
+        };
+        match result {
+            Err(err) if err.is::<CancelledError>() => break,
+            result => result?,
+        }
+        args.progress.end_stage();
+        summary.add_stage_time(stage, start.elapsed());
+    }
+
+    if let Some(manifest) = &args.paths.manifest {
+        RunManifest::new(args, summary.status()).write(manifest)?;
+    }
+    if let Some(run_params) = &args.paths.run_params {
+        RunParams::new(args)?.write(run_params)?;
+    }
+
+    summary.finish(args)
+}
+
+/// Align a single profile to a region of a single target, and print the hit.
+///
+/// The region is turned into a synthetic seed, so the alignment goes through
+/// the same bounded pipeline as a seeded search.
+pub fn align_region(args: &Args) -> Result<()> {
+    let region = &args.region;
+
+    let queries = QuerySet::load(args)?;
+    let mut profile = queries
+        .profiles(args)?
+        .into_iter()
+        .find(|p| p.name == region.profile_name || p.accession == region.profile_name)
+        .ok_or_else(|| MissingRegionProfileError(region.profile_name.clone()))?;
+
+    let targets = TargetStore::load(args)?;
+    let target = targets
+        .sequences
+        .get(&region.target_name)
+        .ok_or_else(|| MissingRegionTargetError(region.target_name.clone()))?;
+
+    let (seed, provenance) = region.seed(&profile, target)?;
+    profile.configure_for_target_length(target.length);
+
+    let mut aligner = SeedAligner::new(
+        args,
+        profile.length,
+        target.length,
+        targets.count,
+        args.max_matrix_bytes,
+    );
+
+    // the hit is reported whatever its E-value, since the region was picked by hand
+    let outcome = aligner.align(
+        &profile,
+        target,
+        &seed,
+        &provenance,
+        &queries.msv_stats[&profile.accession],
+        f32::INFINITY,
+    );
+
+    let (alignment, aligned_hit) = match outcome {
+        SeedOutcome::Hit(hit) => *hit,
+        // align-region sets no filters, but a filtered region is reported like a skipped one
+        SeedOutcome::Skipped(reason) | SeedOutcome::Filtered(reason) => {
+            return Err(RegionNotAlignedError(reason).into())
+        }
+    };
+
+    let alignments = [alignment];
+    let aligned_hits = [aligned_hit];
+    let provenance = Provenance::new(args)?;
+    let mut out = std::io::stdout();
+    results_table(args, &targets, &alignments, &aligned_hits, &provenance).write(&mut out)?;
+    writeln!(out)?;
+    write_alignments(args, &alignments, &aligned_hits, &provenance, &mut out)?;
+
+    Ok(())
+}
+
+/// Write the seeds, mapped onto the query HMM, along with the
+/// alignments and envelopes of the final hits as plot data.
+pub fn view_seeds(args: &Args) -> Result<()> {
+    let p7_profiles = QuerySet::load(args)?.profiles(args)?;
+
+    let profile_to_profile_idx_maps_by_accession =
+        map_p7_to_mmseqs_profiles(&p7_profiles, args, None)?;
+
+    let profile_seeds_by_accession = build_alignment_seeds(
+        &profile_to_profile_idx_maps_by_accession,
+        &ProfileLookup::new(&p7_profiles),
+        args,
+    )?;
+
+    let profile_names_by_accession: HashMap<&str, &str> = p7_profiles
+        .iter()
+        .map(|p| (&p.accession[..], &p.name[..]))
+        .collect();
+
+    let mut rects: Vec<PlotRect> = vec![];
+
+    let mut accessions: Vec<&String> = profile_seeds_by_accession.keys().collect();
+    accessions.sort();
+    for accession in accessions {
+        let profile_name = profile_names_by_accession
+            .get(&accession[..])
+            .copied()
+            .unwrap_or(accession);
+
+        for (seed, provenance) in &profile_seeds_by_accession[accession] {
+            rects.push(PlotRect {
+                feature: PlotFeature::Seed,
+                profile_name: profile_name.to_string(),
+                target_name: seed.target_name.clone(),
+                profile_start: seed.profile_start,
+                profile_end: seed.profile_end,
+                target_start: seed.target_start,
+                target_end: seed.target_end,
+                evalue: provenance.evalue,
+            });
+        }
+    }
+
+    let results_file = File::open(&args.paths.results).with_context(|| {
+        format!(
+            "failed to open results file: {}",
+            args.paths.results.display()
+        )
+    })?;
+    let results = Table::read(BufReader::new(results_file))?;
+    rects.extend(PlotRect::from_results(&results)?);
+
+    write_plot_data(
+        &rects,
+        &args.number_format,
+        &mut args.paths.plot_data.open(true)?,
+    )?;
+
+    Ok(())
+}
+
+pub fn merge(args: &Args) -> Result<()> {
+    let mut merged = Table::default();
+
+    for path in &args.paths.merge_inputs {
+        let results_file = File::open(path)
+            .with_context(|| format!("failed to open results file: {}", path.display()))?;
+
+        let table = Table::read(BufReader::new(results_file))
+            .with_context(|| format!("failed to parse results file: {}", path.display()))?;
+
+        if merged.headers.is_empty() {
+            merged.version = table.version;
+            merged.headers = table.headers;
+        } else if merged.version != table.version || merged.headers != table.headers {
+            return Err(MismatchedResultsError).with_context(|| path.display().to_string());
+        }
+
+        merged.rows.extend(table.rows);
+    }
+
+    deduplicate_rows(&mut merged);
+
+    // each results file is in the order of its own seeds, which doesn't
+    // hold across files, so with no keys the hits are sorted by E-value
+    match args.sort_by.is_empty() {
+        true => sort_rows(&mut merged, &[SortKey::Evalue]),
+        false => sort_rows(&mut merged, &args.sort_by),
+    }
+
+    let mut rows: Vec<Vec<String>> = vec![];
+    for row in merged.rows.drain(..) {
+        if row[EVALUE_COLUMN_IDX].parse::<f32>()? <= args.evalue_cutoff {
+            rows.push(row);
+        }
+    }
+    merged.rows = rows;
+
+    merged.write(&mut args.paths.results.open(true)?)?;
+    args.uploads.closed(&args.paths.results);
+
+    Ok(())
+}
+
+/// Drop the rows of merged results files that repeat a hit of another file.
+///
+/// Rows are the same hit when they share a profile, target, and envelope, or
+/// target coordinates in a v1 table, which has no envelope. The best scoring of
+/// them is kept, in the place of the first.
+fn deduplicate_rows(table: &mut Table) {
+    let column = |header: &str| table.headers.iter().position(|h| h == header);
+    let (start_idx, end_idx) = match (column("env start"), column("env end")) {
+        (Some(start_idx), Some(end_idx)) => (start_idx, end_idx),
+        _ => (TARGET_START_COLUMN_IDX, TARGET_END_COLUMN_IDX),
+    };
+    let bit_score = |row: &[String]| {
+        row[BIT_SCORE_COLUMN_IDX]
+            .parse::<f32>()
+            .unwrap_or(f32::NEG_INFINITY)
+    };
+
+    let mut unique_rows: Vec<Vec<String>> = vec![];
+    let mut row_idx_by_key: HashMap<[String; 4], usize> = HashMap::new();
+    for row in table.rows.drain(..) {
+        let key = [
+            PROFILE_NAME_COLUMN_IDX,
+            TARGET_NAME_COLUMN_IDX,
+            start_idx,
+            end_idx,
+        ]
+        .map(|column_idx| row[column_idx].clone());
+
+        match row_idx_by_key.get(&key) {
+            Some(&row_idx) => {
+                if bit_score(&row) > bit_score(&unique_rows[row_idx]) {
+                    unique_rows[row_idx] = row;
+                }
+            }
+            None => {
+                row_idx_by_key.insert(key, unique_rows.len());
+                unique_rows.push(row);
+            }
+        }
+    }
+    table.rows = unique_rows;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::external_steps::{mmseqs_align_args, MmseqsDbType, ResolvedPrefilter};
+    use crate::mmseqs_profile::ConsensusSource;
+    use crate::runner::{RecordingRunner, Runner};
+    use crate::schedule::Schedule;
+    use crate::tabular::DEFAULT_COLUMN_HEADERS;
+    use crate::test_files::{fixture_hmm, temp_path};
+    use crate::workspace::{mmseqs_db_file, Workspace};
+    use std::fs::{remove_dir_all, write};
+
+    /// The index map of a profile whose consensus matches its match states one to one
+    fn identity_map(length: usize) -> Vec<usize> {
+        (0..=length).collect()
+    }
+
+    fn coords(seed: &Seed) -> [usize; 4] {
+        [
+            seed.target_start,
+            seed.target_end,
+            seed.profile_start,
+            seed.profile_end,
+        ]
+    }
+
+    #[test]
+    fn reversed_seeds_are_put_in_order() {
+        let mapped = map_seed("tgt".to_string(), (40, 10), (20, 5), &identity_map(24));
+        assert!(mapped.reversed);
+        assert!(!mapped.clamped);
+        assert_eq!(coords(&mapped.seed), [10, 40, 5, 20]);
+
+        let mapped = map_seed("tgt".to_string(), (10, 40), (20, 5), &identity_map(24));
+        assert!(mapped.reversed);
+        assert_eq!(coords(&mapped.seed), [10, 40, 5, 20]);
+    }
+
+    #[test]
+    fn seeds_that_start_at_zero_are_clamped_or_out_of_range() {
+        // a profile start of 0 is before the first match state
+        let mapped = map_seed("tgt".to_string(), (1, 30), (0, 12), &identity_map(24));
+        assert!(!mapped.reversed);
+        assert!(mapped.clamped);
+        assert_eq!(coords(&mapped.seed), [1, 30, 1, 12]);
+        assert!(seed_in_range(&mapped.seed, 30, 24));
+
+        // a target start of 0 means the coordinates aren't 1-based
+        let mapped = map_seed("tgt".to_string(), (0, 30), (1, 12), &identity_map(24));
+        assert!(!mapped.clamped);
+        assert_eq!(mapped.seed.target_start, 0);
+        assert!(!seed_in_range(&mapped.seed, 30, 24));
+    }
+
+    #[test]
+    fn seeds_may_end_at_the_last_residue() {
+        let mapped = map_seed("tgt".to_string(), (5, 30), (3, 24), &identity_map(24));
+        assert!(!mapped.clamped);
+        assert_eq!(coords(&mapped.seed), [5, 30, 3, 24]);
+        assert!(seed_in_range(&mapped.seed, 30, 24));
+        assert!(!seed_in_range(&mapped.seed, 29, 24));
+
+        // and reversed, the end is the first coordinate
+        let mapped = map_seed("tgt".to_string(), (30, 5), (24, 3), &identity_map(24));
+        assert!(mapped.reversed);
+        assert_eq!(coords(&mapped.seed), [5, 30, 3, 24]);
+        assert!(seed_in_range(&mapped.seed, 30, 24));
+    }
+
+    #[test]
+    fn seeds_past_the_consensus_are_clamped_onto_the_profile() {
+        // the consensus has an insertion before the first match state
+        let profile_idx_map = vec![0, 0, 1, 2, 3];
+        let mapped = map_seed("tgt".to_string(), (1, 9), (1, 6), &profile_idx_map);
+        assert!(mapped.clamped);
+        assert_eq!(coords(&mapped.seed), [1, 9, 1, 3]);
+    }
+
+    /// The seeds of a TSV seeds file in the mmoreseqs format
+    fn read_seed_rows(contents: &'static str) -> Result<Vec<SeedRow>> {
+        seed_rows(Box::new(contents.as_bytes()), SeedsFormat::Mmoreseqs).collect()
+    }
+
+    #[test]
+    fn seeds_files_are_split_on_tabs_only() {
+        let rows = read_seed_rows(
+            "# query\ttarget\n\
+             \n\
+             TST00001.1\ttgt one\t1\t24\t31\t54\t1.2e-9\t40.5\r\n\
+             TST00001.1\ttgt2\t1\t24\t11\t34\t3.4e-5\n",
+        )
+        .unwrap();
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].target, "tgt one");
+        assert_eq!(rows[0].bit_score, Some(40.5));
+        // seeds files written before the bit score column have 7 columns
+        assert_eq!(rows[1].target, "tgt2");
+        assert_eq!(rows[1].bit_score, None);
+    }
+
+    #[test]
+    fn quoted_seed_fields_may_contain_tabs_and_quotes() {
+        let rows =
+            read_seed_rows("TST00001.1\t\"tgt\tone \"\"a\"\"\"\t1\t24\t31\t54\t1.2e-9\t40.5\n")
+                .unwrap();
+
+        assert_eq!(rows[0].target, "tgt\tone \"a\"");
+        assert_eq!([rows[0].target_start, rows[0].target_end], [31, 54]);
+    }
+
+    #[test]
+    fn malformed_seed_lines_are_reported_by_line() {
+        let err = read_seed_rows("TST00001.1\ttgt1\t1\t24\t31\t54\t1e-9\nTST00001.1\ttgt2\t1\n")
+            .err()
+            .unwrap();
+        let err = err.downcast_ref::<MalformedSeedsError>().unwrap();
+        assert_eq!(err.line, 2);
+
+        let err = read_seed_rows("TST00001.1\ttgt1\t1\t24\tstart\t54\t1e-9\n")
+            .err()
+            .unwrap();
+        let err = err.downcast_ref::<InvalidSeedFieldError>().unwrap();
+        assert_eq!((err.line, err.column, err.value.as_str()), (1, 5, "start"));
+    }
+
+    /// A profile from the query fixture, and a seed on each of a set of targets
+    struct AlignFixture {
+        accession: String,
+        profile_map: HashMap<String, Arc<Profile>>,
+        msv_stats: HashMap<String, MsvStats>,
+        evalue_cutoffs_by_accession: HashMap<String, f32>,
+        seeds: Vec<Seed>,
+        provenance: SeedProvenance,
+        targets: TargetStore,
+    }
+
+    impl AlignFixture {
+        /// A target of each length, each with a seed at its start
+        fn new(target_lengths: &[usize]) -> Self {
+            let hmm = fixture_hmm();
+            let accession = "TST".to_string();
+
+            let mut sequences: HashMap<String, Sequence> = HashMap::new();
+            let mut seeds: Vec<Seed> = vec![];
+            for (target_idx, length) in target_lengths.iter().enumerate() {
+                let mut target =
+                    Sequence::from_utf8(&b"ACDEFGHIKLMNPQRSTVWY".repeat(3)[..*length]).unwrap();
+                target.name = format!("target-{target_idx}");
+                seeds.push(Seed {
+                    target_name: target.name.clone(),
+                    target_start: 1,
+                    target_end: 10,
+                    profile_start: 1,
+                    profile_end: 10,
+                });
+                sequences.insert(target.name.clone(), target);
+            }
+
+            AlignFixture {
+                profile_map: HashMap::from([(accession.clone(), Arc::new(Profile::new(&hmm)))]),
+                msv_stats: HashMap::from([(accession.clone(), MsvStats::new(&hmm))]),
+                evalue_cutoffs_by_accession: HashMap::from([(accession.clone(), f32::INFINITY)]),
+                accession,
+                seeds,
+                provenance: SeedProvenance {
+                    evalue: 0.0,
+                    bit_score: None,
+                    profile_start: 1,
+                    profile_end: 10,
+                    target_start: 1,
+                    target_end: 10,
+                },
+                targets: TargetStore {
+                    count: sequences.len(),
+                    sequences,
+                    taxonomy: None,
+                    ambiguous_names: HashSet::new(),
+                    sources: None,
+                    orfs: None,
+                },
+            }
+        }
+
+        fn align(&self, args: &Args) -> WorkerOutput {
+            let work: Vec<WorkItem> = self
+                .seeds
+                .iter()
+                .enumerate()
+                .map(|(work_idx, seed)| (work_idx, &self.accession, seed, &self.provenance))
+                .collect();
+            run_align_workers(
+                args,
+                &self.profile_map,
+                &self.targets,
+                &self.msv_stats,
+                &self.evalue_cutoffs_by_accession,
+                None,
+                work,
+            )
+        }
+    }
+
+    #[test]
+    fn a_group_of_seeds_configures_its_profile_once() {
+        // the targets alternate between two lengths in the seeds' order
+        let fixture = AlignFixture::new(&[40, 60, 40, 60, 40]);
+        let args = Args {
+            // the whole matrix is aligned, so that no seed fails its bounds
+            dense_cells: usize::MAX,
+            ..Default::default()
+        };
+        let output = fixture.align(&args);
+
+        assert_eq!(output.hits.len(), 5);
+        assert_eq!(output.length_configurations, 2);
+    }
+
+    #[test]
+    fn hits_are_in_seeds_file_order_whatever_the_workers_and_schedule() {
+        let fixture = AlignFixture::new(&[40, 60, 45, 55, 50, 60, 40, 42]);
+        let hits_in_order = |threads: usize, schedule: Schedule| -> Vec<(String, String)> {
+            let args = Args {
+                dense_cells: usize::MAX,
+                threads,
+                schedule,
+                ..Default::default()
+            };
+            in_work_order(fixture.align(&args).hits)
+                .into_iter()
+                .map(|(a, h)| {
+                    (
+                        a.target_name,
+                        format!("{:.4} {}", a.bit_score, h.envelope.end),
+                    )
+                })
+                .collect()
+        };
+
+        let expected = hits_in_order(1, Schedule::Fifo);
+        let target_names: Vec<&str> = expected.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            target_names,
+            (0..8)
+                .map(|idx| format!("target-{idx}"))
+                .collect::<Vec<String>>()
+        );
+        for (threads, schedule) in [
+            (4, Schedule::Lpt),
+            (3, Schedule::Random),
+            (8, Schedule::Fifo),
+        ] {
+            assert_eq!(
+                hits_in_order(threads, schedule),
+                expected,
+                "{threads} {schedule:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn profiles_that_share_a_name_keep_their_own_evalue_cutoffs() {
+        let mut fixture = AlignFixture::new(&[40, 60]);
+        // a second accession for the same profile, and so the same name, that nothing passes
+        let other = "TST2".to_string();
+        let profile = Arc::clone(&fixture.profile_map[&fixture.accession]);
+        let msv_stats = fixture.msv_stats[&fixture.accession];
+        fixture.profile_map.insert(other.clone(), profile);
+        fixture.msv_stats.insert(other.clone(), msv_stats);
+        fixture
+            .evalue_cutoffs_by_accession
+            .insert(other.clone(), 0.0);
+
+        let work: Vec<WorkItem> = vec![
+            (
+                0,
+                &fixture.accession,
+                &fixture.seeds[0],
+                &fixture.provenance,
+            ),
+            (1, &other, &fixture.seeds[1], &fixture.provenance),
+        ];
+        // the fast pass drops the seeds whose Forward E-value is over their cutoff
+        let args = Args {
+            dense_cells: usize::MAX,
+            fast_pass: true,
+            ..Default::default()
+        };
+        let output = run_align_workers(
+            &args,
+            &fixture.profile_map,
+            &fixture.targets,
+            &fixture.msv_stats,
+            &fixture.evalue_cutoffs_by_accession,
+            None,
+            work,
+        );
+
+        let hit_work_idxs: Vec<usize> = output.hits.iter().map(|(idx, _)| *idx).collect();
+        assert_eq!(hit_work_idxs, vec![0]);
+        assert_eq!(output.skipped.len(), 1);
+    }
+
+    #[test]
+    fn work_order_is_restored_from_the_work_indexes() {
+        let outputs = vec![(2, "c"), (0, "a"), (3, "d"), (1, "b")];
+        assert_eq!(in_work_order(outputs), vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn query_dirs_stay_inside_the_output_dir() {
+        assert_eq!(query_dir_name("PF00001.23", "7tm_1").unwrap(), "PF00001.23");
+        assert_eq!(query_dir_name("", "7tm_1").unwrap(), "7tm_1");
+        assert_eq!(
+            query_dir_name("", "../up/and out").unwrap(),
+            ".._up_and_out"
+        );
+        for name in ["", ".", ".."] {
+            assert!(query_dir_name("", name).is_err(), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn merged_rows_keep_one_of_each_hit() {
+        let row = |profile: &str, target: &str, env: [&str; 2], bits: &str, evalue: &str| {
+            let mut row: Vec<String> = [profile, target, "3", "40", "1", "30", bits, evalue]
+                .map(String::from)
+                .to_vec();
+            row.extend(env.map(String::from));
+            row
+        };
+        let mut headers: Vec<String> = DEFAULT_COLUMN_HEADERS.map(String::from).to_vec();
+        headers.extend(["env start", "env end"].map(String::from));
+        let mut table = Table {
+            headers,
+            rows: vec![
+                row("q1", "tgt1", ["1", "45"], "30.1", "1e-6"),
+                row("q1", "tgt2", ["1", "45"], "20.0", "1e-3"),
+                // the first file again, with the second file's score for the first hit
+                row("q1", "tgt1", ["1", "45"], "31.4", "5e-7"),
+                row("q1", "tgt2", ["1", "45"], "20.0", "1e-3"),
+                // another domain of the same query and target
+                row("q1", "tgt1", ["60", "99"], "12.0", "1e-1"),
+            ],
+            ..Default::default()
+        };
+
+        deduplicate_rows(&mut table);
+
+        let kept: Vec<(&str, &str, &str)> = table
+            .rows
+            .iter()
+            .map(|row| (&row[1][..], &row[6][..], &row[8][..]))
+            .collect();
+        assert_eq!(
+            kept,
+            vec![
+                ("tgt1", "31.4", "1"),
+                ("tgt2", "20.0", "1"),
+                ("tgt1", "12.0", "60")
+            ]
+        );
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/integration/fixtures")
+            .join(name)
+    }
+
+    /// Arguments for the test query and target whose external commands are
+    /// recorded rather than run, in a workspace of their own
+    fn recording_args(name: &str) -> (Args, Arc<RecordingRunner>) {
+        let recording_runner = Arc::new(RecordingRunner::new());
+        let mut args = Args {
+            threads: 4,
+            dry_run: true,
+            ..Default::default()
+        };
+        args.runner = Runner::shared(recording_runner.clone());
+        args.set_workspace(Workspace::new(temp_path(name)));
+        args.paths.query = fixture("query.sto");
+        args.paths.target_fasta = fixture("target.fa");
+        (args, recording_runner)
+    }
+
+    fn command_lines(recording_runner: &RecordingRunner) -> Vec<String> {
+        recording_runner
+            .commands()
+            .iter()
+            .map(|command| command.to_string())
+            .collect()
+    }
+
+    fn path(path: &Path) -> String {
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn prep_builds_the_query_profiles_and_hmm_and_the_target_database() {
+        let (args, recording_runner) = recording_args("prep");
+        prep(&args).unwrap();
+        remove_dir_all(&args.workspace.dir).unwrap();
+
+        let paths = &args.paths;
+        assert_eq!(
+            command_lines(&recording_runner),
+            [
+                format!(
+                    "mmseqs convertmsa {} {}",
+                    path(&paths.query),
+                    path(&paths.query_msa_db)
+                ),
+                format!(
+                    "mmseqs msa2profile {} {} --threads 4 --match-mode 1",
+                    path(&paths.query_msa_db),
+                    path(&paths.query_db)
+                ),
+                format!(
+                    "hmmbuild --cpu 2 {} {}",
+                    path(&paths.query_hmm),
+                    path(&paths.query)
+                ),
+                format!(
+                    "mmseqs createdb {} {}",
+                    path(&paths.target_fasta),
+                    path(&paths.target_db)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn overlapping_prep_splits_the_threads_between_the_query_and_target() {
+        for threads in 2..=16 {
+            let (query_threads, target_threads) = split_prep_threads(threads);
+            assert!(query_threads >= 1 && target_threads >= 1);
+            assert!(query_threads <= HMMBUILD_MAX_THREADS);
+            assert_eq!(query_threads + target_threads, threads);
+        }
+        assert_eq!(split_prep_threads(2), (1, 1));
+        assert_eq!(split_prep_threads(3), (1, 2));
+        assert_eq!(split_prep_threads(4), (2, 2));
+        assert_eq!(split_prep_threads(16), (2, 14));
+    }
+
+    #[test]
+    fn seed_searches_the_query_profiles_against_the_target_database() {
+        let (args, recording_runner) = recording_args("seed");
+        run_seed_steps(&args).unwrap();
+
+        let paths = &args.paths;
+        let prefilter = ResolvedPrefilter::new(&args, MmseqsDbType::Profile).unwrap();
+        assert_eq!(
+            command_lines(&recording_runner),
+            [
+                format!(
+                    "mmseqs prefilter {} {} {} --threads 4 {}",
+                    path(&paths.query_db),
+                    path(&paths.target_db),
+                    path(&paths.prefilter_db),
+                    prefilter.mmseqs_args().join(" ")
+                ),
+                format!(
+                    "mmseqs align {} {} {} {} --threads 4 {}",
+                    path(&paths.query_db),
+                    path(&paths.target_db),
+                    path(&paths.prefilter_db),
+                    path(&paths.align_db),
+                    mmseqs_align_args().join(" ")
+                ),
+                format!(
+                    "mmseqs convertalis {} {} {} {} --threads 4 \
+                     --format-output query,target,qstart,qend,tstart,tend,evalue,bits",
+                    path(&paths.query_db),
+                    path(&paths.target_db),
+                    path(&paths.align_db),
+                    path(&paths.seeds)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn align_has_mmseqs_write_the_profile_consensus_sequences() {
+        let (mut args, recording_runner) = recording_args("align");
+        args.dry_run = false;
+        args.consensus_source = ConsensusSource::Mmseqs;
+        args.workspace.create().unwrap();
+        // what prep and the recorded mmseqs commands would have written
+        write(
+            mmseqs_db_file(&args.paths.query_db, ".dbtype"),
+            2u32.to_le_bytes(),
+        )
+        .unwrap();
+        copy(
+            fixture("consensus.fa"),
+            &args.paths.query_profile_consensus_fasta,
+        )
+        .unwrap();
+
+        let consensus_map = extract_mmseqs_profile_consensus_sequences(&args);
+        remove_dir_all(&args.workspace.dir).unwrap();
+
+        assert!(consensus_map.unwrap().contains_key("TST00001.1"));
+        let paths = &args.paths;
+        assert_eq!(
+            command_lines(&recording_runner),
+            [
+                format!(
+                    "mmseqs profile2consensus {} {}",
+                    path(&paths.query_db),
+                    path(&paths.query_profile_consensus_db)
+                ),
+                format!(
+                    "mmseqs convert2fasta {} {}",
+                    path(&paths.query_profile_consensus_db),
+                    path(&paths.query_profile_consensus_fasta)
+                ),
+            ]
+        );
     }
-    prep(args)?;
-    seed(args)?;
-    align(args)?;
-    Ok(())
 }
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use nale::structs::hmm::P7Alphabet;
+use nale::structs::Profile;
+
+/// The special state transition scores of a profile, which are the
+/// only part of a profile that depends on the target length
+type SpecialTransitions = [[f32; 2]; 5];
+
+/// A memo of a single profile's target length configurations.
+///
+/// Configuring a profile for a target length is only needed when the length changes,
+/// and when it does, a previously computed configuration can simply be copied back in.
+#[derive(Default)]
+pub struct LengthConfigCache {
+    special_transitions_by_length: HashMap<usize, SpecialTransitions>,
+}
+
+impl LengthConfigCache {
+    /// Configure the profile for the target length.
+    ///
+    /// The cache must only ever be used with a single profile.
+    pub fn configure(&mut self, profile: &mut Profile, target_length: usize) {
+        if profile.target_length == target_length {
+            return;
+        }
+
+        match self.special_transitions_by_length.get(&target_length) {
+            Some(special_transitions) => {
+                profile.special_transitions = *special_transitions;
+                profile.target_length = target_length;
+            }
+            None => {
+                profile.configure_for_target_length(target_length);
+                self.special_transitions_by_length
+                    .insert(target_length, profile.special_transitions);
+            }
+        }
+    }
+}
+
+/// Group the seeds by the profile and target length that they are aligned with,
+/// keeping the groups in the order of their first seeds, and the seeds of each
+/// group in their order.
+///
+/// The special state transitions are the only part of a profile that depends on
+/// the target length, so the seeds of a group are all aligned with one configuration.
+pub fn group_by_length_config<T, K: Eq + Hash>(
+    items: Vec<T>,
+    key: impl Fn(&T) -> K,
+) -> Vec<Vec<T>> {
+    let mut group_idx_by_key: HashMap<K, usize> = HashMap::new();
+    let mut groups: Vec<Vec<T>> = vec![];
+    for item in items {
+        let group_idx = *group_idx_by_key.entry(key(&item)).or_insert_with(|| {
+            groups.push(vec![]);
+            groups.len() - 1
+        });
+        groups[group_idx].push(item);
+    }
+    groups
+}
+
+/// A copy of a profile, for a worker to configure for its own targets' lengths
+/// without changing the profile under the other workers.
+pub fn copy_profile(profile: &Profile) -> Profile {
+    Profile {
+        name: profile.name.clone(),
+        accession: profile.accession.clone(),
+        length: profile.length,
+        target_length: profile.target_length,
+        max_length: profile.max_length,
+        transitions: profile.transitions.clone(),
+        match_scores: profile.match_scores.clone(),
+        insert_scores: profile.insert_scores.clone(),
+        special_transitions: profile.special_transitions,
+        expected_j_uses: profile.expected_j_uses,
+        consensus_sequence: profile.consensus_sequence.clone(),
+        alphabet: match profile.alphabet {
+            P7Alphabet::Amino => P7Alphabet::Amino,
+            P7Alphabet::Dna => P7Alphabet::Dna,
+            P7Alphabet::Rna => P7Alphabet::Rna,
+            P7Alphabet::AlphabetNotSet => P7Alphabet::AlphabetNotSet,
+        },
+        forward_tau: profile.forward_tau,
+        forward_lambda: profile.forward_lambda,
+    }
+}
+
+/// A worker's view of a profile that the workers share.
+///
+/// The worker aligns with the shared profile while it's configured for the target length
+/// at hand, and only makes a copy of its own once it needs another target length.
+pub struct WorkerProfile {
+    shared: Arc<Profile>,
+    configured: Option<Profile>,
+    length_configs: LengthConfigCache,
+}
+
+impl WorkerProfile {
+    pub fn new(shared: Arc<Profile>) -> Self {
+        WorkerProfile {
+            shared,
+            configured: None,
+            length_configs: LengthConfigCache::default(),
+        }
+    }
+
+    pub fn get(&self) -> &Profile {
+        self.configured.as_ref().unwrap_or(&self.shared)
+    }
+
+    /// Configure the profile for the target length, returning whether its length changed.
+    pub fn configure(&mut self, target_length: usize) -> bool {
+        if self.get().target_length == target_length {
+            return false;
+        }
+        let profile = self
+            .configured
+            .get_or_insert_with(|| copy_profile(&self.shared));
+        self.length_configs.configure(profile, target_length);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::fixture_profile;
+
+    #[test]
+    fn seeds_are_grouped_in_the_order_of_their_first() {
+        // (profile, target length, seed)
+        let seeds = vec![
+            ("a", 40, 0),
+            ("b", 40, 1),
+            ("a", 60, 2),
+            ("a", 40, 3),
+            ("b", 40, 4),
+            ("a", 60, 5),
+        ];
+        let groups = group_by_length_config(seeds, |&(profile, length, _)| (profile, length));
+        let groups: Vec<Vec<usize>> = groups
+            .into_iter()
+            .map(|group| group.into_iter().map(|(_, _, seed)| seed).collect())
+            .collect();
+        assert_eq!(groups, [vec![0, 3], vec![1, 4], vec![2, 5]]);
+    }
+
+    #[test]
+    fn a_worker_only_copies_a_profile_to_change_its_length() {
+        let mut shared = fixture_profile();
+        shared.configure_for_target_length(40);
+        let shared = Arc::new(shared);
+
+        let mut profile = WorkerProfile::new(Arc::clone(&shared));
+        assert!(!profile.configure(40));
+        assert!(std::ptr::eq(profile.get(), &*shared));
+
+        assert!(profile.configure(60));
+        assert_eq!(profile.get().target_length, 60);
+        assert_eq!(shared.target_length, 40);
+
+        // the copy is configured back, rather than going back to the shared profile
+        assert!(profile.configure(40));
+        assert_eq!(
+            profile.get().special_transitions,
+            shared.special_transitions
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use nale::structs::Profile;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error(
+    "query profiles {first} and {second} are both keyed as {key}; give them distinct accessions"
+)]
+pub struct DuplicateProfileKeyError {
+    key: String,
+    first: usize,
+    second: usize,
+}
+
+/// The most unmatched seed keys that are listed by name
+const MAX_LISTED_KEYS: usize = 10;
+
+#[derive(Error, Debug)]
+#[error("skipped {seed_count} seeds whose query matches no profile in the query HMM by accession, name, or case: {keys}")]
+pub struct ProfileNotFoundError {
+    seed_count: usize,
+    keys: String,
+}
+
+impl ProfileNotFoundError {
+    /// The error for the seeds' query keys that weren't found, with their numbers of seeds
+    pub fn new(seed_counts_by_key: &BTreeMap<String, usize>) -> Self {
+        let mut keys: Vec<String> = seed_counts_by_key
+            .iter()
+            .take(MAX_LISTED_KEYS)
+            .map(|(key, count)| format!("{key} ({count})"))
+            .collect();
+        if seed_counts_by_key.len() > MAX_LISTED_KEYS {
+            keys.push(format!(
+                "and {} more",
+                seed_counts_by_key.len() - MAX_LISTED_KEYS
+            ));
+        }
+        ProfileNotFoundError {
+            seed_count: seed_counts_by_key.values().sum(),
+            keys: keys.join(", "),
+        }
+    }
+}
+
+/// How a seed's query was matched to a profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyMatch {
+    /// By the profile's key, which is how mmoreseqs seed names the queries
+    Key,
+    /// By the profile's name, as in seeds from an mmseqs run on the unkeyed MSAs
+    Name,
+    /// By the key or name, ignoring case
+    CaseInsensitive,
+}
+
+/// Finds the profile that a seed's query names.
+///
+/// A seed's query is looked up as a profile key, then as a profile name, then
+/// as either ignoring case. A name that several profiles share matches none of
+/// them, rather than a guess.
+pub struct ProfileLookup {
+    keys: HashMap<String, String>,
+    keys_by_name: HashMap<String, Option<String>>,
+    keys_by_lowercase: HashMap<String, Option<String>>,
+}
+
+impl ProfileLookup {
+    pub fn new(profiles: &[Profile]) -> Self {
+        let mut keys_by_name: HashMap<String, Option<String>> = HashMap::new();
+        let mut keys_by_lowercase: HashMap<String, Option<String>> = HashMap::new();
+        let insert = |map: &mut HashMap<String, Option<String>>, alias: String, key: &String| {
+            map.entry(alias)
+                .and_modify(|existing| {
+                    if existing.as_ref() != Some(key) {
+                        *existing = None;
+                    }
+                })
+                .or_insert_with(|| Some(key.clone()));
+        };
+
+        for profile in profiles {
+            let key = &profile.accession;
+            insert(&mut keys_by_name, profile.name.clone(), key);
+            insert(&mut keys_by_lowercase, key.to_lowercase(), key);
+            insert(&mut keys_by_lowercase, profile.name.to_lowercase(), key);
+        }
+
+        ProfileLookup {
+            keys: profiles
+                .iter()
+                .map(|p| (p.accession.clone(), p.accession.clone()))
+                .collect(),
+            keys_by_name,
+            keys_by_lowercase,
+        }
+    }
+
+    /// The key of the profile that a seed's query names, and how it matched
+    pub fn find(&self, query: &str) -> Option<(&str, KeyMatch)> {
+        if let Some(key) = self.keys.get(query) {
+            return Some((key, KeyMatch::Key));
+        }
+        if let Some(Some(key)) = self.keys_by_name.get(query) {
+            return Some((key, KeyMatch::Name));
+        }
+        match self.keys_by_lowercase.get(&query.to_lowercase()) {
+            Some(Some(key)) => Some((key, KeyMatch::CaseInsensitive)),
+            _ => None,
+        }
+    }
+}
+
+/// The key that a query profile is known by in the seeds and results.
+///
+/// This is the profile's accession, or its name if it has none. If it has
+/// neither, the key is made from its (1-based) position in the query file,
+/// which is stable for as long as the query file is.
+pub fn profile_key(accession: &str, name: &str, profile_idx: usize) -> String {
+    match (accession.trim(), name.trim()) {
+        ("", "") => format!("query-{}", profile_idx + 1),
+        ("", name) => name.to_string(),
+        (accession, _) => accession.to_string(),
+    }
+}
+
+/// Store each profile's key as its accession, and check that the keys are unique.
+pub fn assign_profile_keys(profiles: &mut [Profile]) -> Result<()> {
+    let mut profile_idx_by_key: HashMap<String, usize> = HashMap::new();
+
+    for (profile_idx, profile) in profiles.iter_mut().enumerate() {
+        let key = profile_key(&profile.accession, &profile.name, profile_idx);
+        if let Some(first_idx) = profile_idx_by_key.insert(key.clone(), profile_idx) {
+            return Err(DuplicateProfileKeyError {
+                key,
+                first: first_idx + 1,
+                second: profile_idx + 1,
+            }
+            .into());
+        }
+        profile.accession = key;
+    }
+
+    Ok(())
+}
+
+/// Whether any alignment in a Stockholm file is missing a #=GF AC line.
+pub fn stockholm_lacks_accessions(path: &Path) -> Result<bool> {
+    let file = File::open(path).context(format!(
+        "failed to open query MSA: {}",
+        path.to_string_lossy()
+    ))?;
+
+    let mut has_accession = false;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.starts_with("#=GF AC") {
+            has_accession = true;
+        } else if line.trim() == "//" {
+            if !has_accession {
+                return Ok(true);
+            }
+            has_accession = false;
+        }
+    }
+
+    Ok(false)
+}
+
+/// Copy a Stockholm file, giving every alignment that lacks a #=GF AC line its
+/// profile key as the accession, so that mmseqs and hmmbuild both use the key.
+pub fn write_keyed_stockholm(path: &Path, out: &mut impl Write) -> Result<()> {
+    let file = File::open(path).context(format!(
+        "failed to open query MSA: {}",
+        path.to_string_lossy()
+    ))?;
+
+    let mut block: Vec<String> = vec![];
+    let mut profile_idx: usize = 0;
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let is_end = line.trim() == "//";
+        block.push(line);
+        if !is_end {
+            continue;
+        }
+
+        if !block.iter().any(|l| l.starts_with("#=GF AC")) {
+            let name = block
+                .iter()
+                .find_map(|l| l.strip_prefix("#=GF ID"))
+                .unwrap_or_default();
+            let key = profile_key("", name, profile_idx);
+            // the accession goes right after the format line
+            let insert_idx = block
+                .iter()
+                .position(|l| l.starts_with("# STOCKHOLM"))
+                .map_or(0, |format_idx| format_idx + 1);
+            block.insert(insert_idx, format!("#=GF AC {key}"));
+        }
+
+        for block_line in block.drain(..) {
+            writeln!(out, "{block_line}")?;
+        }
+        profile_idx += 1;
+    }
+
+    // anything after the last alignment is copied as is
+    for block_line in block {
+        writeln!(out, "{block_line}")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,590 @@
+use std::fs::{read_to_string, rename, File};
+use std::io::{pipe, stderr, stdout, BufRead, BufReader, Write};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::spawn;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use nale::output::path_buf_ext::PathBufExt;
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Gauge, List, Paragraph};
+use ratatui::{Frame, Terminal};
+use serde::{Deserialize, Serialize};
+
+use crate::shutdown;
+use crate::workspace::Stage;
+
+/// The least time between two updates of the progress file and dashboard
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the run's dashboard checks for a key that closes it
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The most diagnostic lines that the dashboard shows, which are the latest
+const LOG_LINES_SHOWN: usize = 5;
+
+/// The time spent in one stage of the run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageSpan {
+    pub name: String,
+    pub seconds: f64,
+    pub running: bool,
+}
+
+/// A snapshot of a running search, written to the progress file for mmoreseqs top.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Progress {
+    /// "running", "success", or "failure"
+    pub status: String,
+    pub started_unix: u64,
+    pub updated_unix: u64,
+    pub stages: Vec<StageSpan>,
+    pub seeds_done: usize,
+    pub seeds_total: usize,
+    pub hits: usize,
+    /// The hits found by each worker so far, which add up to the hits
+    #[serde(skip)]
+    hits_by_worker: Vec<usize>,
+    /// The profile and target of the seed that each worker is aligning
+    pub workers: Vec<Option<(String, String)>>,
+    /// The resident memory of the run, where the platform reports it
+    pub rss_bytes: Option<u64>,
+}
+
+struct MonitorState {
+    progress: Progress,
+    path: Option<PathBuf>,
+    dashboard: Option<RunDashboard>,
+    stage_started: Instant,
+    last_published: Option<Instant>,
+}
+
+/// A shared handle that the pipeline reports its progress to.
+///
+/// The progress is written to the progress file, drawn as a dashboard on
+/// stderr, or both. A default monitor has neither, and ignores the reports.
+///
+/// The dashboard is closed with q, and Ctrl-C closes it and stops the run as
+/// SIGINT would; it is also closed when the run finishes.
+#[derive(Clone, Default)]
+pub struct ProgressMonitor(Option<Arc<Mutex<MonitorState>>>);
+
+fn unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// The resident set size of this process, read from /proc on Linux
+fn resident_bytes() -> Option<u64> {
+    let status = read_to_string("/proc/self/status").ok()?;
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+impl ProgressMonitor {
+    pub fn new(path: Option<PathBuf>, tui: bool) -> Self {
+        if path.is_none() && !tui {
+            return ProgressMonitor(None);
+        }
+
+        let dashboard = match tui {
+            true => match RunDashboard::open() {
+                Ok(dashboard) => Some(dashboard),
+                Err(err) => {
+                    eprintln!("warning: can't show the dashboard: {err:#}");
+                    None
+                }
+            },
+            false => None,
+        };
+
+        let has_dashboard = dashboard.is_some();
+        let now = unix_seconds();
+        let state = Arc::new(Mutex::new(MonitorState {
+            progress: Progress {
+                status: "running".to_string(),
+                started_unix: now,
+                updated_unix: now,
+                ..Default::default()
+            },
+            path,
+            dashboard,
+            stage_started: Instant::now(),
+            last_published: None,
+        }));
+
+        if has_dashboard {
+            let state = Arc::downgrade(&state);
+            spawn(move || close_dashboard_on_key(state));
+        }
+        ProgressMonitor(Some(state))
+    }
+
+    /// Apply an update to the progress, and publish it if `force` is set or it's been a while.
+    fn update(&self, force: bool, update: impl FnOnce(&mut Progress)) {
+        let Some(state) = &self.0 else {
+            return;
+        };
+        let mut state = state.lock().unwrap();
+
+        let stage_seconds = state.stage_started.elapsed().as_secs_f64();
+        if let Some(stage) = state.progress.stages.last_mut() {
+            if stage.running {
+                stage.seconds = stage_seconds;
+            }
+        }
+
+        update(&mut state.progress);
+
+        let due = state
+            .last_published
+            .is_none_or(|published| published.elapsed() >= PUBLISH_INTERVAL);
+        if force || due {
+            state.publish();
+        }
+    }
+
+    pub fn start_stage(&self, stage: Stage) {
+        self.update(true, |progress| {
+            progress.stages.push(StageSpan {
+                name: stage.name().to_string(),
+                seconds: 0.0,
+                running: true,
+            });
+        });
+        if let Some(state) = &self.0 {
+            state.lock().unwrap().stage_started = Instant::now();
+        }
+    }
+
+    pub fn end_stage(&self) {
+        self.update(true, |progress| {
+            if let Some(stage) = progress.stages.last_mut() {
+                stage.running = false;
+            }
+        });
+    }
+
+    pub fn start_align(&self, seeds_total: usize, workers: usize) {
+        self.update(true, |progress| {
+            progress.seeds_done = 0;
+            progress.seeds_total = seeds_total;
+            progress.hits = 0;
+            progress.hits_by_worker = vec![0; workers];
+            progress.workers = vec![None; workers];
+        });
+    }
+
+    /// Report that a worker moved on to the next seed, with the number of hits it has found so far.
+    pub fn next_seed(&self, worker_idx: usize, profile_name: &str, target_name: &str, hits: usize) {
+        self.update(false, |progress| {
+            // the worker was busy with the previous seed, which is now done
+            if progress.workers[worker_idx].is_some() {
+                progress.seeds_done += 1;
+            }
+            progress.workers[worker_idx] =
+                Some((profile_name.to_string(), target_name.to_string()));
+            progress.hits_by_worker[worker_idx] = hits;
+            progress.hits = progress.hits_by_worker.iter().sum();
+        });
+    }
+
+    pub fn end_align(&self, hits: usize) {
+        self.update(true, |progress| {
+            progress.seeds_done = progress.seeds_total;
+            progress.hits = hits;
+            progress
+                .workers
+                .iter_mut()
+                .for_each(|worker| *worker = None);
+        });
+    }
+
+    pub fn finish(&self, success: bool) {
+        self.update(true, |progress| {
+            progress.status = match success {
+                true => "success",
+                false => "failure",
+            }
+            .to_string();
+            progress
+                .stages
+                .iter_mut()
+                .for_each(|stage| stage.running = false);
+        });
+        // the terminal is given back, so that what the run prints next can be read
+        if let Some(state) = &self.0 {
+            state.lock().unwrap().dashboard = None;
+        }
+    }
+}
+
+/// Close the run's dashboard when q or Ctrl-C is pressed, or stop watching
+/// the keys once it's been closed.
+fn close_dashboard_on_key(state: Weak<Mutex<MonitorState>>) {
+    loop {
+        let key = quit_key(KEY_POLL_INTERVAL);
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        let mut state = state.lock().unwrap();
+        if state.dashboard.is_none() {
+            return;
+        }
+        match key {
+            Ok(None) => continue,
+            // raw mode keeps Ctrl-C from interrupting the run, so it's passed on here
+            Ok(Some(QuitKey::Interrupt)) => shutdown::interrupt(),
+            Ok(Some(QuitKey::Quit)) | Err(_) => {}
+        }
+        state.dashboard = None;
+        return;
+    }
+}
+
+impl MonitorState {
+    fn publish(&mut self) {
+        self.last_published = Some(Instant::now());
+        self.progress.updated_unix = unix_seconds();
+        self.progress.rss_bytes = resident_bytes();
+
+        if let Some(path) = &self.path {
+            if let Err(err) = write_progress(&self.progress, path) {
+                // a progress file is a convenience, so it isn't worth failing the run over
+                eprintln!("warning: stopped writing the progress file: {err:#}");
+                self.path = None;
+            }
+        }
+
+        if let Some(dashboard) = &mut self.dashboard {
+            let log = dashboard.stderr.log_tail(LOG_LINES_SHOWN);
+            let _ = render_dashboard(&self.progress, &log, &mut dashboard.terminal.terminal);
+        }
+    }
+}
+
+/// Replace the progress file, so that a reader never sees a partial write.
+fn write_progress(progress: &Progress, path: &Path) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    serde_json::to_writer_pretty(&mut tmp_path.open(true)?, progress)?;
+    rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+/// A terminal that the dashboard is drawn on, in an alternate screen and raw
+/// mode, so that the keys reach the dashboard instead of being echoed over it.
+/// The terminal is restored when it is dropped.
+struct Dashboard<W: Write> {
+    terminal: Terminal<CrosstermBackend<W>>,
+}
+
+impl<W: Write> Dashboard<W> {
+    fn new(out: W) -> Result<Self> {
+        enable_raw_mode()?;
+        let mut dashboard = Dashboard {
+            terminal: Terminal::new(CrosstermBackend::new(out))?,
+        };
+        execute!(dashboard.terminal.backend_mut(), EnterAlternateScreen)?;
+        dashboard.terminal.clear()?;
+        Ok(dashboard)
+    }
+}
+
+impl<W: Write> Drop for Dashboard<W> {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(self.terminal.backend_mut(), LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+/// A dashboard of the run, drawn on the terminal that stderr was.
+///
+/// The run's diagnostics would draw over it, so while it's open stderr is a
+/// pipe, whose lines are shown in the dashboard and printed when it closes.
+struct RunDashboard {
+    // dropped first, so that the terminal is restored before the lines are printed
+    terminal: Dashboard<File>,
+    stderr: HeldStderr,
+}
+
+impl RunDashboard {
+    fn open() -> Result<Self> {
+        let stderr = HeldStderr::hold()?;
+        let terminal = Dashboard::new(File::from(stderr.saved.try_clone()?))?;
+        Ok(RunDashboard { terminal, stderr })
+    }
+}
+
+/// The lines written to stderr while it is held
+#[derive(Default)]
+struct HeldLines {
+    lines: Vec<String>,
+    released: bool,
+}
+
+/// Stderr redirected into a pipe, until this is dropped.
+struct HeldStderr {
+    /// The stderr that was replaced, which is put back on drop
+    saved: OwnedFd,
+    held: Arc<Mutex<HeldLines>>,
+}
+
+impl HeldStderr {
+    fn hold() -> Result<Self> {
+        let saved = stderr().as_fd().try_clone_to_owned()?;
+        let (reader, writer) = pipe()?;
+        if unsafe { libc::dup2(writer.as_raw_fd(), libc::STDERR_FILENO) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let held = Arc::new(Mutex::new(HeldLines::default()));
+        let reader_held = held.clone();
+        spawn(move || {
+            for line in BufReader::new(reader).lines().map_while(Result::ok) {
+                let mut held = reader_held.lock().unwrap();
+                match held.released {
+                    // a tool started while stderr was held can still be writing to the pipe
+                    true => {
+                        drop(held);
+                        eprintln!("{line}");
+                    }
+                    false => held.lines.push(line),
+                }
+            }
+        });
+
+        Ok(HeldStderr { saved, held })
+    }
+
+    /// The last lines written to stderr
+    fn log_tail(&self, count: usize) -> Vec<String> {
+        let held = self.held.lock().unwrap();
+        held.lines[held.lines.len().saturating_sub(count)..].to_vec()
+    }
+}
+
+impl Drop for HeldStderr {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dup2(self.saved.as_raw_fd(), libc::STDERR_FILENO);
+        }
+        let mut held = self.held.lock().unwrap();
+        held.released = true;
+        for line in held.lines.drain(..) {
+            eprintln!("{line}");
+        }
+    }
+}
+
+/// A key that closes a dashboard
+enum QuitKey {
+    /// q or Esc
+    Quit,
+    /// Ctrl-C, which is a key rather than a signal in raw mode
+    Interrupt,
+}
+
+/// Wait up to `timeout` for a key that closes a dashboard.
+fn quit_key(timeout: Duration) -> Result<Option<QuitKey>> {
+    let deadline = Instant::now() + timeout;
+    while event::poll(deadline.saturating_duration_since(Instant::now()))? {
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(Some(QuitKey::Interrupt))
+            }
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(Some(QuitKey::Quit)),
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Redraw the terminal with a dashboard of the progress, and the latest lines of the run's log.
+pub fn render_dashboard<B: Backend>(
+    progress: &Progress,
+    log: &[String],
+    terminal: &mut Terminal<B>,
+) -> Result<()> {
+    terminal.draw(|frame| draw_dashboard(frame, progress, log))?;
+    Ok(())
+}
+
+fn draw_dashboard(frame: &mut Frame, progress: &Progress, log: &[String]) {
+    let elapsed = match progress.status.as_str() {
+        "running" => unix_seconds(),
+        _ => progress.updated_unix,
+    }
+    .saturating_sub(progress.started_unix);
+
+    let memory = progress.rss_bytes.map_or("-".to_string(), |bytes| {
+        format!("{:.2} GiB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
+    });
+
+    let stages = progress
+        .stages
+        .iter()
+        .map(|stage| {
+            let running = match stage.running {
+                true => " (running)",
+                false => "",
+            };
+            format!("{} {}{running}", stage.name, format_duration(stage.seconds))
+        })
+        .collect::<Vec<String>>()
+        .join(" | ");
+
+    let log_height = match log.len() {
+        0 => 0,
+        lines => lines as u16 + 2,
+    };
+    let [run_area, align_area, workers_area, log_area] = Layout::vertical([
+        Constraint::Length(5),
+        Constraint::Length(3),
+        Constraint::Min(0),
+        Constraint::Length(log_height),
+    ])
+    .areas(frame.area());
+
+    let run = Paragraph::new(vec![
+        Line::from(format!(
+            "{}  {} elapsed",
+            progress.status,
+            format_duration(elapsed as f64)
+        )),
+        Line::from(format!("memory  {memory}")),
+        Line::from(format!("stages  {stages}")),
+    ])
+    .block(Block::default().borders(Borders::ALL).title(" mmoreseqs "));
+    frame.render_widget(run, run_area);
+
+    let ratio = match progress.seeds_total {
+        0 => 0.0,
+        total => (progress.seeds_done as f64 / total as f64).min(1.0),
+    };
+    let align = Gauge::default()
+        .block(Block::default().borders(Borders::ALL).title(" align "))
+        .ratio(ratio)
+        .label(format!(
+            "{}/{} seeds ({:.1}%)  {} hits",
+            progress.seeds_done,
+            progress.seeds_total,
+            100.0 * ratio,
+            progress.hits
+        ));
+    frame.render_widget(align, align_area);
+
+    let workers = List::new(
+        progress
+            .workers
+            .iter()
+            .enumerate()
+            .map(|(worker_idx, worker)| {
+                let current = match worker {
+                    Some((profile_name, target_name)) => format!("{profile_name} vs {target_name}"),
+                    None => "idle".to_string(),
+                };
+                format!("worker {worker_idx:<3} {current}")
+            }),
+    )
+    .block(Block::default().borders(Borders::ALL).title(" workers "));
+    frame.render_widget(workers, workers_area);
+
+    if !log.is_empty() {
+        let log = List::new(log.iter().map(String::as_str))
+            .block(Block::default().borders(Borders::ALL).title(" log "));
+        frame.render_widget(log, log_area);
+    }
+}
+
+/// Show a live dashboard of the run that is writing a progress file, until it
+/// finishes or q, Esc, or Ctrl-C is pressed.
+pub fn top(path: &Path, interval: Duration) -> Result<()> {
+    let mut dashboard = Dashboard::new(stdout())?;
+    loop {
+        let progress: Progress = serde_json::from_str(&read_to_string(path).context(format!(
+            "failed to read progress file: {}",
+            path.to_string_lossy()
+        ))?)?;
+
+        render_dashboard(&progress, &[], &mut dashboard.terminal)?;
+
+        if progress.status != "running" || quit_key(interval)?.is_some() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    #[test]
+    fn the_dashboard_shows_the_stages_align_progress_workers_and_log() {
+        let progress = Progress {
+            status: "running".to_string(),
+            stages: vec![StageSpan {
+                name: "align".to_string(),
+                seconds: 75.0,
+                running: true,
+            }],
+            seeds_done: 12,
+            seeds_total: 40,
+            hits: 3,
+            workers: vec![Some(("PF00001".to_string(), "tgt7".to_string())), None],
+            ..Default::default()
+        };
+
+        let log = ["combined 2 target fasta files".to_string()];
+        let mut terminal = Terminal::new(TestBackend::new(80, 17)).unwrap();
+        render_dashboard(&progress, &log, &mut terminal).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+
+        assert!(screen.contains("stages  align 00:01:15 (running)"));
+        assert!(screen.contains("12/40 seeds (30.0%)  3 hits"));
+        assert!(screen.contains("worker 0   PF00001 vs tgt7"));
+        assert!(screen.contains("worker 1   idle"));
+        assert!(screen.contains("combined 2 target fasta files"));
+    }
+}
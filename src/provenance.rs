@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::seeds_header::{query_hmm_checksum, target_checksum};
+use crate::version_info::NALE_VERSION;
+use crate::Args;
+
+/// The name of a value of a command line enum, as it is given on the command line
+pub fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|possible_value| possible_value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Quote a command line argument for a shell, if it needs it.
+fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    match is_plain {
+        true => arg.to_string(),
+        false => format!("'{}'", arg.replace('\'', r"'\''")),
+    }
+}
+
+/// How a run's outputs were made: the build of mmoreseqs, how it was run, and
+/// what it was run on. It is made once per run and written into each output.
+#[derive(Debug, Clone, Serialize)]
+pub struct Provenance {
+    pub version: &'static str,
+    /// The commit that mmoreseqs was built from, when it was built from a git checkout
+    pub git_commit: Option<&'static str>,
+    /// The version of nale that mmoreseqs was built with
+    pub nale_version: Option<&'static str>,
+    pub command_line: String,
+    /// The values of the options that change the results, named as they are on the command line
+    pub parameters: BTreeMap<&'static str, String>,
+    /// The checksums of the query HMM and the target fasta, where they are known
+    pub inputs: BTreeMap<&'static str, String>,
+}
+
+impl Provenance {
+    pub fn new(args: &Args) -> Result<Self> {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+        let parameters = BTreeMap::from([
+            ("E", args.evalue_cutoff.to_string()),
+            (
+                "incE",
+                optional(args.inclusion_evalue.map(|e| e.to_string())),
+            ),
+            ("incT", optional(args.inclusion_bits.map(|t| t.to_string()))),
+            ("threads", args.threads.to_string()),
+            ("ali-format", value_name(args.ali_format)),
+            ("output-version", value_name(args.output_version)),
+            (
+                "evalue-format",
+                value_name(args.number_format.evalue_format),
+            ),
+            (
+                "evalue-precision",
+                optional(args.number_format.evalue_precision.map(|n| n.to_string())),
+            ),
+            (
+                "score-precision",
+                optional(args.number_format.score_precision.map(|n| n.to_string())),
+            ),
+            ("seeds-format", value_name(args.seeds_format)),
+            ("sensitivity", value_name(args.sensitivity)),
+            ("consensus-source", value_name(args.consensus_source)),
+            (
+                "sort-by",
+                optional((!args.sort_by.is_empty()).then(|| {
+                    args.sort_by
+                        .iter()
+                        .map(|key| value_name(*key))
+                        .collect::<Vec<String>>()
+                        .join(",")
+                })),
+            ),
+            ("dense-cells", args.dense_cells.to_string()),
+            ("fast-pass", args.fast_pass.to_string()),
+            (
+                "filter",
+                optional(args.hit_filter.as_ref().map(|f| f.to_string())),
+            ),
+            (
+                "msv-pvalue",
+                optional(args.msv_pvalue.map(|p| p.to_string())),
+            ),
+            ("comp-bias-filter", args.comp_bias_filter.to_string()),
+            (
+                "max-bias-fraction",
+                optional(args.max_bias_fraction.map(|f| f.to_string())),
+            ),
+            (
+                "min-aligned-length",
+                optional(args.min_aligned_length.map(|n| n.to_string())),
+            ),
+            (
+                "min-profile-cov",
+                optional(args.min_profile_cov.map(|f| f.to_string())),
+            ),
+            (
+                "min-target-cov",
+                optional(args.min_target_cov.map(|f| f.to_string())),
+            ),
+        ]);
+
+        let mut inputs = BTreeMap::new();
+        if let Some(checksum) = query_hmm_checksum(args)? {
+            inputs.insert("query-hmm", checksum);
+        }
+        if let Some(checksum) = target_checksum(args)? {
+            inputs.insert("target", checksum);
+        }
+
+        Ok(Provenance {
+            version: env!("CARGO_PKG_VERSION"),
+            git_commit: option_env!("MMORESEQS_GIT_COMMIT"),
+            nale_version: NALE_VERSION,
+            command_line: std::env::args()
+                .map(|arg| shell_quote(&arg))
+                .collect::<Vec<String>>()
+                .join(" "),
+            parameters,
+            inputs,
+        })
+    }
+
+    /// The provenance as "key value" lines, for the formats that carry it in comments
+    pub fn comment_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("mmoreseqs-version {}", self.version),
+            format!("git-commit {}", self.git_commit.unwrap_or("-")),
+            format!("nale-version {}", self.nale_version.unwrap_or("-")),
+            format!("command {}", self.command_line),
+        ];
+        lines.extend(
+            self.parameters
+                .iter()
+                .map(|(name, value)| format!("parameter {name}={value}")),
+        );
+        lines.extend(
+            self.inputs
+                .iter()
+                .map(|(name, checksum)| format!("input {name}={checksum}")),
+        );
+        lines
+    }
+}
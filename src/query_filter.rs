@@ -0,0 +1,100 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use nale::structs::Profile;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("no query profiles are left after --query-include and --query-exclude")]
+pub struct NoQueriesSelectedError;
+
+/// Accession or name patterns that select query profiles
+#[derive(Debug, Clone)]
+pub struct QueryPatterns(pub Vec<String>);
+
+/// Parse a list of query patterns: a file with one pattern per line, or a comma separated list.
+pub fn parse_query_patterns(value: &str) -> Result<QueryPatterns, String> {
+    let patterns: Vec<String> = match Path::new(value).is_file() {
+        true => read_to_string(value)
+            .map_err(|err| format!("failed to read {value}: {err}"))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect(),
+        false => value
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(String::from)
+            .collect(),
+    };
+
+    match patterns.is_empty() {
+        true => Err(format!("{value} has no query patterns")),
+        false => Ok(QueryPatterns(patterns)),
+    }
+}
+
+/// Match a name against a glob pattern, where '*' matches any run of
+/// characters (including none) and '?' matches any single character.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let mut pattern_idx: usize = 0;
+    let mut name_idx: usize = 0;
+    // the last star, and the name position that it was matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while name_idx < name.len() {
+        match pattern.get(pattern_idx) {
+            Some('*') => {
+                backtrack = Some((pattern_idx, name_idx));
+                pattern_idx += 1;
+            }
+            Some(&c) if c == '?' || c == name[name_idx] => {
+                pattern_idx += 1;
+                name_idx += 1;
+            }
+            _ => match backtrack {
+                // let the last star swallow one more character
+                Some((star_idx, star_name_idx)) => {
+                    pattern_idx = star_idx + 1;
+                    name_idx = star_name_idx + 1;
+                    backtrack = Some((star_idx, star_name_idx + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[pattern_idx..].iter().all(|c| *c == '*')
+}
+
+/// Which query profiles to align, by accession or name
+#[derive(Debug, Default, Clone)]
+pub struct QueryFilter {
+    /// If not empty, only profiles that match one of these patterns are kept
+    pub include: Vec<String>,
+    /// Profiles that match any of these patterns are removed
+    pub exclude: Vec<String>,
+}
+
+impl QueryFilter {
+    pub fn is_active(&self) -> bool {
+        !self.include.is_empty() || !self.exclude.is_empty()
+    }
+
+    pub fn allows(&self, profile: &Profile) -> bool {
+        let matches = |pattern: &String| {
+            glob_match(pattern, &profile.accession) || glob_match(pattern, &profile.name)
+        };
+
+        if self.exclude.iter().any(matches) {
+            return false;
+        }
+
+        self.include.is_empty() || self.include.iter().any(matches)
+    }
+}
@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use mmoreseqs::seq_io::{write_stockholm, Msa, MsaRow, WriteOptions};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("query looks like unaligned FASTA (the sequences have different lengths); an MSA or HMM is required")]
+pub struct UnalignedFastaQueryError;
+
+#[derive(Error, Debug)]
+#[error("aligned FASTA query has rows of different lengths: {0}")]
+pub struct RaggedAlignmentError(String);
+
+/// The formats that the query file may be given in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum QueryFormat {
+    /// A multiple sequence alignment in Stockholm format
+    Stockholm,
+    /// A multiple sequence alignment in aligned FASTA format
+    Afa,
+    /// A HMMER3 profile HMM
+    Hmm,
+}
+
+/// The rows of an aligned FASTA file, as (name, aligned sequence) pairs
+fn read_fasta_rows(path: &Path) -> Result<Vec<(String, String)>> {
+    let file = File::open(path).context("failed to open query file")?;
+    let mut rows: Vec<(String, String)> = vec![];
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        match line.strip_prefix('>') {
+            Some(header) => {
+                let name = header.split_whitespace().next().unwrap_or_default();
+                rows.push((name.to_string(), String::new()));
+            }
+            None => {
+                if let Some((_, sequence)) = rows.last_mut() {
+                    sequence.extend(line.chars().filter(|c| !c.is_whitespace()));
+                }
+            }
+        }
+    }
+
+    Ok(rows)
+}
+
+impl QueryFormat {
+    /// Guess the format of the query from its contents.
+    ///
+    /// FASTA input is only accepted as an MSA if it contains gap characters
+    /// or all of its sequences have the same length.
+    pub fn detect(path: &Path) -> Result<Self> {
+        let query_file = File::open(path).context("failed to open query file")?;
+        let first_line = BufReader::new(query_file)
+            .lines()
+            .next()
+            .transpose()?
+            .unwrap_or_default();
+
+        if first_line.starts_with("HMMER3") {
+            return Ok(QueryFormat::Hmm);
+        }
+
+        if !first_line.starts_with('>') {
+            return Ok(QueryFormat::Stockholm);
+        }
+
+        let rows = read_fasta_rows(path)?;
+        let has_gaps = rows
+            .iter()
+            .any(|(_, sequence)| sequence.contains(['-', '.']));
+        let equal_lengths = rows.windows(2).all(|w| w[0].1.len() == w[1].1.len());
+
+        match (has_gaps, equal_lengths) {
+            (_, true) => Ok(QueryFormat::Afa),
+            (true, false) => Err(RaggedAlignmentError(path.to_string_lossy().to_string()).into()),
+            (false, false) => Err(UnalignedFastaQueryError.into()),
+        }
+    }
+}
+
+/// Convert an aligned FASTA file to Stockholm, which both mmseqs and hmmbuild read.
+///
+/// The alignment is named after the file, since aligned FASTA has no place for a name.
+pub fn convert_afa_to_stockholm(afa: &Path, out: &mut impl Write) -> Result<()> {
+    let rows = read_fasta_rows(afa)?;
+
+    if rows.windows(2).any(|w| w[0].1.len() != w[1].1.len()) {
+        return Err(RaggedAlignmentError(afa.to_string_lossy().to_string()).into());
+    }
+
+    let name = afa
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "query".to_string());
+
+    let msa = Msa {
+        id: Some(name.clone()),
+        accession: Some(name),
+        comments: vec![],
+        rows: rows
+            .into_iter()
+            .map(|(name, sequence)| MsaRow {
+                name,
+                sequence,
+                posterior: None,
+            })
+            .collect(),
+        reference: None,
+    };
+
+    write_stockholm(
+        &msa,
+        &WriteOptions {
+            wrap_width: None,
+            ..Default::default()
+        },
+        out,
+    )
+}
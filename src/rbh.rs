@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::fs::create_dir_all;
+use std::path::Path;
+
+use anyhow::Result;
+use mmoreseqs::seq_io::{write_fasta, write_stockholm, Msa, MsaRow, SeqRecord, WriteOptions};
+use nale::output::path_buf_ext::PathBufExt;
+use nale::structs::Alignment;
+
+use crate::pipeline::{align_seeds, prep, seed, QuerySet, TargetStore};
+use crate::query_format::QueryFormat;
+use crate::shutdown::{self, CancelledError};
+use crate::tabular::Table;
+use crate::workspace::{Stage, Workspace};
+use crate::Args;
+
+/// The best scoring hit of each query, over its hits to all of the targets
+fn best_hits(alignments: &[Alignment]) -> HashMap<&str, &Alignment> {
+    let mut best: HashMap<&str, &Alignment> = HashMap::new();
+    for alignment in alignments {
+        best.entry(&alignment.profile_name)
+            .and_modify(|current| {
+                if alignment.bit_score > current.bit_score {
+                    *current = alignment;
+                }
+            })
+            .or_insert(alignment);
+    }
+    best
+}
+
+/// One direction of the search, with the queries and targets it loaded
+struct Search {
+    args: Args,
+    queries: QuerySet,
+    targets: TargetStore,
+    hits: Vec<Alignment>,
+}
+
+impl Search {
+    /// Run one direction of the search, in its own workspace under the run's.
+    fn run(args: &Args, name: &str, query: &Path, target: &Path) -> Result<Self> {
+        let mut search_args = args.clone();
+        search_args.set_workspace(Workspace::new(args.workspace.dir.join(name)));
+        search_args.paths.query = query.to_path_buf();
+        search_args.paths.target_fasta = target.to_path_buf();
+        search_args.paths.target_fasta_sources = vec![];
+
+        prep(&search_args)?;
+        seed(&search_args)?;
+        search_args
+            .workspace
+            .validate(Stage::Align, &search_args.paths)?;
+        let queries = QuerySet::load(&search_args)?;
+        let targets = TargetStore::load(&search_args)?;
+        let output = align_seeds(&search_args, &queries, &targets)?;
+        // a search that was cut short can't tell which hits are the best
+        if let Some(signal) = shutdown::caught_signal() {
+            return Err(CancelledError(signal).into());
+        }
+
+        eprintln!("{name} search: {} hits", output.hits.len());
+        Ok(Search {
+            args: search_args,
+            queries,
+            targets,
+            hits: output
+                .hits
+                .into_iter()
+                .map(|(alignment, _)| alignment)
+                .collect(),
+        })
+    }
+}
+
+/// Find the reciprocal best hits of the queries and the targets.
+///
+/// The queries are searched against the targets, and then the best target of
+/// each query is searched, as a single sequence query, against the consensus
+/// sequences of the query models. A query and target are reciprocal best hits
+/// when each is the other's best scoring hit.
+pub fn rbh(args: &Args) -> Result<()> {
+    args.workspace.create()?;
+
+    let forward = Search::run(args, "forward", &args.paths.query, &args.paths.target_fasta)?;
+    let forward_best = best_hits(&forward.hits);
+
+    // the reverse search only needs the targets that are some query's best hit
+    let mut candidate_names: Vec<&str> = forward_best
+        .values()
+        .map(|alignment| &alignment.target_name[..])
+        .collect();
+    candidate_names.sort();
+    candidate_names.dedup();
+
+    let reverse_dir = args.workspace.dir.join("reverse-inputs");
+    create_dir_all(&reverse_dir)?;
+
+    // each candidate target is its own single sequence alignment, named after it
+    let candidates_path = reverse_dir.join("candidates.sto");
+    let mut candidates_file = candidates_path.open(true)?;
+    for name in &candidate_names {
+        let target = &forward.targets.sequences[*name];
+        let residues = std::str::from_utf8(&target.utf8_bytes[1..])?.to_ascii_uppercase();
+        let msa = Msa {
+            id: Some(name.to_string()),
+            accession: Some(name.to_string()),
+            rows: vec![MsaRow {
+                name: name.to_string(),
+                sequence: residues,
+                posterior: None,
+            }],
+            ..Msa::default()
+        };
+        write_stockholm(&msa, &WriteOptions::default(), &mut candidates_file)?;
+    }
+    drop(candidates_file);
+
+    let consensus_records: Vec<SeqRecord> = forward
+        .queries
+        .profiles(&forward.args)?
+        .iter()
+        .map(|profile| {
+            Ok(SeqRecord::new(
+                profile.name.clone(),
+                std::str::from_utf8(&profile.consensus_sequence[1..])?.to_ascii_uppercase(),
+            ))
+        })
+        .collect::<Result<_>>()?;
+    let consensus_path = reverse_dir.join("query-consensus.fa");
+    write_fasta(
+        &consensus_records,
+        &WriteOptions::default(),
+        &mut consensus_path.open(true)?,
+    )?;
+
+    let reverse = match candidate_names.is_empty() {
+        true => None,
+        false => {
+            let mut reverse_args = args.clone();
+            reverse_args.query_format = Some(QueryFormat::Stockholm);
+            Some(Search::run(
+                &reverse_args,
+                "reverse",
+                &candidates_path,
+                &consensus_path,
+            )?)
+        }
+    };
+    let reverse_best = reverse
+        .as_ref()
+        .map(|reverse| best_hits(&reverse.hits))
+        .unwrap_or_default();
+
+    let format = &args.number_format;
+    let mut rows: Vec<Vec<String>> = vec![];
+    let mut query_names: Vec<&&str> = forward_best.keys().collect();
+    query_names.sort();
+    for query_name in query_names {
+        let forward = forward_best[*query_name];
+        let Some(reverse) = reverse_best.get(&forward.target_name[..]) else {
+            continue;
+        };
+        if reverse.target_name != forward.profile_name {
+            continue;
+        }
+        rows.push(vec![
+            forward.profile_name.clone(),
+            forward.target_name.clone(),
+            format.score(forward.bit_score, 2),
+            format.evalue(forward.evalue),
+            format.score(reverse.bit_score, 2),
+            format.evalue(reverse.evalue),
+            format.score((forward.bit_score + reverse.bit_score) / 2.0, 2),
+            format.evalue(forward.evalue.max(reverse.evalue)),
+        ]);
+    }
+
+    eprintln!(
+        "found {} reciprocal best hits for {} queries with hits",
+        rows.len(),
+        forward_best.len()
+    );
+
+    Table {
+        headers: [
+            "query name",
+            "target name",
+            "forward bit score",
+            "forward e-value",
+            "reverse bit score",
+            "reverse e-value",
+            "mean bit score",
+            "max e-value",
+        ]
+        .map(String::from)
+        .to_vec(),
+        rows,
+        ..Table::default()
+    }
+    .write(&mut args.paths.results.open(true)?)?;
+    args.uploads.closed(&args.paths.results);
+    Ok(())
+}
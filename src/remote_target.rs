@@ -0,0 +1,430 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{metadata, remove_file, File};
+use std::io::{copy, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use nale::output::path_buf_ext::PathBufExt;
+use thiserror::Error;
+
+use crate::object_store::{is_object_uri, Download, InvalidObjectUriError, ObjectUri};
+use crate::runner::Runner;
+use crate::Args;
+
+/// The environment variable that gives the path to curl, which reads http(s):// targets
+pub const CURL_ENV_VAR: &str = "MMORESEQS_CURL";
+
+/// Records that are at most this many bytes apart are fetched with one range
+/// request, since a request costs more than the bytes between them
+const RANGE_MERGE_GAP: u64 = 64 * 1024;
+
+#[derive(Error, Debug)]
+#[error("malformed target index line {line}: {path}")]
+pub struct MalformedTargetIndexError {
+    path: String,
+    line: usize,
+}
+
+#[derive(Error, Debug)]
+#[error("failed to read the target fasta from {0}")]
+pub struct TargetStreamError(String);
+
+#[derive(Error, Debug)]
+#[error("fetched {fetched} bytes of the target fasta from {target} for bytes {start}-{end}")]
+pub struct IncompleteRangeError {
+    target: String,
+    start: u64,
+    end: u64,
+    fetched: u64,
+}
+
+/// Whether a target path is a URL or an object store URI rather than a local path
+pub fn is_remote_target(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://") || is_object_uri(path)
+}
+
+/// Check that a target path is a well formed URI, if it is an object store one, for clap.
+pub fn parse_target_location(path: &str) -> Result<String, InvalidObjectUriError> {
+    if is_object_uri(path) {
+        path.parse::<ObjectUri>()?;
+    }
+    Ok(path.to_string())
+}
+
+/// A target fasta that is read from a web server or an object store
+#[derive(Debug, Clone)]
+pub enum RemoteTarget {
+    Http(String),
+    Object(ObjectUri),
+}
+
+impl FromStr for RemoteTarget {
+    type Err = InvalidObjectUriError;
+
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        match is_object_uri(uri) {
+            true => Ok(RemoteTarget::Object(uri.parse()?)),
+            false => Ok(RemoteTarget::Http(uri.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for RemoteTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RemoteTarget::Http(url) => write!(f, "{url}"),
+            RemoteTarget::Object(uri) => write!(f, "{uri}"),
+        }
+    }
+}
+
+/// curl, from its environment variable or else the PATH
+pub fn curl() -> Command {
+    let path = std::env::var_os(CURL_ENV_VAR)
+        .filter(|path| !path.is_empty())
+        .map_or_else(|| PathBuf::from("curl"), PathBuf::from);
+    let mut command = Command::new(path);
+    command.args(["--silent", "--show-error", "--fail", "--location"]);
+    command
+}
+
+impl RemoteTarget {
+    /// The command that writes the whole target fasta to stdout
+    fn stream_command(&self) -> Command {
+        match self {
+            RemoteTarget::Http(url) => {
+                let mut command = curl();
+                command.arg(url);
+                command
+            }
+            RemoteTarget::Object(uri) => uri.stream_command(),
+        }
+    }
+
+    /// The size of the target fasta in bytes, or None if the server doesn't report it.
+    fn size(&self) -> Result<Option<u64>> {
+        let mut command = match self {
+            RemoteTarget::Http(url) => {
+                let mut command = curl();
+                command.arg("--head").arg(url);
+                command
+            }
+            RemoteTarget::Object(uri) => uri.size_command(),
+        };
+        let output = command
+            .output()
+            .with_context(|| format!("failed to read the size of the target fasta at {self}"))?;
+        if !output.status.success() {
+            return Err(TargetStreamError(self.to_string()).into());
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let size = match self {
+            // the headers of each redirect are printed, and the last are the file's
+            RemoteTarget::Http(_) => stdout
+                .lines()
+                .rev()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+                .and_then(|(_, value)| value.trim().parse().ok()),
+            RemoteTarget::Object(_) => stdout.trim().parse().ok(),
+        };
+        Ok(size)
+    }
+
+    /// Download the target fasta, or the inclusive byte range of it, to a local path.
+    fn download(&self, runner: &Runner, range: Option<(u64, u64)>, local: &Path) -> Result<()> {
+        let Download {
+            mut command,
+            to_stdout,
+        } = match self {
+            RemoteTarget::Http(url) => {
+                let mut command = curl();
+                if let Some((start, end)) = range {
+                    command.args(["--range", &format!("{start}-{end}")]);
+                }
+                command.arg(url);
+                Download {
+                    command,
+                    to_stdout: true,
+                }
+            }
+            RemoteTarget::Object(uri) => uri.download_command(range, local),
+        };
+
+        match to_stdout {
+            true => runner.run_to_file(&mut command, local),
+            false => runner.run(&mut command),
+        }
+        .with_context(|| format!("failed to download the target fasta from {self}"))
+    }
+}
+
+/// A target's record in the target fasta
+#[derive(Debug, Clone)]
+struct IndexEntry {
+    name: String,
+    /// The byte offset of the record's header line
+    offset: u64,
+    /// The length of the record in bytes, from its header line to the next
+    length: u64,
+}
+
+/// The byte range of each target's record in the target fasta, so that the
+/// records of the seeded targets can be fetched without the rest.
+///
+/// The index file has the offset, length, and name of a target on each line.
+pub struct TargetIndex {
+    entries: Vec<IndexEntry>,
+}
+
+impl TargetIndex {
+    /// Index a fasta as it is read. The names are the first words of the
+    /// header lines, as the targets are named when they are loaded.
+    pub fn build(mut reader: impl BufRead) -> Result<Self> {
+        let mut entries: Vec<IndexEntry> = vec![];
+        let mut offset: u64 = 0;
+        let mut line: Vec<u8> = vec![];
+
+        loop {
+            line.clear();
+            let line_length = reader.read_until(b'\n', &mut line)? as u64;
+            if line_length == 0 {
+                break;
+            }
+            if let Some(header) = line.strip_prefix(b">") {
+                if let Some(last) = entries.last_mut() {
+                    last.length = offset - last.offset;
+                }
+                entries.push(IndexEntry {
+                    name: String::from_utf8_lossy(header)
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    offset,
+                    length: 0,
+                });
+            }
+            offset += line_length;
+        }
+        if let Some(last) = entries.last_mut() {
+            last.length = offset - last.offset;
+        }
+
+        Ok(TargetIndex { entries })
+    }
+
+    /// Index a remote target fasta by streaming it through, without keeping it.
+    pub fn build_remote(target: &RemoteTarget) -> Result<Self> {
+        let mut child = target
+            .stream_command()
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to read the target fasta from {target}"))?;
+        let stdout = child.stdout.take().expect("the stream's stdout is piped");
+        let index = TargetIndex::build(BufReader::new(stdout));
+        // a failed download may still have written a prefix of the fasta
+        if !child.wait()?.success() {
+            return Err(TargetStreamError(target.to_string()).into());
+        }
+        index
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = File::open(path).context(format!(
+            "failed to open target index: {}",
+            path.to_string_lossy()
+        ))?;
+
+        let mut entries: Vec<IndexEntry> = vec![];
+        for (line_idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let malformed = || MalformedTargetIndexError {
+                path: path.to_string_lossy().to_string(),
+                line: line_idx + 1,
+            };
+            let mut fields = line.splitn(3, '\t');
+            let (Some(offset), Some(length), Some(name)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(malformed().into());
+            };
+            entries.push(IndexEntry {
+                name: name.to_string(),
+                offset: offset.parse().map_err(|_| malformed())?,
+                length: length.parse().map_err(|_| malformed())?,
+            });
+        }
+
+        Ok(TargetIndex { entries })
+    }
+
+    pub fn write(&self, path: &PathBuf) -> Result<()> {
+        let mut out = BufWriter::new(path.open(true)?);
+        for entry in &self.entries {
+            writeln!(out, "{}\t{}\t{}", entry.offset, entry.length, entry.name)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The total size of the target fasta in bytes
+    fn size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.length).sum()
+    }
+
+    /// The inclusive byte ranges that hold the records of the targets, in file
+    /// order, with nearby records fetched together.
+    fn ranges(&self, names: &HashSet<String>) -> Vec<(u64, u64)> {
+        let mut ranges: Vec<(u64, u64)> = vec![];
+        for entry in self.entries.iter().filter(|e| names.contains(&e.name)) {
+            let end = entry.offset + entry.length - 1;
+            match ranges.last_mut() {
+                Some(last) if entry.offset <= last.1 + 1 + RANGE_MERGE_GAP => last.1 = end,
+                _ => ranges.push((entry.offset, end)),
+            }
+        }
+        ranges
+    }
+}
+
+/// Download the whole remote target fasta for prep, which builds the target
+/// database from it, and index it for align to fetch targets from later.
+pub fn download_target(args: &Args, target: &RemoteTarget) -> Result<()> {
+    let local = &args.paths.remote_target_fasta;
+    target.download(&args.host_runner, None, local)?;
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let file = File::open(local).context(format!(
+        "failed to open target fasta: {}",
+        local.to_string_lossy()
+    ))?;
+    let index = TargetIndex::build(BufReader::new(file))?;
+    index.write(&args.paths.target_index)?;
+    eprintln!("downloaded {} targets from {target}", index.len());
+    Ok(())
+}
+
+/// Fetch the records of the named targets from the remote target fasta into
+/// the workspace, and return the index of all of the targets.
+///
+/// The index is read from the workspace, or else built by streaming the
+/// target fasta once and saved there, so that later runs only fetch ranges.
+/// The index covers the whole file, so a saved index whose size isn't the
+/// remote file's is out of date, and is built again.
+pub fn fetch_targets(
+    args: &Args,
+    target: &RemoteTarget,
+    names: &HashSet<String>,
+) -> Result<TargetIndex> {
+    let saved = match args.paths.target_index.exists() {
+        true => Some(TargetIndex::read(&args.paths.target_index)?),
+        false => None,
+    };
+    let index = match saved {
+        Some(index) if target.size()? == Some(index.size()) => index,
+        saved => {
+            if saved.is_some() {
+                eprintln!("indexing {target} again, since its size doesn't match the saved index");
+            }
+            let index = TargetIndex::build_remote(target)?;
+            index.write(&args.paths.target_index)?;
+            index
+        }
+    };
+
+    let ranges = index.ranges(names);
+    let part = args.paths.fetched_target_fasta.with_extension("part");
+    let mut out = BufWriter::new(args.paths.fetched_target_fasta.open(true)?);
+    for &(start, end) in &ranges {
+        target.download(&args.host_runner, Some((start, end)), &part)?;
+        // a server that ignores the range sends the whole file, and one that
+        // fails partway sends less than the range
+        let fetched = metadata(&part)?.len();
+        if fetched != end - start + 1 {
+            return Err(IncompleteRangeError {
+                target: target.to_string(),
+                start,
+                end,
+                fetched,
+            }
+            .into());
+        }
+        copy(&mut File::open(&part)?, &mut out)?;
+    }
+    out.flush()?;
+    if part.exists() {
+        remove_file(&part)?;
+    }
+
+    let fetched_count = index.names().filter(|name| names.contains(*name)).count();
+    let fetched_size: u64 = ranges.iter().map(|(start, end)| end - start + 1).sum();
+    eprintln!(
+        "fetched {fetched_count} of {} targets ({fetched_size} of {} bytes) from {target} in {} range requests",
+        index.len(),
+        index.size(),
+        ranges.len()
+    );
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn each_record_is_indexed_from_its_header_to_the_next() {
+        let fasta = b">tgt1 first\nMKV\nLLA\n>tgt2\nMA\n";
+        let index = TargetIndex::build(&fasta[..]).unwrap();
+        assert_eq!(index.names().collect::<Vec<&str>>(), ["tgt1", "tgt2"]);
+        assert_eq!(index.ranges(&names(&["tgt1"])), [(0, 19)]);
+        assert_eq!(index.ranges(&names(&["tgt2"])), [(20, 28)]);
+        assert_eq!(index.size(), fasta.len() as u64);
+    }
+
+    #[test]
+    fn nearby_records_are_fetched_with_one_range() {
+        let entry = |name: &str, offset: u64, length: u64| IndexEntry {
+            name: name.to_string(),
+            offset,
+            length,
+        };
+        let gap = RANGE_MERGE_GAP;
+        let index = TargetIndex {
+            entries: vec![
+                entry("a", 0, 100),
+                entry("b", 100, gap),
+                entry("c", 100 + gap, 100),
+                entry("d", 200 + gap, 100),
+            ],
+        };
+
+        // the records in between are fetched too, up to the gap
+        assert_eq!(index.ranges(&names(&["a", "b"])), [(0, 99 + gap)]);
+        assert_eq!(index.ranges(&names(&["a", "c"])), [(0, 199 + gap)]);
+        assert_eq!(
+            index.ranges(&names(&["a", "d"])),
+            [(0, 99), (200 + gap, 299 + gap)]
+        );
+        assert_eq!(index.ranges(&names(&["d", "a"])).len(), 2);
+        assert!(index.ranges(&names(&["e"])).is_empty());
+    }
+}
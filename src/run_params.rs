@@ -0,0 +1,453 @@
+use std::fs::read_to_string;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use nale::align::bounded::structs::CloudSearchParams;
+use nale::output::path_buf_ext::PathBufExt;
+use thiserror::Error;
+use toml::{Table, Value};
+
+use crate::external_steps::{check_seed_db_types, mmseqs_align_args, ResolvedPrefilter};
+use crate::provenance::value_name;
+use crate::seed_expansion::SeedExpansion;
+use crate::Args;
+
+/// The subcommands that take --config
+const CONFIG_COMMANDS: [&str; 2] = ["search", "pipeline"];
+
+#[derive(Error, Debug)]
+#[error("malformed config file {path}: {message}")]
+pub struct MalformedConfigError {
+    path: String,
+    message: String,
+}
+
+/// The TOML value of a number option, which is written as it is on the command line
+fn number_value(number: String) -> Value {
+    match (number.parse::<i64>(), number.parse::<f64>()) {
+        (Ok(integer), _) => Value::Integer(integer),
+        (_, Ok(float)) => Value::Float(float),
+        _ => Value::String(number),
+    }
+}
+
+/// The command line option that sets a parameter: -E, or the long option.
+fn option_name(name: &str) -> String {
+    match name.len() {
+        1 => format!("-{name}"),
+        _ => format!("--{name}"),
+    }
+}
+
+/// The parameters of a run after tuning and defaulting, written as
+/// run-params.toml in the run directory.
+///
+/// The options are named as they are on the command line, so that the file
+/// can be given back to --config to run with the same parameters. The mmseqs
+/// search flags and the cloud search parameters follow from the options, and
+/// are recorded in their own tables, which --config doesn't read.
+pub struct RunParams {
+    options: Vec<(&'static str, Value)>,
+    prefilter_args: Vec<String>,
+    cloud_search: CloudSearchParams,
+    /// The config file that the run's options were read from
+    config: Option<PathBuf>,
+}
+
+impl RunParams {
+    pub fn new(args: &Args) -> Result<Self> {
+        let prefilter = ResolvedPrefilter::new(args, check_seed_db_types(args)?)?;
+
+        let mut options: Vec<(&'static str, Value)> = vec![];
+        let mut optional = |name: &'static str, value: Option<Value>| {
+            if let Some(value) = value {
+                options.push((name, value));
+            }
+        };
+        let number = |value: Option<String>| value.map(number_value);
+        let text = |value: String| Some(Value::String(value));
+        let flag = |set: bool| Some(Value::Boolean(set));
+        let path = |path: &Option<PathBuf>| {
+            path.as_ref()
+                .map(|path| Value::String(path.to_string_lossy().to_string()))
+        };
+        let list =
+            |values: Vec<String>| (!values.is_empty()).then(|| Value::String(values.join(",")));
+
+        optional("threads", number(Some(args.threads.to_string())));
+        optional(
+            "query-format",
+            args.query_format.map(value_name).and_then(text),
+        );
+        optional("sensitivity", text(value_name(args.sensitivity)));
+        optional("k-score", number(prefilter.k_score.map(|k| k.to_string())));
+        optional(
+            "kmer-length",
+            number(prefilter.kmer_length.map(|k| k.to_string())),
+        );
+        optional("max-seqs", number(Some(prefilter.max_seqs.to_string())));
+        optional("compress-intermediates", flag(args.compress_intermediates));
+        optional("E", number(Some(args.evalue_cutoff.to_string())));
+        optional("threshold-table", path(&args.paths.threshold_table));
+        optional("incE", number(args.inclusion_evalue.map(|e| e.to_string())));
+        optional("incT", number(args.inclusion_bits.map(|t| t.to_string())));
+        optional("query-include", list(args.query_filter.include.clone()));
+        optional("query-exclude", list(args.query_filter.exclude.clone()));
+        optional(
+            "shard",
+            args.shard
+                .map(|shard| Value::String(format!("{}/{}", shard.index, shard.count))),
+        );
+        optional("on-bound-fail", text(value_name(args.on_bound_fail)));
+        optional("dense-cells", number(Some(args.dense_cells.to_string())));
+        optional("fast-pass", flag(args.fast_pass));
+        optional("msv-pvalue", number(args.msv_pvalue.map(|p| p.to_string())));
+        optional(
+            "seed-expand",
+            number(args.seed_expand.map(|expansion| match expansion {
+                SeedExpansion::Residues(residues) => residues.to_string(),
+                // with a point, so that a whole fraction isn't read back as residues
+                SeedExpansion::Fraction(fraction) => format!("{fraction:?}"),
+            })),
+        );
+        optional(
+            "chain-seeds",
+            number(args.chain_seeds.map(|g| g.to_string())),
+        );
+        optional("comp-bias-filter", flag(args.comp_bias_filter));
+        optional(
+            "max-bias-fraction",
+            number(args.max_bias_fraction.map(|f| f.to_string())),
+        );
+        optional(
+            "min-aligned-length",
+            number(args.min_aligned_length.map(|n| n.to_string())),
+        );
+        optional(
+            "min-profile-cov",
+            number(args.min_profile_cov.map(|f| f.to_string())),
+        );
+        optional(
+            "min-target-cov",
+            number(args.min_target_cov.map(|f| f.to_string())),
+        );
+        optional(
+            "filter",
+            args.hit_filter
+                .as_ref()
+                .map(|filter| Value::String(filter.to_string())),
+        );
+        optional(
+            "seed-max-evalue",
+            number(args.seed_filter.max_evalue.map(|e| e.to_string())),
+        );
+        optional(
+            "seed-keep-top",
+            number(args.seed_filter.keep_top.map(|k| k.to_string())),
+        );
+        optional("consensus-source", text(value_name(args.consensus_source)));
+        optional("ambiguity", text(value_name(args.ambiguity)));
+        optional("bg-freqs", path(&args.model_overrides.background_path));
+        optional(
+            "popen",
+            number(args.model_overrides.gap_open.map(|p| p.to_string())),
+        );
+        optional(
+            "pext",
+            number(args.model_overrides.gap_extend.map(|p| p.to_string())),
+        );
+        optional("target-include-ids", path(&args.paths.target_include_ids));
+        optional("mask-previous", path(&args.paths.mask_previous));
+        optional("taxdb", path(&args.paths.taxdb));
+        let taxa = |taxa: &[u32]| list(taxa.iter().map(|taxon| taxon.to_string()).collect());
+        optional("taxon-include", taxa(&args.taxon_filter.include));
+        optional("taxon-exclude", taxa(&args.taxon_filter.exclude));
+        optional("target-type", text(value_name(args.target_type)));
+        optional(
+            "min-orf-length",
+            number(Some(args.orf_params.min_length.to_string())),
+        );
+        optional(
+            "genetic-code",
+            number(Some(args.orf_params.genetic_code.id.to_string())),
+        );
+        optional("ali-format", text(value_name(args.ali_format)));
+        optional("output-version", text(value_name(args.output_version)));
+        optional(
+            "evalue-format",
+            text(value_name(args.number_format.evalue_format)),
+        );
+        optional(
+            "evalue-precision",
+            number(args.number_format.evalue_precision.map(|n| n.to_string())),
+        );
+        optional(
+            "score-precision",
+            number(args.number_format.score_precision.map(|n| n.to_string())),
+        );
+        optional(
+            "sort-by",
+            list(args.sort_by.iter().map(|key| value_name(*key)).collect()),
+        );
+        optional(
+            "alt-alignments",
+            number(Some(args.alt_alignments.to_string())),
+        );
+        optional("seed-provenance", flag(args.seed_provenance));
+
+        Ok(RunParams {
+            options,
+            prefilter_args: prefilter.mmseqs_args(),
+            cloud_search: args.sensitivity.cloud_search_params(),
+            config: args.paths.config.clone(),
+        })
+    }
+
+    pub fn write(&self, path: &PathBuf) -> Result<()> {
+        let mut out = path.open(true)?;
+        writeln!(
+            out,
+            "# The parameters of an mmoreseqs {} run, after tuning and defaulting.",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        writeln!(
+            out,
+            "# Give this file to --config to run with the same parameters."
+        )?;
+        if let Some(config) = &self.config {
+            writeln!(
+                out,
+                "# The options were read from {}",
+                config.to_string_lossy()
+            )?;
+        }
+        writeln!(out)?;
+        for (name, value) in &self.options {
+            writeln!(out, "{name} = {value}")?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "# The mmseqs search flags that the options resolve to")?;
+        writeln!(out, "[mmseqs]")?;
+        writeln!(
+            out,
+            "prefilter = {}",
+            Value::String(self.prefilter_args.join(" "))
+        )?;
+        writeln!(
+            out,
+            "align = {}",
+            Value::String(mmseqs_align_args().join(" "))
+        )?;
+
+        writeln!(out)?;
+        writeln!(
+            out,
+            "# The alignment cloud search parameters of --sensitivity"
+        )?;
+        writeln!(out, "[cloud-search]")?;
+        writeln!(out, "gamma = {}", self.cloud_search.gamma)?;
+        writeln!(out, "alpha = {:?}", self.cloud_search.alpha)?;
+        writeln!(out, "beta = {:?}", self.cloud_search.beta)?;
+        Ok(())
+    }
+}
+
+/// Read the options of a config file: its top-level keys, with their values as they
+/// are written on the command line, or None for a flag that isn't set.
+fn read_config(path: &Path) -> Result<Vec<(String, Option<String>)>> {
+    let contents = read_to_string(path).context(format!(
+        "failed to read config file: {}",
+        path.to_string_lossy()
+    ))?;
+    let malformed = |message: String| MalformedConfigError {
+        path: path.to_string_lossy().to_string(),
+        message,
+    };
+
+    let table: Table = contents
+        .parse()
+        .map_err(|err: toml::de::Error| malformed(err.to_string()))?;
+
+    let mut options = vec![];
+    for (name, value) in table {
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(malformed(format!(
+                "{name:?} isn't an option name, which has only letters, digits, and dashes"
+            ))
+            .into());
+        }
+        let value = match value {
+            // the tables hold what the options resolve to, which the options set again
+            Value::Table(_) => continue,
+            Value::Boolean(true) => None,
+            Value::Boolean(false) => continue,
+            Value::Integer(integer) => Some(integer.to_string()),
+            // with the shortest form that reads back as the same number
+            Value::Float(float) => Some(format!("{float:?}")),
+            Value::String(text) => Some(text),
+            _ => {
+                return Err(
+                    malformed(format!("{name} must be true, false, a number, or a string")).into(),
+                )
+            }
+        };
+        options.push((name, value));
+    }
+    Ok(options)
+}
+
+/// Expand the --config option of a search or pipeline command into the options
+/// of its file, which are placed before the command's own arguments. An option
+/// that is also given on the command line is taken from the command line.
+pub fn expand_config_args(cli_args: Vec<String>) -> Result<Vec<String>> {
+    if !cli_args
+        .get(1)
+        .is_some_and(|command| CONFIG_COMMANDS.contains(&command.as_str()))
+    {
+        return Ok(cli_args);
+    }
+
+    let mut config = None;
+    for (idx, arg) in cli_args.iter().enumerate() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            config = cli_args.get(idx + 1).cloned();
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config = Some(path.to_string());
+        }
+    }
+    // clap reports a --config without a value
+    let Some(config) = config else {
+        return Ok(cli_args);
+    };
+
+    let given = |option: &str| {
+        cli_args.iter().skip(2).any(|arg| {
+            arg == option
+                || arg.starts_with(&format!("{option}="))
+                // a short option may have its value attached, as in -E1e-5
+                || (option.len() == 2 && arg.starts_with(option))
+        })
+    };
+
+    let mut config_args = vec![];
+    for (name, value) in read_config(Path::new(&config))? {
+        let option = option_name(&name);
+        if given(&option) {
+            continue;
+        }
+        match value {
+            None => config_args.push(option),
+            // with the value attached, so that one that starts with a dash isn't read as an option
+            Some(value) => match option.len() {
+                2 => config_args.push(format!("{option}{value}")),
+                _ => config_args.push(format!("{option}={value}")),
+            },
+        }
+    }
+
+    let mut expanded = cli_args[..2].to_vec();
+    expanded.extend(config_args);
+    expanded.extend_from_slice(&cli_args[2..]);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::temp_path;
+    use std::fs::remove_file;
+
+    /// The arguments of a search command that reads the given config file
+    fn search_args(config: &Path, extra_args: &[&str]) -> Vec<String> {
+        let mut args = vec![
+            "mmoreseqs".to_string(),
+            "search".to_string(),
+            "--config".to_string(),
+            config.to_string_lossy().to_string(),
+        ];
+        args.extend(extra_args.iter().map(|arg| arg.to_string()));
+        args
+    }
+
+    #[test]
+    fn written_params_are_read_back_as_the_same_options() {
+        let path = temp_path("round-trip.toml");
+        let params = RunParams {
+            options: vec![
+                ("threads", number_value("4".to_string())),
+                ("E", number_value("1e-5".to_string())),
+                ("seed-expand", number_value("0.5".to_string())),
+                ("sort-by", Value::String("evalue,bits".to_string())),
+                ("fast-pass", Value::Boolean(false)),
+                ("seed-provenance", Value::Boolean(true)),
+                ("query-include", Value::String("-PF00001.1".to_string())),
+            ],
+            prefilter_args: vec!["--k-score".to_string(), "90".to_string()],
+            cloud_search: CloudSearchParams::default(),
+            config: None,
+        };
+        params.write(&path).unwrap();
+        let expanded = expand_config_args(search_args(&path, &["query.sto", "target.fa"]));
+        remove_file(&path).unwrap();
+
+        assert_eq!(
+            expanded.unwrap()[2..],
+            // in the order of the names, which the options don't depend on
+            [
+                "-E1e-5",
+                "--query-include=-PF00001.1",
+                "--seed-expand=0.5",
+                "--seed-provenance",
+                "--sort-by=evalue,bits",
+                "--threads=4",
+                "--config",
+                &path.to_string_lossy(),
+                "query.sto",
+                "target.fa",
+            ]
+        );
+    }
+
+    #[test]
+    fn command_line_options_take_precedence() {
+        let path = temp_path("precedence.toml");
+        std::fs::write(&path, "E = 0.5\nthreads = 4\n").unwrap();
+        let expanded = expand_config_args(search_args(&path, &["-E1e-12"]));
+        remove_file(&path).unwrap();
+
+        assert_eq!(
+            expanded.unwrap()[2..],
+            [
+                "--threads=4",
+                "--config",
+                &path.to_string_lossy(),
+                "-E1e-12"
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_configs_are_refused() {
+        for (name, contents) in [
+            ("syntax", "E 0.5\n"),
+            ("option-name", "\"seed provenance\" = true\n"),
+            ("value", "threads = [4]\n"),
+        ] {
+            let path = temp_path(name);
+            std::fs::write(&path, contents).unwrap();
+            let result = expand_config_args(search_args(&path, &[]));
+            remove_file(&path).unwrap();
+
+            let err = result.unwrap_err();
+            assert!(
+                err.downcast_ref::<MalformedConfigError>().is_some(),
+                "{name}: {err}"
+            );
+        }
+    }
+}
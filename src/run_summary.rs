@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+use nale::output::path_buf_ext::PathBufExt;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::manifest::RunStatus;
+use crate::pipeline::NO_SEEDS_MESSAGE;
+use crate::provenance::Provenance;
+use crate::shutdown::{self, CancelledError};
+use crate::skipped::{SkipReason, SkippedSeed};
+use crate::target_sources::SourceStats;
+use crate::time_limit::TimeLimitReachedError;
+use crate::workspace::Stage;
+use crate::Args;
+
+/// The E-value thresholds that the hits are counted under
+const HIT_EVALUE_THRESHOLDS: [f32; 6] = [1e-10, 1e-5, 1e-3, 1e-1, 1.0, 10.0];
+
+#[derive(Error, Debug)]
+#[error("the search produced no hits")]
+pub struct NoHitsError;
+
+/// The totals of a single align stage
+pub struct AlignStats {
+    pub query_count: usize,
+    pub target_count: usize,
+    pub seed_count: usize,
+    pub hit_evalues: Vec<f32>,
+    pub skipped_counts: Vec<(SkipReason, usize)>,
+    /// The targets and hits of each target fasta, when several were combined
+    pub source_stats: BTreeMap<String, SourceStats>,
+    /// The names of the queries with seeds left unaligned at the time limit
+    pub incomplete_queries: Vec<String>,
+    pub provenance: Provenance,
+}
+
+impl AlignStats {
+    /// Count the skipped seeds by reason, in the order of the reasons.
+    pub fn count_skipped(skipped: &[SkippedSeed]) -> Vec<(SkipReason, usize)> {
+        let mut counts: BTreeMap<SkipReason, usize> = BTreeMap::new();
+        for seed in skipped {
+            *counts.entry(seed.reason).or_default() += 1;
+        }
+        counts.into_iter().collect()
+    }
+}
+
+/// Machine-readable totals for a run, written as run-summary.json when the run finishes.
+#[derive(Serialize, Default)]
+pub struct RunSummary {
+    pub version: &'static str,
+    /// The provenance of the first align stage's outputs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    pub queries: usize,
+    pub targets: usize,
+    pub seeds: usize,
+    pub hits: usize,
+    /// The number of hits with an E-value at or below each threshold
+    pub hits_by_evalue: BTreeMap<String, usize>,
+    /// The wall-clock time spent in each stage, summed over batch entries
+    pub stage_seconds: BTreeMap<String, f64>,
+    /// The targets and hits of each target fasta, when several were combined
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub target_sources: BTreeMap<String, SourceStats>,
+    /// The queries whose results are partial, because the time limit was reached
+    /// before all of their seeds were aligned, or the batch entries that weren't started
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub incomplete_queries: Vec<String>,
+    pub warnings: Vec<String>,
+    #[serde(skip)]
+    aligned: bool,
+}
+
+impl RunSummary {
+    pub fn new() -> Self {
+        RunSummary {
+            version: env!("CARGO_PKG_VERSION"),
+            ..Default::default()
+        }
+    }
+
+    pub fn add_stage_time(&mut self, stage: Stage, elapsed: Duration) {
+        *self
+            .stage_seconds
+            .entry(stage.name().to_string())
+            .or_default() += elapsed.as_secs_f64();
+    }
+
+    pub fn add_align(&mut self, stats: &AlignStats) {
+        self.aligned = true;
+        self.provenance
+            .get_or_insert_with(|| stats.provenance.clone());
+        self.queries += stats.query_count;
+        // the batch entries all share the same targets
+        self.targets = stats.target_count;
+        self.seeds += stats.seed_count;
+        self.hits += stats.hit_evalues.len();
+
+        for threshold in HIT_EVALUE_THRESHOLDS {
+            let count = stats
+                .hit_evalues
+                .iter()
+                .filter(|&&evalue| evalue <= threshold)
+                .count();
+            *self
+                .hits_by_evalue
+                .entry(format!("{threshold:.0e}"))
+                .or_default() += count;
+        }
+
+        for (source, source_stats) in &stats.source_stats {
+            let totals = self.target_sources.entry(source.clone()).or_default();
+            totals.targets = source_stats.targets;
+            totals.hits += source_stats.hits;
+        }
+
+        if stats.seed_count == 0 {
+            self.warnings.push(NO_SEEDS_MESSAGE.to_string());
+        }
+
+        self.incomplete_queries
+            .extend(stats.incomplete_queries.iter().cloned());
+
+        for (reason, count) in &stats.skipped_counts {
+            self.warnings
+                .push(format!("skipped {count} seeds: {reason}"));
+        }
+    }
+
+    /// Whether the run searched everything, stopped at its time limit, or was cancelled
+    pub fn status(&self) -> RunStatus {
+        match () {
+            _ if shutdown::requested() => RunStatus::Cancelled,
+            _ if !self.incomplete_queries.is_empty() => RunStatus::Partial,
+            _ => RunStatus::Complete,
+        }
+    }
+
+    /// Write the summary, if a path for it was given, and apply --fail-if-no-hits.
+    ///
+    /// A run that reached its time limit is an error, once its partial results are written.
+    pub fn finish(&mut self, args: &Args) -> Result<()> {
+        let no_hits = self.aligned && self.hits == 0;
+        if no_hits {
+            self.warnings.push("no hits".to_string());
+        }
+        if let Some(signal) = shutdown::caught_signal() {
+            self.warnings.push(format!("cancelled by signal {signal}"));
+        }
+
+        if let Some(path) = &args.paths.run_summary {
+            self.write(path)?;
+        }
+
+        if let Some(signal) = shutdown::caught_signal() {
+            return Err(CancelledError(signal).into());
+        }
+        if !self.incomplete_queries.is_empty() {
+            return Err(TimeLimitReachedError(self.incomplete_queries.len()).into());
+        }
+        match no_hits && args.fail_if_no_hits {
+            true => Err(NoHitsError.into()),
+            false => Ok(()),
+        }
+    }
+
+    fn write(&self, path: &PathBuf) -> Result<()> {
+        serde_json::to_writer_pretty(&mut path.open(true)?, self)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use std::ffi::OsStr;
+use std::fs::{create_dir_all, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use crate::command_ext::CommandExt;
+
+/// Something that runs the external tools (MMseqs2 and HMMER) for the pipeline.
+///
+/// The pipeline steps build their commands and hand them to the runner, so
+/// that the orchestration can be exercised without the tools installed.
+pub trait ExternalRunner: Send + Sync {
+    fn run(&self, command: &mut Command) -> Result<()>;
+
+    /// Run a command with its stdout written to a file.
+    fn run_to_file(&self, command: &mut Command, out: &Path) -> Result<()> {
+        command.stdout(File::create(out).context(format!(
+            "failed to create output file: {}",
+            out.to_string_lossy()
+        ))?);
+        self.run(command)
+    }
+}
+
+/// Runs the commands as child processes.
+///
+/// If a logs directory is set, the output of each command is written to a
+/// file of its own there; otherwise it is only printed if the command fails.
+#[derive(Default)]
+pub struct ProcessRunner {
+    logs_dir: Option<PathBuf>,
+    /// The number of commands logged so far, which keeps the logs of commands
+    /// started in the same millisecond (by parallel steps or batch entries) apart
+    log_count: AtomicUsize,
+}
+
+impl ProcessRunner {
+    pub fn new(logs_dir: Option<PathBuf>) -> Self {
+        ProcessRunner {
+            logs_dir,
+            log_count: AtomicUsize::new(0),
+        }
+    }
+
+    /// The log file for a command, named by the time, the process, the count of
+    /// commands before it, and its step, e.g. logs/1700000000123-4242-7-mmseqs-prefilter.log
+    fn log_path(&self, logs_dir: &Path, command: &Command) -> PathBuf {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let log_idx = self.log_count.fetch_add(1, Ordering::Relaxed);
+
+        // a tool given by its path is logged by its file name
+        let program = Path::new(command.get_program());
+        let program = program.file_name().unwrap_or(program.as_os_str());
+        let mut name = format!(
+            "{timestamp}-{}-{log_idx}-{}",
+            std::process::id(),
+            program.to_string_lossy()
+        );
+        // the mmseqs modules are the first argument
+        if let Some(module) = command.get_args().next() {
+            let module = module.to_string_lossy();
+            if !module.starts_with('-') {
+                name.push_str(&format!("-{module}"));
+            }
+        }
+
+        logs_dir.join(format!("{name}.log"))
+    }
+}
+
+impl ExternalRunner for ProcessRunner {
+    fn run(&self, command: &mut Command) -> Result<()> {
+        match &self.logs_dir {
+            Some(logs_dir) => {
+                if !logs_dir.exists() {
+                    eprintln!(
+                        "writing the logs of the external tools to {}",
+                        logs_dir.to_string_lossy()
+                    );
+                    create_dir_all(logs_dir).context("failed to create logs directory")?;
+                }
+                command.run_logged(&self.log_path(logs_dir, command))
+            }
+            None => command.run(),
+        }
+    }
+}
+
+/// A command as seen by the RecordingRunner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl RecordedCommand {
+    fn new(command: &Command) -> Self {
+        let lossy = |s: &OsStr| s.to_string_lossy().to_string();
+        RecordedCommand {
+            program: lossy(command.get_program()),
+            args: command.get_args().map(lossy).collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for RecordedCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {arg}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Records the commands instead of running them.
+#[derive(Default)]
+pub struct RecordingRunner {
+    commands: Mutex<Vec<RecordedCommand>>,
+}
+
+impl RecordingRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The commands that have been run so far, in order.
+    pub fn commands(&self) -> Vec<RecordedCommand> {
+        self.commands.lock().unwrap().clone()
+    }
+}
+
+impl ExternalRunner for RecordingRunner {
+    fn run(&self, command: &mut Command) -> Result<()> {
+        self.commands
+            .lock()
+            .unwrap()
+            .push(RecordedCommand::new(command));
+        Ok(())
+    }
+}
+
+/// The runner shared by the pipeline steps, which defaults to running real processes.
+#[derive(Clone)]
+pub struct Runner(Arc<dyn ExternalRunner>);
+
+impl Runner {
+    pub fn new(runner: impl ExternalRunner + 'static) -> Self {
+        Runner(Arc::new(runner))
+    }
+
+    pub fn shared(runner: Arc<dyn ExternalRunner>) -> Self {
+        Runner(runner)
+    }
+
+    pub fn run(&self, command: &mut Command) -> Result<()> {
+        self.0.run(command)
+    }
+
+    pub fn run_to_file(&self, command: &mut Command, out: &Path) -> Result<()> {
+        self.0.run_to_file(command, out)
+    }
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Runner::new(ProcessRunner::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_command_gets_a_log_of_its_own() {
+        let logs_dir = Path::new("logs");
+        let runner = ProcessRunner::new(Some(logs_dir.to_path_buf()));
+        let mut prefilter = Command::new("/opt/mmseqs/bin/mmseqs");
+        prefilter.args(["prefilter", "queryDB", "targetDB", "prefilterDB"]);
+
+        let first = runner.log_path(logs_dir, &prefilter);
+        let second = runner.log_path(logs_dir, &prefilter);
+        assert_ne!(first, second);
+
+        let pid = std::process::id();
+        for (log_idx, log) in [first, second].iter().enumerate() {
+            let name = log.file_name().unwrap().to_string_lossy();
+            assert!(
+                name.ends_with(&format!("-{pid}-{log_idx}-mmseqs-prefilter.log")),
+                "{name}"
+            );
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use clap::ValueEnum;
+
+/// The seed used to shuffle the work for the random schedule, so that runs are repeatable
+const SHUFFLE_SEED: u64 = 0x9e3779b97f4a7c15;
+
+/// The order that the seeds are handed out to the align workers in, a group of
+/// one profile and target length at a time
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+pub enum Schedule {
+    /// Longest processing time first: the groups of seeds with the most DP cells go first
+    Lpt,
+    /// The order of the seeds file
+    #[default]
+    Fifo,
+    /// A fixed pseudo-random order
+    Random,
+}
+
+impl Schedule {
+    /// Order the work, given a function that estimates the work of an item.
+    pub fn order<T>(&self, items: &mut [T], expected_work: impl Fn(&T) -> usize) {
+        match self {
+            // the sort is stable, so ties keep the seeds file order
+            Schedule::Lpt => items.sort_by_key(|item| std::cmp::Reverse(expected_work(item))),
+            Schedule::Fifo => {}
+            Schedule::Random => shuffle(items),
+        }
+    }
+}
+
+/// A Fisher-Yates shuffle driven by a xorshift generator
+fn shuffle<T>(items: &mut [T]) {
+    let mut state = SHUFFLE_SEED;
+    for idx in (1..items.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        items.swap(idx, (state % (idx as u64 + 1)) as usize);
+    }
+}
+
+/// Work that is shared out between workers in order, each item to a single worker.
+///
+/// A worker takes the next item when it's done with its last one, so with the
+/// longest items first, the workers all run out of work at about the same time.
+pub struct WorkQueue<'a, T> {
+    items: &'a [T],
+    next_idx: AtomicUsize,
+}
+
+impl<'a, T> WorkQueue<'a, T> {
+    pub fn new(items: &'a [T]) -> Self {
+        WorkQueue {
+            items,
+            next_idx: AtomicUsize::new(0),
+        }
+    }
+
+    /// Take the next item of work, if there is any left.
+    pub fn take(&self) -> Option<&'a T> {
+        self.items
+            .get(self.next_idx.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+    use std::thread;
+
+    #[test]
+    fn lpt_puts_the_most_work_first_and_keeps_ties_in_order() {
+        let mut items = vec![(0, 5), (1, 20), (2, 5), (3, 50), (4, 20)];
+        Schedule::Lpt.order(&mut items, |(_, work)| *work);
+        let order: Vec<usize> = items.iter().map(|(idx, _)| *idx).collect();
+        assert_eq!(order, vec![3, 1, 4, 0, 2]);
+    }
+
+    #[test]
+    fn fifo_keeps_the_order() {
+        let mut items: Vec<usize> = (0..10).collect();
+        Schedule::Fifo.order(&mut items, |item| *item);
+        assert_eq!(items, (0..10).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn random_is_a_repeatable_shuffle() {
+        let mut items: Vec<usize> = (0..100).collect();
+        Schedule::Random.order(&mut items, |item| *item);
+        assert_ne!(items, (0..100).collect::<Vec<usize>>());
+
+        let mut again: Vec<usize> = (0..100).collect();
+        Schedule::Random.order(&mut again, |item| *item);
+        assert_eq!(items, again);
+
+        items.sort();
+        assert_eq!(items, (0..100).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn the_items_are_taken_in_order_until_they_run_out() {
+        let items = vec!["a", "b", "c"];
+        let queue = WorkQueue::new(&items);
+        assert_eq!(queue.take(), Some(&"a"));
+        assert_eq!(queue.take(), Some(&"b"));
+        assert_eq!(queue.take(), Some(&"c"));
+        assert_eq!(queue.take(), None);
+        assert_eq!(queue.take(), None);
+    }
+
+    #[test]
+    fn each_item_goes_to_one_worker() {
+        let items: Vec<usize> = (0..1000).collect();
+        let queue = WorkQueue::new(&items);
+        let taken = Mutex::new(vec![]);
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    while let Some(item) = queue.take() {
+                        taken.lock().unwrap().push(*item);
+                    }
+                });
+            }
+        });
+
+        let taken = taken.into_inner().unwrap();
+        assert_eq!(taken.len(), items.len());
+        assert_eq!(
+            taken.into_iter().collect::<HashSet<usize>>().len(),
+            items.len()
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use nale::align::bounded::structs::Seed;
+
+use crate::alignment_output::SeedProvenance;
+use crate::seed_stats::SeedsByAccession;
+
+/// The number of seeds that were chained, and the number of chains they made
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ChainCounts {
+    pub seeds: usize,
+    pub chains: usize,
+}
+
+/// Whether a seed continues a chain that ends with another: it must move forward
+/// on both the profile and the target, and start within the gap tolerance of
+/// where the other ends. Seeds may overlap, as mmseqs fragments often do.
+fn continues(last: &Seed, next: &Seed, max_gap: usize) -> bool {
+    next.target_name == last.target_name
+        && next.profile_start > last.profile_start
+        && next.profile_end > last.profile_end
+        && next.target_start > last.target_start
+        && next.target_end > last.target_end
+        && next.profile_start <= last.profile_end + 1 + max_gap
+        && next.target_start <= last.target_end + 1 + max_gap
+}
+
+/// Join a seed onto the end of a chain.
+///
+/// The chain keeps the best E-value and bit score of its seeds, and its
+/// coordinates span all of them.
+fn extend(chain: &mut (Seed, SeedProvenance), next: &(Seed, SeedProvenance)) {
+    let (seed, provenance) = chain;
+    let (next_seed, next_provenance) = next;
+
+    seed.profile_end = next_seed.profile_end;
+    seed.target_end = next_seed.target_end;
+    provenance.profile_end = next_provenance.profile_end;
+    provenance.target_end = next_provenance.target_end;
+    provenance.evalue = provenance.evalue.min(next_provenance.evalue);
+    provenance.bit_score = match (provenance.bit_score, next_provenance.bit_score) {
+        (Some(bit_score), Some(next_bit_score)) => Some(bit_score.max(next_bit_score)),
+        (bit_score, next_bit_score) => bit_score.or(next_bit_score),
+    };
+}
+
+/// Chain the collinear seeds of each query and target into single seeds, so
+/// that the fragments mmseqs reports for a divergent domain are aligned as one
+/// seed, whose cloud search starts from the whole of the domain.
+pub fn chain_seeds(seeds_by_accession: &mut SeedsByAccession, max_gap: usize) -> ChainCounts {
+    let mut counts = ChainCounts::default();
+
+    for seeds in seeds_by_accession.values_mut() {
+        // each chain takes the place of its first seed in the seeds file order
+        let mut sorted: Vec<(usize, (Seed, SeedProvenance))> =
+            std::mem::take(seeds).into_iter().enumerate().collect();
+        sorted.sort_by(|(_, (a, _)), (_, (b, _))| {
+            (&a.target_name, a.target_start, a.profile_start).cmp(&(
+                &b.target_name,
+                b.target_start,
+                b.profile_start,
+            ))
+        });
+
+        let mut chains: Vec<(usize, (Seed, SeedProvenance), usize)> = vec![];
+        for (seed_idx, seed) in sorted {
+            match chains.last_mut() {
+                Some((first_idx, chain, chain_length)) if continues(&chain.0, &seed.0, max_gap) => {
+                    extend(chain, &seed);
+                    *first_idx = (*first_idx).min(seed_idx);
+                    *chain_length += 1;
+                }
+                _ => chains.push((seed_idx, seed, 1)),
+            }
+        }
+
+        for (_, _, chain_length) in &chains {
+            if *chain_length > 1 {
+                counts.seeds += chain_length;
+                counts.chains += 1;
+            }
+        }
+
+        chains.sort_by_key(|(first_idx, _, _)| *first_idx);
+        *seeds = chains.into_iter().map(|(_, chain, _)| chain).collect();
+    }
+
+    counts
+}
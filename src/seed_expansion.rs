@@ -0,0 +1,115 @@
+use nale::align::bounded::structs::Seed;
+use nale::structs::Alignment;
+
+/// How far the seeds are padded on each side before the cloud search.
+///
+/// mmseqs' local alignments often stop short of the ends of a domain, and the
+/// cloud search doesn't always recover the rest, so a padded seed gives it a
+/// head start.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeedExpansion {
+    /// A number of residues (or profile positions)
+    Residues(usize),
+    /// A fraction of the seed's own length
+    Fraction(f32),
+}
+
+/// Parse a seed expansion: a whole number of residues, or a fraction of the seed's length.
+pub fn parse_seed_expansion(value: &str) -> Result<SeedExpansion, String> {
+    if let Ok(residues) = value.parse::<usize>() {
+        return Ok(SeedExpansion::Residues(residues));
+    }
+    match value.parse::<f32>() {
+        Ok(fraction) if fraction.is_finite() && fraction >= 0.0 => {
+            Ok(SeedExpansion::Fraction(fraction))
+        }
+        _ => Err(format!(
+            "{value} is neither a number of residues nor a fraction of the seed length"
+        )),
+    }
+}
+
+impl SeedExpansion {
+    fn padding(&self, span_length: usize) -> usize {
+        match self {
+            SeedExpansion::Residues(residues) => *residues,
+            SeedExpansion::Fraction(fraction) => (*fraction * span_length as f32).round() as usize,
+        }
+    }
+
+    /// Pad a seed, keeping it within the profile and the target.
+    pub fn expand(&self, seed: &Seed, profile_length: usize, target_length: usize) -> Seed {
+        let pad = |start: usize, end: usize, length: usize| -> (usize, usize) {
+            let padding = self.padding(end + 1 - start.min(end));
+            (
+                start.saturating_sub(padding).max(1),
+                (end + padding).min(length),
+            )
+        };
+        let (profile_start, profile_end) =
+            pad(seed.profile_start, seed.profile_end, profile_length);
+        let (target_start, target_end) = pad(seed.target_start, seed.target_end, target_length);
+
+        Seed {
+            target_name: seed.target_name.clone(),
+            target_start,
+            target_end,
+            profile_start,
+            profile_end,
+        }
+    }
+}
+
+/// How often the hits of expanded seeds reach the edge of the expansion.
+///
+/// A hit that runs into the edge may have been held back by it, so a high
+/// rate suggests a larger --seed-expand.
+#[derive(Debug, Default)]
+pub struct ExpansionCounts {
+    pub hits: usize,
+    pub at_boundary: usize,
+}
+
+impl ExpansionCounts {
+    /// Add in the counts of another worker.
+    pub fn add(&mut self, other: &ExpansionCounts) {
+        self.hits += other.hits;
+        self.at_boundary += other.at_boundary;
+    }
+
+    /// Count a hit of an expanded seed. The ends of the profile and target aren't
+    /// a boundary of the expansion, since there is nothing past them to reach.
+    pub fn count(
+        &mut self,
+        alignment: &Alignment,
+        expanded: &Seed,
+        profile_length: usize,
+        target_length: usize,
+    ) {
+        // an alignment that starts or ends past the padded seed ran into it, too
+        let reaches_start = |aligned: usize, boundary: usize| boundary > 1 && aligned <= boundary;
+        let reaches_end = |aligned: usize, boundary: usize, length: usize| {
+            boundary < length && aligned >= boundary
+        };
+
+        self.hits += 1;
+        if reaches_start(alignment.profile_start, expanded.profile_start)
+            || reaches_end(alignment.profile_end, expanded.profile_end, profile_length)
+            || reaches_start(alignment.target_start, expanded.target_start)
+            || reaches_end(alignment.target_end, expanded.target_end, target_length)
+        {
+            self.at_boundary += 1;
+        }
+    }
+
+    pub fn print(&self) {
+        if self.hits > 0 {
+            eprintln!(
+                "seed expansion: {} of {} hits reached the edge of their expanded seed ({:.1}%)",
+                self.at_boundary,
+                self.hits,
+                100.0 * self.at_boundary as f32 / self.hits as f32
+            );
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::fs::{read_dir, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use anyhow::{Context, Result};
+use thiserror::Error;
+
+use crate::Args;
+
+/// The first two bytes of every gzip file
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Error, Debug)]
+#[error("the seeds directory {0} has no seeds files")]
+pub struct EmptySeedsDirError(String);
+
+/// The seeds files to read: the files given to align, then the files in the
+/// --seeds-dir directory in name order, or else the one the seed step wrote.
+pub fn seed_files(args: &Args) -> Result<Vec<PathBuf>> {
+    let mut files = args.paths.seed_files.clone();
+
+    if let Some(dir) = &args.paths.seeds_dir {
+        let mut dir_files: Vec<PathBuf> = vec![];
+        let entries = read_dir(dir)
+            .with_context(|| format!("failed to read seeds directory: {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            let hidden = path
+                .file_name()
+                .map(|name| name.to_string_lossy().starts_with('.'))
+                .unwrap_or(true);
+            if path.is_file() && !hidden {
+                dir_files.push(path);
+            }
+        }
+        if dir_files.is_empty() {
+            return Err(EmptySeedsDirError(dir.display().to_string()).into());
+        }
+        dir_files.sort();
+        files.extend(dir_files);
+    }
+
+    if files.is_empty() {
+        files.push(args.paths.seeds.clone());
+    }
+    Ok(files)
+}
+
+/// Reads a gzipped file through gzip, so that it is decompressed as it is read.
+struct GzipReader {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for GzipReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read_count = self.stdout.read(buf)?;
+        // a truncated or corrupt file only shows up in gzip's exit status
+        if read_count == 0 && !buf.is_empty() && !self.child.wait()?.success() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "gzip failed to decompress the file",
+            ));
+        }
+        Ok(read_count)
+    }
+}
+
+impl Drop for GzipReader {
+    fn drop(&mut self) {
+        // a reader that stops early leaves gzip blocked on a full pipe
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Open a seeds file for reading, decompressing it if it is gzipped.
+pub fn open_seeds_file(path: &Path) -> Result<Box<dyn BufRead>> {
+    let open = || {
+        File::open(path)
+            .with_context(|| format!("failed to open seeds file: {}", path.to_string_lossy()))
+    };
+
+    let mut magic = [0u8; 2];
+    let is_gzipped = open()?.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+    if !is_gzipped {
+        return Ok(Box::new(BufReader::new(open()?)));
+    }
+
+    let mut child = Command::new("gzip")
+        .arg("-dc")
+        .stdin(open()?)
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("failed to run gzip to decompress the seeds file")?;
+    let stdout = child.stdout.take().expect("gzip's stdout is piped");
+    Ok(Box::new(BufReader::new(GzipReader { child, stdout })))
+}
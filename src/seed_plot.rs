@@ -0,0 +1,124 @@
+use std::io::Write;
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::number_format::NumberFormat;
+use crate::tabular::Table;
+
+#[derive(Error, Debug)]
+#[error("results table is missing the column: {0}")]
+pub struct MissingResultsColumnError(String);
+
+/// What a row of the plot data describes
+#[derive(Debug, Clone, Copy)]
+pub enum PlotFeature {
+    /// An mmseqs seed, in query HMM coordinates
+    Seed,
+    /// The final alignment of a hit
+    Alignment,
+    /// The envelope of a hit; its profile range is that of the alignment
+    Envelope,
+}
+
+impl PlotFeature {
+    fn name(&self) -> &'static str {
+        match self {
+            PlotFeature::Seed => "seed",
+            PlotFeature::Alignment => "alignment",
+            PlotFeature::Envelope => "envelope",
+        }
+    }
+}
+
+/// One rectangle in the profile × target plane
+pub struct PlotRect {
+    pub feature: PlotFeature,
+    pub profile_name: String,
+    pub target_name: String,
+    pub profile_start: usize,
+    pub profile_end: usize,
+    pub target_start: usize,
+    pub target_end: usize,
+    pub evalue: f32,
+}
+
+impl PlotRect {
+    /// Build the alignment and envelope rectangles of each hit in a results table.
+    pub fn from_results(table: &Table) -> Result<Vec<PlotRect>> {
+        let column = |header: &str| {
+            table
+                .headers
+                .iter()
+                .position(|h| h == header)
+                .ok_or_else(|| MissingResultsColumnError(header.to_string()))
+        };
+
+        let target_name = column("target name")?;
+        let profile_name = column("profile name")?;
+        let target_start = column("target start")?;
+        let target_end = column("target end")?;
+        let profile_start = column("profile start")?;
+        let profile_end = column("profile end")?;
+        let evalue = column("e-value")?;
+        let env_start = column("env start")?;
+        let env_end = column("env end")?;
+
+        let mut rects: Vec<PlotRect> = vec![];
+        for row in &table.rows {
+            let alignment = PlotRect {
+                feature: PlotFeature::Alignment,
+                profile_name: row[profile_name].clone(),
+                target_name: row[target_name].clone(),
+                profile_start: row[profile_start].parse()?,
+                profile_end: row[profile_end].parse()?,
+                target_start: row[target_start].parse()?,
+                target_end: row[target_end].parse()?,
+                evalue: row[evalue].parse()?,
+            };
+
+            let envelope = PlotRect {
+                feature: PlotFeature::Envelope,
+                profile_name: alignment.profile_name.clone(),
+                target_name: alignment.target_name.clone(),
+                target_start: row[env_start].parse()?,
+                target_end: row[env_end].parse()?,
+                ..alignment
+            };
+
+            rects.push(alignment);
+            rects.push(envelope);
+        }
+
+        Ok(rects)
+    }
+}
+
+/// Write the rectangles as a tidy TSV, with one rectangle per row.
+pub fn write_plot_data(
+    rects: &[PlotRect],
+    format: &NumberFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    writeln!(
+        out,
+        "feature\tprofile\ttarget\tprofile_start\tprofile_end\ttarget_start\ttarget_end\tevalue"
+    )?;
+
+    for rect in rects {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            rect.feature.name(),
+            rect.profile_name,
+            rect.target_name,
+            rect.profile_start,
+            rect.profile_end,
+            rect.target_start,
+            rect.target_end,
+            format.evalue(rect.evalue)
+        )?;
+    }
+
+    Ok(())
+}
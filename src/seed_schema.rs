@@ -0,0 +1,206 @@
+use std::io::{BufRead, Lines};
+use std::iter::Enumerate;
+use std::num::NonZeroUsize;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The schema name on the header line of a JSON seeds file
+pub const SEED_SCHEMA_NAME: &str = "mmoreseqs-seeds";
+
+/// The version of the JSON seeds schema read by this version of mmoreseqs
+pub const SEED_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+#[error("seeds file line {line}, column {column}: {message}")]
+pub struct InvalidSeedRecordError {
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+#[derive(Error, Debug)]
+#[error("seeds file line {line}: {field}")]
+pub struct InvalidSeedCoordsError {
+    line: usize,
+    field: String,
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "the JSON seeds file doesn't start with a {SEED_SCHEMA_NAME} header line, \
+     e.g. {{\"schema\": \"{SEED_SCHEMA_NAME}\", \"version\": {SEED_SCHEMA_VERSION}}}"
+)]
+pub struct MissingSeedSchemaError;
+
+#[derive(Error, Debug)]
+#[error("seeds schema version {0} is newer than this version of mmoreseqs supports")]
+pub struct UnsupportedSeedSchemaError(u32);
+
+/// The first line of a JSON seeds file, which names the schema and its version
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedSchemaHeader {
+    pub schema: String,
+    pub version: u32,
+}
+
+/// A 1-based sequence or profile position
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Coord(NonZeroUsize);
+
+impl Coord {
+    pub fn get(&self) -> usize {
+        self.0.get()
+    }
+}
+
+/// The tool that found a seed
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SeedSource {
+    Mmseqs,
+    Hmmer,
+    Other,
+}
+
+/// A seed in a JSON seeds file.
+///
+/// The coordinates are 1-based and inclusive, and the start of each range
+/// may not come after its end. The query coordinates are positions in the
+/// query's consensus sequence, as mmseqs reports them.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedRecord {
+    /// The accession of the query profile
+    pub query: String,
+    pub target: String,
+    pub query_start: Coord,
+    pub query_end: Coord,
+    pub target_start: Coord,
+    pub target_end: Coord,
+    pub evalue: f32,
+    #[serde(default)]
+    pub bit_score: Option<f32>,
+    pub source: SeedSource,
+}
+
+impl SeedRecord {
+    /// Check the values that the types alone don't rule out.
+    fn validate(&self, line: usize) -> Result<()> {
+        let invalid =
+            |field: String| -> Result<()> { Err(InvalidSeedCoordsError { line, field }.into()) };
+
+        if self.query_start > self.query_end {
+            return invalid(format!(
+                "query_start {} is after query_end {}",
+                self.query_start.get(),
+                self.query_end.get()
+            ));
+        }
+        if self.target_start > self.target_end {
+            return invalid(format!(
+                "target_start {} is after target_end {}",
+                self.target_start.get(),
+                self.target_end.get()
+            ));
+        }
+        if self.evalue < 0.0 {
+            return invalid(format!("evalue {} is negative", self.evalue));
+        }
+        Ok(())
+    }
+}
+
+/// Turn a serde_json error on a line into one that names the line of the file.
+fn record_error(error: serde_json::Error, line: usize) -> InvalidSeedRecordError {
+    // each line is parsed alone, so serde_json's own position is always line 1
+    let message = error.to_string();
+    let message = match message.rfind(" at line ") {
+        Some(position_idx) => message[..position_idx].to_string(),
+        None => message,
+    };
+    InvalidSeedRecordError {
+        line,
+        column: error.column(),
+        message,
+    }
+}
+
+/// The seeds of a JSON seeds file, read a line at a time: JSON Lines with a
+/// schema header line, then one seed per line. Blank lines are skipped.
+pub struct SeedRecords<R: BufRead> {
+    lines: Enumerate<Lines<R>>,
+    header_read: bool,
+}
+
+impl<R: BufRead> SeedRecords<R> {
+    pub fn new(reader: R) -> Self {
+        SeedRecords {
+            lines: reader.lines().enumerate(),
+            header_read: false,
+        }
+    }
+
+    fn check_header(line: &str) -> Result<()> {
+        let header: SeedSchemaHeader =
+            serde_json::from_str(line).map_err(|_| MissingSeedSchemaError)?;
+        if header.schema != SEED_SCHEMA_NAME {
+            return Err(MissingSeedSchemaError.into());
+        }
+        if header.version > SEED_SCHEMA_VERSION {
+            return Err(UnsupportedSeedSchemaError(header.version).into());
+        }
+        Ok(())
+    }
+
+    fn parse_record(line: &str, line_number: usize) -> Result<SeedRecord> {
+        let record: SeedRecord =
+            serde_json::from_str(line).map_err(|error| record_error(error, line_number))?;
+        record.validate(line_number)?;
+        Ok(record)
+    }
+}
+
+impl<R: BufRead> Iterator for SeedRecords<R> {
+    type Item = Result<SeedRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some((line_idx, line)) = self.lines.next() else {
+                // an empty file has no header either
+                if !self.header_read {
+                    self.header_read = true;
+                    return Some(Err(MissingSeedSchemaError.into()));
+                }
+                return None;
+            };
+
+            let line_number = line_idx + 1;
+            let line =
+                match line {
+                    Ok(line) => line,
+                    Err(error) => {
+                        return Some(Err(error).with_context(|| {
+                            format!("failed to read seeds file line {line_number}")
+                        }))
+                    }
+                };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if !self.header_read {
+                self.header_read = true;
+                if let Err(error) = Self::check_header(&line) {
+                    return Some(Err(error));
+                }
+                continue;
+            }
+
+            return Some(Self::parse_record(&line, line_number));
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use nale::align::bounded::structs::Seed;
+
+use crate::alignment_output::SeedProvenance;
+
+/// The upper edges of the seed E-value histogram bins; the last bin holds everything above
+const EVALUE_BIN_EDGES: [f32; 7] = [1e-50, 1e-20, 1e-10, 1e-5, 1e-3, 1e-1, 1.0];
+
+/// The number of profiles listed in the per-profile seed counts
+const TOP_PROFILE_COUNT: usize = 5;
+
+/// The widest that a histogram bar may be drawn
+const MAX_BAR_WIDTH: usize = 40;
+
+/// The seeds read from a seeds file, grouped by profile accession
+pub type SeedsByAccession = HashMap<String, Vec<(Seed, SeedProvenance)>>;
+
+/// Limits applied to the seeds as they are imported
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SeedFilter {
+    /// Drop any seed with an E-value above this
+    pub max_evalue: Option<f32>,
+    /// Keep only this many of each profile's best seeds
+    pub keep_top: Option<usize>,
+}
+
+impl SeedFilter {
+    /// Apply the filter to the seeds, returning the number of seeds that were dropped.
+    pub fn apply(&self, seeds_by_accession: &mut SeedsByAccession) -> usize {
+        let mut dropped_count = 0;
+
+        for seeds in seeds_by_accession.values_mut() {
+            let before = seeds.len();
+
+            if let Some(max_evalue) = self.max_evalue {
+                seeds.retain(|(_, provenance)| provenance.evalue <= max_evalue);
+            }
+
+            if let Some(keep_top) = self.keep_top {
+                seeds.sort_by(|(_, a), (_, b)| a.evalue.total_cmp(&b.evalue));
+                seeds.truncate(keep_top);
+            }
+
+            dropped_count += before - seeds.len();
+        }
+
+        dropped_count
+    }
+}
+
+/// Print a histogram of the seed E-values and the profiles with the most seeds
+/// to stderr, where it doesn't mix with results written to stdout.
+pub fn print_seed_stats(seeds_by_accession: &SeedsByAccession) {
+    let total: usize = seeds_by_accession.values().map(|s| s.len()).sum();
+    eprintln!(
+        "imported {total} seeds for {} profiles",
+        seeds_by_accession.len()
+    );
+    if total == 0 {
+        return;
+    }
+
+    let mut bin_counts = [0usize; EVALUE_BIN_EDGES.len() + 1];
+    for (_, provenance) in seeds_by_accession.values().flatten() {
+        let bin_idx = EVALUE_BIN_EDGES
+            .iter()
+            .position(|&edge| provenance.evalue <= edge)
+            .unwrap_or(EVALUE_BIN_EDGES.len());
+        bin_counts[bin_idx] += 1;
+    }
+
+    let max_bin_count = *bin_counts.iter().max().unwrap_or(&1);
+    eprintln!("seed E-values:");
+    for (bin_idx, count) in bin_counts.iter().enumerate() {
+        let label = match EVALUE_BIN_EDGES.get(bin_idx) {
+            Some(edge) => format!("<= {edge:.0e}"),
+            None => format!(" > {:.0e}", EVALUE_BIN_EDGES[EVALUE_BIN_EDGES.len() - 1]),
+        };
+        let bar_width = (count * MAX_BAR_WIDTH).div_ceil(max_bin_count);
+        eprintln!("  {label:>8} {count:>9} {}", "#".repeat(bar_width));
+    }
+
+    let mut counts: Vec<(&String, usize)> = seeds_by_accession
+        .iter()
+        .map(|(accession, seeds)| (accession, seeds.len()))
+        .collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    eprintln!("profiles with the most seeds:");
+    for (accession, count) in counts.iter().take(TOP_PROFILE_COUNT) {
+        eprintln!("  {accession}: {count}");
+    }
+}
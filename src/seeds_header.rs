@@ -0,0 +1,303 @@
+use std::fs::{read_to_string, rename, File};
+use std::io::{copy, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use nale::output::path_buf_ext::PathBufExt;
+use thiserror::Error;
+
+use crate::pipeline::pressed_hmm_db_base;
+use crate::seed_files::open_seeds_file;
+use crate::Args;
+
+/// The version of the seeds file layout written by this version of mmoreseqs
+pub const SEEDS_FORMAT_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+#[error("seeds file format version {0} is newer than this version of mmoreseqs supports")]
+pub struct UnsupportedSeedsFormatError(u32);
+
+#[derive(Error, Debug)]
+#[error(
+    "the seeds were generated from a different {input} than {path} (use --force to align anyway)"
+)]
+pub struct SeedsInputMismatchError {
+    input: &'static str,
+    path: String,
+}
+
+/// The initial value of a 64-bit FNV-1a hash
+pub const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+/// Continue a 64-bit FNV-1a hash over some bytes.
+pub fn fnv1a64(mut hash: u64, bytes: &[u8]) -> u64 {
+    const FNV_PRIME: u64 = 0x100000001b3;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Compute the 64-bit FNV-1a checksum of a file's contents.
+pub fn checksum_file(path: &Path) -> Result<String> {
+    let file = File::open(path).context(format!(
+        "failed to open file for checksum: {}",
+        path.to_string_lossy()
+    ))?;
+
+    let mut reader = BufReader::new(file);
+    let mut buffer = [0u8; 1 << 16];
+    let mut hash = FNV_OFFSET_BASIS;
+    loop {
+        let read_count = reader.read(&mut buffer)?;
+        if read_count == 0 {
+            break;
+        }
+        hash = fnv1a64(hash, &buffer[..read_count]);
+    }
+
+    Ok(format!("fnv1a64:{hash:016x}"))
+}
+
+/// The checksum of the query HMM, if it has been built.
+pub fn query_hmm_checksum(args: &Args) -> Result<Option<String>> {
+    match args.paths.query_hmm.exists() {
+        true => Ok(Some(checksum_file(&args.paths.query_hmm)?)),
+        false => Ok(None),
+    }
+}
+
+/// The checksum of the target fasta, which is recorded in the workspace when it is prepared.
+pub fn target_checksum(args: &Args) -> Result<Option<String>> {
+    match args.paths.target_checksum.exists() {
+        true => Ok(Some(
+            read_to_string(&args.paths.target_checksum)?
+                .trim()
+                .to_string(),
+        )),
+        false => Ok(None),
+    }
+}
+
+/// The provenance of a seeds file, written as '#' lines at the top of the file.
+///
+/// Seeds files written before the header was added have none of these fields.
+#[derive(Debug, Default)]
+pub struct SeedsHeader {
+    pub format_version: Option<u32>,
+    pub tool_version: Option<String>,
+    /// The checksum of the query HMM that the seeds were mapped from
+    pub query_hmm: Option<String>,
+    /// The checksum of the target fasta that the target database was built from
+    pub target: Option<String>,
+}
+
+impl SeedsHeader {
+    /// Describe the inputs of the seed step that is running with these arguments.
+    pub fn new(args: &Args) -> Result<Self> {
+        Ok(SeedsHeader {
+            format_version: Some(SEEDS_FORMAT_VERSION),
+            tool_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            query_hmm: query_hmm_checksum(args)?,
+            target: target_checksum(args)?,
+        })
+    }
+
+    /// Read the header from the leading '#' lines of a seeds file.
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut header = SeedsHeader::default();
+
+        for line in open_seeds_file(path)?.lines() {
+            let line = line?;
+            let Some(line) = line.strip_prefix('#') else {
+                break;
+            };
+
+            let mut tokens = line.split_whitespace();
+            let (Some(key), Some(value)) = (tokens.next(), tokens.next()) else {
+                continue;
+            };
+
+            match key {
+                "seeds-format" => header.format_version = value.parse().ok(),
+                "mmoreseqs-version" => header.tool_version = Some(value.to_string()),
+                "query-hmm" => header.query_hmm = Some(value.to_string()),
+                "target" => header.target = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        Ok(header)
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> Result<()> {
+        let or_dash = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+
+        writeln!(
+            out,
+            "#seeds-format\t{}",
+            self.format_version.unwrap_or(SEEDS_FORMAT_VERSION)
+        )?;
+        writeln!(out, "#mmoreseqs-version\t{}", or_dash(&self.tool_version))?;
+        writeln!(out, "#query-hmm\t{}", or_dash(&self.query_hmm))?;
+        writeln!(out, "#target\t{}", or_dash(&self.target))?;
+
+        Ok(())
+    }
+
+    /// Check that the seeds were generated from the query HMM and target
+    /// that were supplied to the align step.
+    ///
+    /// Inputs that either side didn't record are not checked, and neither is
+    /// a pressed HMM database, since its bytes differ from the flat file's.
+    /// With --force, a mismatch is only reported as a warning.
+    pub fn check(&self, args: &Args) -> Result<()> {
+        if let Some(format_version) = self.format_version {
+            if format_version > SEEDS_FORMAT_VERSION {
+                return Err(UnsupportedSeedsFormatError(format_version).into());
+            }
+        }
+
+        let inputs = [
+            ("query HMM", &self.query_hmm, &args.paths.query_hmm),
+            ("target", &self.target, &args.paths.target_fasta),
+        ];
+
+        for (input, recorded, path) in inputs {
+            let Some(recorded) = recorded.as_ref().filter(|r| *r != "-") else {
+                continue;
+            };
+            if !path.is_file() || pressed_hmm_db_base(path).is_some() {
+                continue;
+            }
+
+            if *recorded != checksum_file(path)? {
+                let mismatch = SeedsInputMismatchError {
+                    input,
+                    path: path.to_string_lossy().to_string(),
+                };
+                match args.force {
+                    true => eprintln!("warning: {mismatch}"),
+                    false => return Err(mismatch.into()),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Prepend the header to a seeds file written by mmseqs convertalis.
+///
+/// The seeds are copied after the header into a new file that then replaces
+/// the original, so that a large seeds file is never read into memory.
+pub fn prepend_seeds_header(args: &Args) -> Result<()> {
+    let tmp_path = args.paths.seeds.with_extension("tmp");
+    {
+        let mut out = tmp_path.open(true)?;
+        SeedsHeader::new(args)?.write(&mut out)?;
+        let mut seeds = File::open(&args.paths.seeds).context(format!(
+            "failed to open seeds file: {}",
+            args.paths.seeds.to_string_lossy()
+        ))?;
+        copy(&mut seeds, &mut out)?;
+        out.flush()?;
+    }
+    rename(&tmp_path, &args.paths.seeds)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::temp_file;
+    use std::fs::remove_file;
+
+    #[test]
+    fn checksums_are_fnv1a_of_the_contents() {
+        let empty = temp_file("empty", "");
+        let a = temp_file("a", "a");
+        assert_eq!(checksum_file(&empty).unwrap(), "fnv1a64:cbf29ce484222325");
+        assert_eq!(checksum_file(&a).unwrap(), "fnv1a64:af63dc4c8601ec8c");
+        remove_file(empty).unwrap();
+        remove_file(a).unwrap();
+    }
+
+    #[test]
+    fn headers_are_read_back_as_written() {
+        let header = SeedsHeader {
+            format_version: Some(1),
+            tool_version: Some("0.1.0".to_string()),
+            query_hmm: Some("fnv1a64:0123456789abcdef".to_string()),
+            target: None,
+        };
+        let mut bytes: Vec<u8> = vec![];
+        header.write(&mut bytes).unwrap();
+        bytes.extend(b"query\ttarget\t1\t10\t1\t10\t1e-5\t20.1\n#not-header\tx\n");
+
+        let path = temp_file("round-trip.tsv", &String::from_utf8(bytes).unwrap());
+        let read = SeedsHeader::read(&path).unwrap();
+        remove_file(path).unwrap();
+
+        assert_eq!(read.format_version, Some(1));
+        assert_eq!(read.tool_version.as_deref(), Some("0.1.0"));
+        assert_eq!(read.query_hmm.as_deref(), Some("fnv1a64:0123456789abcdef"));
+        // an input that wasn't recorded is written as a dash
+        assert_eq!(read.target.as_deref(), Some("-"));
+    }
+
+    #[test]
+    fn seeds_files_without_a_header_have_no_fields() {
+        let path = temp_file("headerless.tsv", "query\ttarget\t1\t10\t1\t10\t1e-5\n");
+        let read = SeedsHeader::read(&path).unwrap();
+        remove_file(path).unwrap();
+
+        assert_eq!(read.format_version, None);
+        assert_eq!(read.tool_version, None);
+        assert_eq!(read.query_hmm, None);
+        assert_eq!(read.target, None);
+    }
+
+    #[test]
+    fn newer_seeds_formats_are_refused() {
+        let header = SeedsHeader {
+            format_version: Some(SEEDS_FORMAT_VERSION + 1),
+            ..SeedsHeader::default()
+        };
+        let err = header.check(&Args::default()).unwrap_err();
+        assert!(err.downcast_ref::<UnsupportedSeedsFormatError>().is_some());
+    }
+
+    #[test]
+    fn mismatched_inputs_fail_unless_forced() {
+        let query_hmm = temp_file("query.hmm", "HMMER3/f\n");
+        let mut args = Args::default();
+        args.paths.query_hmm = query_hmm.clone();
+
+        let matching = SeedsHeader {
+            query_hmm: Some(checksum_file(&query_hmm).unwrap()),
+            ..SeedsHeader::default()
+        };
+        assert!(matching.check(&args).is_ok());
+
+        // an input recorded as a dash isn't checked
+        let unrecorded = SeedsHeader {
+            query_hmm: Some("-".to_string()),
+            ..SeedsHeader::default()
+        };
+        assert!(unrecorded.check(&args).is_ok());
+
+        let mismatched = SeedsHeader {
+            query_hmm: Some("fnv1a64:0000000000000000".to_string()),
+            ..SeedsHeader::default()
+        };
+        let err = mismatched.check(&args).unwrap_err();
+        assert!(err.downcast_ref::<SeedsInputMismatchError>().is_some());
+
+        args.force = true;
+        assert!(mismatched.check(&args).is_ok());
+        remove_file(query_hmm).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+use std::path::Path;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use nale::align::bounded::structs::CloudSearchParams;
+use nale::alphabet::AMINO_BACKGROUND_FREQUENCIES;
+use nale::structs::hmm::{parse_hmms_from_p7hmm_file, Hmm};
+
+/// The mean relative entropy, in bits per match state, that hmmbuild weights
+/// protein models to; the k-score thresholds are tuned for models near it
+const REFERENCE_INFORMATION: f32 = 0.59;
+
+/// Models shorter than this have few k-mers to find a target with
+const SHORT_MODEL_LENGTH: f32 = 50.0;
+
+/// With at most this many models, the prefilter is cheap enough to keep more hits per model
+const SMALL_QUERY_SET: usize = 100;
+
+/// With more than this many models, fewer hits are kept per model, so the align step stays tractable
+const LARGE_QUERY_SET: usize = 10_000;
+
+/// The trade-off between speed and sensitivity that the seed and align steps are tuned for
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Sensitivity {
+    /// Fewer, stronger seeds and narrow alignment clouds
+    Fast,
+    #[default]
+    Default,
+    /// More seeds and wider alignment clouds
+    Sensitive,
+    /// As many seeds as mmseqs will find, and the widest alignment clouds
+    Exhaustive,
+}
+
+/// The parameters of the mmseqs prefilter in the seed step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrefilterParams {
+    /// The k-mer length, or None to let mmseqs pick it
+    pub kmer_length: Option<u32>,
+    /// The k-mer score threshold, for profile queries
+    pub k_score: u32,
+    pub max_seqs: usize,
+    /// mmseqs' own sensitivity (-s), for sequence queries, where it picks the k-mer score
+    pub mmseqs_sensitivity: f32,
+}
+
+/// The statistics of a query set that the prefilter parameters are tuned to
+#[derive(Debug, Clone, Copy)]
+pub struct QueryStats {
+    pub model_count: usize,
+    /// The mean number of match states per model
+    pub mean_length: f32,
+    /// The mean relative entropy of the match states to the background, in bits
+    pub mean_information: f32,
+}
+
+impl QueryStats {
+    pub fn from_hmms(hmms: &[Hmm]) -> Option<Self> {
+        let mut state_count: usize = 0;
+        let mut information_sum: f32 = 0.0;
+        for hmm in hmms {
+            // the first row of the match probabilities is the begin state
+            for probabilities in hmm.model.match_probabilities.iter().skip(1) {
+                state_count += 1;
+                information_sum += probabilities
+                    .iter()
+                    .zip(AMINO_BACKGROUND_FREQUENCIES)
+                    .filter(|(&probability, _)| probability > 0.0)
+                    .map(|(&probability, background)| {
+                        probability * (probability / background).log2()
+                    })
+                    .sum::<f32>();
+            }
+        }
+
+        match state_count {
+            0 => None,
+            _ => Some(QueryStats {
+                model_count: hmms.len(),
+                mean_length: state_count as f32 / hmms.len() as f32,
+                mean_information: information_sum / state_count as f32,
+            }),
+        }
+    }
+
+    /// The statistics of the models in an HMM file, if it exists yet.
+    pub fn read(hmm_path: &Path) -> Result<Option<Self>> {
+        if !hmm_path.is_file() {
+            return Ok(None);
+        }
+        let hmms = parse_hmms_from_p7hmm_file(hmm_path.to_str().unwrap())?;
+        Ok(Self::from_hmms(&hmms))
+    }
+}
+
+impl Sensitivity {
+    /// The prefilter parameters for a typical query set
+    fn base_prefilter_params(self) -> PrefilterParams {
+        let (kmer_length, k_score, max_seqs, mmseqs_sensitivity) = match self {
+            Sensitivity::Fast => (Some(7), 100, 300, 4.0),
+            Sensitivity::Default => (None, 80, 1000, 5.7),
+            Sensitivity::Sensitive => (Some(6), 70, 4000, 7.5),
+            Sensitivity::Exhaustive => (Some(6), 55, 20000, 7.5),
+        };
+        PrefilterParams {
+            kmer_length,
+            k_score,
+            max_seqs,
+            mmseqs_sensitivity,
+        }
+    }
+
+    /// The prefilter parameters, adjusted to the query set when its statistics are known.
+    ///
+    /// A k-mer's score is a sum of match scores, so the k-score threshold is scaled
+    /// by the models' information content, and lowered further for short models,
+    /// which have fewer k-mers. More hits are kept per model for small query sets,
+    /// and fewer for very large ones.
+    pub fn prefilter_params(self, stats: Option<&QueryStats>) -> PrefilterParams {
+        let mut params = self.base_prefilter_params();
+        let Some(stats) = stats else {
+            return params;
+        };
+
+        let mut k_score_scale = (stats.mean_information / REFERENCE_INFORMATION).clamp(0.75, 1.25);
+        if stats.mean_length < SHORT_MODEL_LENGTH {
+            k_score_scale *= 0.9;
+        }
+        params.k_score = (params.k_score as f32 * k_score_scale).round() as u32;
+
+        if stats.model_count <= SMALL_QUERY_SET {
+            params.max_seqs *= 2;
+        } else if stats.model_count > LARGE_QUERY_SET {
+            params.max_seqs /= 2;
+        }
+
+        params
+    }
+
+    /// The cloud search parameters of the align step. Larger values prune the
+    /// alignment cloud later, so that it reaches further from the seed.
+    pub fn cloud_search_params(self) -> CloudSearchParams {
+        let (gamma, alpha, beta) = match self {
+            Sensitivity::Fast => (3, 10.0, 16.0),
+            Sensitivity::Default => return CloudSearchParams::default(),
+            Sensitivity::Sensitive => (8, 16.0, 28.0),
+            Sensitivity::Exhaustive => (12, 24.0, 40.0),
+        };
+        CloudSearchParams { gamma, alpha, beta }
+    }
+}
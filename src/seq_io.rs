@@ -0,0 +1,381 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+/// The line width that sequences are wrapped at by default
+pub const DEFAULT_WRAP_WIDTH: usize = 80;
+
+/// How sequence files are written
+#[derive(Debug, Clone, Copy)]
+pub struct WriteOptions {
+    /// The number of residues per line (or alignment columns per block), or None for one line
+    pub wrap_width: Option<usize>,
+    /// Replace the characters in names that would break the format
+    pub sanitize_names: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            wrap_width: Some(DEFAULT_WRAP_WIDTH),
+            sanitize_names: true,
+        }
+    }
+}
+
+impl WriteOptions {
+    fn name(&self, name: &str) -> String {
+        match self.sanitize_names {
+            true => sanitize_name(name),
+            false => name.to_string(),
+        }
+    }
+
+    /// Split a line into the chunks it is wrapped into, of `wrap_width` characters each.
+    fn chunks<'a>(&self, line: &'a str) -> Vec<&'a str> {
+        match self.wrap_width {
+            Some(width) if width > 0 && !line.is_empty() => {
+                // split on character boundaries, so that a multibyte character isn't cut in two
+                let mut starts: Vec<usize> = line
+                    .char_indices()
+                    .step_by(width)
+                    .map(|(byte_idx, _)| byte_idx)
+                    .collect();
+                starts.push(line.len());
+                starts
+                    .windows(2)
+                    .map(|bounds| &line[bounds[0]..bounds[1]])
+                    .collect()
+            }
+            _ => vec![line],
+        }
+    }
+}
+
+/// Make a name safe to use as a FASTA or Stockholm sequence name, by
+/// replacing whitespace and control characters with underscores.
+pub fn sanitize_name(name: &str) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|c| match c.is_whitespace() || c.is_control() {
+            true => '_',
+            false => c,
+        })
+        .collect();
+
+    // a leading '>' would start another record in FASTA
+    match name.strip_prefix('>') {
+        Some(rest) => format!("_{rest}"),
+        None => name,
+    }
+}
+
+/// A named sequence
+#[derive(Debug, Clone, Default)]
+pub struct SeqRecord {
+    pub name: String,
+    /// Free text that follows the name on a FASTA header line
+    pub description: Option<String>,
+    pub sequence: String,
+}
+
+impl SeqRecord {
+    pub fn new(name: impl Into<String>, sequence: impl Into<String>) -> Self {
+        SeqRecord {
+            name: name.into(),
+            description: None,
+            sequence: sequence.into(),
+        }
+    }
+}
+
+/// Write sequences in FASTA format.
+///
+/// ```
+/// use mmoreseqs::seq_io::{write_fasta, SeqRecord, WriteOptions};
+///
+/// let records = vec![SeqRecord::new("seq 1", "ACDEFGHIK")];
+/// let options = WriteOptions {
+///     wrap_width: Some(4),
+///     sanitize_names: true,
+/// };
+/// let mut out = vec![];
+/// write_fasta(&records, &options, &mut out).unwrap();
+/// assert_eq!(out, b">seq_1\nACDE\nFGHI\nK\n");
+///
+/// // lines are wrapped by characters, not bytes
+/// let records = vec![SeqRecord::new("seq2", "AC·DEFGH·")];
+/// let mut out = vec![];
+/// write_fasta(&records, &options, &mut out).unwrap();
+/// assert_eq!(String::from_utf8(out).unwrap(), ">seq2\nAC·D\nEFGH\n·\n");
+/// ```
+pub fn write_fasta(
+    records: &[SeqRecord],
+    options: &WriteOptions,
+    out: &mut impl Write,
+) -> Result<()> {
+    for record in records {
+        write!(out, ">{}", options.name(&record.name))?;
+        if let Some(description) = &record.description {
+            // the description must stay on the header line
+            let description = description.replace(['\n', '\r'], " ");
+            write!(out, " {description}")?;
+        }
+        writeln!(out)?;
+
+        for chunk in options.chunks(&record.sequence) {
+            writeln!(out, "{chunk}")?;
+        }
+    }
+    Ok(())
+}
+
+/// One sequence of a multiple sequence alignment
+#[derive(Debug, Clone, Default)]
+pub struct MsaRow {
+    pub name: String,
+    /// The aligned sequence, with one character per alignment column
+    pub sequence: String,
+    /// Per-column posterior probability annotation (Stockholm's #=GR PP)
+    pub posterior: Option<String>,
+}
+
+/// A multiple sequence alignment, as written to Stockholm
+#[derive(Debug, Clone, Default)]
+pub struct Msa {
+    /// The alignment's name (#=GF ID)
+    pub id: Option<String>,
+    /// The alignment's accession (#=GF AC)
+    pub accession: Option<String>,
+    /// Free text comment lines (#=GF CC)
+    pub comments: Vec<String>,
+    pub rows: Vec<MsaRow>,
+    /// The reference annotation, with one character per alignment column (#=GC RF)
+    pub reference: Option<String>,
+}
+
+/// Write an alignment in Stockholm format.
+///
+/// With a wrap width, the alignment is written in blocks of that many columns.
+///
+/// ```
+/// use mmoreseqs::seq_io::{write_stockholm, Msa, MsaRow, WriteOptions};
+///
+/// let msa = Msa {
+///     id: Some("fam".to_string()),
+///     rows: vec![MsaRow {
+///         name: "seq1".to_string(),
+///         sequence: "AC-DE".to_string(),
+///         posterior: None,
+///     }],
+///     ..Default::default()
+/// };
+/// let mut out = vec![];
+/// write_stockholm(&msa, &WriteOptions::default(), &mut out).unwrap();
+/// assert_eq!(out, b"# STOCKHOLM 1.0\n#=GF ID fam\n\nseq1 AC-DE\n//\n");
+/// ```
+pub fn write_stockholm(msa: &Msa, options: &WriteOptions, out: &mut impl Write) -> Result<()> {
+    // the labels of the alignment lines, and the strings that they label
+    let mut lines: Vec<(String, &str)> = vec![];
+    for row in &msa.rows {
+        let name = options.name(&row.name);
+        if let Some(posterior) = &row.posterior {
+            lines.push((name.clone(), &row.sequence));
+            lines.push((format!("#=GR {name} PP"), posterior));
+        } else {
+            lines.push((name, &row.sequence));
+        }
+    }
+    if let Some(reference) = &msa.reference {
+        lines.push(("#=GC RF".to_string(), reference));
+    }
+
+    let width = lines
+        .iter()
+        .map(|(label, _)| label.len())
+        .max()
+        .unwrap_or(0);
+
+    writeln!(out, "# STOCKHOLM 1.0")?;
+    if let Some(id) = &msa.id {
+        writeln!(out, "#=GF ID {}", options.name(id))?;
+    }
+    if let Some(accession) = &msa.accession {
+        writeln!(out, "#=GF AC {}", options.name(accession))?;
+    }
+    for comment in &msa.comments {
+        writeln!(out, "#=GF CC {comment}")?;
+    }
+
+    let chunked: Vec<(&String, Vec<&str>)> = lines
+        .iter()
+        .map(|(label, line)| (label, options.chunks(line)))
+        .collect();
+    let block_count = chunked
+        .iter()
+        .map(|(_, chunks)| chunks.len())
+        .max()
+        .unwrap_or(0);
+
+    for block_idx in 0..block_count {
+        writeln!(out)?;
+        for (label, chunks) in &chunked {
+            if let Some(chunk) = chunks.get(block_idx) {
+                writeln!(out, "{label:width$} {chunk}")?;
+            }
+        }
+    }
+
+    writeln!(out, "//")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(wrap_width: Option<usize>) -> WriteOptions {
+        WriteOptions {
+            wrap_width,
+            sanitize_names: true,
+        }
+    }
+
+    fn fasta(records: &[SeqRecord], options: &WriteOptions) -> String {
+        let mut out = vec![];
+        write_fasta(records, options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn stockholm(msa: &Msa, options: &WriteOptions) -> String {
+        let mut out = vec![];
+        write_stockholm(msa, options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Read FASTA the way a downstream parser would: a record starts at
+    /// each '>' line, and its sequence lines are joined.
+    fn read_fasta(fasta: &str) -> Vec<(String, String)> {
+        let mut records: Vec<(String, String)> = vec![];
+        for line in fasta.lines() {
+            match line.strip_prefix('>') {
+                Some(header) => records.push((header.to_string(), String::new())),
+                None => records.last_mut().unwrap().1.push_str(line.trim()),
+            }
+        }
+        records
+    }
+
+    #[test]
+    fn fasta_is_wrapped_at_the_width() {
+        let records = vec![SeqRecord::new("a", "ACDEFGHIKL")];
+        assert_eq!(fasta(&records, &options(Some(4))), ">a\nACDE\nFGHI\nKL\n");
+        assert_eq!(fasta(&records, &options(Some(5))), ">a\nACDEF\nGHIKL\n");
+        assert_eq!(fasta(&records, &options(None)), ">a\nACDEFGHIKL\n");
+        assert_eq!(fasta(&records, &options(Some(0))), ">a\nACDEFGHIKL\n");
+    }
+
+    #[test]
+    fn multi_line_fasta_reads_back_as_written() {
+        let sequence = "ACDEFGHIKLMNPQRSTVWY".repeat(10);
+        let mut described = SeqRecord::new("b", "MK");
+        described.description = Some("two\nlines".to_string());
+        let records = vec![SeqRecord::new("a", sequence.clone()), described];
+
+        let written = fasta(&records, &WriteOptions::default());
+        assert_eq!(written.lines().count(), 1 + 3 + 2);
+        assert!(written.lines().all(|l| l.len() <= DEFAULT_WRAP_WIDTH));
+        assert_eq!(
+            read_fasta(&written),
+            vec![
+                ("a".to_string(), sequence),
+                ("b two lines".to_string(), "MK".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_records_keep_their_place() {
+        let records = vec![
+            SeqRecord::new("a", ""),
+            SeqRecord::new("b", "MK"),
+            SeqRecord::new("c", ""),
+        ];
+
+        let written = fasta(&records, &options(Some(4)));
+        assert_eq!(written, ">a\n\n>b\nMK\n>c\n\n");
+        assert_eq!(
+            read_fasta(&written),
+            vec![
+                ("a".to_string(), String::new()),
+                ("b".to_string(), "MK".to_string()),
+                ("c".to_string(), String::new()),
+            ]
+        );
+        assert_eq!(fasta(&[], &options(Some(4))), "");
+    }
+
+    #[test]
+    fn names_are_sanitized_only_when_asked() {
+        assert_eq!(sanitize_name(" seq 1\tx "), "seq_1_x");
+        assert_eq!(sanitize_name(">seq"), "_seq");
+
+        let records = vec![SeqRecord::new(">seq 1", "MK")];
+        assert_eq!(fasta(&records, &options(None)), ">_seq_1\nMK\n");
+        let unsanitized = WriteOptions {
+            wrap_width: None,
+            sanitize_names: false,
+        };
+        assert_eq!(fasta(&records, &unsanitized), ">>seq 1\nMK\n");
+    }
+
+    #[test]
+    fn stockholm_is_written_in_blocks_with_aligned_labels() {
+        let msa = Msa {
+            id: Some("fam 1".to_string()),
+            accession: Some("PF00001.1".to_string()),
+            comments: vec!["a comment".to_string()],
+            rows: vec![
+                MsaRow {
+                    name: "seq1".to_string(),
+                    sequence: "AC-DEF".to_string(),
+                    posterior: Some("99.*87".to_string()),
+                },
+                MsaRow {
+                    name: "s2".to_string(),
+                    sequence: "ACGDE-".to_string(),
+                    posterior: None,
+                },
+            ],
+            reference: Some("xx.xxx".to_string()),
+        };
+
+        assert_eq!(
+            stockholm(&msa, &options(Some(4))),
+            "# STOCKHOLM 1.0\n\
+             #=GF ID fam_1\n\
+             #=GF AC PF00001.1\n\
+             #=GF CC a comment\n\
+             \n\
+             seq1         AC-D\n\
+             #=GR seq1 PP 99.*\n\
+             s2           ACGD\n\
+             #=GC RF      xx.x\n\
+             \n\
+             seq1         EF\n\
+             #=GR seq1 PP 87\n\
+             s2           E-\n\
+             #=GC RF      xx\n\
+             //\n"
+        );
+    }
+
+    #[test]
+    fn empty_stockholm_has_no_blocks() {
+        assert_eq!(
+            stockholm(&Msa::default(), &WriteOptions::default()),
+            "# STOCKHOLM 1.0\n//\n"
+        );
+    }
+}
@@ -0,0 +1,471 @@
+use std::fs::{remove_dir_all, File};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::external_steps::{run_mmseqs_createindex, run_mmseqs_touchdb};
+use crate::pipeline::{
+    align_seeds, prep_query, run_seed_steps, AlignOutput, QuerySet, TargetStore,
+};
+use crate::target_subset::select_target_subset;
+use crate::workspace::{set_target_db, Workspace};
+use crate::Args;
+
+/// The largest request body that will be accepted
+const MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+#[error("malformed HTTP request: {0}")]
+pub struct MalformedRequestError(&'static str);
+
+/// Whether reading a request failed because the client stalled past the read timeout
+fn timed_out(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut))
+}
+
+/// The parts of an HTTP request that the server routes on
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn read(stream: &TcpStream) -> Result<Self> {
+        let mut reader = BufReader::new(stream);
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut tokens = request_line.split_whitespace();
+        let method = tokens
+            .next()
+            .ok_or(MalformedRequestError("missing method"))?
+            .to_string();
+        let path = tokens
+            .next()
+            .ok_or(MalformedRequestError("missing path"))?
+            .to_string();
+
+        let mut content_length: usize = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 {
+                return Err(MalformedRequestError("unterminated headers").into());
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value
+                        .trim()
+                        .parse()
+                        .map_err(|_| MalformedRequestError("invalid content length"))?;
+                }
+            }
+        }
+
+        if content_length > MAX_BODY_BYTES {
+            return Err(MalformedRequestError("body too large").into());
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        Ok(Request { method, path, body })
+    }
+}
+
+fn write_response(mut stream: &TcpStream, status: u16, body: &serde_json::Value) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        408 => "Request Timeout",
+        _ => "Internal Server Error",
+    };
+    let body = body.to_string();
+
+    write!(
+        stream,
+        "HTTP/1.1 {status} {reason}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// The body of a POST /search request
+#[derive(Deserialize)]
+struct SearchRequest {
+    /// The contents of a query MSA (stockholm) or HMM file
+    query: String,
+    /// Overrides the server's E-value cutoff
+    evalue_cutoff: Option<f32>,
+}
+
+/// The body of a POST /batch request
+#[derive(Deserialize)]
+struct BatchRequest {
+    queries: Vec<BatchQuery>,
+}
+
+/// A query of a batch request, which is named in the response
+#[derive(Deserialize)]
+struct BatchQuery {
+    name: String,
+    #[serde(flatten)]
+    search: SearchRequest,
+}
+
+/// A single hit in a search response
+#[derive(Serialize)]
+struct Hit {
+    id: String,
+    profile: String,
+    target: String,
+    bit_score: f32,
+    evalue: f32,
+    profile_start: usize,
+    profile_end: usize,
+    target_start: usize,
+    target_end: usize,
+    env_start: usize,
+    env_end: usize,
+    identity: f32,
+    similarity: f32,
+}
+
+#[derive(Serialize)]
+struct SearchResponse {
+    hits: Vec<Hit>,
+    skipped: usize,
+}
+
+/// Run a single query through the pipeline, in its own workspace under the server's work dir.
+///
+/// The pipeline runs with all of --threads, so the search lock
+/// is held for it, letting only one search run at a time.
+fn search(
+    args: &Args,
+    targets: &TargetStore,
+    search_lock: &Mutex<()>,
+    request: SearchRequest,
+    workspace_name: &str,
+) -> Result<SearchResponse> {
+    let workspace = Workspace::new(args.workspace.dir.join(workspace_name));
+
+    let mut query_args = args.clone();
+    // the target paths are left at the target index's
+    workspace.fill_query_paths(&mut query_args.paths);
+    query_args.paths.query = workspace.dir.join("query");
+    query_args.workspace = workspace;
+    if let Some(evalue_cutoff) = request.evalue_cutoff {
+        query_args.evalue_cutoff = evalue_cutoff;
+    }
+
+    query_args.workspace.create()?;
+    File::create(&query_args.paths.query)
+        .context("failed to create query file")?
+        .write_all(request.query.as_bytes())?;
+
+    let searching = search_lock.lock().unwrap_or_else(PoisonError::into_inner);
+    let output = prep_query(&query_args)
+        .and_then(|_| run_seed_steps(&query_args))
+        .and_then(|_| QuerySet::load(&query_args))
+        .and_then(|queries| align_seeds(&query_args, &queries, targets));
+    drop(searching);
+
+    remove_dir_all(&query_args.workspace.dir).context("failed to remove query workspace")?;
+
+    let AlignOutput { hits, skipped, .. } = output?;
+
+    Ok(SearchResponse {
+        hits: hits
+            .into_iter()
+            .map(|(alignment, aligned_hit)| Hit {
+                id: aligned_hit.id,
+                profile: alignment.profile_name,
+                target: alignment.target_name,
+                bit_score: alignment.bit_score,
+                evalue: alignment.evalue,
+                profile_start: alignment.profile_start,
+                profile_end: alignment.profile_end,
+                target_start: alignment.target_start,
+                target_end: alignment.target_end,
+                env_start: aligned_hit.envelope.start,
+                env_end: aligned_hit.envelope.end,
+                identity: aligned_hit.identity,
+                similarity: aligned_hit.similarity,
+            })
+            .collect(),
+        skipped: skipped.len(),
+    })
+}
+
+/// Run the queries of a batch one after another, each in a workspace of its own, and
+/// answer with each query's response, or its error, under its name.
+fn search_batch(
+    args: &Args,
+    targets: &TargetStore,
+    search_lock: &Mutex<()>,
+    request: BatchRequest,
+    request_idx: usize,
+) -> Result<serde_json::Value> {
+    let mut results = vec![];
+    for (query_idx, query) in request.queries.into_iter().enumerate() {
+        let workspace_name = format!("query-{request_idx}-{query_idx}");
+        let result = match search(args, targets, search_lock, query.search, &workspace_name) {
+            Ok(response) => {
+                let mut result = serde_json::to_value(response)?;
+                result["name"] = json!(query.name);
+                result
+            }
+            Err(e) => json!({ "name": query.name, "error": format!("{e:#}") }),
+        };
+        results.push(result);
+    }
+    Ok(json!({ "results": results }))
+}
+
+fn handle_connection(
+    stream: &TcpStream,
+    args: &Args,
+    targets: &TargetStore,
+    search_lock: &Mutex<()>,
+    request_idx: usize,
+) -> Result<()> {
+    let request = match Request::read(stream) {
+        Ok(request) => request,
+        Err(e) if timed_out(&e) => {
+            return write_response(
+                stream,
+                408,
+                &json!({ "error": "timed out reading the request" }),
+            )
+        }
+        Err(e) => return write_response(stream, 400, &json!({ "error": format!("{e:#}") })),
+    };
+
+    match (&request.method[..], &request.path[..]) {
+        ("GET", "/health") => write_response(
+            stream,
+            200,
+            &json!({ "status": "ok", "targets": targets.count }),
+        ),
+        ("POST", "/search") => match serde_json::from_slice::<SearchRequest>(&request.body) {
+            Ok(search_request) => {
+                let workspace_name = format!("query-{request_idx}");
+                match search(args, targets, search_lock, search_request, &workspace_name) {
+                    Ok(response) => write_response(stream, 200, &serde_json::to_value(response)?),
+                    Err(e) => write_response(stream, 500, &json!({ "error": format!("{e:#}") })),
+                }
+            }
+            Err(e) => write_response(stream, 400, &json!({ "error": e.to_string() })),
+        },
+        ("POST", "/batch") => match serde_json::from_slice::<BatchRequest>(&request.body) {
+            Ok(batch_request) => {
+                match search_batch(args, targets, search_lock, batch_request, request_idx) {
+                    Ok(response) => write_response(stream, 200, &response),
+                    Err(e) => write_response(stream, 500, &json!({ "error": format!("{e:#}") })),
+                }
+            }
+            Err(e) => write_response(stream, 400, &json!({ "error": e.to_string() })),
+        },
+        _ => write_response(stream, 404, &json!({ "error": "not found" })),
+    }
+}
+
+/// Load the targets once, then answer search requests until the process is stopped.
+///
+/// The requests are handled by a pool of --threads workers, which share the one copy of the
+/// targets. A search runs with all of --threads, so searches run one at a time, while the other
+/// workers can still answer health checks; when every worker is busy, new connections wait.
+/// A client that stalls for --request-timeout is answered 408, so it can't hold a worker.
+/// The target database's k-mer index, and the subset of --target-include-ids, are also only
+/// built once, so that a request's seed step only searches them.
+///
+/// The API is:
+///   GET  /health  -> {"status": "ok", "targets": N}
+///   POST /search  {"query": "<MSA or HMM>", "evalue_cutoff": E} -> {"hits": [...], "skipped": N}
+///   POST /batch   {"queries": [{"name": "...", "query": "...", "evalue_cutoff": E}, ...]}
+///                 -> {"results": [{"name": "...", "hits": [...], "skipped": N}, ...]}, where a
+///                    query that failed has an "error" in place of its hits
+pub fn serve(args: &Args) -> Result<()> {
+    args.workspace.validate_target_index(&args.paths)?;
+    args.workspace.create()?;
+
+    let mut args = args.clone();
+    if let Some(ids_path) = args.paths.target_include_ids.take() {
+        // the subset is written to the server's work dir, leaving the target index as it was
+        args.paths.target_subset_keys = args.workspace.dir.join("targetSubset.keys");
+        args.paths.target_subset_db = args.workspace.dir.join("targetSubsetDB");
+        select_target_subset(&args, &ids_path)?;
+        let subset_db = args.paths.target_subset_db.clone();
+        set_target_db(&mut args.paths, subset_db);
+    }
+
+    // the index is kept next to the target database, for the next server to reuse
+    if !args.paths.target_db_kmer_index.exists() {
+        let tmp_dir = args.workspace.dir.join("createindex-tmp");
+        run_mmseqs_createindex(&args, &tmp_dir)?;
+        if tmp_dir.exists() {
+            remove_dir_all(&tmp_dir).context("failed to remove the createindex tmp dir")?;
+        }
+    }
+
+    if args.preload {
+        run_mmseqs_touchdb(&args, &args.paths.target_db)?;
+    }
+
+    let targets = Arc::new(TargetStore::load(&args)?);
+    let args = Arc::new(args);
+
+    let listener = TcpListener::bind((&args.host[..], args.port))
+        .context(format!("failed to bind to {}:{}", args.host, args.port))?;
+    eprintln!(
+        "serving {} targets on {}:{}",
+        targets.count, args.host, args.port
+    );
+
+    answer_connections(listener, args, targets)
+}
+
+/// Answer the connections to the listener on a pool of --threads workers, for as long as it accepts them.
+fn answer_connections(
+    listener: TcpListener,
+    args: Arc<Args>,
+    targets: Arc<TargetStore>,
+) -> Result<()> {
+    let timeout = Some(args.request_timeout).filter(|timeout| !timeout.is_zero());
+
+    // an accepted connection waits for a free worker before the next is accepted
+    let (sender, receiver) = sync_channel::<(usize, TcpStream)>(0);
+    let receiver = Arc::new(Mutex::new(receiver));
+    let search_lock = Arc::new(Mutex::new(()));
+    for _ in 0..args.threads.max(1) {
+        let args = Arc::clone(&args);
+        let targets = Arc::clone(&targets);
+        let receiver = Arc::clone(&receiver);
+        let search_lock = Arc::clone(&search_lock);
+        std::thread::spawn(move || loop {
+            let next = receiver
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .recv();
+            let Ok((request_idx, stream)) = next else {
+                break;
+            };
+
+            // a failed request shouldn't take the server down, or its worker
+            let handled = catch_unwind(AssertUnwindSafe(|| {
+                handle_connection(&stream, &args, &targets, &search_lock, request_idx)
+            }));
+            match handled {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("failed to handle request: {e:#}"),
+                Err(_) => eprintln!("failed to handle request: it panicked"),
+            }
+        });
+    }
+
+    for (request_idx, stream) in listener.incoming().enumerate() {
+        let stream = match stream.and_then(|stream| {
+            stream.set_read_timeout(timeout)?;
+            stream.set_write_timeout(timeout)?;
+            Ok(stream)
+        }) {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("failed to accept connection: {e}");
+                continue;
+            }
+        };
+
+        sender
+            .send((request_idx, stream))
+            .context("the server's request workers have stopped")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    /// Answer connections on a local port in the background, with a store of three targets
+    fn serve_in_background(threads: usize, request_timeout: Duration) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let args = Args {
+            threads,
+            request_timeout,
+            ..Default::default()
+        };
+        let targets = TargetStore {
+            sequences: HashMap::new(),
+            count: 3,
+            taxonomy: None,
+            ambiguous_names: HashSet::new(),
+            sources: None,
+            orfs: None,
+        };
+        std::thread::spawn(move || answer_connections(listener, Arc::new(args), Arc::new(targets)));
+        addr
+    }
+
+    fn response(mut stream: TcpStream) -> String {
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn an_idle_connection_times_out_instead_of_blocking_health_checks() {
+        // with one worker, the idle connection holds it until the timeout
+        let addr = serve_in_background(1, Duration::from_millis(200));
+        let idle = TcpStream::connect(addr).unwrap();
+
+        let start = Instant::now();
+        let mut health = TcpStream::connect(addr).unwrap();
+        health.write_all(b"GET /health HTTP/1.1\r\n\r\n").unwrap();
+        let health = response(health);
+        assert!(health.starts_with("HTTP/1.1 200 OK"), "{health}");
+        assert!(health.contains(r#""targets":3"#), "{health}");
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        let idle = response(idle);
+        assert!(idle.starts_with("HTTP/1.1 408 Request Timeout"), "{idle}");
+    }
+
+    #[test]
+    fn a_stalled_request_is_answered_408() {
+        let addr = serve_in_background(2, Duration::from_millis(100));
+        let mut stalled = TcpStream::connect(addr).unwrap();
+        // the headers promise a body that never comes
+        stalled
+            .write_all(b"POST /search HTTP/1.1\r\nContent-Length: 10\r\n\r\n{")
+            .unwrap();
+        let stalled = response(stalled);
+        assert!(
+            stalled.starts_with("HTTP/1.1 408 Request Timeout"),
+            "{stalled}"
+        );
+    }
+}
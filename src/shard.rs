@@ -0,0 +1,43 @@
+use std::str::FromStr;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("shard must be given as i/n, where 1 <= i <= n")]
+pub struct InvalidShardError;
+
+/// One of n deterministic partitions of the query profiles, written as i/n on the command line.
+#[derive(Debug, Clone, Copy)]
+pub struct Shard {
+    /// The 1-based index of this shard
+    pub index: usize,
+    /// The total number of shards
+    pub count: usize,
+}
+
+impl Shard {
+    /// Select this shard's contiguous slice of items.
+    ///
+    /// The items are expected to already be in a deterministic order (e.g. sorted by accession).
+    pub fn select<'a, T>(&self, items: &'a [T]) -> &'a [T] {
+        let start = items.len() * (self.index - 1) / self.count;
+        let end = items.len() * self.index / self.count;
+        &items[start..end]
+    }
+}
+
+impl FromStr for Shard {
+    type Err = InvalidShardError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, count) = s.split_once('/').ok_or(InvalidShardError)?;
+        let index = index.parse::<usize>().map_err(|_| InvalidShardError)?;
+        let count = count.parse::<usize>().map_err(|_| InvalidShardError)?;
+
+        if index == 0 || index > count {
+            return Err(InvalidShardError);
+        }
+
+        Ok(Shard { index, count })
+    }
+}
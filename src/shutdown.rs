@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use thiserror::Error;
+
+/// The signal that was caught, or 0 if there hasn't been one
+static CAUGHT_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+#[derive(Error, Debug)]
+#[error("cancelled by signal {0}; the results of the seeds aligned so far were written")]
+pub struct CancelledError(pub i32);
+
+impl CancelledError {
+    /// The exit status of a run that was cancelled, which is 128 plus the signal, as a shell reports it
+    pub fn exit_code(&self) -> i32 {
+        128 + self.0
+    }
+}
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    CAUGHT_SIGNAL.store(signal, Ordering::SeqCst);
+    // a second signal terminates the run right away
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+    }
+}
+
+/// Catch SIGINT and SIGTERM, so that the run can stop between seeds and
+/// stages, and write what it has, instead of being terminated mid-write.
+pub fn install_signal_handlers() {
+    let handler = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Ask the run to stop as SIGINT would, for a Ctrl-C that was read as a key.
+pub fn interrupt() {
+    handle_signal(libc::SIGINT);
+}
+
+/// The signal that asked the run to stop, if one was caught
+pub fn caught_signal() -> Option<i32> {
+    match CAUGHT_SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// Whether the run has been asked to stop
+pub fn requested() -> bool {
+    caught_signal().is_some()
+}
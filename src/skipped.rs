@@ -0,0 +1,117 @@
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+
+use anyhow::Result;
+use nale::align::bounded::structs::Seed;
+
+/// The reason that a seed was skipped during the align step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipReason {
+    /// The seed's target was not found in the target fasta
+    MissingTarget,
+    /// The seed's coordinates fall outside of the target or profile
+    CoordinatesOutOfRange,
+    /// The cloud search did not produce valid row bounds
+    InvalidBounds,
+    /// The seed's target was dropped for containing ambiguous residues
+    AmbiguousTarget,
+    /// The seed's target range is mostly low complexity sequence
+    LowComplexity,
+    /// The run's or the query's time limit was reached before the seed was aligned
+    TimeLimit,
+    /// The run was cancelled by a signal before the seed was aligned
+    Cancelled,
+    /// The seed's MSV score did not pass the `--msv-pvalue` filter
+    MsvFilter,
+    /// The seed's bounded forward E-value did not pass the fast pass
+    FastPass,
+}
+
+impl SkipReason {
+    /// The machine-readable code written to the skipped seeds file.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SkipReason::MissingTarget => "missing_target",
+            SkipReason::CoordinatesOutOfRange => "coords_out_of_range",
+            SkipReason::InvalidBounds => "invalid_bounds",
+            SkipReason::AmbiguousTarget => "ambiguous_target",
+            SkipReason::LowComplexity => "low_complexity",
+            SkipReason::TimeLimit => "time_limit",
+            SkipReason::Cancelled => "cancelled",
+            SkipReason::MsvFilter => "msv_filter",
+            SkipReason::FastPass => "fast_pass",
+        }
+    }
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// A record of a seed that was dropped before producing an alignment.
+pub struct SkippedSeed {
+    pub profile_name: String,
+    pub target_name: String,
+    pub profile_start: usize,
+    pub profile_end: usize,
+    pub target_start: usize,
+    pub target_end: usize,
+    pub reason: SkipReason,
+}
+
+impl SkippedSeed {
+    pub fn new(profile_name: &str, seed: &Seed, reason: SkipReason) -> Self {
+        SkippedSeed {
+            profile_name: profile_name.to_string(),
+            target_name: seed.target_name.clone(),
+            profile_start: seed.profile_start,
+            profile_end: seed.profile_end,
+            target_start: seed.target_start,
+            target_end: seed.target_end,
+            reason,
+        }
+    }
+}
+
+pub fn write_skipped_seeds(skipped: &[SkippedSeed], out: &mut impl Write) -> Result<()> {
+    writeln!(
+        out,
+        "profile\ttarget\tprofile_start\tprofile_end\ttarget_start\ttarget_end\treason"
+    )?;
+
+    for seed in skipped {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            seed.profile_name,
+            seed.target_name,
+            seed.profile_start,
+            seed.profile_end,
+            seed.target_start,
+            seed.target_end,
+            seed.reason,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Print a count of skipped seeds, broken down by reason, to stderr so that it
+/// stays out of any results written to stdout.
+pub fn print_skipped_summary(skipped: &[SkippedSeed]) {
+    if skipped.is_empty() {
+        return;
+    }
+
+    let mut reasons: Vec<SkipReason> = skipped.iter().map(|s| s.reason).collect();
+    reasons.sort();
+    reasons.dedup();
+
+    eprintln!("skipped {} seeds:", skipped.len());
+    for reason in reasons {
+        let count = skipped.iter().filter(|s| s.reason == reason).count();
+        eprintln!("  {reason}: {count}");
+    }
+}
@@ -0,0 +1,21 @@
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::Args;
+
+#[derive(Error, Debug)]
+#[error("{0} (an error because of --strict)")]
+pub struct StrictModeError(String);
+
+/// Report a problem with the inputs that the run can recover from.
+///
+/// It is only a warning, unless --strict makes it an error.
+pub fn warn_or_fail(args: &Args, message: String) -> Result<()> {
+    match args.strict {
+        true => Err(StrictModeError(message).into()),
+        false => {
+            eprintln!("warning: {message}");
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::io::{stderr, IsTerminal, Write};
+
+use nale::structs::Alignment;
+
+const BOLD: &str = "\x1b[1m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// The widest a name may be in the summary before it is truncated
+const MAX_NAME_WIDTH: usize = 30;
+
+/// Shorten a name to MAX_NAME_WIDTH characters, marking the truncation with '~'
+fn truncate(name: &str) -> String {
+    match name.chars().count() > MAX_NAME_WIDTH {
+        true => {
+            let mut short: String = name.chars().take(MAX_NAME_WIDTH - 1).collect();
+            short.push('~');
+            short
+        }
+        false => name.to_string(),
+    }
+}
+
+/// The color used for an E-value: green for strong hits, yellow for marginal ones
+fn evalue_color(evalue: f32) -> &'static str {
+    if evalue <= 1e-10 {
+        GREEN
+    } else if evalue <= 1e-3 {
+        YELLOW
+    } else {
+        RESET
+    }
+}
+
+/// Print a table of the top hits (by E-value) to stderr.
+///
+/// Color is only used when stderr is a terminal and NO_COLOR isn't set.
+pub fn print_hit_summary(alignments: &[Alignment], count: usize, color: bool) {
+    if count == 0 || alignments.is_empty() {
+        return;
+    }
+
+    let color = color && stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let paint = |text: String, code: &str| match color {
+        true => format!("{code}{text}{RESET}"),
+        false => text,
+    };
+
+    let mut top: Vec<&Alignment> = alignments.iter().collect();
+    top.sort_by(|a, b| a.evalue.total_cmp(&b.evalue));
+    top.truncate(count);
+
+    let rows: Vec<[String; 6]> = top
+        .iter()
+        .map(|a| {
+            [
+                truncate(&a.profile_name),
+                // target names are full fasta headers
+                truncate(a.target_name.split_whitespace().next().unwrap_or_default()),
+                format!("{:.1e}", a.evalue),
+                format!("{:.1}", a.bit_score),
+                format!("{}-{}", a.profile_start, a.profile_end),
+                format!("{}-{}", a.target_start, a.target_end),
+            ]
+        })
+        .collect();
+
+    let headers = [
+        "query",
+        "target",
+        "E-value",
+        "bits",
+        "query coords",
+        "target coords",
+    ];
+    let mut widths = headers.map(|h| h.len());
+    for row in &rows {
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.len());
+        }
+    }
+
+    let mut out = stderr().lock();
+
+    let _ = writeln!(out, "top {} of {} hits:", rows.len(), alignments.len());
+
+    let header_line = headers
+        .iter()
+        .zip(widths)
+        .map(|(h, w)| format!("{h:<w$}"))
+        .collect::<Vec<String>>()
+        .join("  ");
+    let _ = writeln!(out, "{}", paint(header_line, BOLD));
+
+    for (row, alignment) in rows.iter().zip(&top) {
+        let fields: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(field_idx, (field, w))| {
+                // names are left aligned, numbers are right aligned
+                let padded = match field_idx {
+                    0 | 1 => format!("{field:<w$}"),
+                    _ => format!("{field:>w$}"),
+                };
+                match field_idx {
+                    2 => paint(padded, evalue_color(alignment.evalue)),
+                    _ => padded,
+                }
+            })
+            .collect();
+        let _ = writeln!(out, "{}", fields.join("  "));
+    }
+}
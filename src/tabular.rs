@@ -0,0 +1,275 @@
+use std::io::{BufRead, Write};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use nale::structs::Alignment;
+use thiserror::Error;
+
+use crate::number_format::NumberFormat;
+
+#[derive(Error, Debug)]
+#[error("malformed results table")]
+pub struct MalformedTableError;
+
+/// The versions of the results table schema.
+///
+/// v1 has only the default columns. v2 appends the envelope, seed count, and
+/// alignment statistics columns, and v3 appends the bias, domain E-value, and
+/// hit id columns after those. The optional columns, e.g. seed provenance and
+/// taxonomy, always follow every required column of the version. Each version
+/// after v1 starts with a "# mmoreseqs-tsv vN" line, and a new version is
+/// needed whenever the required columns change.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum OutputVersion {
+    /// The legacy columns, with no version line
+    V1,
+    /// The v1 columns, then the envelope, seed count, and alignment statistics
+    V2,
+    #[default]
+    V3,
+}
+
+impl OutputVersion {
+    const V2_HEADER: &'static str = "# mmoreseqs-tsv v2";
+    const V3_HEADER: &'static str = "# mmoreseqs-tsv v3";
+
+    /// The line that identifies the schema at the top of a results table
+    fn header_line(&self) -> Option<&'static str> {
+        match self {
+            OutputVersion::V1 => None,
+            OutputVersion::V2 => Some(Self::V2_HEADER),
+            OutputVersion::V3 => Some(Self::V3_HEADER),
+        }
+    }
+
+    fn from_header_line(line: &str) -> Option<Self> {
+        match line.trim() {
+            Self::V2_HEADER => Some(OutputVersion::V2),
+            Self::V3_HEADER => Some(OutputVersion::V3),
+            _ => None,
+        }
+    }
+}
+
+/// The default columns of the results table, one per field of the Alignment struct
+pub const DEFAULT_COLUMN_HEADERS: [&str; 8] = [
+    "target name",
+    "profile name",
+    "target start",
+    "target end",
+    "profile start",
+    "profile end",
+    "bit score",
+    "e-value",
+];
+
+pub const TARGET_NAME_COLUMN_IDX: usize = 0;
+pub const PROFILE_NAME_COLUMN_IDX: usize = 1;
+pub const TARGET_START_COLUMN_IDX: usize = 2;
+pub const TARGET_END_COLUMN_IDX: usize = 3;
+pub const BIT_SCORE_COLUMN_IDX: usize = 6;
+pub const EVALUE_COLUMN_IDX: usize = 7;
+
+/// The values of the default columns for an alignment
+pub fn alignment_row(alignment: &Alignment, format: &NumberFormat) -> Vec<String> {
+    vec![
+        alignment.target_name.clone(),
+        alignment.profile_name.clone(),
+        alignment.target_start.to_string(),
+        alignment.target_end.to_string(),
+        alignment.profile_start.to_string(),
+        alignment.profile_end.to_string(),
+        format.score(alignment.bit_score, 2),
+        format.evalue(alignment.evalue),
+    ]
+}
+
+/// A whitespace-aligned results table.
+///
+/// Column headers may contain spaces, but values may not.
+#[derive(Default)]
+pub struct Table {
+    pub version: OutputVersion,
+    /// The '#' lines under the version line, e.g. the run's provenance. v1 tables have none.
+    pub comments: Vec<String>,
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn from_alignments(alignments: &[Alignment], format: &NumberFormat) -> Self {
+        Table {
+            version: OutputVersion::default(),
+            comments: vec![],
+            headers: DEFAULT_COLUMN_HEADERS.map(String::from).to_vec(),
+            rows: alignments
+                .iter()
+                .map(|alignment| alignment_row(alignment, format))
+                .collect(),
+        }
+    }
+
+    /// Append a column to the table, with one value per row.
+    pub fn add_column(&mut self, header: &str, values: Vec<String>) {
+        debug_assert_eq!(values.len(), self.rows.len());
+        self.headers.push(header.to_string());
+        for (row, value) in self.rows.iter_mut().zip(values) {
+            row.push(value);
+        }
+    }
+
+    /// Read a table that was written with Table::write().
+    ///
+    /// The separator line under the headers is used to find the column boundaries.
+    pub fn read(reader: impl BufRead) -> Result<Self> {
+        let mut lines = reader.lines();
+
+        let mut header_line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(Table::default()),
+        };
+
+        // v1 tables have no version line
+        let version = match OutputVersion::from_header_line(&header_line) {
+            Some(version) => {
+                header_line = lines.next().ok_or(MalformedTableError)??;
+                version
+            }
+            None => OutputVersion::V1,
+        };
+
+        let mut comments: Vec<String> = vec![];
+        if version.header_line().is_some() {
+            while let Some(comment) = header_line.strip_prefix('#') {
+                comments.push(comment.trim_start().to_string());
+                header_line = lines.next().ok_or(MalformedTableError)??;
+            }
+        }
+        let separator_line = lines.next().ok_or(MalformedTableError)??;
+
+        let mut headers: Vec<String> = vec![];
+        let mut column_start: usize = 0;
+        for dashes in separator_line.split(' ') {
+            let column_end = (column_start + dashes.len()).min(header_line.len());
+            let header = header_line
+                .get(column_start..column_end)
+                .ok_or(MalformedTableError)?;
+            headers.push(header.trim().to_string());
+            column_start += dashes.len() + 1;
+        }
+
+        let mut rows: Vec<Vec<String>> = vec![];
+        for line in lines {
+            let line = line?;
+            let row: Vec<String> = line.split_whitespace().map(String::from).collect();
+
+            if row.is_empty() {
+                continue;
+            }
+
+            if row.len() != headers.len() {
+                return Err(MalformedTableError.into());
+            }
+
+            rows.push(row);
+        }
+
+        Ok(Table {
+            version,
+            comments,
+            headers,
+            rows,
+        })
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> Result<()> {
+        // the comments would be read as the header line of a v1 table
+        if let Some(version_line) = self.version.header_line() {
+            writeln!(out, "{version_line}")?;
+            for comment in &self.comments {
+                writeln!(out, "# {comment}")?;
+            }
+        }
+
+        let mut column_widths: Vec<usize> = self.headers.iter().map(|h| h.len()).collect();
+        for row in &self.rows {
+            for (width, value) in column_widths.iter_mut().zip(row) {
+                *width = (*width).max(value.len());
+            }
+        }
+
+        let header_line: Vec<String> = self
+            .headers
+            .iter()
+            .zip(&column_widths)
+            .map(|(header, width)| format!("{header:width$}"))
+            .collect();
+        writeln!(out, "{}", header_line.join(" "))?;
+
+        let separator_line: Vec<String> = column_widths.iter().map(|w| "-".repeat(*w)).collect();
+        writeln!(out, "{}", separator_line.join(" "))?;
+
+        for row in &self.rows {
+            let row_line: Vec<String> = row
+                .iter()
+                .zip(&column_widths)
+                .map(|(value, width)| format!("{value:width$}"))
+                .collect();
+            writeln!(out, "{}", row_line.join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alphabet::{parse_amino_fasta, AmbiguityPolicy};
+
+    #[test]
+    fn a_table_of_targets_with_described_headers_reads_back() {
+        let fasta = b">tgt1 some description\nACDEFGHIKLMNPQRSTVWY\n";
+        let targets = parse_amino_fasta(&fasta[..], AmbiguityPolicy::ScoreAsX, None).unwrap();
+        let alignment = Alignment {
+            profile_name: "prof".to_string(),
+            target_name: targets.sequences[0].name.clone(),
+            bit_score: 31.4,
+            evalue: 2.1e-10,
+            length: 12,
+            profile_start: 1,
+            profile_end: 12,
+            profile_string: String::new(),
+            target_start: 3,
+            target_end: 14,
+            target_string: String::new(),
+            middle_string: String::new(),
+            posterior_probability_string: String::new(),
+        };
+        let table = Table::from_alignments(&[alignment], &NumberFormat::default());
+
+        let mut written: Vec<u8> = vec![];
+        table.write(&mut written).unwrap();
+        let read = Table::read(&written[..]).unwrap();
+
+        assert_eq!(read.headers, table.headers);
+        assert_eq!(read.rows, table.rows);
+        assert_eq!(read.rows[0][TARGET_NAME_COLUMN_IDX], "tgt1");
+    }
+
+    #[test]
+    fn a_v2_table_reads_with_its_version_and_headers() {
+        let written = "# mmoreseqs-tsv v2\n\
+                       # command: mmoreseqs search\n\
+                       target name env start\n\
+                       ----------- ---------\n\
+                       tgt1        3\n";
+
+        let read = Table::read(written.as_bytes()).unwrap();
+
+        assert_eq!(read.version, OutputVersion::V2);
+        assert_eq!(read.comments, vec!["command: mmoreseqs search"]);
+        assert_eq!(read.headers, vec!["target name", "env start"]);
+        assert_eq!(read.rows, vec![vec!["tgt1", "3"]]);
+    }
+}
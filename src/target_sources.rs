@@ -0,0 +1,190 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{read_dir, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use nale::output::path_buf_ext::PathBufExt;
+use serde::Serialize;
+use thiserror::Error;
+
+/// The extensions of the files that are read from a directory of targets
+const FASTA_EXTENSIONS: [&str; 4] = ["fa", "fasta", "faa", "fas"];
+
+#[derive(Error, Debug)]
+#[error("target {name} is in both {first} and {second}")]
+pub struct DuplicateTargetError {
+    name: String,
+    first: String,
+    second: String,
+}
+
+#[derive(Error, Debug)]
+#[error("no fasta files (.fa, .fasta, .faa, or .fas) in target directory: {0}")]
+pub struct EmptyTargetDirError(PathBuf);
+
+/// List the target fasta files, replacing any directory with the fasta files in it.
+pub fn expand_target_sources(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut sources: Vec<PathBuf> = vec![];
+
+    for path in paths {
+        if !path.is_dir() {
+            sources.push(path.clone());
+            continue;
+        }
+
+        let mut dir_sources: Vec<PathBuf> = read_dir(path)
+            .context(format!(
+                "failed to read target directory: {}",
+                path.to_string_lossy()
+            ))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<_>>()?;
+        dir_sources.retain(|p| {
+            p.is_file()
+                && p.extension()
+                    .is_some_and(|ext| FASTA_EXTENSIONS.iter().any(|e| ext == *e))
+        });
+
+        if dir_sources.is_empty() {
+            return Err(EmptyTargetDirError(path.clone()).into());
+        }
+
+        // sorted, so that the combined targets don't depend on the directory order
+        dir_sources.sort();
+        sources.extend(dir_sources);
+    }
+
+    Ok(sources)
+}
+
+/// Concatenate the target fasta files into one, and record which file each target came from.
+///
+/// The source map has a target name and its source file on each line.
+pub fn combine_targets(
+    sources: &[PathBuf],
+    combined: &PathBuf,
+    source_map: &PathBuf,
+) -> Result<()> {
+    let mut combined_out = BufWriter::new(combined.open(true)?);
+    let mut source_map_out = BufWriter::new(source_map.open(true)?);
+    let mut source_by_name: HashMap<String, &Path> = HashMap::new();
+
+    for source in sources {
+        let file = File::open(source).context(format!(
+            "failed to open target fasta: {}",
+            source.to_string_lossy()
+        ))?;
+
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+
+            if let Some(header) = line.strip_prefix('>') {
+                let name = header.split_whitespace().next().unwrap_or_default();
+                if let Some(first) = source_by_name.insert(name.to_string(), source) {
+                    return Err(DuplicateTargetError {
+                        name: name.to_string(),
+                        first: first.to_string_lossy().to_string(),
+                        second: source.to_string_lossy().to_string(),
+                    }
+                    .into());
+                }
+                writeln!(source_map_out, "{name}\t{}", source.to_string_lossy())?;
+            }
+
+            writeln!(combined_out, "{line}")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The number of targets and hits from one target source
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SourceStats {
+    pub targets: usize,
+    pub hits: usize,
+}
+
+/// The source file of each target, for targets combined from several fasta files.
+pub struct TargetSources {
+    source_by_name: HashMap<String, String>,
+}
+
+impl TargetSources {
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = File::open(path).context(format!(
+            "failed to open target source map: {}",
+            path.to_string_lossy()
+        ))?;
+
+        let mut source_by_name = HashMap::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if let Some((name, source)) = line.split_once('\t') {
+                source_by_name.insert(name.to_string(), source.to_string());
+            }
+        }
+
+        Ok(TargetSources { source_by_name })
+    }
+
+    /// The source file of a target, looked up by the first word of its
+    /// name, which is the name the source map was written with.
+    pub fn source(&self, target_name: &str) -> Option<&str> {
+        let name = target_name.split_whitespace().next()?;
+        self.source_by_name.get(name).map(|s| &s[..])
+    }
+
+    /// Count the targets and hits from each source.
+    ///
+    /// Only the targets that were loaded are counted, so
+    /// that targets dropped by the filters are left out.
+    pub fn stats<'a>(
+        &self,
+        target_names: impl Iterator<Item = &'a String>,
+        hit_target_names: impl Iterator<Item = &'a String>,
+    ) -> BTreeMap<String, SourceStats> {
+        let mut stats: BTreeMap<String, SourceStats> = BTreeMap::new();
+        for name in target_names {
+            if let Some(source) = self.source(name) {
+                stats.entry(source.to_string()).or_default().targets += 1;
+            }
+        }
+        for name in hit_target_names {
+            if let Some(source) = self.source(name) {
+                stats.entry(source.to_string()).or_default().hits += 1;
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::{temp_file, temp_path};
+
+    #[test]
+    fn sources_are_found_by_the_first_word_of_a_described_name() {
+        let a = temp_file("a.fa", ">tgt1 some description\nACDE\n");
+        let b = temp_file("b.fa", ">tgt2\nFGHI\n");
+        let combined = temp_path("combined.fa");
+        let source_map = temp_path("sources.tsv");
+        combine_targets(&[a.clone(), b.clone()], &combined, &source_map).unwrap();
+
+        let sources = TargetSources::read(&source_map).unwrap();
+        let a = a.to_string_lossy();
+        let b = b.to_string_lossy();
+        assert_eq!(sources.source("tgt1 some description"), Some(&a[..]));
+        assert_eq!(sources.source("tgt1"), Some(&a[..]));
+        assert_eq!(sources.source("tgt2"), Some(&b[..]));
+        assert_eq!(sources.source("tgt3"), None);
+
+        let names = ["tgt1 some description".to_string(), "tgt2".to_string()];
+        let stats = sources.stats(names.iter(), names[..1].iter());
+        assert_eq!(stats[&a[..]].targets, 1);
+        assert_eq!(stats[&a[..]].hits, 1);
+        assert_eq!(stats[&b[..]].hits, 0);
+    }
+}
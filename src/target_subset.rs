@@ -0,0 +1,93 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use nale::output::path_buf_ext::PathBufExt;
+use thiserror::Error;
+
+use crate::external_steps::run_mmseqs_createsubdb;
+use crate::strict::warn_or_fail;
+use crate::Args;
+
+#[derive(Error, Debug)]
+#[error("none of the targets in {0} are in the target database")]
+pub struct NoTargetsSelectedError(String);
+
+/// Read the target names in an ID list, one per line.
+///
+/// Only the first word of each line is used, so that fasta headers can be pasted in.
+fn read_target_ids(path: &Path) -> Result<HashSet<String>> {
+    let file = File::open(path).context(format!(
+        "failed to open target ID list: {}",
+        path.to_string_lossy()
+    ))?;
+
+    let mut ids = HashSet::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let id = line.trim_start_matches('>');
+        ids.insert(id.split_whitespace().next().unwrap_or_default().to_string());
+    }
+    Ok(ids)
+}
+
+/// Write the database keys of the listed targets, looked up in the target database's
+/// lookup file, and return the number of listed targets that weren't found.
+///
+/// The lookup file has a key, a name, and a file number on each line.
+fn write_target_keys(ids: &HashSet<String>, lookup: &Path, out: &mut impl Write) -> Result<usize> {
+    let file = File::open(lookup).context(format!(
+        "failed to open target database lookup: {}",
+        lookup.to_string_lossy()
+    ))?;
+
+    let mut found: HashSet<&str> = HashSet::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let mut fields = line.split('\t');
+        let (Some(key), Some(name)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        if let Some(id) = ids.get(name) {
+            writeln!(out, "{key}")?;
+            found.insert(id);
+        }
+    }
+
+    Ok(ids.len() - found.len())
+}
+
+/// Cut the target database down to the targets in the ID list.
+///
+/// mmseqs reads the listed entries through the database index, so
+/// the target fasta never has to be filtered and reindexed.
+pub fn select_target_subset(args: &Args, ids_path: &Path) -> Result<()> {
+    if !args.dry_run {
+        let ids = read_target_ids(ids_path)?;
+        let mut keys_out = BufWriter::new(args.paths.target_subset_keys.open(true)?);
+        let missing_count = write_target_keys(&ids, &args.paths.target_db_lookup, &mut keys_out)?;
+        keys_out.flush()?;
+
+        if missing_count == ids.len() {
+            return Err(NoTargetsSelectedError(ids_path.to_string_lossy().to_string()).into());
+        }
+        if missing_count > 0 {
+            warn_or_fail(
+                args,
+                format!("{missing_count} listed targets are not in the target database"),
+            )?;
+        }
+        eprintln!(
+            "restricted the targets to {} listed sequences",
+            ids.len() - missing_count
+        );
+    }
+
+    run_mmseqs_createsubdb(args)
+}
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use thiserror::Error;
+
+use crate::workspace::mmseqs_db_file;
+
+#[derive(Error, Debug)]
+#[error("malformed line in taxonomy file")]
+pub struct MalformedTaxonomyError;
+
+#[derive(Error, Debug)]
+#[error(
+    "{} is neither a taxonomy dump directory nor an mmseqs taxonomy database with _nodes.dmp and _names.dmp",
+    .0.display()
+)]
+pub struct MissingTaxonomyDumpError(PathBuf);
+
+/// The taxid of the root of the NCBI taxonomy, which is its own parent
+const ROOT_TAXID: u32 = 1;
+
+/// An NCBI-style taxonomy, along with a map from target names to taxids.
+///
+/// This is loaded either from a directory that contains:
+///
+///   - nodes.dmp: the NCBI taxonomy nodes dump
+///   - names.dmp: the NCBI taxonomy names dump
+///   - mapping: two whitespace-delimited columns, the target name and its taxid
+///
+/// or from an mmseqs taxonomy database, the sequence database that `mmseqs
+/// createtaxdb` was run on, given by its path prefix. That has the dumps as
+/// DB_nodes.dmp and DB_names.dmp, and maps the database keys to taxids in
+/// DB_mapping, whose targets are named by the keys' accessions in DB.lookup.
+pub struct Taxonomy {
+    parents: HashMap<u32, u32>,
+    names: HashMap<u32, String>,
+    target_taxids: HashMap<String, u32>,
+}
+
+/// Split a line of an NCBI .dmp file into its fields.
+fn dmp_fields(line: &str) -> Vec<&str> {
+    line.split("\t|").map(|field| field.trim()).collect()
+}
+
+/// Open a file of a taxonomy, with its name in the error if it can't be opened.
+fn open_taxonomy_file(path: &Path) -> Result<BufReader<File>> {
+    let file = File::open(path).context(format!("failed to open {}", path.display()))?;
+    Ok(BufReader::new(file))
+}
+
+/// Read the parent of each taxid from a nodes.dmp file.
+fn read_nodes(reader: impl BufRead) -> Result<HashMap<u32, u32>> {
+    let mut parents: HashMap<u32, u32> = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let fields = dmp_fields(&line);
+        if fields.len() < 2 {
+            return Err(MalformedTaxonomyError).context("failed to parse line in nodes.dmp");
+        }
+        parents.insert(fields[0].parse::<u32>()?, fields[1].parse::<u32>()?);
+    }
+    Ok(parents)
+}
+
+/// Read the scientific name of each taxid from a names.dmp file.
+fn read_names(reader: impl BufRead) -> Result<HashMap<u32, String>> {
+    let mut names: HashMap<u32, String> = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let fields = dmp_fields(&line);
+        if fields.len() < 4 {
+            return Err(MalformedTaxonomyError).context("failed to parse line in names.dmp");
+        }
+        if fields[3] == "scientific name" {
+            names.insert(fields[0].parse::<u32>()?, fields[1].to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Read the lines of two whitespace-delimited columns, a key and a value, whose
+/// other columns are ignored.
+fn read_key_values<V: FromStr>(reader: impl BufRead, file_name: &str) -> Result<Vec<(String, V)>>
+where
+    V::Err: std::error::Error + Send + Sync + 'static,
+{
+    let mut pairs: Vec<(String, V)> = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() < 2 {
+            return Err(MalformedTaxonomyError)
+                .context(format!("failed to parse line in {file_name}"));
+        }
+        pairs.push((tokens[0].to_string(), tokens[1].parse::<V>()?));
+    }
+    Ok(pairs)
+}
+
+/// The taxid of each target of an mmseqs taxonomy database, from the taxids of
+/// its keys in DB_mapping and the accessions of the keys in DB.lookup.
+fn mmseqs_target_taxids(
+    mapping: impl BufRead,
+    lookup: impl BufRead,
+) -> Result<HashMap<String, u32>> {
+    let accessions: HashMap<String, String> =
+        read_key_values(lookup, "lookup")?.into_iter().collect();
+
+    let key_taxids: Vec<(String, u32)> = read_key_values(mapping, "mapping")?;
+
+    let mut target_taxids: HashMap<String, u32> = HashMap::new();
+    for (key, taxid) in key_taxids {
+        if let Some(accession) = accessions.get(&key) {
+            target_taxids.insert(accession.clone(), taxid);
+        }
+    }
+    Ok(target_taxids)
+}
+
+impl Taxonomy {
+    /// Load a taxonomy from an NCBI dump directory or an mmseqs taxonomy database.
+    pub fn load(path: &Path) -> Result<Self> {
+        match path.is_dir() {
+            true => Self::load_ncbi_dump(path),
+            false => Self::load_mmseqs_db(path),
+        }
+    }
+
+    fn load_ncbi_dump(dir: &Path) -> Result<Self> {
+        Ok(Taxonomy {
+            parents: read_nodes(open_taxonomy_file(&dir.join("nodes.dmp"))?)?,
+            names: read_names(open_taxonomy_file(&dir.join("names.dmp"))?)?,
+            target_taxids: read_key_values(open_taxonomy_file(&dir.join("mapping"))?, "mapping")?
+                .into_iter()
+                .collect(),
+        })
+    }
+
+    fn load_mmseqs_db(db: &Path) -> Result<Self> {
+        let nodes = mmseqs_db_file(db, "_nodes.dmp");
+        let names = mmseqs_db_file(db, "_names.dmp");
+        if !nodes.exists() || !names.exists() {
+            return Err(MissingTaxonomyDumpError(db.to_path_buf()).into());
+        }
+
+        Ok(Taxonomy {
+            parents: read_nodes(open_taxonomy_file(&nodes)?)?,
+            names: read_names(open_taxonomy_file(&names)?)?,
+            target_taxids: mmseqs_target_taxids(
+                open_taxonomy_file(&mmseqs_db_file(db, "_mapping"))?,
+                open_taxonomy_file(&mmseqs_db_file(db, ".lookup"))?,
+            )?,
+        })
+    }
+
+    /// Look up the taxid of a target, using the first word of its name.
+    pub fn taxid(&self, target_name: &str) -> Option<u32> {
+        let accession = target_name.split_whitespace().next()?;
+        self.target_taxids.get(accession).copied()
+    }
+
+    /// The taxids from the root down to (and including) the given taxid.
+    pub fn lineage(&self, taxid: u32) -> Vec<u32> {
+        let mut lineage = vec![taxid];
+        let mut current = taxid;
+        while current != ROOT_TAXID {
+            match self.parents.get(&current) {
+                // guard against cycles in malformed taxonomies
+                Some(&parent) if parent != current && !lineage.contains(&parent) => {
+                    lineage.push(parent);
+                    current = parent;
+                }
+                _ => break,
+            }
+        }
+        lineage.reverse();
+        lineage
+    }
+
+    /// The lineage as a ';' delimited list of scientific names.
+    ///
+    /// Spaces are replaced with underscores so that the lineage can be written as a table value.
+    pub fn lineage_string(&self, taxid: u32) -> String {
+        self.lineage(taxid)
+            .iter()
+            .filter(|&&t| t != ROOT_TAXID)
+            .map(|t| match self.names.get(t) {
+                Some(name) => name.replace(' ', "_"),
+                None => t.to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join(";")
+    }
+}
+
+/// A set of taxa used to restrict which targets are searched.
+#[derive(Default, Clone)]
+pub struct TaxonFilter {
+    /// If not empty, only targets under one of these taxa are kept
+    pub include: Vec<u32>,
+    /// Targets under any of these taxa are removed
+    pub exclude: Vec<u32>,
+}
+
+impl TaxonFilter {
+    pub fn allows(&self, taxonomy: &Taxonomy, target_name: &str) -> bool {
+        let lineage = match taxonomy.taxid(target_name) {
+            Some(taxid) => taxonomy.lineage(taxid),
+            // targets without a taxid can only pass if we aren't restricting to specific taxa
+            None => return self.include.is_empty(),
+        };
+
+        if lineage.iter().any(|t| self.exclude.contains(t)) {
+            return false;
+        }
+
+        self.include.is_empty() || lineage.iter().any(|t| self.include.contains(t))
+    }
+}
+
+/// Copy the records of a fasta file that pass the taxon filter into a new fasta file.
+///
+/// Returns the number of records that were kept.
+pub fn filter_fasta(
+    fasta: &Path,
+    taxonomy: &Taxonomy,
+    filter: &TaxonFilter,
+    out: &mut impl Write,
+) -> Result<usize> {
+    let fasta_file = File::open(fasta).context("failed to open target fasta")?;
+
+    let mut keep = false;
+    let mut kept_count: usize = 0;
+    for line in BufReader::new(fasta_file).lines() {
+        let line = line?;
+        if let Some(header) = line.strip_prefix('>') {
+            keep = filter.allows(taxonomy, header);
+            if keep {
+                kept_count += 1;
+            }
+        }
+        if keep {
+            writeln!(out, "{line}")?;
+        }
+    }
+
+    Ok(kept_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small taxonomy: two bacteria under Proteobacteria, and a human under Eukaryota
+    fn taxonomy() -> Taxonomy {
+        let parents = [
+            (1, 1),
+            (2, 1),
+            (1224, 2),
+            (562, 1224),
+            (590, 1224),
+            (2759, 1),
+            (9606, 2759),
+        ];
+        let names = [
+            (2, "Bacteria"),
+            (1224, "Proteobacteria"),
+            (562, "Escherichia coli"),
+            (2759, "Eukaryota"),
+            (9606, "Homo sapiens"),
+        ];
+        let target_taxids = [("ecoli1", 562), ("salmonella1", 590), ("human1", 9606)];
+
+        Taxonomy {
+            parents: parents.into_iter().collect(),
+            names: names
+                .into_iter()
+                .map(|(taxid, name)| (taxid, name.to_string()))
+                .collect(),
+            target_taxids: target_taxids
+                .into_iter()
+                .map(|(name, taxid)| (name.to_string(), taxid))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn dmp_fields_are_split_on_tab_pipes_and_trimmed() {
+        assert_eq!(
+            dmp_fields("562\t|\tEscherichia coli\t|\t\t|\tscientific name\t|"),
+            ["562", "Escherichia coli", "", "scientific name", ""]
+        );
+    }
+
+    #[test]
+    fn mmseqs_databases_map_taxids_through_the_key_accessions() {
+        let mapping = "1\t562\n2\t9606\n3\t590\n";
+        // key 3 has no accession, and tgt4 has no taxid
+        let lookup = "1\tecoli1\t0\n2\thuman1\t0\n4\ttgt4\t0\n";
+
+        let target_taxids = mmseqs_target_taxids(mapping.as_bytes(), lookup.as_bytes()).unwrap();
+
+        assert_eq!(
+            target_taxids,
+            HashMap::from([("ecoli1".to_string(), 562), ("human1".to_string(), 9606)])
+        );
+        assert!(mmseqs_target_taxids("1\n".as_bytes(), lookup.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn targets_are_looked_up_by_the_first_word_of_their_name() {
+        let taxonomy = taxonomy();
+        assert_eq!(taxonomy.taxid("ecoli1 outer membrane protein"), Some(562));
+        assert_eq!(taxonomy.taxid("ecoli2"), None);
+        assert_eq!(taxonomy.taxid(""), None);
+    }
+
+    #[test]
+    fn lineages_run_from_the_root_down() {
+        let taxonomy = taxonomy();
+        assert_eq!(taxonomy.lineage(562), [1, 2, 1224, 562]);
+        assert_eq!(taxonomy.lineage(1), [1]);
+        // a taxid that isn't in the nodes is its own lineage
+        assert_eq!(taxonomy.lineage(42), [42]);
+    }
+
+    #[test]
+    fn lineages_stop_at_a_cycle() {
+        let mut taxonomy = taxonomy();
+        taxonomy.parents.insert(7, 8);
+        taxonomy.parents.insert(8, 7);
+        assert_eq!(taxonomy.lineage(7), [8, 7]);
+    }
+
+    #[test]
+    fn lineage_strings_name_each_taxon_below_the_root() {
+        let taxonomy = taxonomy();
+        assert_eq!(
+            taxonomy.lineage_string(562),
+            "Bacteria;Proteobacteria;Escherichia_coli"
+        );
+        // a taxon without a scientific name is written as its taxid
+        assert_eq!(taxonomy.lineage_string(590), "Bacteria;Proteobacteria;590");
+    }
+
+    #[test]
+    fn filters_include_and_exclude_whole_subtrees() {
+        let taxonomy = taxonomy();
+
+        let bacteria = TaxonFilter {
+            include: vec![2],
+            exclude: vec![],
+        };
+        assert!(bacteria.allows(&taxonomy, "ecoli1"));
+        assert!(bacteria.allows(&taxonomy, "salmonella1"));
+        assert!(!bacteria.allows(&taxonomy, "human1"));
+        assert!(!bacteria.allows(&taxonomy, "unmapped1"));
+
+        // an exclusion wins over an inclusion of an ancestor
+        let bacteria_but_ecoli = TaxonFilter {
+            include: vec![2],
+            exclude: vec![562],
+        };
+        assert!(!bacteria_but_ecoli.allows(&taxonomy, "ecoli1"));
+        assert!(bacteria_but_ecoli.allows(&taxonomy, "salmonella1"));
+
+        // without inclusions, the targets without a taxid are kept
+        let not_human = TaxonFilter {
+            include: vec![],
+            exclude: vec![9606],
+        };
+        assert!(not_human.allows(&taxonomy, "ecoli1"));
+        assert!(!not_human.allows(&taxonomy, "human1"));
+        assert!(not_human.allows(&taxonomy, "unmapped1"));
+    }
+}
@@ -0,0 +1,44 @@
+//! Temporary files for the unit tests that read and write files, and the
+//! fixtures they share with the integration tests.
+
+use std::fs::write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use nale::structs::hmm::{parse_hmms_from_p7hmm_file, Hmm};
+use nale::structs::Profile;
+
+/// The number of temporary paths handed out so far by this test run
+static TEMP_PATH_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A path in the temporary directory that no other test, or test run, is given.
+///
+/// The name is kept at the end of the file name, so that its extension is the file's.
+pub fn temp_path(name: &str) -> PathBuf {
+    let count = TEMP_PATH_COUNT.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!(
+        "mmoreseqs-test-{}-{count}-{name}",
+        std::process::id()
+    ))
+}
+
+/// A file at a temporary path of its own, with the given contents.
+pub fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let path = temp_path(name);
+    write(&path, contents).unwrap();
+    path
+}
+
+/// The first HMM of the integration tests' query fixture.
+pub fn fixture_hmm() -> Hmm {
+    let hmm_path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/integration/fixtures/query.hmm"
+    );
+    parse_hmms_from_p7hmm_file(hmm_path).unwrap().remove(0)
+}
+
+/// The profile of the integration tests' query fixture.
+pub fn fixture_profile() -> Profile {
+    Profile::new(&fixture_hmm())
+}
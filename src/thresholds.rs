@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("invalid threshold table: {0}")]
+pub struct InvalidThresholdTableError(String);
+
+#[derive(Error, Debug)]
+#[error("the threshold table sets inclusion E-values, which need --incE or --incT")]
+pub struct InclusionNotEnabledError;
+
+/// Per-query E-value reporting and inclusion thresholds, keyed by profile accession.
+#[derive(Debug, Default)]
+pub struct ThresholdTable {
+    evalue_cutoffs: HashMap<String, f32>,
+    inclusion_cutoffs: HashMap<String, f32>,
+}
+
+impl ThresholdTable {
+    /// Read a threshold table.
+    ///
+    /// Each line holds a query accession, its reporting E-value threshold, and
+    /// optionally its inclusion E-value threshold, separated by whitespace, and
+    /// lines starting with '#' are ignored.
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = File::open(path).context(format!(
+            "failed to open threshold table: {}",
+            path.to_string_lossy()
+        ))?;
+
+        let mut evalue_cutoffs = HashMap::new();
+        let mut inclusion_cutoffs = HashMap::new();
+
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let (accession, evalue, inclusion_evalue) = match tokens[..] {
+                [accession, evalue] => (accession, evalue, None),
+                [accession, evalue, inclusion_evalue] => {
+                    (accession, evalue, Some(inclusion_evalue))
+                }
+                _ => {
+                    return Err(
+                        InvalidThresholdTableError(format!("malformed line: {line}")).into(),
+                    )
+                }
+            };
+
+            let parse_evalue = |evalue: &str| {
+                evalue
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|e| *e >= 0.0)
+                    .ok_or_else(|| InvalidThresholdTableError(format!("invalid E-value: {evalue}")))
+            };
+            let evalue = parse_evalue(evalue)?;
+            if let Some(inclusion_evalue) = inclusion_evalue {
+                inclusion_cutoffs.insert(accession.to_string(), parse_evalue(inclusion_evalue)?);
+            }
+
+            if evalue_cutoffs
+                .insert(accession.to_string(), evalue)
+                .is_some()
+            {
+                return Err(InvalidThresholdTableError(format!(
+                    "duplicate accession: {accession}"
+                ))
+                .into());
+            }
+        }
+
+        Ok(ThresholdTable {
+            evalue_cutoffs,
+            inclusion_cutoffs,
+        })
+    }
+
+    /// The E-value threshold of a query, or the default if it isn't listed.
+    pub fn evalue_cutoff(&self, accession: &str, default: f32) -> f32 {
+        self.evalue_cutoffs
+            .get(accession)
+            .copied()
+            .unwrap_or(default)
+    }
+
+    /// The inclusion E-value threshold of a query, or the default if it isn't listed.
+    pub fn inclusion_cutoff(&self, accession: &str, default: Option<f32>) -> Option<f32> {
+        self.inclusion_cutoffs.get(accession).copied().or(default)
+    }
+
+    /// Whether any query has its own inclusion threshold
+    pub fn has_inclusion_cutoffs(&self) -> bool {
+        !self.inclusion_cutoffs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::temp_file;
+    use std::fs::remove_file;
+
+    #[test]
+    fn listed_queries_get_their_own_threshold() {
+        let path = temp_file(
+            "listed",
+            "# accession evalue\n\nPF00001.1\t1e-5\n  PF00002.3   0.5  \n",
+        );
+        let table = ThresholdTable::read(&path).unwrap();
+        remove_file(&path).unwrap();
+
+        assert_eq!(table.evalue_cutoff("PF00001.1", 10.0), 1e-5);
+        assert_eq!(table.evalue_cutoff("PF00002.3", 10.0), 0.5);
+        assert_eq!(table.evalue_cutoff("PF00003.1", 10.0), 10.0);
+        assert!(!table.has_inclusion_cutoffs());
+    }
+
+    #[test]
+    fn listed_queries_may_get_their_own_inclusion_threshold() {
+        let path = temp_file("inclusion", "PF00001.1 1e-5 1e-8\nPF00002.3 0.5\n");
+        let table = ThresholdTable::read(&path).unwrap();
+        remove_file(&path).unwrap();
+
+        assert!(table.has_inclusion_cutoffs());
+        assert_eq!(table.evalue_cutoff("PF00001.1", 10.0), 1e-5);
+        assert_eq!(table.inclusion_cutoff("PF00001.1", Some(0.01)), Some(1e-8));
+        assert_eq!(table.inclusion_cutoff("PF00002.3", Some(0.01)), Some(0.01));
+        assert_eq!(table.inclusion_cutoff("PF00002.3", None), None);
+    }
+
+    #[test]
+    fn malformed_tables_are_refused() {
+        for (name, contents) in [
+            ("missing", "PF00001.1\n"),
+            ("extra", "PF00001.1 1e-5 1e-8 extra\n"),
+            ("unparsable inclusion", "PF00001.1 1e-5 small\n"),
+            ("unparsable", "PF00001.1 small\n"),
+            ("negative", "PF00001.1 -1\n"),
+            ("duplicate", "PF00001.1 1e-5\nPF00001.1 1e-3\n"),
+        ] {
+            let path = temp_file(name, contents);
+            let result = ThresholdTable::read(&path);
+            remove_file(&path).unwrap();
+
+            let err = result.unwrap_err();
+            assert!(
+                err.downcast_ref::<InvalidThresholdTableError>().is_some(),
+                "{name}: {err}"
+            );
+        }
+    }
+}
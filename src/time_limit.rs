@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+/// The exit status of a run that stopped at its time limit, after writing its partial results
+pub const TIME_LIMIT_EXIT_CODE: i32 = 3;
+
+#[derive(Error, Debug)]
+#[error(
+    "the time limit was reached before {0} queries were fully searched; their results are partial"
+)]
+pub struct TimeLimitReachedError(pub usize);
+
+/// Parse a duration for --time-limit: seconds, a number with a unit of s, m, h,
+/// or d (e.g. 90m), or a walltime as H:MM:SS or D-HH:MM:SS, as schedulers give it.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("{value} is not a duration, e.g. 3600, 90m, 12h, or 1-12:00:00");
+
+    let seconds: f64 = match value.contains(':') {
+        true => {
+            let (days, clock) = match value.split_once('-') {
+                Some((days, clock)) => (days.parse::<f64>().map_err(|_| invalid())?, clock),
+                None => (0.0, value),
+            };
+            let mut seconds = 0.0;
+            for field in clock.split(':') {
+                seconds = seconds * 60.0 + field.parse::<f64>().map_err(|_| invalid())?;
+            }
+            days * 86400.0 + seconds
+        }
+        false => {
+            let (number, unit_seconds) = match value.char_indices().last() {
+                Some((idx, 's')) => (&value[..idx], 1.0),
+                Some((idx, 'm')) => (&value[..idx], 60.0),
+                Some((idx, 'h')) => (&value[..idx], 3600.0),
+                Some((idx, 'd')) => (&value[..idx], 86400.0),
+                _ => (value, 1.0),
+            };
+            number.parse::<f64>().map_err(|_| invalid())? * unit_seconds
+        }
+    };
+
+    match seconds.is_finite() && seconds > 0.0 {
+        true => Ok(Duration::from_secs_f64(seconds)),
+        false => Err(invalid()),
+    }
+}
+
+/// The time limits of a run, from --time-limit and --per-query-time-limit.
+///
+/// The run's limit counts from when mmoreseqs started, so that it can be set to
+/// the job's walltime less the time it takes to write the results.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeLimits {
+    deadline: Option<Instant>,
+    per_query: Option<Duration>,
+}
+
+impl TimeLimits {
+    pub fn new(time_limit: Option<Duration>, per_query_time_limit: Option<Duration>) -> Self {
+        TimeLimits {
+            deadline: time_limit.map(|limit| Instant::now() + limit),
+            per_query: per_query_time_limit,
+        }
+    }
+
+    /// Drop the run's time limit, for a server, which has no walltime
+    pub fn clear_deadline(&mut self) {
+        self.deadline = None;
+    }
+
+    /// Whether the run is past its time limit
+    pub fn expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Whether a query that has been aligned for this long is past its time limit
+    pub fn query_expired(&self, elapsed: Duration) -> bool {
+        self.per_query.is_some_and(|limit| elapsed >= limit)
+    }
+}
@@ -0,0 +1,218 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::container::Container;
+
+/// The environment variable that gives the path to mmseqs, if --mmseqs-path doesn't
+pub const MMSEQS_ENV_VAR: &str = "MMORESEQS_MMSEQS";
+
+/// The environment variable that gives the path to hmmbuild, if --hmmer-path doesn't
+pub const HMMBUILD_ENV_VAR: &str = "MMORESEQS_HMMBUILD";
+
+#[derive(Error, Debug)]
+#[error("the {tool} path given by {given_by} ({}) {problem}", path.to_string_lossy())]
+pub struct ToolPathError {
+    tool: &'static str,
+    given_by: &'static str,
+    path: PathBuf,
+    problem: &'static str,
+}
+
+/// An external tool, and where its path came from
+#[derive(Debug, Clone)]
+pub struct Tool {
+    pub path: PathBuf,
+    /// The flag or environment variable that gave the path, or None if it is looked up in the PATH
+    pub given_by: Option<&'static str>,
+    /// The flag that gives the path
+    pub flag: &'static str,
+}
+
+impl Tool {
+    fn on_path(name: &str, flag: &'static str) -> Self {
+        Tool {
+            path: PathBuf::from(name),
+            given_by: None,
+            flag,
+        }
+    }
+
+    /// The tool at the path given by a flag, or else by an environment variable.
+    fn given(flag: &'static str, flag_value: Option<&str>, env_var: &'static str) -> Option<Self> {
+        match flag_value {
+            Some(path) => Some(Tool {
+                path: PathBuf::from(path),
+                given_by: Some(flag),
+                flag,
+            }),
+            None => std::env::var_os(env_var)
+                .filter(|path| !path.is_empty())
+                .map(|path| Tool {
+                    path: PathBuf::from(path),
+                    given_by: Some(env_var),
+                    flag,
+                }),
+        }
+    }
+
+    /// Check that a given path is an executable file; a tool looked up in the PATH isn't checked.
+    fn validate(&self, tool: &'static str) -> Result<()> {
+        let Some(given_by) = self.given_by else {
+            return Ok(());
+        };
+        let problem = if !self.path.exists() {
+            Some("doesn't exist")
+        } else if !self.path.is_file() {
+            Some("isn't a file")
+        } else if !is_executable(&self.path) {
+            Some("isn't executable")
+        } else {
+            None
+        };
+
+        match problem {
+            Some(problem) => Err(ToolPathError {
+                tool,
+                given_by,
+                path: self.path.clone(),
+                problem,
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|metadata| metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    true
+}
+
+/// The paths of the external tools that mmoreseqs runs.
+///
+/// Each is looked up in the PATH unless it is given by a flag or an environment
+/// variable, for clusters that load tools as modules and containers that install
+/// them somewhere unusual.
+#[derive(Debug, Clone)]
+pub struct ToolPaths {
+    pub mmseqs: Tool,
+    pub hmmbuild: Tool,
+    /// hmmconvert is always the one that sits next to hmmbuild
+    pub hmmconvert: Tool,
+    /// The container image that the tools are run in, if any
+    pub container: Option<Container>,
+}
+
+impl Default for ToolPaths {
+    fn default() -> Self {
+        ToolPaths {
+            mmseqs: Tool::on_path("mmseqs", "--mmseqs-path"),
+            hmmbuild: Tool::on_path("hmmbuild", "--hmmer-path"),
+            hmmconvert: Tool::on_path("hmmconvert", "--hmmer-path"),
+            container: None,
+        }
+    }
+}
+
+impl ToolPaths {
+    /// The tool paths from the --mmseqs-path and --hmmer-path flags, then the
+    /// environment variables. The HMMER path is either the hmmbuild binary or
+    /// the directory that holds the HMMER binaries.
+    pub fn new(mmseqs_path: Option<&str>, hmmer_path: Option<&str>) -> Self {
+        let mut tools = ToolPaths::default();
+
+        if let Some(mmseqs) = Tool::given("--mmseqs-path", mmseqs_path, MMSEQS_ENV_VAR) {
+            tools.mmseqs = mmseqs;
+        }
+
+        if let Some(mut hmmbuild) = Tool::given("--hmmer-path", hmmer_path, HMMBUILD_ENV_VAR) {
+            if hmmbuild.path.is_dir() {
+                hmmbuild.path = hmmbuild.path.join("hmmbuild");
+            }
+            let hmmer_dir = hmmbuild.path.parent().unwrap_or(Path::new(""));
+            tools.hmmconvert = Tool {
+                path: hmmer_dir.join("hmmconvert"),
+                ..hmmbuild.clone()
+            };
+            tools.hmmbuild = hmmbuild;
+        }
+
+        tools
+    }
+
+    /// Check the paths that were given; hmmconvert is only needed for
+    /// hmmpress-ed queries, so its absence is left for that step to report.
+    /// The paths inside a container can't be checked from the host.
+    pub fn validate(&self) -> Result<()> {
+        if self.container.is_some() {
+            return Ok(());
+        }
+        self.mmseqs.validate("mmseqs")?;
+        self.hmmbuild.validate("hmmbuild")
+    }
+
+    pub fn mmseqs(&self) -> Command {
+        Command::new(&self.mmseqs.path)
+    }
+
+    pub fn hmmbuild(&self) -> Command {
+        Command::new(&self.hmmbuild.path)
+    }
+
+    pub fn hmmconvert(&self) -> Command {
+        Command::new(&self.hmmconvert.path)
+    }
+
+    /// What to tell the user when a tool doesn't run at all.
+    pub fn failed_to_run(&self, name: &str, tool: &Tool) -> String {
+        match (&self.container, tool.given_by) {
+            (Some(container), _) => format!("{name} failed to run in {}", container.image()),
+            (None, Some(given_by)) => format!("the {name} given by {given_by} failed to run"),
+            (None, None) => format!(
+                "{name} does not appear to be in the system path; give its path with {}",
+                tool.flag
+            ),
+        }
+    }
+
+    /// The flags that pass the tool paths and container on to another mmoreseqs run.
+    pub fn cli_args(&self) -> Vec<OsString> {
+        let mut cli_args: Vec<OsString> = vec![];
+        for (flag, tool) in [
+            ("--mmseqs-path", &self.mmseqs),
+            ("--hmmer-path", &self.hmmbuild),
+        ] {
+            if tool.given_by.is_some() {
+                cli_args.push(flag.into());
+                cli_args.push(tool.path.clone().into());
+            }
+        }
+        if let Some(container) = &self.container {
+            cli_args.push(container.flag().into());
+            cli_args.push(container.image().into());
+        }
+        cli_args
+    }
+
+    /// The command that runs a tool directly, inside the container if there is one,
+    /// for checks that don't go through the runner.
+    pub fn direct_command(&self, tool: &Tool) -> Result<Command> {
+        let command = Command::new(&tool.path);
+        match &self.container {
+            Some(container) => container.wrap(&command),
+            None => Ok(command),
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::io::Write;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use nale::structs::{Alignment, Trace};
+use serde::Serialize;
+
+use crate::alignment_output::AlignedHit;
+use crate::provenance::Provenance;
+
+/// The version of the trace file layouts, which changes with any change to either
+const TRACE_FORMAT_VERSION: u32 = 2;
+
+/// The first bytes of a binary trace file
+const BINARY_MAGIC: &[u8; 8] = b"MMSTRACE";
+
+/// The one letter code of each nale trace state, by state index
+const STATE_CODES: &[u8; 12] = b"?MDISNBECTJX";
+
+/// The format of the --trace-output file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TraceFormat {
+    /// One JSON document, with the run's provenance and a record per hit
+    #[default]
+    Json,
+    /// A compact little-endian layout of the same records, without the provenance
+    Binary,
+}
+
+/// The state path of a hit's alignment, from its traceback
+#[derive(Debug, Clone)]
+pub struct HitTrace {
+    /// The one letter code of each state: S, N, B, M, I, D, E, J, C, or T
+    states: String,
+    /// The profile position of each state, or 0 for the states outside of the model
+    profile_positions: Vec<u32>,
+    /// The target position of each state, or 0 for the states that don't emit a residue
+    target_positions: Vec<u32>,
+    /// The posterior probability of each state's emission, or 0 for those that don't emit
+    posteriors: Vec<f32>,
+}
+
+impl HitTrace {
+    pub fn new(trace: &Trace) -> Self {
+        let steps = 0..trace.length;
+        HitTrace {
+            states: steps
+                .clone()
+                .map(|step| STATE_CODES[trace.states[step]] as char)
+                .collect(),
+            profile_positions: steps
+                .clone()
+                .map(|step| trace.profile_idx[step] as u32)
+                .collect(),
+            target_positions: steps
+                .clone()
+                .map(|step| trace.target_idx[step] as u32)
+                .collect(),
+            posteriors: steps
+                .map(|step| trace.posterior_probabilities[step])
+                .collect(),
+        }
+    }
+}
+
+/// A hit and its state path, as it is written to a JSON trace file
+#[derive(Serialize)]
+struct TraceRecord<'a> {
+    hit_id: &'a str,
+    target_name: &'a str,
+    profile_name: &'a str,
+    profile_accession: &'a str,
+    target_start: usize,
+    target_end: usize,
+    profile_start: usize,
+    profile_end: usize,
+    bit_score: f32,
+    evalue: f32,
+    states: &'a str,
+    profile_positions: &'a [u32],
+    target_positions: &'a [u32],
+    posteriors: &'a [f32],
+}
+
+#[derive(Serialize)]
+struct TraceFile<'a> {
+    format_version: u32,
+    provenance: &'a Provenance,
+    hits: Vec<TraceRecord<'a>>,
+}
+
+/// Write a string with its length as a u16.
+fn write_binary_str(value: &str, out: &mut impl Write) -> Result<()> {
+    let bytes = &value.as_bytes()[..value.len().min(u16::MAX as usize)];
+    out.write_all(&(bytes.len() as u16).to_le_bytes())?;
+    out.write_all(bytes)?;
+    Ok(())
+}
+
+/// Write the records in the binary layout, in which every number is little-endian:
+///
+/// - the magic bytes `MMSTRACE`, the u32 format version, and the u32 number of hits
+/// - for each hit: its identifier, target name, profile name, and profile accession,
+///   each a u16 length and UTF-8 bytes; its target start and end and profile start and end as
+///   u32s; its bit score and E-value as f32s; and the u32 number of steps
+/// - then, for each hit, its state codes as one byte per step, and its profile
+///   positions, target positions (u32s), and posteriors (f32s) in step order
+fn write_binary(records: &[TraceRecord], out: &mut impl Write) -> Result<()> {
+    out.write_all(BINARY_MAGIC)?;
+    out.write_all(&TRACE_FORMAT_VERSION.to_le_bytes())?;
+    out.write_all(&(records.len() as u32).to_le_bytes())?;
+
+    for record in records {
+        write_binary_str(record.hit_id, out)?;
+        write_binary_str(record.target_name, out)?;
+        write_binary_str(record.profile_name, out)?;
+        write_binary_str(record.profile_accession, out)?;
+        for coordinate in [
+            record.target_start,
+            record.target_end,
+            record.profile_start,
+            record.profile_end,
+        ] {
+            out.write_all(&(coordinate as u32).to_le_bytes())?;
+        }
+        out.write_all(&record.bit_score.to_le_bytes())?;
+        out.write_all(&record.evalue.to_le_bytes())?;
+        out.write_all(&(record.states.len() as u32).to_le_bytes())?;
+
+        out.write_all(record.states.as_bytes())?;
+        for position in record.profile_positions {
+            out.write_all(&position.to_le_bytes())?;
+        }
+        for position in record.target_positions {
+            out.write_all(&position.to_le_bytes())?;
+        }
+        for posterior in record.posteriors {
+            out.write_all(&posterior.to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the state path of each hit, so that its alignment can be rebuilt
+/// exactly, or rescored, downstream.
+pub fn write_traces(
+    alignments: &[Alignment],
+    aligned_hits: &[AlignedHit],
+    provenance: &Provenance,
+    format: TraceFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    let records: Vec<TraceRecord> = alignments
+        .iter()
+        .zip(aligned_hits)
+        .filter_map(|(alignment, hit)| {
+            let trace = hit.trace.as_ref()?;
+            Some(TraceRecord {
+                hit_id: &hit.id,
+                target_name: &alignment.target_name,
+                profile_name: &alignment.profile_name,
+                profile_accession: &hit.profile_accession,
+                target_start: alignment.target_start,
+                target_end: alignment.target_end,
+                profile_start: alignment.profile_start,
+                profile_end: alignment.profile_end,
+                bit_score: alignment.bit_score,
+                evalue: alignment.evalue,
+                states: &trace.states,
+                profile_positions: &trace.profile_positions,
+                target_positions: &trace.target_positions,
+                posteriors: &trace.posteriors,
+            })
+        })
+        .collect();
+
+    match format {
+        TraceFormat::Json => {
+            serde_json::to_writer(
+                &mut *out,
+                &TraceFile {
+                    format_version: TRACE_FORMAT_VERSION,
+                    provenance,
+                    hits: records,
+                },
+            )?;
+            writeln!(out)?;
+        }
+        TraceFormat::Binary => write_binary(&records, out)?,
+    }
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::doctor::discover_version;
+use crate::tools::{Tool, ToolPaths};
+
+/// The version of nale that mmoreseqs was built with, from the lock file
+pub const NALE_VERSION: Option<&str> = option_env!("MMORESEQS_NALE_VERSION");
+
+/// An external tool as it was found at runtime
+#[derive(Serialize)]
+struct ToolVersion {
+    path: String,
+    /// Null when the tool couldn't be run
+    version: Option<String>,
+}
+
+impl ToolVersion {
+    fn discover(tools: &ToolPaths, name: &str, tool: &Tool, args: &[&str], pattern: &str) -> Self {
+        ToolVersion {
+            path: tool.path.to_string_lossy().to_string(),
+            version: discover_version(tools, name, tool, args, pattern).ok(),
+        }
+    }
+}
+
+/// The build of mmoreseqs, and the versions of the tools that it would run
+#[derive(Serialize)]
+struct VersionInfo {
+    mmoreseqs: &'static str,
+    git_commit: Option<&'static str>,
+    nale: Option<&'static str>,
+    build_profile: Option<&'static str>,
+    target: Option<&'static str>,
+    features: Vec<&'static str>,
+    mmseqs: ToolVersion,
+    hmmer: ToolVersion,
+}
+
+/// Print the versions as one JSON document, for orchestration systems to record.
+///
+/// The tools are found as they would be for a run: from the environment
+/// variables, or else on the PATH.
+pub fn print_version_json() -> Result<()> {
+    let tools = ToolPaths::new(None, None);
+    let info = VersionInfo {
+        mmoreseqs: env!("CARGO_PKG_VERSION"),
+        git_commit: option_env!("MMORESEQS_GIT_COMMIT"),
+        nale: NALE_VERSION,
+        build_profile: option_env!("MMORESEQS_BUILD_PROFILE"),
+        target: option_env!("MMORESEQS_BUILD_TARGET"),
+        features: env!("MMORESEQS_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .collect(),
+        mmseqs: ToolVersion::discover(&tools, "mmseqs", &tools.mmseqs, &["version"], ""),
+        hmmer: ToolVersion::discover(&tools, "hmmbuild", &tools.hmmbuild, &["-h"], "HMMER"),
+    };
+    println!("{}", serde_json::to_string_pretty(&info)?);
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use std::fs::{create_dir_all, remove_file};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use thiserror::Error;
+
+use crate::FilePaths;
+
+#[derive(Error, Debug)]
+#[error("{stage} step is missing {path}; has mmoreseqs prep been run on this directory?")]
+pub struct MissingArtifactError {
+    stage: &'static str,
+    path: PathBuf,
+}
+
+#[derive(Error, Debug)]
+#[error("{0:?} can't be used as a file name")]
+pub struct UnsafeFileNameError(String);
+
+/// The stages of the pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Stage {
+    /// Build the mmseqs databases and the query HMM
+    Prep,
+    /// Find alignment seeds with mmseqs
+    Seed,
+    /// Align the seeds with bounded profile HMM DP
+    Align,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Prep => "prep",
+            Stage::Seed => "seed",
+            Stage::Align => "align",
+        }
+    }
+}
+
+/// The path of a file that mmseqs keeps next to a database, e.g. DB.index for the suffix ".index".
+pub fn mmseqs_db_file(db: &Path, suffix: &str) -> PathBuf {
+    let mut path = db.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Make a name safe to use as a single path component, by replacing every character
+/// but ASCII letters, digits, '.', '-', and '_' with '_'. The names that don't leave
+/// a file of their own, "", "." and "..", are rejected.
+pub fn safe_file_name(name: &str) -> Result<String> {
+    let safe_name: String = name
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect();
+    match &safe_name[..] {
+        "" | "." | ".." => Err(UnsafeFileNameError(name.to_string()).into()),
+        _ => Ok(safe_name),
+    }
+}
+
+/// Set the query mmseqs database, and the files that mmseqs keeps next to it.
+pub fn set_query_db(paths: &mut FilePaths, query_db: PathBuf) {
+    paths.query_db_index = mmseqs_db_file(&query_db, ".index");
+    paths.query_db_h = mmseqs_db_file(&query_db, "_h");
+    paths.query_db_h_index = mmseqs_db_file(&query_db, "_h.index");
+    paths.query_db = query_db;
+}
+
+/// Set the target mmseqs database, and the files that mmseqs keeps next to it.
+pub fn set_target_db(paths: &mut FilePaths, target_db: PathBuf) {
+    paths.target_db_index = mmseqs_db_file(&target_db, ".index");
+    paths.target_db_lookup = mmseqs_db_file(&target_db, ".lookup");
+    paths.target_db_kmer_index = mmseqs_db_file(&target_db, ".idx");
+    paths.target_db = target_db;
+}
+
+/// A directory that holds the intermediate files shared between the pipeline steps.
+///
+/// This is the single place that the names of the artifacts are derived. They
+/// are filled into the FilePaths, which the steps then read them from.
+#[derive(Debug, Default, Clone)]
+pub struct Workspace {
+    pub dir: PathBuf,
+}
+
+impl Workspace {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Workspace {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn create(&self) -> Result<()> {
+        create_dir_all(&self.dir).context(format!(
+            "failed to create workspace directory: {}",
+            self.dir.to_string_lossy()
+        ))
+    }
+
+    /// Set all of the workspace artifact paths.
+    ///
+    /// The seeds path is also set, but may be overridden by the caller.
+    pub fn fill_paths(&self, paths: &mut FilePaths) {
+        self.fill_query_paths(paths);
+        self.fill_target_paths(paths);
+    }
+
+    /// Set the paths of the artifacts built from the query, and of the seed
+    /// search, leaving the target paths to point at another workspace's.
+    pub fn fill_query_paths(&self, paths: &mut FilePaths) {
+        set_query_db(paths, self.dir.join("queryDB"));
+        paths.query_msa_db = self.dir.join("msaDB");
+        paths.query_hmm = self.dir.join("query.hmm");
+        paths.query_stockholm = self.dir.join("query.sto");
+        // the query MSA, with an accession added to each alignment that lacked one
+        paths.query_keyed_stockholm = self.dir.join("query.keyed.sto");
+        paths.query_consensus_fasta = self.dir.join("consensus.fa");
+        // the consensus sequences of the query profiles, as mmseqs computes them
+        paths.query_profile_consensus_db = self.dir.join("queryConsensusDB");
+        paths.query_profile_consensus_fasta = self.dir.join("queryConsensus.fa");
+        paths.prefilter_db = self.dir.join("prefilterDB");
+        paths.align_db = self.dir.join("alignDB");
+        paths.seeds = self.dir.join("seeds.tsv");
+    }
+
+    /// Set the paths of the artifacts built from the target.
+    pub fn fill_target_paths(&self, paths: &mut FilePaths) {
+        set_target_db(paths, self.dir.join("targetDB"));
+        // the keys of the targets selected with --target-include-ids
+        paths.target_subset_keys = self.dir.join("targetSubset.keys");
+        paths.target_subset_db = self.dir.join("targetSubsetDB");
+        // the checksum of the target fasta that the target database was built from
+        paths.target_checksum = self.dir.join("target.checksum");
+        // the target fasta files, concatenated, when there is more than one
+        paths.combined_target_fasta = self.dir.join("targets.combined.fa");
+        paths.target_sources = self.dir.join("target-sources.tsv");
+        // the translations of the ORFs called on the target contigs, with --target-type contigs
+        paths.target_orfs = self.dir.join("target.orfs.fa");
+        paths.orf_map = self.dir.join("orf-map.tsv");
+        // the targets with the hits of a previous run masked, with --mask-previous
+        paths.masked_target_fasta = self.dir.join("target.masked.fa");
+        paths.filtered_target_fasta = self.dir.join("target.fa");
+        // the local copy of a target fasta that is read from a URL or an object store
+        paths.remote_target_fasta = self.dir.join("target.remote.fa");
+        paths.target_index = self.dir.join("target.index.tsv");
+        // the seeded targets, fetched from the remote target fasta when there is no local copy
+        paths.fetched_target_fasta = self.dir.join("target.fetched.fa");
+    }
+
+    /// Check that the artifacts that a stage reads exist.
+    pub fn validate(&self, stage: Stage, paths: &FilePaths) -> Result<()> {
+        let required = match stage {
+            Stage::Prep => vec![],
+            Stage::Seed => vec![
+                &paths.query_db,
+                &paths.query_db_index,
+                &paths.target_db_index,
+            ],
+            Stage::Align => vec![
+                &paths.query_db,
+                &paths.query_db_index,
+                &paths.query_db_h,
+                &paths.query_db_h_index,
+            ],
+        };
+
+        require(stage.name(), required)
+    }
+
+    /// Remove the artifacts that a stage was writing when it was cancelled, so
+    /// that the next run finds the stage missing instead of half written, and
+    /// the stage can be run again.
+    pub fn discard_partial(&self, stage: Stage, paths: &FilePaths) -> Result<()> {
+        let partial = match stage {
+            Stage::Prep => vec![
+                &paths.query_db_index,
+                &paths.query_db_h_index,
+                &paths.query_hmm,
+                &paths.target_db_index,
+                &paths.target_checksum,
+            ],
+            Stage::Seed => vec![&paths.seeds],
+            Stage::Align => vec![],
+        };
+
+        for path in partial {
+            match remove_file(path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => {
+                    return Err(err).with_context(|| format!("failed to remove {path:?}"));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the paths hold a target database that can be served.
+    pub fn validate_target_index(&self, paths: &FilePaths) -> Result<()> {
+        require("serve", vec![&paths.target_db, &paths.target_db_index])
+    }
+}
+
+fn require(stage_name: &'static str, required: Vec<&PathBuf>) -> Result<()> {
+    match required.into_iter().find(|path| !path.exists()) {
+        Some(path) => Err(MissingArtifactError {
+            stage: stage_name,
+            path: path.clone(),
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_kept_to_a_single_path_component() {
+        assert_eq!(safe_file_name("PF00001.23").unwrap(), "PF00001.23");
+        assert_eq!(safe_file_name("../etc/passwd").unwrap(), ".._etc_passwd");
+        assert_eq!(safe_file_name("a b\\c").unwrap(), "a_b_c");
+        for name in ["", ".", ".."] {
+            assert!(safe_file_name(name).is_err());
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::ffi::OsStr;
+use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Set to regenerate the golden files from the current outputs, instead of comparing against them
+pub const UPDATE_GOLDEN_ENV_VAR: &str = "MMORESEQS_UPDATE_GOLDEN";
+
+/// The directory of the integration test suite
+fn suite_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("integration")
+}
+
+fn fixture_dir() -> PathBuf {
+    suite_dir().join("fixtures")
+}
+
+/// The path of a committed input fixture, as an argument for mmoreseqs
+pub fn fixture(name: &str) -> String {
+    fixture_dir().join(name).to_string_lossy().to_string()
+}
+
+/// The stand-in external tools, which replay the recorded outputs in the fixtures
+pub fn mock_tool(name: &str) -> PathBuf {
+    suite_dir().join("mock").join(name)
+}
+
+/// A scratch directory that mmoreseqs is run in, with the mock tools in place of mmseqs and HMMER.
+pub struct Harness {
+    dir: PathBuf,
+}
+
+impl Harness {
+    /// A fresh scratch directory for a test; the name keeps parallel tests apart.
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "mmoreseqs-integration-{name}-{}",
+            std::process::id()
+        ));
+        if dir.exists() {
+            remove_dir_all(&dir).expect("failed to clear the scratch directory");
+        }
+        create_dir_all(&dir).expect("failed to create the scratch directory");
+        Harness { dir }
+    }
+
+    /// The path of a file in the scratch directory
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    fn tools_log(&self) -> PathBuf {
+        self.path("tools.log")
+    }
+
+    /// Run mmoreseqs in the scratch directory with the mock tools, and
+    /// return its output, failing the test if it doesn't succeed.
+    pub fn run<I, S>(&self, args: I) -> Output
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let output = self.run_unchecked(args);
+        assert!(
+            output.status.success(),
+            "mmoreseqs failed: {}\nstdout:\n{}\nstderr:\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output
+    }
+
+    /// Run mmoreseqs in the scratch directory with the mock tools, and return
+    /// its output, whether or not it succeeds.
+    pub fn run_unchecked<I, S>(&self, args: I) -> Output
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.run_with_env(args, &[])
+    }
+
+    /// Run mmoreseqs like `run_unchecked`, with extra environment variables
+    /// that change how the mock tools behave.
+    pub fn run_with_env<I, S>(&self, args: I, vars: &[(&str, &str)]) -> Output
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Command::new(env!("CARGO_BIN_EXE_mmoreseqs"))
+            .args(args)
+            .arg("--mmseqs-path")
+            .arg(mock_tool("mmseqs"))
+            .arg("--hmmer-path")
+            .arg(mock_tool("hmmbuild"))
+            .current_dir(&self.dir)
+            .env("MOCK_TOOLS_LOG", self.tools_log())
+            .env_remove("MMORESEQS_MMSEQS")
+            .env_remove("MMORESEQS_HMMBUILD")
+            .envs(vars.iter().copied())
+            .output()
+            .expect("failed to run mmoreseqs")
+    }
+
+    /// Run mmoreseqs with the mock tools given by their environment variables
+    /// instead of by flags, for the commands that don't take the tool flags, and
+    /// for the options that can't be given with others.
+    pub fn run_with_tool_env<I, S>(&self, args: I) -> Output
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Command::new(env!("CARGO_BIN_EXE_mmoreseqs"))
+            .args(args)
+            .current_dir(&self.dir)
+            .env("MOCK_TOOLS_LOG", self.tools_log())
+            .env("MMORESEQS_MMSEQS", mock_tool("mmseqs"))
+            .env("MMORESEQS_HMMBUILD", mock_tool("hmmbuild"))
+            .output()
+            .expect("failed to run mmoreseqs")
+    }
+
+    /// Read a file that mmoreseqs wrote, with the paths of the fixtures made
+    /// relative, so that it doesn't depend on where the repository is.
+    pub fn read(&self, name: &str) -> String {
+        let contents = read_to_string(self.path(name))
+            .unwrap_or_else(|_| panic!("mmoreseqs didn't write {name}"));
+        contents.replace(&fixture(""), "$FIXTURES/")
+    }
+
+    /// The external tool commands that mmoreseqs ran, one per line. They are
+    /// sorted, since prep builds the query and target databases at the same time.
+    pub fn tool_commands(&self) -> String {
+        if !self.tools_log().exists() {
+            return String::new();
+        }
+        let log = self.read("tools.log");
+        let mut commands: Vec<&str> = log.lines().collect();
+        commands.sort();
+        commands
+            .iter()
+            .map(|command| format!("{command}\n"))
+            .collect()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        // a failed test leaves its scratch directory behind to be looked at
+        if !std::thread::panicking() {
+            let _ = remove_dir_all(&self.dir);
+        }
+    }
+}
+
+/// Compare an output against its golden file in the fixtures, or rewrite the
+/// golden file if MMORESEQS_UPDATE_GOLDEN is set.
+pub fn assert_golden(actual: &str, golden_name: &str) {
+    let golden_path = fixture_dir().join("golden").join(golden_name);
+
+    if std::env::var_os(UPDATE_GOLDEN_ENV_VAR).is_some() {
+        create_dir_all(golden_path.parent().unwrap())
+            .expect("failed to create the golden directory");
+        write(&golden_path, actual).expect("failed to write the golden file");
+        return;
+    }
+
+    let expected = read_to_string(&golden_path).unwrap_or_else(|_| {
+        panic!(
+            "missing golden file {}; run with {UPDATE_GOLDEN_ENV_VAR}=1 to create it",
+            golden_path.display()
+        )
+    });
+    assert!(
+        actual == expected,
+        "{golden_name} doesn't match its golden file (run with {UPDATE_GOLDEN_ENV_VAR}=1 to accept the change)\n\
+         expected:\n{expected}\nactual:\n{actual}"
+    );
+}
@@ -0,0 +1,1319 @@
+//! End-to-end runs of the pipeline on a tiny dataset, with stand-ins for mmseqs
+//! and HMMER that replay recorded outputs, checked against golden outputs.
+#![cfg(unix)]
+
+mod harness;
+
+use std::fs::{copy, create_dir_all, read_dir, read_to_string, rename, write};
+use std::path::PathBuf;
+
+use harness::{assert_golden, fixture, mock_tool, Harness};
+
+/// Mask the provenance that changes from build to build and run to run in an
+/// output's header, so that it doesn't change the golden file: the mmoreseqs
+/// version and commit, and the command line, which has the absolute paths.
+fn mask_provenance(output: &str) -> String {
+    output
+        .lines()
+        .map(|line| {
+            if line.starts_with("#mmoreseqs-version") {
+                return "#mmoreseqs-version\tVERSION";
+            }
+            for (prefix, masked) in [
+                ("# mmoreseqs-version ", "# mmoreseqs-version VERSION"),
+                ("# git-commit ", "# git-commit COMMIT"),
+                ("# command ", "# command COMMAND"),
+            ] {
+                if line.starts_with(prefix) {
+                    return masked;
+                }
+            }
+            line
+        })
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+/// Run prep and seed into the prep directory
+fn prep_and_seed(harness: &Harness) {
+    harness.run([
+        "prep",
+        &fixture("query.sto"),
+        &fixture("target.fa"),
+        "-o",
+        "prep",
+    ]);
+    harness.run(["seed", "prep", "-o", "prep/seeds.tsv"]);
+}
+
+/// Run align on the prep directory, with extra arguments
+fn align(harness: &Harness, extra_args: &[&str]) {
+    align_target(harness, &fixture("target.fa"), extra_args);
+}
+
+/// Run align on the prep directory against a target, with extra arguments
+fn align_target(harness: &Harness, target: &str, extra_args: &[&str]) {
+    let mut args = vec![
+        "align",
+        "prep/query.hmm",
+        target,
+        "prep/seeds.tsv",
+        "--prep-dir",
+        "prep",
+        "-o",
+        "results.tsv",
+        "--consensus-source",
+        "mmseqs",
+    ];
+    args.extend(extra_args);
+    harness.run(args);
+}
+
+/// The values of a column of a results table
+fn column(results: &str, column_idx: usize) -> Vec<String> {
+    results
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        // the header and separator lines
+        .skip(2)
+        .map(|line| line.split_whitespace().nth(column_idx).unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn prep_seed_align_match_golden_outputs() {
+    let harness = Harness::new("stages");
+
+    prep_and_seed(&harness);
+    align(&harness, &[]);
+
+    assert_golden(
+        &mask_provenance(&harness.read("prep/seeds.tsv")),
+        "seeds.tsv",
+    );
+    assert_golden(
+        &mask_provenance(&harness.read("results.tsv")),
+        "results.tsv",
+    );
+    assert_golden(&harness.tool_commands(), "stages-commands.txt");
+}
+
+#[test]
+fn seed_still_takes_the_query_and_target_databases() {
+    let harness = Harness::new("legacy-seed");
+    // the seeds, without the header, which records the prep directory's target checksum
+    let seeds = |name: &str| -> Vec<String> {
+        harness
+            .read(name)
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(String::from)
+            .collect()
+    };
+
+    prep_and_seed(&harness);
+    let output = harness.run([
+        "seed",
+        "prep/queryDB",
+        "prep/query.hmm",
+        "prep/targetDB",
+        "--work-dir",
+        "legacy-work",
+        "-o",
+        "legacy-seeds.tsv",
+    ]);
+
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is deprecated"));
+    assert!(harness.path("legacy-work/prefilterDB").exists());
+    assert_eq!(seeds("legacy-seeds.tsv"), seeds("prep/seeds.tsv"));
+}
+
+#[test]
+fn search_matches_the_separate_stages() {
+    let harness = Harness::new("search");
+
+    harness.run([
+        "search",
+        &fixture("query.sto"),
+        &fixture("target.fa"),
+        "--work-dir",
+        "work",
+        "-o",
+        "results.tsv",
+        "--consensus-source",
+        "mmseqs",
+    ]);
+
+    assert_golden(
+        &mask_provenance(&harness.read("results.tsv")),
+        "results.tsv",
+    );
+    assert_golden(&harness.tool_commands(), "search-commands.txt");
+}
+
+#[test]
+fn merged_shards_match_an_unsharded_run() {
+    let harness = Harness::new("merge");
+    // the rows, without the provenance comments that record the shard
+    let rows = |name: &str| -> Vec<String> {
+        harness
+            .read(name)
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(String::from)
+            .collect()
+    };
+
+    prep_and_seed(&harness);
+    // merge sorts the hits by E-value when it isn't given keys
+    align(&harness, &["--sort-by", "evalue"]);
+    let unsharded = rows("results.tsv");
+
+    for sort_by in [vec![], vec!["--sort-by", "evalue"]] {
+        for shard in ["1/2", "2/2"] {
+            let mut args = vec!["--shard", shard];
+            args.extend(&sort_by);
+            align(&harness, &args);
+            rename(
+                harness.path("results.tsv"),
+                harness.path(&format!("shard-{}.tsv", &shard[..1])),
+            )
+            .unwrap();
+        }
+
+        let mut args = vec!["merge", "shard-1.tsv", "shard-2.tsv", "-o", "merged.tsv"];
+        args.extend(&sort_by);
+        // merge runs no tools, so it takes no tool paths
+        assert!(harness.run_with_tool_env(args).status.success());
+        assert_eq!(rows("merged.tsv"), unsharded);
+    }
+
+    // a shard that is merged twice, e.g. from a resubmitted array job, adds no hits
+    let args = [
+        "merge",
+        "shard-1.tsv",
+        "shard-2.tsv",
+        "shard-1.tsv",
+        "-o",
+        "merged.tsv",
+    ];
+    assert!(harness.run_with_tool_env(args).status.success());
+    assert_eq!(rows("merged.tsv"), unsharded);
+}
+
+#[test]
+fn skipped_seeds_are_written_next_to_the_results() {
+    let harness = Harness::new("skipped-file");
+
+    prep_and_seed(&harness);
+    create_dir_all(harness.path("out")).unwrap();
+    harness.run([
+        "align",
+        "prep/query.hmm",
+        &fixture("target.fa"),
+        "prep/seeds.tsv",
+        "--prep-dir",
+        "prep",
+        "-o",
+        "out/results.tsv",
+        "--consensus-source",
+        "mmseqs",
+    ]);
+    assert!(harness.path("out/skipped.tsv").exists());
+    assert!(!harness.path("skipped.tsv").exists());
+}
+
+#[test]
+fn sort_by_orders_the_results() {
+    let harness = Harness::new("sort-by");
+
+    prep_and_seed(&harness);
+    align(&harness, &["--sort-by", "bits"]);
+    let bit_scores: Vec<f32> = column(&harness.read("results.tsv"), 6)
+        .iter()
+        .map(|score| score.parse().unwrap())
+        .collect();
+    assert!(bit_scores.windows(2).all(|pair| pair[0] >= pair[1]));
+
+    align(&harness, &["--sort-by", "target,coords"]);
+    let results = harness.read("results.tsv");
+    assert_eq!(column(&results, 0), ["tgt1", "tgt2", "tgt3", "tgt3"]);
+    assert_eq!(column(&results, 2), ["31", "11", "16", "60"]);
+
+    align(&harness, &["--sort-by", "evalue", "--per-query-dirs"]);
+    let query_results = harness.read("TST00001.1/results.tsv");
+    assert_eq!(column(&query_results, 0), ["tgt1", "tgt3", "tgt2", "tgt3"]);
+}
+
+#[test]
+fn provenance_is_written_into_the_outputs() {
+    let harness = Harness::new("provenance");
+
+    prep_and_seed(&harness);
+    align(
+        &harness,
+        &[
+            "--ali-format",
+            "stockholm",
+            "--ali-file",
+            "alignments.sto",
+            "--run-summary",
+            "run-summary.json",
+            "-E",
+            "0.001",
+        ],
+    );
+
+    let results = harness.read("results.tsv");
+    let alignments = harness.read("alignments.sto");
+    let seeds = harness.read("prep/seeds.tsv");
+    let query_hmm_checksum = seeds
+        .lines()
+        .find_map(|line| line.strip_prefix("#query-hmm\t"))
+        .unwrap();
+
+    for (prefix, output) in [("# ", &results), ("#=GF CC ", &alignments)] {
+        assert!(output.contains(&format!("{prefix}parameter E=0.001\n")));
+        assert!(output.contains(&format!("{prefix}parameter ali-format=stockholm\n")));
+        assert!(output.contains(&format!("{prefix}input query-hmm={query_hmm_checksum}\n")));
+        let command_line = output
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{prefix}command ")))
+            .unwrap();
+        assert!(command_line.contains(" align prep/query.hmm "));
+    }
+    // only the first alignment block carries the provenance
+    assert_eq!(alignments.matches("#=GF CC command ").count(), 1);
+
+    let summary = harness.read("run-summary.json");
+    assert!(summary.contains("\"provenance\""));
+    assert!(summary.contains(&format!("\"query-hmm\": \"{query_hmm_checksum}\"")));
+}
+
+#[test]
+fn alt_alignments_avoid_the_residues_of_their_hit() {
+    let harness = Harness::new("alt-alignments");
+
+    prep_and_seed(&harness);
+    align(&harness, &[]);
+    assert!(!harness.path("alt-alignments.tsv").exists());
+
+    align(&harness, &["--alt-alignments", "2", "--seed-expand", "40"]);
+    let alternatives = harness.read("alt-alignments.tsv");
+    assert!(alternatives.contains("alt rank accuracy hit target start hit target end"));
+    assert!(alternatives.contains("# parameter E=10\n"));
+
+    let ranks = column(&alternatives, 8);
+    let starts = column(&alternatives, 2);
+    let ends = column(&alternatives, 3);
+    let hit_starts = column(&alternatives, 10);
+    let hit_ends = column(&alternatives, 11);
+    for row_idx in 0..ranks.len() {
+        assert!(["1", "2"].contains(&ranks[row_idx].as_str()));
+        let start: usize = starts[row_idx].parse().unwrap();
+        let end: usize = ends[row_idx].parse().unwrap();
+        let hit_start: usize = hit_starts[row_idx].parse().unwrap();
+        let hit_end: usize = hit_ends[row_idx].parse().unwrap();
+        assert!(end < hit_start || start > hit_end);
+    }
+}
+
+#[test]
+fn sensitivity_tunes_the_prefilter_unless_overridden() {
+    let harness = Harness::new("sensitivity");
+
+    prep_and_seed(&harness);
+    harness.run(["seed", "prep", "-o", "fast.tsv", "--sensitivity", "fast"]);
+    harness.run([
+        "seed",
+        "prep",
+        "-o",
+        "exhaustive.tsv",
+        "--sensitivity",
+        "exhaustive",
+        "--k-score",
+        "60",
+        "--max-seqs",
+        "500",
+    ]);
+
+    let prefilters: Vec<String> = harness
+        .read("tools.log")
+        .lines()
+        .filter(|command| command.starts_with("mmseqs prefilter"))
+        .map(String::from)
+        .collect();
+    assert_eq!(prefilters.len(), 3);
+    // the fixture's one query is short but informative, which raises the bundles'
+    // k-scores, and a single query keeps twice their hits
+    assert!(prefilters[0].ends_with("--k-score 90 --min-ungapped-score 15 --max-seqs 2000"));
+    assert!(prefilters[1].contains("-k 7 --k-score 113 "));
+    assert!(prefilters[1].ends_with("--max-seqs 600"));
+    assert!(prefilters[2].contains("-k 6 --k-score 60 "));
+    assert!(prefilters[2].ends_with("--max-seqs 500"));
+}
+
+#[test]
+fn run_params_reproduce_the_run_with_config() {
+    let harness = Harness::new("run-params");
+
+    let (query, target) = (fixture("query.sto"), fixture("target.fa"));
+    let search = |extra_args: &[&str]| {
+        let mut args = vec!["search", &query, &target, "--consensus-source", "mmseqs"];
+        args.extend(extra_args);
+        harness.run(args);
+    };
+
+    search(&[
+        "--output-dir",
+        "run1",
+        "-E",
+        "0.5",
+        "--sort-by",
+        "evalue,bits",
+        "--comp-bias-filter",
+        "--seed-provenance",
+    ]);
+    let params = harness.read("run1/run-params.toml");
+    assert!(params.contains("\nE = 0.5\n"));
+    assert!(params.contains("\nseed-provenance = true\n"));
+    assert!(params.contains("\nsort-by = \"evalue,bits\"\n"));
+    assert!(params.contains("\ncomp-bias-filter = true\n"));
+    assert!(params.contains("\nfast-pass = false\n"));
+    // the tuned prefilter parameters are written, along with the mmseqs flags they make
+    assert!(params.contains("\nk-score = "));
+    assert!(params.contains("\nmax-seqs = "));
+    assert!(params.contains("\n[mmseqs]\nprefilter = \"--k-score "));
+
+    search(&["--output-dir", "run2", "--config", "run1/run-params.toml"]);
+    assert_eq!(
+        mask_provenance(&harness.read("run2/results/results.tsv")),
+        mask_provenance(&harness.read("run1/results/results.tsv"))
+    );
+    let reproduced = harness.read("run2/run-params.toml");
+    assert!(reproduced.contains("# The options were read from run1/run-params.toml\n"));
+    assert_eq!(
+        reproduced.lines().skip(3).collect::<Vec<&str>>(),
+        params.lines().skip(2).collect::<Vec<&str>>()
+    );
+
+    // the options on the command line take precedence over the config file's
+    search(&[
+        "--output-dir",
+        "run3",
+        "--config",
+        "run1/run-params.toml",
+        "-E",
+        "1e-12",
+    ]);
+    assert!(harness
+        .read("run3/run-params.toml")
+        .contains("\nE = 0.000000000001\n"));
+    assert_eq!(
+        column(&harness.read("run3/results/results.tsv"), 0).len(),
+        2
+    );
+
+    // a config file may be written by hand, with comments
+    write(
+        harness.path("hand.toml"),
+        "E = 0.5 # the defaults are too loose\nseed-provenance = true\n",
+    )
+    .unwrap();
+    search(&["--output-dir", "run4", "--config", "hand.toml"]);
+    assert!(harness
+        .read("run4/results/results.tsv")
+        .contains("seed evalue"));
+
+    write(harness.path("bad.toml"), "E 0.5\n").unwrap();
+    let output = harness.run_unchecked(["search", &query, &target, "--config", "bad.toml"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("malformed config file bad.toml: TOML parse error at line 1"));
+}
+
+#[test]
+fn abc_output_has_one_edge_per_query_and_target() {
+    let harness = Harness::new("abc-output");
+
+    prep_and_seed(&harness);
+    align(
+        &harness,
+        &["--abc-output", "hits.abc", "--edge-list", "edges.tsv"],
+    );
+
+    let results = harness.read("results.tsv");
+    let mut pairs: Vec<String> = column(&results, 1)
+        .iter()
+        .zip(column(&results, 0))
+        .map(|(profile, target)| format!("{profile}\t{target}"))
+        .collect();
+    pairs.sort();
+    pairs.dedup();
+
+    let abc = harness.read("hits.abc");
+    let abc_pairs: Vec<String> = abc
+        .lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            assert_eq!(fields.len(), 3);
+            assert!(fields[2].parse::<f32>().is_ok());
+            format!("{}\t{}", fields[0], fields[1])
+        })
+        .collect();
+    assert!(!abc_pairs.is_empty());
+    assert_eq!(abc_pairs, pairs);
+
+    let edges = harness.read("edges.tsv");
+    let mut edge_lines = edges.lines();
+    assert_eq!(
+        edge_lines.next(),
+        Some("query\ttarget\tbit score\te-value\tdomains")
+    );
+    for (edge, abc_line) in edge_lines.zip(abc.lines()) {
+        assert!(edge.starts_with(abc_line));
+    }
+}
+
+#[test]
+fn contig_hits_are_mapped_back_to_the_contigs() {
+    let harness = Harness::new("contigs");
+
+    harness.run([
+        "prep",
+        &fixture("query.sto"),
+        &fixture("contigs.fa"),
+        "-o",
+        "prep",
+        "--target-type",
+        "contigs",
+    ]);
+    assert!(harness
+        .tool_commands()
+        .contains("mmseqs createdb prep/target.orfs.fa prep/targetDB"));
+
+    // the contigs are back-translations of tgt1, and of tgt3 on the reverse strand
+    let orfs = harness.read("prep/target.orfs.fa");
+    let target = read_to_string(fixture("target.fa")).unwrap();
+    let orf_name = |target_name: &str| -> String {
+        let residues = target
+            .split('>')
+            .find_map(|record| record.strip_prefix(&format!("{target_name}\n")))
+            .unwrap()
+            .replace('\n', "");
+        let header_idx = orfs.find(&format!("\n{residues}\n")).unwrap();
+        orfs[..header_idx].rsplit('>').next().unwrap().to_string()
+    };
+    let (orf1, orf3) = (orf_name("tgt1"), orf_name("tgt3"));
+    assert!(orf1.starts_with("ctgA_"));
+    assert!(orf3.starts_with("ctgB_"));
+
+    let orf_map = harness.read("prep/orf-map.tsv");
+    assert!(orf_map.contains(&format!("{orf1}\tctgA\t+\t1\t4\t240\n")));
+    assert!(orf_map.contains(&format!("{orf3}\tctgB\t-\t1\t4\t288\n")));
+
+    // the recorded seeds, renamed to the ORFs
+    let seeds = read_to_string(fixture("seeds.tsv"))
+        .unwrap()
+        .lines()
+        .filter(|line| line.contains("\ttgt1\t") || line.contains("\ttgt3\t"))
+        .map(|line| {
+            let line = line.replace("\ttgt1\t", &format!("\t{orf1}\t"));
+            format!("{}\n", line.replace("\ttgt3\t", &format!("\t{orf3}\t")))
+        })
+        .collect::<String>();
+    write(harness.path("prep/seeds.tsv"), seeds).unwrap();
+    align_target(&harness, "prep/target.orfs.fa", &[]);
+
+    let results = harness.read("results.tsv");
+    assert!(results.contains("contig contig start contig end strand frame"));
+    let rows: Vec<String> = column(&results, 0)
+        .iter()
+        .zip(column(&results, 2))
+        .zip(column(&results, 3))
+        .map(|((orf, start), end)| format!("{orf} {start} {end}"))
+        .collect();
+    let contig_rows: Vec<String> = column(&results, 20)
+        .iter()
+        .zip(column(&results, 21))
+        .zip(column(&results, 22))
+        .zip(column(&results, 23))
+        .map(|(((contig, start), end), strand)| format!("{contig} {start} {end} {strand}"))
+        .collect();
+    for (row, contig_row) in rows.iter().zip(&contig_rows) {
+        let expected = match &row[..] {
+            row if row == format!("{orf1} 31 54") => "ctgA 94 165 +",
+            row if row == format!("{orf3} 16 39") => "ctgB 172 243 -",
+            row if row == format!("{orf3} 60 83") => "ctgB 40 111 -",
+            row => panic!("unexpected hit: {row}"),
+        };
+        assert_eq!(contig_row, expected);
+    }
+    assert_eq!(contig_rows.len(), 3);
+}
+
+#[test]
+fn genetic_code_changes_the_stop_codons() {
+    let harness = Harness::new("genetic-code");
+
+    // the ciliate code reads TAA, which ends the ORFs of the contigs, as glutamine
+    let output = harness.run([
+        "prep",
+        &fixture("query.sto"),
+        &fixture("contigs.fa"),
+        "-o",
+        "prep",
+        "--target-type",
+        "contigs",
+        "--genetic-code",
+        "6",
+    ]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("with genetic code 6 ("));
+
+    let orf_map = harness.read("prep/orf-map.tsv");
+    assert!(orf_map.contains("\tctgA\t+\t1\t1\t243\n"));
+    assert!(orf_map.contains("\tctgB\t-\t1\t1\t291\n"));
+}
+
+#[test]
+fn time_limits_write_partial_results() {
+    let harness = Harness::new("time-limit");
+
+    prep_and_seed(&harness);
+    let target = fixture("target.fa");
+    let align_with = |limit_args: &[&str]| {
+        let mut args = vec![
+            "align",
+            "prep/query.hmm",
+            &target,
+            "prep/seeds.tsv",
+            "--prep-dir",
+            "prep",
+            "-o",
+            "results.tsv",
+            "--consensus-source",
+            "mmseqs",
+            "--run-summary",
+            "summary.json",
+        ];
+        args.extend(limit_args);
+        harness.run_unchecked(args)
+    };
+
+    // the query's first seed is aligned, but it takes longer than the limit
+    let output = align_with(&["--per-query-time-limit", "0.000001s"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("time limit was reached"));
+    assert_eq!(column(&harness.read("results.tsv"), 1), vec!["TST_fam"]);
+    let time_limited = harness
+        .read("skipped.tsv")
+        .lines()
+        .filter(|line| line.ends_with("\ttime_limit"))
+        .count();
+    assert_eq!(time_limited, 4);
+    let summary = harness.read("summary.json");
+    assert!(summary.contains("\"incomplete_queries\": [\n    \"TST_fam\"\n  ]"));
+
+    // the run's limit counts from when mmoreseqs started, so no seed is aligned
+    let output = align_with(&["--time-limit", "0:00:00.000001"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(column(&harness.read("results.tsv"), 1).is_empty());
+
+    let output = align_with(&["--time-limit", "1h", "--per-query-time-limit", "30m"]);
+    assert!(output.status.success());
+    assert!(!harness.read("summary.json").contains("incomplete_queries"));
+}
+
+#[test]
+fn a_cancelled_search_writes_its_manifest_and_can_be_rerun() {
+    let harness = Harness::new("cancel");
+    let search_args = [
+        "search",
+        &fixture("query.sto"),
+        &fixture("target.fa"),
+        "--output-dir",
+        "run",
+        "--consensus-source",
+        "mmseqs",
+    ];
+
+    // SIGTERM arrives during the seed stage, whose partial seeds are removed
+    let output = harness.run_with_env(search_args, &[("MOCK_INTERRUPT_PREFILTER", "1")]);
+    assert_eq!(output.status.code(), Some(128 + 15));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cancelled by signal 15"));
+    assert!(harness
+        .read("run/run.json")
+        .contains("\"status\": \"cancelled\""));
+    assert!(harness
+        .read("run/run-summary.json")
+        .contains("cancelled by signal 15"));
+    assert!(harness.path("run/prep/query.hmm").exists());
+    assert!(!harness.path("run/prep/seeds.tsv").exists());
+
+    harness.run(search_args);
+    assert!(harness
+        .read("run/run.json")
+        .contains("\"status\": \"complete\""));
+    assert_eq!(column(&harness.read("run/results/results.tsv"), 1).len(), 4);
+}
+
+#[test]
+fn bench_parallel_times_every_schedule_and_thread_count() {
+    let harness = Harness::new("bench-parallel");
+
+    let output = harness.run_with_tool_env([
+        "bench-parallel",
+        &fixture("query.hmm"),
+        "--seeds",
+        "20",
+        "--max-threads",
+        "2",
+        "--repeats",
+        "1",
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let rows: Vec<Vec<&str>> = stdout
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.starts_with("recommendation"))
+        .map(|line| line.split('\t').collect())
+        .collect();
+
+    let configurations: Vec<(&str, &str)> = rows.iter().map(|row| (row[0], row[1])).collect();
+    assert_eq!(
+        configurations,
+        [
+            ("lpt", "1"),
+            ("lpt", "2"),
+            ("fifo", "1"),
+            ("fifo", "2"),
+            ("random", "1"),
+            ("random", "2")
+        ]
+    );
+    // every configuration aligns the same seeds to the same hits
+    assert!(rows.iter().all(|row| row[5] == "20"));
+    assert!(stdout.contains("\nrecommendation: --schedule "));
+}
+
+#[test]
+fn bound_stats_describe_the_bounds_of_each_hit() {
+    let harness = Harness::new("bound-stats");
+
+    prep_and_seed(&harness);
+    align(&harness, &["--bound-stats", "bounds.tsv"]);
+
+    let results = harness.read("results.tsv");
+    let bounds = harness.read("bounds.tsv");
+
+    assert_eq!(column(&bounds, 0), column(&results, 0));
+    // the hit's columns, the seed's E-value and bit score, and then the bounds
+    let cells = column(&bounds, 11);
+    let full_cells = column(&bounds, 12);
+    for (cells, full_cells) in cells.iter().zip(&full_cells) {
+        let cells: usize = cells.parse().unwrap();
+        assert!(cells > 0 && cells < full_cells.parse().unwrap());
+    }
+    assert!(column(&bounds, 10).iter().all(|source| source == "cloud"));
+}
+
+#[test]
+fn object_store_outputs_are_uploaded_as_they_are_written() {
+    let harness = Harness::new("object-store");
+    let store = harness.path("store");
+    let aws = mock_tool("aws");
+    let env = [
+        ("MMORESEQS_AWS", aws.to_str().unwrap()),
+        ("MOCK_OBJECT_STORE", store.to_str().unwrap()),
+    ];
+
+    let output = harness.run_with_env(
+        [
+            "search",
+            &fixture("query.sto"),
+            &fixture("target.fa"),
+            "--output-dir",
+            "s3://bucket/runs/run1",
+            "--consensus-source",
+            "mmseqs",
+        ],
+        &env,
+    );
+    assert!(output.status.success());
+
+    // the results are uploaded once written, before the run directory is synced
+    let commands = harness.tool_commands();
+    let results_upload = commands
+        .find("aws s3 cp")
+        .expect("the results weren't uploaded");
+    let sync = commands.find("aws s3 sync").expect("the run wasn't synced");
+    assert!(results_upload < sync);
+    assert!(commands.contains("s3://bucket/runs/run1/results/results.tsv"));
+
+    // the run stages its outputs in a directory of its own
+    let staging_dirs: Vec<PathBuf> = read_dir(harness.path("."))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("mmoreseqs-staging-")
+        })
+        .collect();
+    assert_eq!(staging_dirs.len(), 1);
+
+    // the run directory is uploaded without its intermediate files
+    let uploaded = store.join("bucket/runs/run1");
+    assert_eq!(
+        read_to_string(uploaded.join("results/results.tsv")).unwrap(),
+        read_to_string(staging_dirs[0].join("s3/bucket/runs/run1/results/results.tsv")).unwrap()
+    );
+    assert!(uploaded.join("run.json").exists());
+    assert!(!uploaded.join("prep").exists());
+
+    // with a failing upload, the run fails rather than leave the results behind
+    let output = harness.run_with_env(
+        [
+            "search",
+            &fixture("query.sto"),
+            &fixture("target.fa"),
+            "-o",
+            "s3://bucket/results.tsv",
+            "--consensus-source",
+            "mmseqs",
+        ],
+        &[("MMORESEQS_AWS", "false")],
+    );
+    assert!(!output.status.success());
+    // the results and the skipped seeds next to them
+    assert!(String::from_utf8_lossy(&output.stderr).contains("failed to upload 2 of 2 outputs"));
+}
+
+#[test]
+fn remote_targets_are_fetched_by_range_for_their_seeds() {
+    let harness = Harness::new("remote-target");
+    let curl = mock_tool("curl");
+    let http_root = harness.path("http");
+    create_dir_all(&http_root).unwrap();
+    copy(fixture("target.fa"), http_root.join("target.fa")).unwrap();
+    let env = [
+        ("MMORESEQS_CURL", curl.to_str().unwrap()),
+        ("MOCK_HTTP_ROOT", http_root.to_str().unwrap()),
+    ];
+
+    // without the seeds on tgt4, its record isn't needed
+    prep_and_seed(&harness);
+    let seeds: String = harness
+        .read("prep/seeds.tsv")
+        .lines()
+        .filter(|line| !line.contains("\ttgt4\t"))
+        .map(|line| format!("{line}\n"))
+        .collect();
+    write(harness.path("prep/seeds.tsv"), seeds).unwrap();
+    align(&harness, &[]);
+    let local_results = harness.read("results.tsv");
+
+    let remote_align = || {
+        let output = harness.run_with_env(
+            [
+                "align",
+                "prep/query.hmm",
+                "https://example.org/target.fa",
+                "prep/seeds.tsv",
+                "--prep-dir",
+                "prep",
+                "-o",
+                "results.tsv",
+                "--consensus-source",
+                "mmseqs",
+            ],
+            &env,
+        );
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stderr).to_string()
+    };
+    assert!(remote_align().contains("fetched 3 of 4 targets"));
+    assert!(harness.tool_commands().contains("--range 0-"));
+    assert!(harness.path("prep/target.index.tsv").exists());
+
+    // the E-values are still over all of the targets, and not only the fetched ones
+    let hits = |results: &str| -> Vec<String> {
+        results
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(String::from)
+            .collect()
+    };
+    assert_eq!(hits(&harness.read("results.tsv")), hits(&local_results));
+
+    // prep downloads the whole fasta for mmseqs, and indexes it for align
+    let output = harness.run_with_env(
+        [
+            "prep",
+            &fixture("query.sto"),
+            "https://example.org/target.fa",
+            "-o",
+            "remote-prep",
+        ],
+        &env,
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        harness.read("remote-prep/target.remote.fa"),
+        read_to_string(fixture("target.fa")).unwrap()
+    );
+    assert_eq!(
+        harness.read("remote-prep/target.index.tsv").lines().count(),
+        4
+    );
+
+    // the saved index is reused until the remote fasta changes size
+    assert!(!remote_align().contains("indexing"));
+    let mut grown = read_to_string(http_root.join("target.fa")).unwrap();
+    grown.push_str(">tgt5\nMKVLVTGAGSGIGLELAKRLAEEG\n");
+    write(http_root.join("target.fa"), grown).unwrap();
+    let stderr = remote_align();
+    assert!(stderr.contains("indexing https://example.org/target.fa again"));
+    assert!(stderr.contains("fetched 3 of 5 targets"));
+
+    // a range that comes back short fails the run, rather than align truncated targets
+    let output = harness.run_with_env(
+        [
+            "align",
+            "prep/query.hmm",
+            "https://example.org/target.fa",
+            "prep/seeds.tsv",
+            "--prep-dir",
+            "prep",
+            "-o",
+            "results.tsv",
+            "--consensus-source",
+            "mmseqs",
+        ],
+        &[env[0], env[1], ("MOCK_SHORT_RANGE", "1")],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("bytes of the target fasta from https://example.org/target.fa"));
+}
+
+#[test]
+fn hit_ids_join_the_outputs_and_reruns() {
+    let harness = Harness::new("hit-ids");
+
+    prep_and_seed(&harness);
+    align(&harness, &[]);
+    let ids = column(&harness.read("results.tsv"), 19);
+
+    align(
+        &harness,
+        &[
+            "--threads",
+            "2",
+            "--sort-by",
+            "evalue",
+            "--ali-format",
+            "stockholm",
+            "--ali-file",
+            "alignments.sto",
+            "--hits-fasta",
+            "hits.fa",
+        ],
+    );
+    let mut rerun_ids = column(&harness.read("results.tsv"), 19);
+    let mut sorted_ids = ids.clone();
+    sorted_ids.sort();
+    rerun_ids.sort();
+    assert_eq!(rerun_ids, sorted_ids);
+
+    let alignments = harness.read("alignments.sto");
+    let hits_fasta = harness.read("hits.fa");
+    for id in &ids {
+        assert!(alignments.contains(&format!("#=GF CC hit-id {id}\n")));
+        assert!(hits_fasta.contains(&format!("hit-id={id}\n")));
+    }
+}
+
+#[test]
+fn filter_drops_the_hits_that_fail_its_condition() {
+    let harness = Harness::new("filter");
+
+    prep_and_seed(&harness);
+    align(
+        &harness,
+        &[
+            "--filter",
+            "evalue < 1e-10 && (bias < 0.2*bits || -seeds >= 0)",
+        ],
+    );
+    let results = harness.read("results.tsv");
+    assert!(results.contains("# parameter filter=evalue < 1e-10"));
+    // the 1.9e-9 hit on tgt3 is the only one dropped
+    assert_eq!(column(&results, 0), ["tgt1", "tgt3", "tgt2"]);
+    assert_eq!(column(&results, 2), ["31", "16", "11"]);
+    // the dropped hit still counts towards the sequence score and domains of tgt3
+    assert_eq!(column(&results, 16), ["46.20", "80.31", "40.30"]);
+    assert_eq!(column(&results, 18), ["1", "2", "1"]);
+
+    for (filter, error) in [
+        (
+            "evalue <",
+            "invalid filter at column 9: unexpected end of the filter",
+        ),
+        ("evalue < 1 && score > 5", "unknown field score"),
+        ("evalue && bits > 5", "expected a comparison"),
+        (
+            "(evalue < 1) * 2 > 1",
+            "expected a number, not a comparison",
+        ),
+    ] {
+        let output = harness.run_unchecked([
+            "align",
+            "prep/query.hmm",
+            "t.fa",
+            "s.tsv",
+            "--filter",
+            filter,
+        ]);
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains(error),
+            "{filter}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+#[test]
+fn version_json_reports_the_build_and_the_tools_found() {
+    let harness = Harness::new("version-json");
+
+    let output = harness.run_with_tool_env(["--version-json"]);
+    assert!(output.status.success());
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(info["mmoreseqs"], env!("CARGO_PKG_VERSION"));
+    assert!(info["nale"].is_string());
+    assert!(info["build_profile"].is_string());
+    assert!(info["features"].is_array());
+    assert_eq!(info["mmseqs"]["version"], "mock mmseqs");
+    assert_eq!(info["hmmer"]["version"], "mock hmmbuild");
+}
+
+#[test]
+fn dense_cells_aligns_small_seeds_over_the_whole_matrix() {
+    let harness = Harness::new("dense-cells");
+
+    prep_and_seed(&harness);
+    align(&harness, &[]);
+    let cloud_results = harness.read("results.tsv");
+
+    // every fixture matrix is under 24 x 100 cells
+    align(
+        &harness,
+        &["--dense-cells", "2400", "--bound-stats", "bounds.tsv"],
+    );
+    let dense_results = harness.read("results.tsv");
+    let bounds = harness.read("bounds.tsv");
+
+    assert!(column(&bounds, 10).iter().all(|source| source == "dense"));
+    assert_eq!(column(&bounds, 11), column(&bounds, 12));
+    // every target is still hit, though the two hits on tgt3 become one,
+    // since both of its seeds are aligned over the whole target
+    let targets = |results: &str| {
+        let mut targets = column(results, 0);
+        targets.dedup();
+        targets
+    };
+    assert_eq!(targets(&dense_results), targets(&cloud_results));
+    assert_eq!(column(&dense_results, 0).len(), 3);
+}
+
+#[test]
+fn chain_seeds_joins_collinear_fragments() {
+    let harness = Harness::new("chain-seeds");
+
+    prep_and_seed(&harness);
+    // split the seed of tgt1 into two fragments, 6 positions apart on both sequences
+    let seeds = harness.read("prep/seeds.tsv").replace(
+        "TST00001.1\ttgt1\t1\t24\t31\t54\t2.100E-10\t45\n",
+        "TST00001.1\ttgt1\t1\t8\t31\t38\t4.000E-03\t20\n\
+         TST00001.1\ttgt1\t15\t24\t45\t54\t6.000E-03\t18\n",
+    );
+    write(harness.path("prep/seeds.tsv"), seeds).unwrap();
+
+    let align_with = |max_gap: &str| {
+        let output = harness.run([
+            "align",
+            "prep/query.hmm",
+            &fixture("target.fa"),
+            "prep/seeds.tsv",
+            "--prep-dir",
+            "prep",
+            "-o",
+            "results.tsv",
+            "--consensus-source",
+            "mmseqs",
+            "--chain-seeds",
+            max_gap,
+        ]);
+        String::from_utf8_lossy(&output.stderr).to_string()
+    };
+
+    assert!(align_with("6").contains("chained 2 collinear seeds into 1 longer seeds"));
+    assert!(column(&harness.read("results.tsv"), 0).contains(&"tgt1".to_string()));
+
+    assert!(!align_with("5").contains("chained"));
+}
+
+#[test]
+fn seeds_match_their_query_profiles_by_name_or_case() {
+    let harness = Harness::new("seed-query-keys");
+
+    prep_and_seed(&harness);
+    // key the seeds by the profile's name, in two cases, and add one for a profile that isn't there
+    let seeds = harness
+        .read("prep/seeds.tsv")
+        .replacen("TST00001.1\t", "TST_fam\t", 2)
+        .replacen("TST00001.1\t", "tst_FAM\t", 1)
+        + "PF99999.1\ttgt1\t1\t24\t31\t54\t2.100E-10\t45\n";
+    write(harness.path("prep/seeds.tsv"), seeds).unwrap();
+
+    let output = harness.run_unchecked([
+        "align",
+        "prep/query.hmm",
+        &fixture("target.fa"),
+        "prep/seeds.tsv",
+        "--prep-dir",
+        "prep",
+        "-o",
+        "results.tsv",
+        "--consensus-source",
+        "mmseqs",
+    ]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("matched 3 seeds to their query profiles by name or ignoring case"));
+    assert!(stderr.contains("skipped 1 seeds whose query matches no profile"));
+    assert!(stderr.contains("PF99999.1 (1)"));
+
+    let targets = column(&harness.read("results.tsv"), 0);
+    assert!(targets.contains(&"tgt1".to_string()));
+    assert!(targets.contains(&"tgt3".to_string()));
+
+    let output = harness.run_unchecked([
+        "align",
+        "prep/query.hmm",
+        &fixture("target.fa"),
+        "prep/seeds.tsv",
+        "--prep-dir",
+        "prep",
+        "-o",
+        "results.tsv",
+        "--consensus-source",
+        "mmseqs",
+        "--strict",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("PF99999.1 (1)"));
+}
+
+#[test]
+fn logo_data_has_one_row_per_match_state() {
+    let harness = Harness::new("logo-data");
+
+    harness.run([
+        "view",
+        "hmm",
+        &fixture("query.hmm"),
+        "--logo-data",
+        "logo.tsv",
+    ]);
+
+    let logo = harness.read("logo.tsv");
+    let mut lines = logo.lines();
+    let header: Vec<&str> = lines.next().unwrap().split('\t').collect();
+    assert_eq!(
+        header[..4],
+        ["profile", "position", "consensus", "information"]
+    );
+    assert_eq!(header.len(), 24);
+
+    let rows: Vec<Vec<&str>> = lines.map(|line| line.split('\t').collect()).collect();
+    // the fixture model has 24 match states
+    assert_eq!(rows.len(), 24);
+    for (idx, row) in rows.iter().enumerate() {
+        assert_eq!(row[0], "TST_fam");
+        assert_eq!(row[1], (idx + 1).to_string());
+        let information: f32 = row[3].parse().unwrap();
+        assert!(information >= 0.0);
+        let total: f32 = row[4..].iter().map(|p| p.parse::<f32>().unwrap()).sum();
+        assert!((total - 1.0).abs() < 0.01);
+    }
+}
+
+#[test]
+fn mask_previous_hides_the_regions_of_earlier_hits() {
+    let harness = Harness::new("mask-previous");
+
+    prep_and_seed(&harness);
+    align(&harness, &[]);
+    let first_targets = column(&harness.read("results.tsv"), 0);
+    assert!(!first_targets.is_empty());
+    copy(harness.path("results.tsv"), harness.path("previous.tsv")).unwrap();
+
+    harness.run([
+        "prep",
+        &fixture("query.sto"),
+        &fixture("target.fa"),
+        "-o",
+        "prep",
+        "--mask-previous",
+        "previous.tsv",
+    ]);
+    assert!(harness
+        .tool_commands()
+        .contains("createdb prep/target.masked.fa"));
+    assert!(harness.read("prep/target.masked.fa").contains("XXXX"));
+
+    align(&harness, &["--mask-previous", "previous.tsv"]);
+    // the seeds still point at the masked regions, which no longer align
+    let masked_targets = column(&harness.read("results.tsv"), 0);
+    assert!(masked_targets.len() < first_targets.len());
+}
+
+#[test]
+fn trace_output_has_the_state_path_of_each_hit() {
+    let harness = Harness::new("trace-output");
+
+    prep_and_seed(&harness);
+    align(&harness, &["--trace-output", "traces.json"]);
+
+    let results = harness.read("results.tsv");
+    let traces: serde_json::Value = serde_json::from_str(&harness.read("traces.json")).unwrap();
+    let hits = traces["hits"].as_array().unwrap();
+    assert_eq!(hits.len(), column(&results, 0).len());
+
+    for hit in hits {
+        let states = hit["states"].as_str().unwrap();
+        let target_positions = hit["target_positions"].as_array().unwrap();
+        assert_eq!(
+            hit["profile_positions"].as_array().unwrap().len(),
+            states.len()
+        );
+        assert_eq!(target_positions.len(), states.len());
+        assert!(states.starts_with('S') && states.ends_with('T'));
+
+        // the match and insert states emit the hit's target residues, in order
+        let emitted: Vec<u64> = states
+            .chars()
+            .zip(target_positions)
+            .filter(|(state, _)| *state == 'M' || *state == 'I')
+            .map(|(_, position)| position.as_u64().unwrap())
+            .collect();
+        assert_eq!(emitted.first(), hit["target_start"].as_u64().as_ref());
+        assert_eq!(emitted.last(), hit["target_end"].as_u64().as_ref());
+        assert!(emitted.windows(2).all(|pair| pair[1] == pair[0] + 1));
+    }
+
+    align(
+        &harness,
+        &["--trace-output", "traces.bin", "--trace-format", "binary"],
+    );
+    let binary = std::fs::read(harness.path("traces.bin")).unwrap();
+    assert_eq!(&binary[..8], b"MMSTRACE");
+    assert_eq!(
+        u32::from_le_bytes(binary[12..16].try_into().unwrap()) as usize,
+        hits.len()
+    );
+}
+
+#[test]
+fn number_format_controls_evalues_and_scores() {
+    let harness = Harness::new("number-format");
+
+    prep_and_seed(&harness);
+    align(&harness, &[]);
+    let default_evalues = column(&harness.read("results.tsv"), 7);
+    assert!(default_evalues.iter().all(|evalue| evalue.contains('e')));
+
+    align(
+        &harness,
+        &["--evalue-format", "decimal", "--score-precision", "4"],
+    );
+    let results = harness.read("results.tsv");
+    for (evalue, default_evalue) in column(&results, 7).iter().zip(&default_evalues) {
+        assert!(!evalue.contains('e'));
+        let (evalue, default_evalue): (f32, f32) =
+            (evalue.parse().unwrap(), default_evalue.parse().unwrap());
+        assert!((evalue - default_evalue).abs() <= default_evalue * 0.05);
+    }
+    for bit_score in column(&results, 6) {
+        assert_eq!(bit_score.split('.').nth(1).unwrap().len(), 4);
+    }
+
+    align(&harness, &["--evalue-precision", "3"]);
+    for evalue in column(&harness.read("results.tsv"), 7) {
+        let mantissa = evalue.split('e').next().unwrap();
+        assert_eq!(mantissa.split('.').nth(1).unwrap().len(), 3);
+    }
+}
+
+#[test]
+fn reruns_write_the_same_outputs_whatever_the_threads_and_schedule() {
+    let harness = Harness::new("determinism");
+    // an output without its command line and thread count, the only lines that differ between the runs
+    let without_run_settings = |name: &str| -> String {
+        harness
+            .read(name)
+            .lines()
+            .filter(|line| !line.contains("command ") && !line.contains("parameter threads="))
+            .map(|line| format!("{line}\n"))
+            .collect()
+    };
+    let align_outputs = |extra_args: &[&str]| -> (String, String) {
+        let mut args = vec!["--ali-format", "stockholm", "--ali-file", "alignments.sto"];
+        args.extend(extra_args);
+        align(&harness, &args);
+        (
+            without_run_settings("results.tsv"),
+            without_run_settings("alignments.sto"),
+        )
+    };
+
+    prep_and_seed(&harness);
+    let expected = align_outputs(&["--threads", "1"]);
+    assert_eq!(column(&expected.0, 0).len(), 4);
+
+    for extra_args in [
+        ["--threads", "1", "--schedule", "fifo"],
+        ["--threads", "4", "--schedule", "fifo"],
+        ["--threads", "3", "--schedule", "lpt"],
+        ["--threads", "2", "--schedule", "random"],
+    ] {
+        assert_eq!(align_outputs(&extra_args), expected, "{extra_args:?}");
+    }
+
+    // the in-run check reports on stderr, so that stdout only has the run's own output
+    let output = harness.run([
+        "align",
+        "prep/query.hmm",
+        &fixture("target.fa"),
+        "prep/seeds.tsv",
+        "--prep-dir",
+        "prep",
+        "-o",
+        "checked.tsv",
+        "--consensus-source",
+        "mmseqs",
+        "--threads",
+        "2",
+        "--verify-deterministic",
+        "3",
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("determinism check: all 3 runs produced identical results"));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("determinism check"));
+}